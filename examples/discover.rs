@@ -0,0 +1,30 @@
+//! Lists the Dante devices on the network and their channels.
+//!
+//! cargo run --example discover [settle seconds]
+
+use std::time::Duration;
+
+fn main() {
+    let settle_seconds = std::env::args()
+        .nth(1)
+        .map(|seconds| seconds.parse().expect("settle time should be in seconds"))
+        .unwrap_or(2);
+
+    let devices = match dante_control_rs::discover(
+        Duration::from_secs(settle_seconds),
+        Duration::from_secs(30),
+    ) {
+        Ok(devices) => devices,
+        Err(error) => {
+            eprintln!("Discovery failed: {}", error);
+            std::process::exit(1);
+        }
+    };
+
+    if devices.is_empty() {
+        println!("No Dante devices found");
+    }
+    for device in devices {
+        println!("{:#}\n", device);
+    }
+}
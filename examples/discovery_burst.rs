@@ -0,0 +1,101 @@
+//! Replays a burst of made up mdns events, like a big network answering all at once after a switch reboot, and prints how fast the device list gets through them. Most of the events are services resolving again to what's already cached, which is what discovery spends its time on.
+//!
+//! cargo run --release --example discovery_burst --features test-util [devices] [channels per device] [rounds]
+
+use dante_control_rs::{DanteDeviceManager, DanteService, PreparedEvent, SyntheticEvent};
+use std::collections::HashMap;
+use std::net::Ipv4Addr;
+use std::time::Instant;
+
+fn main() {
+    let mut args = std::env::args()
+        .skip(1)
+        .map(|arg| arg.parse::<usize>().expect("arguments should be numbers"));
+    let device_count = args.next().unwrap_or(200);
+    let channel_count = args.next().unwrap_or(32);
+    let rounds = args.next().unwrap_or(10);
+
+    let manager = DanteDeviceManager::new();
+    let mut events = Vec::new();
+    for device in 0..device_count {
+        let device_name = format!("Burst-{:04}", device);
+        let address = Ipv4Addr::new(10, 0, (device / 250) as u8, (device % 250) as u8 + 1);
+        for service in [DanteService::Dbc, DanteService::Cmc, DanteService::Arc] {
+            let fullname = format!("{}.{}", device_name, service.service_type());
+            let txt = match service {
+                DanteService::Cmc => HashMap::from([
+                    ("mf".to_owned(), "Burst".to_owned()),
+                    ("model".to_owned(), "BURST-1".to_owned()),
+                    ("nchan".to_owned(), channel_count.to_string()),
+                ]),
+                DanteService::Arc => {
+                    HashMap::from([("router_vers".to_owned(), "4.4.1.3".to_owned())])
+                }
+                _ => HashMap::new(),
+            };
+            events.push((service, fullname, address, 4440, txt));
+        }
+        for channel in 1..=channel_count {
+            let fullname = format!(
+                "{:02}@{}.{}",
+                channel,
+                device_name,
+                DanteService::Chan.service_type()
+            );
+            let txt = HashMap::from([
+                ("id".to_owned(), channel.to_string()),
+                ("rate".to_owned(), "48000".to_owned()),
+                ("en".to_owned(), "24".to_owned()),
+                ("latency_ns".to_owned(), "1000000".to_owned()),
+            ]);
+            events.push((DanteService::Chan, fullname, address, 4455, txt));
+        }
+    }
+
+    for (service, fullname, ..) in &events {
+        manager
+            .inject_event(
+                *service,
+                SyntheticEvent::Found {
+                    fullname: fullname.clone(),
+                },
+            )
+            .expect("made up event should be valid");
+    }
+
+    // Prepared up front, like mdns hands them to discovery, so only the device list's handling gets timed.
+    let rounds_of_events: Vec<Vec<PreparedEvent>> = (0..rounds)
+        .map(|_| {
+            events
+                .iter()
+                .map(|(service, fullname, address, port, txt)| {
+                    SyntheticEvent::Resolved {
+                        fullname: fullname.clone(),
+                        addrs: vec![*address],
+                        port: *port,
+                        txt: txt.clone(),
+                    }
+                    .prepare(*service)
+                    .expect("made up event should be valid")
+                })
+                .collect()
+        })
+        .collect();
+
+    for (round, round_events) in rounds_of_events.into_iter().enumerate() {
+        let started = Instant::now();
+        let event_count = round_events.len();
+        for event in round_events {
+            manager.inject_prepared_event(event);
+        }
+        let elapsed = started.elapsed();
+        println!(
+            "Round {}{}: {} events in {:?}, {:.0} events/s",
+            round + 1,
+            if round == 0 { " (first resolve)" } else { "" },
+            event_count,
+            elapsed,
+            event_count as f64 / elapsed.as_secs_f64()
+        );
+    }
+}
@@ -0,0 +1,24 @@
+//! Prints devices coming and going, channels being added and removed, and other discovery events as they happen. Stop with Ctrl-C.
+//!
+//! cargo run --example monitor
+
+use dante_control_rs::{DanteDeviceEvent, DanteDeviceManager};
+
+fn main() {
+    let manager = DanteDeviceManager::new();
+    let events = manager.subscribe_events();
+    if let Err(error) = manager.start_discovery() {
+        eprintln!("Discovery failed: {}", error);
+        std::process::exit(1);
+    }
+
+    for event in events {
+        match event {
+            DanteDeviceEvent::DeviceAdded { device_name } => println!("+ {}", device_name),
+            DanteDeviceEvent::DeviceRemoved { device_name } => println!("- {}", device_name),
+            // Every few minutes for every service of every device.
+            DanteDeviceEvent::ServiceResolved { .. } => {}
+            event => println!("{:?}", event),
+        }
+    }
+}
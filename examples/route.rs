@@ -0,0 +1,49 @@
+//! Subscribes one RX channel to one TX channel by name. The Dante version of the RX device is picked up from discovery.
+//!
+//! cargo run --example route -- "rx device/rx channel" "tx device/tx channel"
+
+use dante_control_rs::{ChannelNameKind, DanteDeviceManager};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let (Some(rx_path), Some(tx_path)) = (args.next(), args.next()) else {
+        eprintln!("usage: route \"rx device/rx channel\" \"tx device/tx channel\"");
+        std::process::exit(2);
+    };
+    let rx_device = rx_path.split('/').next().unwrap_or_default();
+
+    let mut manager = DanteDeviceManager::new();
+    if let Err(error) = manager.start_discovery() {
+        eprintln!("Discovery failed: {}", error);
+        std::process::exit(1);
+    }
+
+    // The version comes from the ARC record, so wait for that to resolve.
+    let give_up_at = Instant::now() + Duration::from_secs(10);
+    while !manager
+        .get_device_info(rx_device)
+        .is_some_and(|info| info.router_vers.is_some() && !info.addresses.is_empty())
+    {
+        if Instant::now() > give_up_at {
+            eprintln!("{} wasn't discovered", rx_device);
+            std::process::exit(1);
+        }
+        sleep(Duration::from_millis(200));
+    }
+
+    let result = manager.subscribe_by_channel_path(&rx_path, &tx_path);
+    manager.stop_discovery();
+    match result {
+        Ok(Some(ChannelNameKind::Canonical)) => println!(
+            "Subscribed {} to {}, going by the canonical name of the TX channel",
+            rx_path, tx_path
+        ),
+        Ok(_) => println!("Subscribed {} to {}", rx_path, tx_path),
+        Err(error) => {
+            eprintln!("Subscribing {} to {} failed: {}", rx_path, tx_path, error);
+            std::process::exit(1);
+        }
+    }
+}
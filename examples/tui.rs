@@ -0,0 +1,266 @@
+//! A live table of the Dante devices on the network, kept up to date from discovery events alone. Up and down pick a device, c clears its subscriptions, q quits.
+//!
+//! cargo run --example tui --features tui-example
+
+use dante_control_rs::{DanteDeviceEvent, DanteDeviceManager, DeviceInfo};
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Style, Stylize};
+use ratatui::widgets::{Block, Paragraph, Row, Table, TableState};
+use ratatui::{DefaultTerminal, Frame};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::mpsc::Receiver;
+use std::time::{Duration, Instant};
+
+/// A device of the table. info is looked up again whenever an event about the device comes in, which only reads the manager's cache.
+struct DeviceRow {
+    info: Option<DeviceInfo>,
+    last_seen: Option<Instant>,
+}
+
+struct App {
+    manager: DanteDeviceManager,
+    events: Receiver<DanteDeviceEvent>,
+    devices: BTreeMap<String, DeviceRow>,
+    unresolved_routes: HashMap<String, usize>,
+    table: TableState,
+    /// Device whose subscriptions c is about to clear, waiting for a y.
+    confirm_clear: Option<String>,
+    status: String,
+}
+
+fn main() {
+    let manager = DanteDeviceManager::new();
+    let events = manager.subscribe_events();
+    if let Err(error) = manager.start_discovery() {
+        eprintln!("Discovery failed: {}", error);
+        std::process::exit(1);
+    }
+    let mut app = App {
+        manager,
+        events,
+        devices: BTreeMap::new(),
+        unresolved_routes: HashMap::new(),
+        table: TableState::default(),
+        confirm_clear: None,
+        status: "Waiting for devices".to_owned(),
+    };
+
+    let mut terminal = ratatui::init();
+    let result = app.run(&mut terminal);
+    ratatui::restore();
+    app.manager.stop_discovery();
+    if let Err(error) = result {
+        eprintln!("{}", error);
+        std::process::exit(1);
+    }
+}
+
+impl App {
+    fn run(&mut self, terminal: &mut DefaultTerminal) -> std::io::Result<()> {
+        loop {
+            while let Ok(event) = self.events.try_recv() {
+                self.handle_event(event);
+            }
+            terminal.draw(|frame| self.draw(frame))?;
+            // Also wakes up now and then to age the last seen column.
+            if !event::poll(Duration::from_millis(250))? {
+                continue;
+            }
+            let Event::Key(key) = event::read()? else {
+                continue;
+            };
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            if let Some(device_name) = self.confirm_clear.take() {
+                match key.code {
+                    KeyCode::Char('y') => self.clear_subscriptions(&device_name),
+                    _ => self.status = "Nothing cleared".to_owned(),
+                }
+                continue;
+            }
+            match key.code {
+                KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+                KeyCode::Up => self.table.select_previous(),
+                KeyCode::Down => self.table.select_next(),
+                KeyCode::Char('c') => {
+                    if let Some(device_name) = self.selected() {
+                        self.status = format!("Clear every subscription of {}? (y/n)", device_name);
+                        self.confirm_clear = Some(device_name);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn handle_event(&mut self, event: DanteDeviceEvent) {
+        match &event {
+            DanteDeviceEvent::DeviceRemoved { device_name } => {
+                self.devices.remove(device_name);
+                self.status = format!("{} is gone", device_name);
+                return;
+            }
+            DanteDeviceEvent::DeviceAdded { device_name } => {
+                self.status = format!("Found {}", device_name);
+            }
+            DanteDeviceEvent::RouteUnresolved(_) | DanteDeviceEvent::RouteResolved { .. } => {
+                self.unresolved_routes.clear();
+                for route in self.manager.unresolved_routes() {
+                    *self.unresolved_routes.entry(route.rx_device).or_default() += 1;
+                }
+            }
+            _ => {}
+        }
+        let Some(device_name) = event.device_name() else {
+            return;
+        };
+        let row = self
+            .devices
+            .entry(device_name.to_owned())
+            .or_insert(DeviceRow {
+                info: None,
+                last_seen: None,
+            });
+        row.info = self.manager.get_device_info(device_name);
+        if let DanteDeviceEvent::ServiceResolved { .. } = event {
+            row.last_seen = Some(Instant::now());
+        }
+    }
+
+    fn selected(&self) -> Option<String> {
+        self.devices.keys().nth(self.table.selected()?).cloned()
+    }
+
+    /// Reads back the device's subscriptions and clears the subscribed channels one by one.
+    fn clear_subscriptions(&mut self, device_name: &str) {
+        let Some(info) = self.manager.get_device_info(device_name) else {
+            self.status = format!("{} is gone", device_name);
+            return;
+        };
+        let version = info.effective_version();
+        let (Some(version), Some(device_ip)) = (version, info.addresses.first()) else {
+            self.status = format!("{} isn't ready for commands", device_name);
+            return;
+        };
+        let subscriptions = match self.manager.get_subscriptions(device_name, true) {
+            Ok(subscriptions) => subscriptions.value,
+            Err(error) => {
+                self.status = format!("Couldn't read back {}: {}", device_name, error);
+                return;
+            }
+        };
+        let mut cleared = 0;
+        for subscription in subscriptions
+            .iter()
+            .filter(|subscription| subscription.tx_device.is_some())
+        {
+            match self
+                .manager
+                .clear_subscription(&version, device_ip, subscription.rx_channel_id)
+            {
+                Ok(()) => cleared += 1,
+                Err(error) => {
+                    self.status = format!(
+                        "Clearing {} of {} failed: {}",
+                        subscription.rx_channel_name, device_name, error
+                    );
+                    return;
+                }
+            }
+        }
+        self.status = format!("Cleared {} subscriptions of {}", cleared, device_name);
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [table_area, status_area] =
+            Layout::vertical([Constraint::Fill(1), Constraint::Length(1)]).areas(frame.area());
+
+        let rows: Vec<Row> = self
+            .devices
+            .iter()
+            .map(|(device_name, row)| {
+                let unresolved_routes = self
+                    .unresolved_routes
+                    .get(device_name)
+                    .copied()
+                    .unwrap_or(0);
+                let last_seen = match row.last_seen {
+                    Some(last_seen) => format!("{}s ago", last_seen.elapsed().as_secs()),
+                    None => "-".to_owned(),
+                };
+                match &row.info {
+                    Some(info) => Row::new(vec![
+                        device_name.clone(),
+                        readiness(info).to_owned(),
+                        services(info),
+                        info.addresses
+                            .first()
+                            .map_or_else(String::new, ToString::to_string),
+                        match info.channel_count_hint {
+                            Some(hint) => format!("{}/{}", info.channels.len(), hint),
+                            None => info.channels.len().to_string(),
+                        },
+                        unresolved_routes.to_string(),
+                        last_seen,
+                    ]),
+                    None => Row::new(vec![device_name.clone(), "gone".to_owned()]),
+                }
+            })
+            .collect();
+        let table = Table::new(
+            rows,
+            [
+                Constraint::Fill(2),
+                Constraint::Length(10),
+                Constraint::Length(16),
+                Constraint::Length(15),
+                Constraint::Length(8),
+                Constraint::Length(10),
+                Constraint::Length(10),
+            ],
+        )
+        .header(
+            Row::new([
+                "Device",
+                "State",
+                "Services",
+                "Address",
+                "TX",
+                "Unresolved",
+                "Last seen",
+            ])
+            .bold(),
+        )
+        .block(Block::bordered().title(" Dante devices "))
+        .highlight_style(Style::new().reversed());
+        frame.render_stateful_widget(table, table_area, &mut self.table);
+        frame.render_widget(Paragraph::new(self.status.as_str()), status_area);
+    }
+}
+
+/// Whether commands can be sent to the device: it needs an address and a Dante version the crate can talk to.
+fn readiness(info: &DeviceInfo) -> &'static str {
+    let version_known = info.effective_version().is_some();
+    match (info.unresolved, info.addresses.is_empty(), version_known) {
+        (true, _, _) => "unresolved",
+        (false, true, _) => "resolving",
+        (false, false, false) => "no version",
+        (false, false, true) => "ready",
+    }
+}
+
+fn services(info: &DeviceInfo) -> String {
+    [
+        (info.dbc_connected, "dbc"),
+        (info.cmc_connected, "cmc"),
+        (info.arc_connected, "arc"),
+        (info.chan_connected, "chan"),
+    ]
+    .into_iter()
+    .filter(|(connected, _)| *connected)
+    .map(|(_, service)| service)
+    .collect::<Vec<_>>()
+    .join(" ")
+}
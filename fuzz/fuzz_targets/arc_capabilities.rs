@@ -0,0 +1,11 @@
+#![no_main]
+
+use dante_control_rs::ArcCapabilities;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|router_vers: &str| {
+    if let Some(capabilities) = ArcCapabilities::parse(router_vers) {
+        assert!(!capabilities.version.is_empty());
+        let _ = capabilities.dante_version();
+    }
+});
@@ -0,0 +1,223 @@
+use crate::{
+    as_dante_name, CancellationToken, DanteDeviceManager, DanteVersion, MakeSubscriptionError,
+};
+use log::warn;
+use std::net::Ipv4Addr;
+#[cfg(feature = "serde")]
+use std::path::Path;
+
+/// One subscription of an AudioGroup: the RX channel rx_channel of rx_device listens to tx_channel of tx_device, all by name.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct GroupRoute {
+    pub rx_device: String,
+    pub rx_channel: String,
+    pub tx_device: String,
+    pub tx_channel: String,
+}
+
+/// A named set of subscriptions that belong together, like the channels of a mix, so they can be made and cleared in one go with DanteDeviceManager::subscribe_audio_group() and clear_audio_group().
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AudioGroup {
+    pub name: String,
+    pub routes: Vec<GroupRoute>,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum AudioGroupError {
+    /// A route couldn't be looked up, like when its RX device isn't discovered, a name isn't ascii or more than one channel has it. Nothing was sent.
+    #[error("route {index} of group \"{group}\" can't be resolved")]
+    InvalidRoute {
+        group: String,
+        index: usize,
+        #[source]
+        source: MakeSubscriptionError,
+    },
+    /// The commands for the routes at these indices couldn't be sent. The other routes' commands were.
+    #[error("{} routes of group \"{group}\" couldn't be sent", failed.len())]
+    SendFailed { group: String, failed: Vec<usize> },
+    /// Cancelled part way: the first sent routes were gone through, the ones at the indices in failed without getting their command out, and the rest weren't sent at all.
+    #[error("group \"{group}\" was cancelled after {sent} routes")]
+    Cancelled {
+        group: String,
+        sent: usize,
+        failed: Vec<usize>,
+    },
+}
+
+#[cfg(feature = "serde")]
+#[derive(thiserror::Error, Debug)]
+pub enum AudioGroupLoadError {
+    #[error("error reading file")]
+    Io(#[from] std::io::Error),
+    #[error("invalid json")]
+    InvalidJson(#[from] serde_json::Error),
+}
+
+impl AudioGroup {
+    pub fn new(name: &str) -> Self {
+        AudioGroup {
+            name: name.to_owned(),
+            routes: Vec::new(),
+        }
+    }
+
+    pub fn add_route(
+        &mut self,
+        rx_device: &str,
+        rx_channel: &str,
+        tx_device: &str,
+        tx_channel: &str,
+    ) {
+        self.routes.push(GroupRoute {
+            rx_device: rx_device.to_owned(),
+            rx_channel: rx_channel.to_owned(),
+            tx_device: tx_device.to_owned(),
+            tx_channel: tx_channel.to_owned(),
+        });
+    }
+
+    #[cfg(feature = "serde")]
+    pub fn to_json(&self) -> Result<String, serde_json::Error> {
+        serde_json::to_string_pretty(self)
+    }
+
+    #[cfg(feature = "serde")]
+    pub fn from_json(json: &str) -> Result<AudioGroup, serde_json::Error> {
+        serde_json::from_str(json)
+    }
+
+    /// Loads a group saved with save_to_file() or written by hand.
+    #[cfg(feature = "serde")]
+    pub fn load_from_file(path: impl AsRef<Path>) -> Result<AudioGroup, AudioGroupLoadError> {
+        Ok(AudioGroup::from_json(&std::fs::read_to_string(path)?)?)
+    }
+
+    #[cfg(feature = "serde")]
+    pub fn save_to_file(&self, path: impl AsRef<Path>) -> Result<(), AudioGroupLoadError> {
+        std::fs::write(path, self.to_json()?)?;
+        Ok(())
+    }
+
+    fn invalid_route(&self, index: usize, source: MakeSubscriptionError) -> AudioGroupError {
+        AudioGroupError::InvalidRoute {
+            group: self.name.clone(),
+            index,
+            source,
+        }
+    }
+
+    /// What sending the routes came to: sent is how many routes were gone through, fewer than all of them when cancelled.
+    fn result(&self, sent: usize, failed: Vec<usize>) -> Result<(), AudioGroupError> {
+        if sent < self.routes.len() {
+            Err(AudioGroupError::Cancelled {
+                group: self.name.clone(),
+                sent,
+                failed,
+            })
+        } else if failed.is_empty() {
+            Ok(())
+        } else {
+            Err(AudioGroupError::SendFailed {
+                group: self.name.clone(),
+                failed,
+            })
+        }
+    }
+}
+
+impl DanteDeviceManager {
+    /// Makes every subscription of the group. UDP doesn't give us transactions, so this gets as close as it can: every route is looked up before anything is sent, and if one can't be (RX device not discovered, RX channel not found, non-ascii or ambiguous name) nothing is sent at all. Once sending starts a failed command doesn't stop the rest, the ones that failed are listed in AudioGroupError::SendFailed.
+    pub fn subscribe_audio_group(&mut self, group: &AudioGroup) -> Result<(), AudioGroupError> {
+        self.subscribe_audio_group_cancellable(group, &CancellationToken::new())
+    }
+
+    /// subscribe_audio_group() that stops before the next route once cancel is cancelled, failing with AudioGroupError::Cancelled saying how far it got.
+    pub fn subscribe_audio_group_cancellable(
+        &mut self,
+        group: &AudioGroup,
+        cancel: &CancellationToken,
+    ) -> Result<(), AudioGroupError> {
+        let mut resolved = Vec::with_capacity(group.routes.len());
+        for (index, route) in group.routes.iter().enumerate() {
+            let tx_device = as_dante_name(&route.tx_device)
+                .map_err(|error| group.invalid_route(index, error))?;
+            let tx_channel = as_dante_name(&route.tx_channel)
+                .map_err(|error| group.invalid_route(index, error))?;
+            self.device_list
+                .lock()
+                .unwrap()
+                .resolve_tx_channel_name(&route.tx_device, &route.tx_channel)
+                .map_err(|error| group.invalid_route(index, error))?;
+            let (rx_device_ip, version, rx_channel_id) = self
+                .resolve_rx_channel(&route.rx_device, &route.rx_channel)
+                .map_err(|error| group.invalid_route(index, error))?;
+            resolved.push((rx_device_ip, version, rx_channel_id, tx_device, tx_channel));
+        }
+
+        let mut failed = Vec::new();
+        let mut sent = 0;
+        for (index, (rx_device_ip, version, rx_channel_id, tx_device, tx_channel)) in
+            resolved.into_iter().enumerate()
+        {
+            if cancel.is_cancelled() {
+                break;
+            }
+            sent += 1;
+            if let Err(error) = self.make_subscription_unchecked(
+                &version,
+                &rx_device_ip,
+                rx_channel_id,
+                tx_device,
+                tx_channel,
+            ) {
+                warn!(
+                    "Subscribing route {} of group {} failed: {}",
+                    index, group.name, error
+                );
+                failed.push(index);
+            }
+        }
+        group.result(sent, failed)
+    }
+
+    /// Clears the RX channel of every route of the group, looking them all up before anything is sent like subscribe_audio_group() does.
+    pub fn clear_audio_group(&mut self, group: &AudioGroup) -> Result<(), AudioGroupError> {
+        self.clear_audio_group_cancellable(group, &CancellationToken::new())
+    }
+
+    /// clear_audio_group() that stops before the next route once cancel is cancelled, like subscribe_audio_group_cancellable().
+    pub fn clear_audio_group_cancellable(
+        &mut self,
+        group: &AudioGroup,
+        cancel: &CancellationToken,
+    ) -> Result<(), AudioGroupError> {
+        let resolved: Vec<(Ipv4Addr, DanteVersion, u16)> = group
+            .routes
+            .iter()
+            .enumerate()
+            .map(|(index, route)| {
+                self.resolve_rx_channel(&route.rx_device, &route.rx_channel)
+                    .map_err(|error| group.invalid_route(index, error))
+            })
+            .collect::<Result<_, _>>()?;
+
+        let mut failed = Vec::new();
+        let mut sent = 0;
+        for (index, (rx_device_ip, version, rx_channel_id)) in resolved.into_iter().enumerate() {
+            if cancel.is_cancelled() {
+                break;
+            }
+            sent += 1;
+            if let Err(error) = self.clear_subscription(&version, &rx_device_ip, rx_channel_id) {
+                warn!(
+                    "Clearing route {} of group {} failed: {}",
+                    index, group.name, error
+                );
+                failed.push(index);
+            }
+        }
+        group.result(sent, failed)
+    }
+}
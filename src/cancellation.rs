@@ -0,0 +1,66 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+/// How often waits check whether they were cancelled.
+const CANCEL_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Cheap cloneable handle for cancelling long running operations from another thread. Operations check it between devices or routes, and waits check it every 50 ms, so a cancelled operation returns early with whatever it had done so far and reports that it was cancelled.
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        CancellationToken::default()
+    }
+
+    /// Cancels every operation holding a clone of this token. Can't be undone.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Sleeps for duration, or until cancelled. Returns whether it was cancelled.
+    pub(crate) fn sleep(&self, duration: Duration) -> bool {
+        let until = Instant::now() + duration;
+        while !self.is_cancelled() {
+            let remaining = until.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return false;
+            }
+            sleep(remaining.min(CANCEL_POLL_INTERVAL));
+        }
+        true
+    }
+}
+
+/// What an operation taking a CancellationToken got done, and whether it was cancelled before it was through.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Partial<T> {
+    pub value: T,
+    /// The operation stopped early, value only has what was done before.
+    pub cancelled: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sleep_returns_once_cancelled() {
+        let cancel = CancellationToken::new();
+        assert!(!cancel.sleep(Duration::ZERO));
+        let canceller = cancel.clone();
+        let thread = std::thread::spawn(move || canceller.cancel());
+        // Only returns this early when it noticed the cancel.
+        assert!(cancel.sleep(Duration::from_secs(3600)));
+        thread.join().unwrap();
+        assert!(cancel.is_cancelled());
+    }
+}
@@ -0,0 +1,166 @@
+use std::net::Ipv4Addr;
+use std::sync::{Arc, Mutex};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PacketDirection {
+    Inbound,
+    Outbound,
+}
+
+/// One datagram on a control socket. address and port are the device's side either way.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PacketRecord {
+    pub timestamp: Instant,
+    /// Same moment as timestamp, for the pcap export which needs a wall clock time.
+    pub wall_time: SystemTime,
+    pub direction: PacketDirection,
+    pub address: Ipv4Addr,
+    pub port: u16,
+    pub data: Vec<u8>,
+}
+
+/// Raw IPv4, so records don't need a made up ethernet header.
+const LINKTYPE_RAW: u32 = 101;
+
+impl PacketRecord {
+    /// Formats the record as a pcap packet record, an IPv4 + UDP datagram behind the 16 byte record header. Our side of the conversation shows up as 0.0.0.0:0 since the capture doesn't know the local address. Prefix with pcap_header(), or use to_pcap_file(), to get something Wireshark opens.
+    pub fn to_pcap_bytes(&self) -> Vec<u8> {
+        let (source, source_port, destination, destination_port) = match self.direction {
+            PacketDirection::Outbound => (Ipv4Addr::UNSPECIFIED, 0, self.address, self.port),
+            PacketDirection::Inbound => (self.address, self.port, Ipv4Addr::UNSPECIFIED, 0),
+        };
+        let udp_length = (8 + self.data.len()) as u16;
+        let ip_length = 20 + udp_length;
+
+        let mut ip_header = Vec::with_capacity(20);
+        ip_header.extend_from_slice(&[0x45, 0x00]);
+        ip_header.extend_from_slice(&ip_length.to_be_bytes());
+        // Identification, flags and fragment offset, TTL 64, protocol 17 (UDP), checksum filled in below.
+        ip_header.extend_from_slice(&[0x00, 0x00, 0x00, 0x00, 64, 17, 0x00, 0x00]);
+        ip_header.extend_from_slice(&source.octets());
+        ip_header.extend_from_slice(&destination.octets());
+        let checksum = ipv4_checksum(&ip_header);
+        ip_header[10..12].copy_from_slice(&checksum.to_be_bytes());
+
+        let since_epoch = self
+            .wall_time
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let captured_length = ip_length as u32;
+
+        let mut bytes = Vec::with_capacity(16 + ip_length as usize);
+        bytes.extend_from_slice(&(since_epoch.as_secs() as u32).to_le_bytes());
+        bytes.extend_from_slice(&since_epoch.subsec_micros().to_le_bytes());
+        bytes.extend_from_slice(&captured_length.to_le_bytes());
+        bytes.extend_from_slice(&captured_length.to_le_bytes());
+        bytes.extend_from_slice(&ip_header);
+        bytes.extend_from_slice(&source_port.to_be_bytes());
+        bytes.extend_from_slice(&destination_port.to_be_bytes());
+        bytes.extend_from_slice(&udp_length.to_be_bytes());
+        // No UDP checksum, which is allowed over IPv4.
+        bytes.extend_from_slice(&[0x00, 0x00]);
+        bytes.extend_from_slice(&self.data);
+        bytes
+    }
+}
+
+/// The 24 byte pcap file header that has to come before the records.
+pub fn pcap_header() -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(24);
+    bytes.extend_from_slice(&0xa1b2c3d4u32.to_le_bytes());
+    bytes.extend_from_slice(&2u16.to_le_bytes());
+    bytes.extend_from_slice(&4u16.to_le_bytes());
+    // Time zone and timestamp accuracy, always 0.
+    bytes.extend_from_slice(&[0x00; 8]);
+    bytes.extend_from_slice(&65535u32.to_le_bytes());
+    bytes.extend_from_slice(&LINKTYPE_RAW.to_le_bytes());
+    bytes
+}
+
+/// A whole pcap file of the records.
+pub fn to_pcap_file(records: &[PacketRecord]) -> Vec<u8> {
+    let mut bytes = pcap_header();
+    for record in records {
+        bytes.extend_from_slice(&record.to_pcap_bytes());
+    }
+    bytes
+}
+
+fn ipv4_checksum(header: &[u8]) -> u16 {
+    let mut sum: u32 = header
+        .chunks(2)
+        .map(|word| u16::from_be_bytes([word[0], *word.get(1).unwrap_or(&0)]) as u32)
+        .sum();
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+type Recording = Arc<Mutex<Vec<PacketRecord>>>;
+
+/// The captures that are currently recording. Shared by the manager and its capture handles.
+#[derive(Default)]
+pub(crate) struct PacketCapture {
+    recordings: Mutex<Vec<Recording>>,
+}
+
+impl PacketCapture {
+    /// Cheap when nothing is capturing, so it's fine to call for every datagram.
+    pub(crate) fn record(
+        &self,
+        direction: PacketDirection,
+        address: Ipv4Addr,
+        port: u16,
+        data: &[u8],
+    ) {
+        let recordings = self.recordings.lock().unwrap();
+        if recordings.is_empty() {
+            return;
+        }
+        let record = PacketRecord {
+            timestamp: Instant::now(),
+            wall_time: SystemTime::now(),
+            direction,
+            address,
+            port,
+            data: data.to_vec(),
+        };
+        for recording in recordings.iter() {
+            recording.lock().unwrap().push(record.clone());
+        }
+    }
+}
+
+/// A packet capture that's recording. Get one from DanteDeviceManager::enable_packet_capture(). Dropping it stops the capture and throws the records away.
+pub struct PacketCaptureHandle {
+    capture: Arc<PacketCapture>,
+    recording: Recording,
+}
+
+impl PacketCaptureHandle {
+    pub(crate) fn new(capture: &Arc<PacketCapture>) -> PacketCaptureHandle {
+        let recording = Recording::default();
+        capture.recordings.lock().unwrap().push(recording.clone());
+        PacketCaptureHandle {
+            capture: capture.clone(),
+            recording,
+        }
+    }
+
+    /// Stops the capture and returns everything it recorded, oldest first.
+    pub fn stop_and_collect(self) -> Vec<PacketRecord> {
+        std::mem::take(&mut *self.recording.lock().unwrap())
+    }
+}
+
+impl Drop for PacketCaptureHandle {
+    fn drop(&mut self) {
+        self.capture
+            .recordings
+            .lock()
+            .unwrap()
+            .retain(|recording| !Arc::ptr_eq(recording, &self.recording));
+    }
+}
@@ -0,0 +1,358 @@
+use crate::capture::PacketCapture;
+use crate::metrics::Metrics;
+use crate::{parse_dante_response, DanteDeviceEvent, DanteDeviceList, PacketDirection, QueryError};
+use log::{debug, warn};
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+/// Times a datagram is sent again when the OS has no buffer space for it, with twice the wait each time.
+pub(crate) const SEND_RETRIES: u32 = 4;
+const SEND_BACKOFF: Duration = Duration::from_millis(5);
+
+/// ENOBUFS, which is what a send gets when the OS's UDP send buffers are full, like during a burst of hundreds of commands. std has no ErrorKind for it.
+#[cfg(windows)]
+pub(crate) const ENOBUFS: i32 = 10055;
+#[cfg(any(target_os = "macos", target_os = "ios", target_os = "freebsd"))]
+pub(crate) const ENOBUFS: i32 = 55;
+#[cfg(not(any(windows, target_os = "macos", target_os = "ios", target_os = "freebsd")))]
+pub(crate) const ENOBUFS: i32 = 105;
+
+/// Whether a recv_from() failed because its read timeout passed. That's WouldBlock on Unix and TimedOut on Windows.
+pub(crate) fn is_receive_timeout(error: &std::io::Error) -> bool {
+    matches!(
+        error.kind(),
+        std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+    )
+}
+
+/// Whether a send failed because the OS was out of buffers for the moment rather than for good: ENOBUFS, or WouldBlock, which some systems give instead when the send buffer is full.
+fn is_send_buffer_full(error: &std::io::Error) -> bool {
+    error.kind() == std::io::ErrorKind::WouldBlock || error.raw_os_error() == Some(ENOBUFS)
+}
+
+/// Runs send, and runs it again after a short wait while it fails with a full send buffer, ENOBUFS or WouldBlock, up to SEND_RETRIES times. Every full buffer is counted in the metrics. Any other error, or a full buffer on the last try, is returned. Every datagram the crate sends goes through this, send is only a parameter so the retrying can be tried out with made up errors.
+pub(crate) fn send_with_backoff(
+    metrics: &Metrics,
+    mut send: impl FnMut() -> std::io::Result<usize>,
+) -> std::io::Result<usize> {
+    let mut retries = 0;
+    loop {
+        match send() {
+            Err(error) if is_send_buffer_full(&error) => {
+                metrics.record_send_buffer_full();
+                if retries == SEND_RETRIES {
+                    return Err(error);
+                }
+                let backoff = SEND_BACKOFF * 2u32.pow(retries);
+                debug!("Send buffer full, sending again in {:?}", backoff);
+                std::thread::sleep(backoff);
+                retries += 1;
+                metrics.record_command_retried();
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Maps the address and port a command is for to where it's actually sent, like a NAT or a UDP relay in front of the devices. See DanteDeviceManager::set_address_translator().
+pub type AddressTranslator = dyn Fn(Ipv4Addr, u16) -> (IpAddr, u16) + Send + Sync;
+
+/// Where commands go out from and where they're sent to.
+#[derive(Clone, Copy)]
+pub(crate) struct Transport<'a> {
+    pub(crate) command_socket: Option<&'a CommandSocket>,
+    pub(crate) translator: Option<&'a AddressTranslator>,
+    /// See DanteDeviceManager::set_response_timeout().
+    pub(crate) response_timeout: Duration,
+}
+
+impl Transport<'_> {
+    /// Where a command for the device at address and port goes.
+    pub(crate) fn target(&self, address: Ipv4Addr, port: u16) -> SocketAddr {
+        match self.translator {
+            Some(translator) => translator(address, port).into(),
+            None => (address, port).into(),
+        }
+    }
+
+    /// A socket of its own for one command, of the same address family as the target.
+    pub(crate) fn bind_throwaway(target: &SocketAddr) -> std::io::Result<UdpSocket> {
+        match target {
+            SocketAddr::V4(_) => UdpSocket::bind("0.0.0.0:0"),
+            SocketAddr::V6(_) => UdpSocket::bind("[::]:0"),
+        }
+    }
+}
+
+/// A command that went out on the socket, by the address it was sent to and sequence ID. device is the address it was for, which is different when it went through an AddressTranslator. response is None for commands nobody waits on, whose acks are only taken off the socket so they don't look unsolicited, until timeout has passed.
+struct Transaction {
+    device: Ipv4Addr,
+    sent_at: Instant,
+    timeout: Duration,
+    response: Option<Sender<Vec<u8>>>,
+}
+
+type Transactions = Arc<Mutex<HashMap<(IpAddr, u16), Transaction>>>;
+
+/// The socket every command goes out on once DanteDeviceManager::bind_command_socket() was called. Devices answer, and sometimes push status, to the port that last commanded them, so a thread keeps reading it: responses go to whoever is waiting for them and everything else becomes a DanteDeviceEvent::UnsolicitedMessage.
+pub(crate) struct CommandSocket {
+    socket: UdpSocket,
+    metrics: Arc<Metrics>,
+    capture: Arc<PacketCapture>,
+    transactions: Transactions,
+    running: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl CommandSocket {
+    pub(crate) fn bind(
+        port: u16,
+        device_list: Arc<Mutex<DanteDeviceList>>,
+        metrics: Arc<Metrics>,
+        capture: Arc<PacketCapture>,
+    ) -> std::io::Result<CommandSocket> {
+        let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, port))?;
+        // Short timeout so the receive thread notices when the socket is unbound.
+        socket.set_read_timeout(Some(Duration::from_millis(100)))?;
+
+        let transactions = Transactions::default();
+        let running = Arc::new(AtomicBool::new(true));
+        let thread = spawn_receiver(
+            socket.try_clone()?,
+            device_list,
+            metrics.clone(),
+            capture.clone(),
+            transactions.clone(),
+            running.clone(),
+        );
+        Ok(CommandSocket {
+            socket,
+            metrics,
+            capture,
+            transactions,
+            running,
+            thread: Some(thread),
+        })
+    }
+
+    pub(crate) fn port(&self) -> u16 {
+        self.socket.local_addr().map_or(0, |address| address.port())
+    }
+
+    /// Sends a command for the device at address to target without waiting for the answer.
+    pub(crate) fn send(
+        &self,
+        address: &Ipv4Addr,
+        target: SocketAddr,
+        bytes: &[u8],
+        timeout: Duration,
+    ) -> std::io::Result<()> {
+        self.send_transaction(address, target, bytes, timeout, None)
+    }
+
+    /// Sends a command and waits up to timeout for the response carrying the same sequence ID.
+    pub(crate) fn query(
+        &self,
+        address: &Ipv4Addr,
+        target: SocketAddr,
+        bytes: &[u8],
+        timeout: Duration,
+    ) -> Result<Vec<u8>, QueryError> {
+        let (sender, receiver) = channel();
+        self.send_transaction(address, target, bytes, timeout, Some(sender))
+            .map_err(QueryError::ConnectionFailed)?;
+        receiver.recv_timeout(timeout).map_err(|_| {
+            if let Some(sequence_id) = sequence_id(bytes) {
+                self.transactions
+                    .lock()
+                    .unwrap()
+                    .remove(&(target.ip(), sequence_id));
+            }
+            self.metrics.record_command_timed_out();
+            QueryError::Timeout
+        })
+    }
+
+    fn send_transaction(
+        &self,
+        address: &Ipv4Addr,
+        target: SocketAddr,
+        bytes: &[u8],
+        timeout: Duration,
+        response: Option<Sender<Vec<u8>>>,
+    ) -> std::io::Result<()> {
+        // In place before the datagram goes out, so an answer can't arrive before it.
+        let key = sequence_id(bytes).map(|sequence_id| (target.ip(), sequence_id));
+        if let Some(key) = key {
+            self.transactions.lock().unwrap().insert(
+                key,
+                Transaction {
+                    device: *address,
+                    sent_at: Instant::now(),
+                    timeout,
+                    response,
+                },
+            );
+        }
+
+        debug!("Sent bytes {:?} to {}", hex::encode(bytes), target);
+        send_with_backoff(&self.metrics, || self.socket.send_to(bytes, target))?;
+        // Timed from here, waiting out a full send buffer is on our side and not the network's.
+        if let Some(key) = key {
+            if let Some(transaction) = self.transactions.lock().unwrap().get_mut(&key) {
+                transaction.sent_at = Instant::now();
+            }
+        }
+        self.metrics.record_command_sent(bytes.len());
+        self.capture
+            .record(PacketDirection::Outbound, *address, target.port(), bytes);
+        Ok(())
+    }
+}
+
+impl Drop for CommandSocket {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                warn!("Command socket receive thread panicked");
+            }
+        }
+    }
+}
+
+/// The sequence ID of a command or of the response to it. None for anything that isn't a well formed control protocol datagram, so a garbled answer is never taken for the response to a command.
+pub(crate) fn sequence_id(datagram: &[u8]) -> Option<u16> {
+    parse_dante_response(datagram)
+        .ok()
+        .map(|response| response.sequence_id)
+}
+
+fn spawn_receiver(
+    socket: UdpSocket,
+    device_list: Arc<Mutex<DanteDeviceList>>,
+    metrics: Arc<Metrics>,
+    capture: Arc<PacketCapture>,
+    transactions: Transactions,
+    running: Arc<AtomicBool>,
+) -> JoinHandle<()> {
+    std::thread::spawn(move || {
+        debug!("Starting command socket receive thread");
+        let mut buffer = [0u8; 2048];
+        while running.load(Ordering::Relaxed) {
+            // Acks that never came in would otherwise pile up.
+            transactions.lock().unwrap().retain(|_, transaction| {
+                transaction.response.is_some()
+                    || transaction.sent_at.elapsed() < transaction.timeout
+            });
+
+            let (length, from) = match socket.recv_from(&mut buffer) {
+                Ok((length, SocketAddr::V4(from))) => (length, from),
+                Ok(_) => continue,
+                Err(error) if is_receive_timeout(&error) => continue,
+                Err(error) => {
+                    warn!("Command socket receive thread stopped: {}", error);
+                    return;
+                }
+            };
+            let datagram = &buffer[..length];
+            debug!("Received bytes {:?} from {}", hex::encode(datagram), from);
+            metrics.record_received(length);
+
+            let transaction = sequence_id(datagram).and_then(|sequence_id| {
+                transactions
+                    .lock()
+                    .unwrap()
+                    .remove(&(IpAddr::V4(*from.ip()), sequence_id))
+            });
+            // Recorded as coming from the device the command was for, which isn't where it came from when it went through a relay.
+            let device = transaction
+                .as_ref()
+                .map_or(*from.ip(), |transaction| transaction.device);
+            capture.record(PacketDirection::Inbound, device, from.port(), datagram);
+            match transaction {
+                Some(transaction) => {
+                    metrics.record_command_acked(transaction.device, transaction.sent_at.elapsed());
+                    if let Some(response) = transaction.response {
+                        // The query may have timed out and gone away in the meantime, which is fine.
+                        let _ = response.send(datagram.to_vec());
+                    }
+                }
+                None => {
+                    debug!("Unsolicited message from {}", from);
+                    let mut device_list = device_list.lock().unwrap();
+                    // None of these are decoded yet, but the ones devices are known to push are about subscriptions, so the read-back of whatever is at the address is out of date.
+                    device_list.invalidate_subscriptions_for_ip(from.ip());
+                    device_list
+                        .events
+                        .emit(DanteDeviceEvent::UnsolicitedMessage {
+                            from,
+                            payload: datagram.to_vec(),
+                        });
+                }
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Error, ErrorKind};
+
+    /// Sends through send_with_backoff() with made up results, one per attempt, and returns the result, how many attempts were made and the metrics.
+    fn send_with_results(
+        results: Vec<std::io::Result<usize>>,
+    ) -> (std::io::Result<usize>, usize, crate::MetricsSnapshot) {
+        let metrics = Metrics::default();
+        let mut results = results.into_iter();
+        let mut attempts = 0;
+        let result = send_with_backoff(&metrics, || {
+            attempts += 1;
+            results.next().unwrap_or(Ok(1))
+        });
+        (result, attempts, metrics.snapshot(0))
+    }
+
+    fn enobufs() -> std::io::Result<usize> {
+        Err(Error::from_raw_os_error(ENOBUFS))
+    }
+
+    fn would_block() -> std::io::Result<usize> {
+        Err(Error::from(ErrorKind::WouldBlock))
+    }
+
+    #[test]
+    fn full_buffers_are_sent_again() {
+        let (result, attempts, metrics) = send_with_results(vec![enobufs(), would_block(), Ok(12)]);
+        assert_eq!(result.unwrap(), 12);
+        assert_eq!(attempts, 3);
+        assert_eq!(metrics.send_buffer_full, 2);
+        assert_eq!(metrics.commands_retried, 2);
+    }
+
+    #[test]
+    fn full_buffer_on_the_last_try_is_returned() {
+        let results = (0..=SEND_RETRIES).map(|_| enobufs()).collect();
+        let (result, attempts, metrics) = send_with_results(results);
+        assert_eq!(result.unwrap_err().raw_os_error(), Some(ENOBUFS));
+        assert_eq!(attempts as u32, SEND_RETRIES + 1);
+        assert_eq!(metrics.send_buffer_full, SEND_RETRIES as u64 + 1);
+        assert_eq!(metrics.commands_retried, SEND_RETRIES as u64);
+    }
+
+    #[test]
+    fn other_errors_are_not_sent_again() {
+        let (result, attempts, metrics) =
+            send_with_results(vec![Err(Error::from(ErrorKind::PermissionDenied))]);
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::PermissionDenied);
+        assert_eq!(attempts, 1);
+        assert_eq!(metrics.send_buffer_full, 0);
+        assert_eq!(metrics.commands_retried, 0);
+    }
+}
@@ -0,0 +1,94 @@
+use crate::{ArcCapabilities, DanteDeviceList, DanteDeviceManager, DanteVersion};
+use log::info;
+
+/// Whether an RX device can be expected to subscribe to a TX device, going by their Dante versions. See DanteDeviceManager::check_version_compatibility().
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum VersionCompatibility {
+    /// Both run the same major and minor version.
+    Compatible,
+    /// Same major version but a different minor one, or the version of one of them isn't known. Says which.
+    MayWork(String),
+    /// Different major versions. Says which.
+    Incompatible(String),
+}
+
+impl DanteDeviceList {
+    /// The parsed router_vers of the device's ARC record, or why there's none.
+    fn arc_capabilities(&self, device_name: &str) -> Result<ArcCapabilities, String> {
+        let arc_info = self
+            .caches
+            .get(device_name)
+            .and_then(|cache| cache.arc_info.as_ref())
+            .ok_or_else(|| format!("the Dante version of {} isn't known", device_name))?;
+        ArcCapabilities::parse(&arc_info.router_vers).ok_or_else(|| {
+            format!(
+                "{} advertises a Dante version that isn't one, \"{}\"",
+                device_name, arc_info.router_vers
+            )
+        })
+    }
+}
+
+impl DanteDeviceManager {
+    /// Compares the ARC router_vers of two discovered devices before subscribing rx_device to tx_device. Subscriptions generally need both to run the same major version of the Dante protocol, and a different minor version is worth a warning. A device that isn't known or has no ARC record yet makes it MayWork, since there's no telling.
+    pub fn check_version_compatibility(
+        &self,
+        rx_device: &str,
+        tx_device: &str,
+    ) -> VersionCompatibility {
+        let device_list = self.read_device_list();
+        let (rx, tx) = match (
+            device_list.arc_capabilities(rx_device),
+            device_list.arc_capabilities(tx_device),
+        ) {
+            (Ok(rx), Ok(tx)) => (rx, tx),
+            (Err(reason), _) | (_, Err(reason)) => return VersionCompatibility::MayWork(reason),
+        };
+        let describe = |device_name: &str, capabilities: &ArcCapabilities| {
+            format!(
+                "{} runs {}.{}",
+                device_name,
+                capabilities.protocol_major(),
+                capabilities.protocol_minor()
+            )
+        };
+        if rx.protocol_major() != tx.protocol_major() {
+            VersionCompatibility::Incompatible(format!(
+                "{} and {}, subscriptions need the same major version",
+                describe(rx_device, &rx),
+                describe(tx_device, &tx)
+            ))
+        } else if rx.protocol_minor() != tx.protocol_minor() {
+            VersionCompatibility::MayWork(format!(
+                "{} and {}, subscriptions usually work across minor versions but aren't guaranteed to",
+                describe(rx_device, &rx),
+                describe(tx_device, &tx)
+            ))
+        } else {
+            VersionCompatibility::Compatible
+        }
+    }
+
+    /// Pins the version of the commands sent to a device, for when its router_vers is wrong about it, like a beta firmware or a vendor build that says 4.4.1.3 but takes the 4.2.1.3 layout. Every command by device name uses it instead of what the ARC record says. It's kept in the device's cache, so it survives the device going away and coming back and is in snapshots, but not a rename. Returns false, and pins nothing, when the device was never discovered.
+    pub fn set_device_version_override(&self, device_name: &str, version: DanteVersion) -> bool {
+        let mut device_list = self.device_list.lock().unwrap();
+        let Some(cache) = device_list.caches.get_mut(device_name) else {
+            return false;
+        };
+        info!("Pinning {} to Dante version {}", device_name, version);
+        cache.version_override = Some(version);
+        true
+    }
+
+    /// Goes back to the version from the device's ARC record. Returns the override there was.
+    pub fn clear_device_version_override(&self, device_name: &str) -> Option<DanteVersion> {
+        self.device_list
+            .lock()
+            .unwrap()
+            .caches
+            .get_mut(device_name)?
+            .version_override
+            .take()
+    }
+}
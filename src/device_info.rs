@@ -0,0 +1,591 @@
+use crate::{
+    port_if_valid, ChannelInfo, ChannelNameConflict, DanteDeviceList, DanteService, DanteVersion,
+    SnapshotDetail, DEFAULT_ARC_PORT,
+};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{Display, Formatter};
+use std::net::Ipv4Addr;
+
+/// A snapshot of everything discovery knows about a device.
+///
+/// Formatting with `{}` gives the multi-line layout get_device_descriptions() always had, `{:#}` adds every address, the ports that were ignored, and a channel table with the TXT properties of each channel, and compact() gives a single line for logs.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DeviceInfo {
+    /// mdns instance name, which is what Dante calls the device.
+    pub name: String,
+    /// Host name of the device's A record, without ".local.". Differs from name for a device that was renamed and hasn't rebooted since, and for some virtual devices.
+    pub hostname: Option<String>,
+    pub dbc_connected: bool,
+    pub cmc_connected: bool,
+    pub arc_connected: bool,
+    pub chan_connected: bool,
+    /// Services discovery wasn't browsing for, see DanteDeviceManager::set_discovery_services(). Their connected flags are always false.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub untracked_services: Vec<DanteService>,
+    /// Found but none of the services resolved an address within the resolve timeout.
+    pub unresolved: bool,
+    /// Added with DanteDeviceManager::update_device_list() rather than found by mdns, so it's only as accurate as the JSON it came from.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub manually_added: bool,
+    /// Every address any of the services resolved to, sorted.
+    pub addresses: Vec<Ipv4Addr>,
+    pub arc_port: Option<u16>,
+    /// Addresses of the ARC record, the ones commands go to. None when the device has no ARC record.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub arc_addresses: Option<Vec<Ipv4Addr>>,
+    pub dbc_port: Option<u16>,
+    pub cmc_port: Option<u16>,
+    /// Ports services advertised that were ignored for being bogus, like 0. The port fields above are None for these.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub bogus_ports: Vec<(DanteService, u16)>,
+    /// CMC "id" property.
+    pub id: Option<String>,
+    /// CMC "mf" property.
+    pub manufacturer: Option<String>,
+    /// CMC "model" property.
+    pub model: Option<String>,
+    /// ARC "router_vers" property.
+    pub router_vers: Option<String>,
+    /// ARC "router_info" property.
+    pub router_info: Option<String>,
+    /// router_vers parsed, None when the device has no ARC record or router_vers isn't a version number.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub arc_capabilities: Option<ArcCapabilities>,
+    /// The commands arc_capabilities says the device takes, None when that's a version the crate can't talk to.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub detected_version: Option<DanteVersion>,
+    /// The version pinned with DanteDeviceManager::set_device_version_override(), used instead of detected_version.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub version_override: Option<DanteVersion>,
+    /// "nchan" property of the CMC record, or of the CHAN records if CMC doesn't have it.
+    pub channel_count_hint: Option<u16>,
+    /// Channels advertised over mdns, sorted by id with channels without one last.
+    pub channels: Vec<ChannelInfo>,
+    /// Names more than one channel of a direction has, see DanteDeviceManager::channel_name_conflicts().
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub channel_name_conflicts: Vec<ChannelNameConflict>,
+}
+
+/// What the ARC record of a device says about the commands it takes. Only router_vers has turned out to mean something so far, router_info is kept raw in DeviceInfo until someone works out what it says.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ArcCapabilities {
+    /// router_vers split at the dots, like [4, 4, 1, 3] for "4.4.1.3".
+    pub version: Vec<u16>,
+}
+
+impl ArcCapabilities {
+    /// None when router_vers isn't numbers separated by dots.
+    pub fn parse(router_vers: &str) -> Option<ArcCapabilities> {
+        let version = router_vers
+            .trim()
+            .split('.')
+            .map(|part| part.parse().ok())
+            .collect::<Option<Vec<u16>>>()?;
+        Some(ArcCapabilities { version })
+    }
+
+    pub fn protocol_major(&self) -> u16 {
+        self.version.first().copied().unwrap_or(0)
+    }
+
+    pub fn protocol_minor(&self) -> u16 {
+        self.version.get(1).copied().unwrap_or(0)
+    }
+
+    /// Which variant of the subscription commands the device takes. None for versions whose commands haven't been captured, which make_subscription() and friends can't talk to.
+    pub fn dante_version(&self) -> Option<DanteVersion> {
+        let version: Vec<String> = self.version.iter().map(u16::to_string).collect();
+        DanteVersion::from_string(&version.join("."))
+    }
+}
+
+/// Whether discovery currently sees one of a device's services.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ServiceStatus {
+    Connected,
+    Disconnected,
+    /// Discovery isn't browsing for the service, so there's no telling.
+    NotTracked,
+}
+
+impl Display for ServiceStatus {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            ServiceStatus::Connected => "Connected",
+            ServiceStatus::Disconnected => "Disconnected",
+            ServiceStatus::NotTracked => "Not tracked",
+        })
+    }
+}
+
+/// 1, 0 or - for the compact format.
+fn compact_status(status: ServiceStatus) -> &'static str {
+    match status {
+        ServiceStatus::Connected => "1",
+        ServiceStatus::Disconnected => "0",
+        ServiceStatus::NotTracked => "-",
+    }
+}
+
+fn or_na<T: Display>(value: &Option<T>) -> String {
+    match value {
+        Some(value) => value.to_string(),
+        None => "N/A".to_string(),
+    }
+}
+
+impl Display for DeviceInfo {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        self.fmt_with(f, f.alternate(), None)
+    }
+}
+
+impl DeviceInfo {
+    /// The layout get_device_descriptions() always had, then the extra lines of `{:#}` and the channels, at most max_channels of them.
+    fn fmt_with(
+        &self,
+        f: &mut Formatter<'_>,
+        verbose: bool,
+        max_channels: Option<usize>,
+    ) -> std::fmt::Result {
+        write!(
+            f,
+            "{}:\ndbc status: {}\ncmc status: {}\narc status: {}\nchan status: {}\nid: {}\nmanufacturer: {}\nmodel: {}\nrouter_vers: {}\nrouter_info: {}\nARC port: {}\nIP: {}",
+            self.name,
+            self.service_status(DanteService::Dbc),
+            self.service_status(DanteService::Cmc),
+            self.service_status(DanteService::Arc),
+            self.service_status(DanteService::Chan),
+            or_na(&self.id),
+            or_na(&self.manufacturer),
+            or_na(&self.model),
+            or_na(&self.router_vers),
+            or_na(&self.router_info),
+            or_na(&self.arc_port),
+            match &self.arc_addresses {
+                Some(arc_addresses) => format!("{:?}", BTreeSet::from_iter(arc_addresses)),
+                None => "N/A".to_string(),
+            },
+        )?;
+        let shown = max_channels.unwrap_or(usize::MAX);
+        if verbose {
+            self.fmt_details(f)?;
+            self.fmt_channel_table(f, shown)?;
+        } else {
+            write!(f, "\nChannels:")?;
+            for channel in self.channels.iter().take(shown) {
+                write!(f, "\n\"{}\"", channel.name)?;
+            }
+        }
+        if self.channels.len() > shown {
+            write!(f, "\n  ... and {} more", self.channels.len() - shown)?;
+        }
+        Ok(())
+    }
+
+    /// What `{:#}` adds before the channels.
+    fn fmt_details(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "\nhostname: {}\nchannel count hint: {}\naddresses: {:?}",
+            or_na(&self.hostname),
+            or_na(&self.channel_count_hint),
+            self.addresses,
+        )?;
+        if self.unresolved {
+            write!(f, "\nunresolved: no address resolved")?;
+        }
+        if let Some(version) = self.version_override {
+            write!(
+                f,
+                "\nversion: pinned to {} (detected {})",
+                version,
+                or_na(&self.detected_version)
+            )?;
+        }
+        for (service, port) in &self.bogus_ports {
+            write!(f, "\nadvertises {} port {}", service, port)?;
+            if *service == DanteService::Arc {
+                write!(f, " (using default {})", DEFAULT_ARC_PORT)?;
+            }
+        }
+        for conflict in &self.channel_name_conflicts {
+            write!(
+                f,
+                "\n{:?} channels {:?} are all called \"{}\"",
+                conflict.direction, conflict.ids, conflict.name
+            )?;
+        }
+        Ok(())
+    }
+
+    /// The channels of `{:#}`, a row each with the TXT properties of the record under it.
+    fn fmt_channel_table(&self, f: &mut Formatter<'_>, shown: usize) -> std::fmt::Result {
+        write!(f, "\nChannels:")?;
+        write!(
+            f,
+            "\n{:>5}  {:<32} {:>7} {:>8} {:>12}",
+            "id", "name", "rate", "encoding", "latency"
+        )?;
+        for channel in self.channels.iter().take(shown) {
+            write!(
+                f,
+                "\n{:>5}  {:<32} {:>7} {:>8} {:>12}",
+                or_na(&channel.id),
+                format!("\"{}\"", channel.name),
+                or_na(&channel.sample_rate),
+                or_na(&channel.encoding),
+                match channel.latency {
+                    Some(latency) => format!("{:?}", latency),
+                    None => "N/A".to_string(),
+                },
+            )?;
+            if !channel.extra_properties.is_empty() {
+                let properties: BTreeMap<_, _> = channel.extra_properties.iter().collect();
+                let properties: Vec<String> = properties
+                    .into_iter()
+                    .map(|(key, value)| format!("{}={}", key, value))
+                    .collect();
+                write!(f, "\n       txt: {}", properties.join(" "))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The layout of get_device_descriptions(), `{}` with at most max_channels channel names.
+    pub(crate) fn description(&self, max_channels: usize) -> String {
+        DeviceInfoFormat {
+            info: self,
+            verbose: false,
+            max_channels: Some(max_channels),
+        }
+        .to_string()
+    }
+}
+
+/// A DeviceInfo formatted with a shorter channel list, see DeviceInfo::verbose().
+pub struct VerboseDeviceInfo<'a>(DeviceInfoFormat<'a>);
+
+impl Display for VerboseDeviceInfo<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+struct DeviceInfoFormat<'a> {
+    info: &'a DeviceInfo,
+    verbose: bool,
+    max_channels: Option<usize>,
+}
+
+impl Display for DeviceInfoFormat<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        self.info.fmt_with(f, self.verbose, self.max_channels)
+    }
+}
+
+/// Single line format of a DeviceInfo, see DeviceInfo::compact().
+pub struct CompactDeviceInfo<'a>(&'a DeviceInfo);
+
+impl Display for CompactDeviceInfo<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let info = self.0;
+        write!(
+            f,
+            "{} ({} {}) dbc:{} cmc:{} arc:{} chan:{} ips:{:?} channels:{}",
+            info.name,
+            or_na(&info.manufacturer),
+            or_na(&info.model),
+            compact_status(info.service_status(DanteService::Dbc)),
+            compact_status(info.service_status(DanteService::Cmc)),
+            compact_status(info.service_status(DanteService::Arc)),
+            compact_status(info.service_status(DanteService::Chan)),
+            info.addresses,
+            info.channels.len(),
+        )?;
+        if info.unresolved {
+            write!(f, " unresolved")?;
+        }
+        Ok(())
+    }
+}
+
+impl DeviceInfo {
+    pub fn service_status(&self, service: DanteService) -> ServiceStatus {
+        if self.untracked_services.contains(&service) {
+            return ServiceStatus::NotTracked;
+        }
+        let connected = match service {
+            DanteService::Dbc => self.dbc_connected,
+            DanteService::Cmc => self.cmc_connected,
+            DanteService::Arc => self.arc_connected,
+            DanteService::Chan => self.chan_connected,
+        };
+        match connected {
+            true => ServiceStatus::Connected,
+            false => ServiceStatus::Disconnected,
+        }
+    }
+
+    /// The version commands to the device are built for, the override if there is one.
+    pub fn effective_version(&self) -> Option<DanteVersion> {
+        self.version_override.or(self.detected_version)
+    }
+
+    /// Formats the device on a single line, for logs.
+    pub fn compact(&self) -> CompactDeviceInfo<'_> {
+        CompactDeviceInfo(self)
+    }
+
+    /// Formats the device like `{:#}`, listing only the first max_channels channels. A Dante Via host or a virtual soundcard can have hundreds, which makes for a long description.
+    pub fn verbose(&self, max_channels: Option<usize>) -> VerboseDeviceInfo<'_> {
+        VerboseDeviceInfo(DeviceInfoFormat {
+            info: self,
+            verbose: true,
+            max_channels,
+        })
+    }
+}
+
+impl DanteDeviceList {
+    pub(crate) fn get_device_info(&self, device_name: &str) -> Option<DeviceInfo> {
+        self.get_device_info_with(device_name, SnapshotDetail::Full)
+    }
+
+    /// get_device_info(), leaving the TXT properties of the channels empty for SnapshotDetail::Summary.
+    pub(crate) fn get_device_info_with(
+        &self,
+        device_name: &str,
+        detail: SnapshotDetail,
+    ) -> Option<DeviceInfo> {
+        let status = self.devices.get(device_name)?;
+        let cache = self.caches.get(device_name)?;
+
+        let mut addresses: Vec<Ipv4Addr> = self
+            .get_device_ips(device_name)
+            .unwrap_or_default()
+            .into_iter()
+            .collect();
+        addresses.sort();
+
+        let mut chan_infos: Vec<_> = cache.chan_info.iter().collect();
+        // Channels without an id go last, by name so the order doesn't change from one call to the next.
+        chan_infos
+            .sort_by(|a, b| (a.id.is_none(), a.id, &a.name).cmp(&(b.id.is_none(), b.id, &b.name)));
+
+        Some(DeviceInfo {
+            name: device_name.to_owned(),
+            hostname: cache.hostname.clone(),
+            dbc_connected: status.connected_dbc,
+            cmc_connected: status.connected_cmc,
+            arc_connected: status.connected_arc,
+            chan_connected: status.connected_chan,
+            untracked_services: self.untracked_services.clone(),
+            unresolved: status.unresolved,
+            manually_added: status.manually_added,
+            addresses,
+            arc_port: self.get_arc_port(device_name),
+            arc_addresses: cache.arc_info.as_ref().map(|arc_info| {
+                let mut arc_addresses: Vec<Ipv4Addr> = arc_info.addresses.iter().copied().collect();
+                arc_addresses.sort();
+                arc_addresses
+            }),
+            dbc_port: self.get_dbc_port(device_name),
+            cmc_port: self.get_cmc_port(device_name),
+            bogus_ports: [DanteService::Dbc, DanteService::Cmc, DanteService::Arc]
+                .into_iter()
+                .filter_map(|service| {
+                    self.get_advertised_port(device_name, service)
+                        .filter(|port| port_if_valid(*port).is_none())
+                        .map(|port| (service, port))
+                })
+                .collect(),
+            id: cache.cmc_info.as_ref().map(|cmc_info| cmc_info.id.clone()),
+            manufacturer: cache
+                .cmc_info
+                .as_ref()
+                .map(|cmc_info| cmc_info.manufacturer.clone()),
+            model: cache
+                .cmc_info
+                .as_ref()
+                .map(|cmc_info| cmc_info.model.clone()),
+            router_vers: cache
+                .arc_info
+                .as_ref()
+                .map(|arc_info| arc_info.router_vers.clone()),
+            router_info: cache
+                .arc_info
+                .as_ref()
+                .map(|arc_info| arc_info.router_info.clone()),
+            arc_capabilities: cache
+                .arc_info
+                .as_ref()
+                .and_then(|arc_info| ArcCapabilities::parse(&arc_info.router_vers)),
+            detected_version: self.get_detected_version(device_name),
+            version_override: cache.version_override,
+            channel_count_hint: self.get_channel_count_hint(device_name),
+            channels: chan_infos
+                .into_iter()
+                .map(|chan_info| match detail {
+                    SnapshotDetail::Full => cache.tx_channel_info(chan_info, None),
+                    SnapshotDetail::Summary => cache.tx_channel_summary(chan_info),
+                })
+                .collect(),
+            channel_name_conflicts: self.channel_name_conflicts(device_name),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ChannelDirection, ChannelSource};
+    use std::collections::HashMap;
+
+    fn device_info() -> DeviceInfo {
+        let channel = |id: u16, name: &str| ChannelInfo {
+            id: Some(id),
+            name: name.to_owned(),
+            canonical: None,
+            direction: ChannelDirection::Tx,
+            sample_rate: Some(48000),
+            encoding: None,
+            latency: None,
+            routing: None,
+            extra_properties: HashMap::from([
+                ("id".to_owned(), id.to_string()),
+                ("rate".to_owned(), "48000".to_owned()),
+            ]),
+            source: ChannelSource::Mdns,
+        };
+        DeviceInfo {
+            name: "Stage-Box".to_owned(),
+            hostname: Some("Stage-Box".to_owned()),
+            dbc_connected: true,
+            cmc_connected: true,
+            arc_connected: true,
+            chan_connected: false,
+            untracked_services: Vec::new(),
+            unresolved: false,
+            manually_added: false,
+            addresses: vec![Ipv4Addr::new(10, 0, 0, 5), Ipv4Addr::new(10, 1, 0, 5)],
+            arc_port: Some(4440),
+            arc_addresses: Some(vec![Ipv4Addr::new(10, 0, 0, 5)]),
+            dbc_port: Some(4455),
+            cmc_port: Some(8800),
+            bogus_ports: Vec::new(),
+            id: Some("001dc1fffe000001".to_owned()),
+            manufacturer: Some("Audinate".to_owned()),
+            model: Some("DIOUSB".to_owned()),
+            router_vers: Some("4.4.1.3".to_owned()),
+            router_info: Some("\"Dante Via\"".to_owned()),
+            arc_capabilities: ArcCapabilities::parse("4.4.1.3"),
+            detected_version: None,
+            version_override: None,
+            channel_count_hint: Some(2),
+            channels: vec![channel(1, "Left"), channel(2, "Right")],
+            channel_name_conflicts: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn arc_capabilities_of_brooklyn() {
+        // A Brooklyn II card, like in a console or stage box.
+        let capabilities = ArcCapabilities::parse("4.4.1.3").unwrap();
+        assert_eq!(capabilities.version, vec![4, 4, 1, 3]);
+        assert_eq!(capabilities.protocol_major(), 4);
+        assert_eq!(capabilities.protocol_minor(), 4);
+        assert_eq!(
+            capabilities.dante_version(),
+            Some(DanteVersion::Dante4_4_1_3)
+        );
+    }
+
+    #[test]
+    fn arc_capabilities_of_ultimo() {
+        // Ultimo chips, like in the AVIO adapters and wall plates.
+        let capabilities = ArcCapabilities::parse("4.2.1.3").unwrap();
+        assert_eq!(capabilities.protocol_minor(), 2);
+        assert_eq!(
+            capabilities.dante_version(),
+            Some(DanteVersion::Dante4_2_1_3)
+        );
+        // Advertised with whitespace around it by some firmware.
+        assert_eq!(ArcCapabilities::parse(" 4.2.1.3\n"), Some(capabilities));
+    }
+
+    #[test]
+    fn arc_capabilities_of_dvs() {
+        // Dante Virtual Soundcard and Dante Via run their own versions, whose commands haven't been captured.
+        let capabilities = ArcCapabilities::parse("4.1.7.1").unwrap();
+        assert_eq!(capabilities.version, vec![4, 1, 7, 1]);
+        assert_eq!(capabilities.protocol_major(), 4);
+        assert_eq!(capabilities.protocol_minor(), 1);
+        assert_eq!(capabilities.dante_version(), None);
+        let short = ArcCapabilities::parse("4").unwrap();
+        assert_eq!(short.protocol_minor(), 0);
+        assert_eq!(short.dante_version(), None);
+    }
+
+    #[test]
+    fn arc_capabilities_of_garbage() {
+        assert_eq!(ArcCapabilities::parse("N/A"), None);
+        assert_eq!(ArcCapabilities::parse(""), None);
+        assert_eq!(ArcCapabilities::parse("4.4..3"), None);
+        assert_eq!(ArcCapabilities::parse("4.4.1.3-beta"), None);
+        assert_eq!(ArcCapabilities::parse("4.70000.1.3"), None);
+    }
+
+    #[test]
+    fn display_keeps_the_description_layout() {
+        assert_eq!(
+            device_info().to_string(),
+            "Stage-Box:\n\
+             dbc status: Connected\n\
+             cmc status: Connected\n\
+             arc status: Connected\n\
+             chan status: Disconnected\n\
+             id: 001dc1fffe000001\n\
+             manufacturer: Audinate\n\
+             model: DIOUSB\n\
+             router_vers: 4.4.1.3\n\
+             router_info: \"Dante Via\"\n\
+             ARC port: 4440\n\
+             IP: {10.0.0.5}\n\
+             Channels:\n\
+             \"Left\"\n\
+             \"Right\""
+        );
+    }
+
+    #[test]
+    fn display_without_arc_record() {
+        let mut info = device_info();
+        info.arc_port = None;
+        info.arc_addresses = None;
+        info.router_vers = None;
+        info.router_info = None;
+        let description = info.to_string();
+        assert!(
+            description.contains("\nrouter_vers: N/A\nrouter_info: N/A\nARC port: N/A\nIP: N/A\n")
+        );
+    }
+
+    #[test]
+    fn alternate_display_adds_addresses_and_txt_properties() {
+        let description = format!("{:#}", device_info());
+        assert!(description.starts_with("Stage-Box:\ndbc status: Connected\n"));
+        assert!(description.contains("\nIP: {10.0.0.5}\nhostname: Stage-Box\n"));
+        assert!(description.contains("\naddresses: [10.0.0.5, 10.1.0.5]"));
+        assert!(description.contains("\n       txt: id=1 rate=48000"));
+        assert!(description.contains("\n       txt: id=2 rate=48000"));
+    }
+
+    #[test]
+    fn description_cuts_off_channel_names() {
+        let description = device_info().description(1);
+        assert!(description.ends_with("\nChannels:\n\"Left\"\n  ... and 1 more"));
+    }
+}
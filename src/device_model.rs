@@ -0,0 +1,94 @@
+use crate::{DanteDeviceList, DanteDeviceManager};
+
+/// Channel limits of hardware whose CMC "mf" and "model" properties are known, as (manufacturer, model, max TX channels, max RX channels). Matched ignoring case. The AVIO adapters are listed under their product codes, which is what they're expected to advertise as their model but hasn't been checked against a real one yet.
+const KNOWN_MODELS: &[(&str, &str, u16, u16)] = &[
+    ("Audinate", "ADP-DAI-AU-2X0", 2, 0),
+    ("Audinate", "ADP-DAI-AU-1X0", 1, 0),
+    ("Audinate", "ADP-DAO-AU-0X2", 0, 2),
+    ("Audinate", "ADP-DAO-AU-0X1", 0, 1),
+    ("Audinate", "ADP-USB-AU-2X2", 2, 2),
+    ("Audinate", "ADP-AES3-AU-2X2", 2, 2),
+    ("Audinate", "ADP-BT-AU-2X1", 2, 1),
+];
+
+/// What a device says it is, see DanteDeviceManager::get_device_model_info().
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DeviceModelInfo {
+    /// CMC "mf" property, "N/A" if the record doesn't have one.
+    pub manufacturer: String,
+    /// CMC "model" property, "N/A" if the record doesn't have one.
+    pub model: String,
+    /// Nothing on the network has been seen advertising a product family, so this is None until somewhere to get it from turns up.
+    pub product_family: Option<String>,
+    /// The most TX channels the model has, if it's in the built-in list of known models. DeviceInfo::channel_count_hint is what the device itself advertises.
+    pub max_tx_channels: Option<u16>,
+    /// The most RX channels the model has, if it's in the built-in list of known models.
+    pub max_rx_channels: Option<u16>,
+}
+
+/// The TX and RX channel limits of a model from KNOWN_MODELS.
+fn known_channel_limits(manufacturer: &str, model: &str) -> Option<(u16, u16)> {
+    KNOWN_MODELS
+        .iter()
+        .find(|(known_manufacturer, known_model, _, _)| {
+            known_manufacturer.eq_ignore_ascii_case(manufacturer)
+                && known_model.eq_ignore_ascii_case(model)
+        })
+        .map(|(_, _, max_tx_channels, max_rx_channels)| (*max_tx_channels, *max_rx_channels))
+}
+
+impl DanteDeviceList {
+    fn get_device_model_info(&self, device_name: &str) -> Option<DeviceModelInfo> {
+        let cmc_info = self.caches.get(device_name)?.cmc_info.as_ref()?;
+        let limits = known_channel_limits(&cmc_info.manufacturer, &cmc_info.model);
+        Some(DeviceModelInfo {
+            manufacturer: cmc_info.manufacturer.clone(),
+            model: cmc_info.model.clone(),
+            product_family: None,
+            max_tx_channels: limits.map(|(max_tx_channels, _)| max_tx_channels),
+            max_rx_channels: limits.map(|(_, max_rx_channels)| max_rx_channels),
+        })
+    }
+}
+
+impl DanteDeviceManager {
+    /// Returns the manufacturer and model of a device from its CMC record, with the channel limits of the model if it's a known one. None if the device isn't known or its CMC service hasn't resolved.
+    pub fn get_device_model_info(&self, device_name: &str) -> Option<DeviceModelInfo> {
+        self.device_list
+            .lock()
+            .unwrap()
+            .get_device_model_info(device_name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn known_models_ignore_case() {
+        assert_eq!(
+            known_channel_limits("Audinate", "ADP-USB-AU-2X2"),
+            Some((2, 2))
+        );
+        assert_eq!(
+            known_channel_limits("audinate", "adp-bt-au-2x1"),
+            Some((2, 1))
+        );
+        assert_eq!(
+            known_channel_limits("AUDINATE", "ADP-DAO-AU-0X2"),
+            Some((0, 2))
+        );
+    }
+
+    #[test]
+    fn unknown_models() {
+        // Brooklyn II cards and DVS report their host's or their own model, none of which are listed.
+        assert_eq!(known_channel_limits("Yamaha", "Rio3224-D2"), None);
+        assert_eq!(known_channel_limits("Audinate", "DVS"), None);
+        // A model is only known together with its manufacturer.
+        assert_eq!(known_channel_limits("Focusrite", "ADP-USB-AU-2X2"), None);
+        assert_eq!(known_channel_limits("N/A", "N/A"), None);
+    }
+}
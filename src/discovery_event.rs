@@ -0,0 +1,270 @@
+#[cfg(any(test, feature = "test-util"))]
+use crate::DanteDeviceManager;
+use crate::{
+    cutoff_address, split_chan_fullname, ARCInfo, CHANInfo, CMCInfo, DBCInfo, DanteDeviceList,
+    DanteService,
+};
+use mdns_sd::ServiceInfo;
+#[cfg(any(test, feature = "test-util"))]
+use std::collections::HashMap;
+#[cfg(any(test, feature = "test-util"))]
+use std::net::Ipv4Addr;
+
+/// What a discovery thread got from mdns, without the events the device list doesn't care about. The threads translate mdns_sd::ServiceEvent into these, so the same handling can be fed made up events.
+pub(crate) enum DiscoveryEvent {
+    Found { fullname: String },
+    Resolved(ServiceInfo),
+    Removed { fullname: String },
+}
+
+impl DanteDeviceList {
+    /// Everything discovery does with an event for one of the Dante services. CHAN events with a name that isn't "channel@device" are dropped.
+    pub(crate) fn handle_discovery_event(&mut self, service: DanteService, event: DiscoveryEvent) {
+        match event {
+            DiscoveryEvent::Found { fullname } => {
+                let device_name = match service {
+                    DanteService::Chan => match split_chan_fullname(&fullname) {
+                        Some((_, device_name)) => device_name,
+                        None => return,
+                    },
+                    _ => cutoff_address(&fullname, Some(service.service_type())),
+                };
+                self.connect_service(device_name, service);
+            }
+            DiscoveryEvent::Resolved(service_info) => {
+                let fullname = service_info.get_fullname();
+                let hostname = service_info.get_hostname();
+                let device_name = cutoff_address(fullname, Some(service.service_type()));
+                match service {
+                    DanteService::Dbc => {
+                        self.resolve_dbc(device_name, hostname, DBCInfo::from(&service_info))
+                    }
+                    DanteService::Cmc => {
+                        self.resolve_cmc(device_name, hostname, CMCInfo::from(&service_info))
+                    }
+                    DanteService::Arc => {
+                        self.resolve_arc(device_name, hostname, ARCInfo::from(&service_info))
+                    }
+                    DanteService::Chan => {
+                        if let Some((chan_name, device_name)) = split_chan_fullname(fullname) {
+                            match self.chan_record_unchanged(device_name, chan_name, &service_info)
+                            {
+                                true => self.resolve_unchanged_chan(device_name, hostname),
+                                false => self.resolve_chan(
+                                    device_name,
+                                    hostname,
+                                    CHANInfo::from_service_info(chan_name, &service_info),
+                                ),
+                            }
+                        }
+                    }
+                }
+            }
+            DiscoveryEvent::Removed { fullname } => match service {
+                DanteService::Chan => {
+                    if let Some((chan_name, device_name)) = split_chan_fullname(&fullname) {
+                        self.remove_chan(device_name, chan_name);
+                    }
+                }
+                _ => self.disconnect_service(
+                    cutoff_address(&fullname, Some(service.service_type())),
+                    service,
+                ),
+            },
+        }
+    }
+}
+
+/// A made up mdns event for DanteDeviceManager::inject_event(). fullname is the full service name, like "Stage-Box._netaudio-arc._udp.local." or "01@Stage-Box._netaudio-chan._udp.local." for a channel.
+#[cfg(any(test, feature = "test-util"))]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SyntheticEvent {
+    Found {
+        fullname: String,
+    },
+    Resolved {
+        fullname: String,
+        addrs: Vec<Ipv4Addr>,
+        port: u16,
+        txt: HashMap<String, String>,
+    },
+    Removed {
+        fullname: String,
+    },
+}
+
+#[cfg(any(test, feature = "test-util"))]
+#[derive(thiserror::Error, Debug)]
+pub enum InjectError {
+    #[error("\"{fullname}\" isn't a {service} service name")]
+    WrongServiceType {
+        fullname: String,
+        service: DanteService,
+    },
+    #[error("invalid service")]
+    InvalidService(#[source] mdns_sd::Error),
+}
+
+/// A SyntheticEvent turned into what mdns would have handed discovery, see SyntheticEvent::prepare().
+#[cfg(any(test, feature = "test-util"))]
+pub struct PreparedEvent {
+    service: DanteService,
+    event: DiscoveryEvent,
+}
+
+#[cfg(any(test, feature = "test-util"))]
+impl SyntheticEvent {
+    /// Does the part of DanteDeviceManager::inject_event() that mdns does for real events, building the resolved record, so it can be done ahead of time and only the handling of the event timed. Resolved services get the host name "<device>.local.".
+    pub fn prepare(self, service: DanteService) -> Result<PreparedEvent, InjectError> {
+        let event = match self {
+            SyntheticEvent::Found { fullname } => DiscoveryEvent::Found { fullname },
+            SyntheticEvent::Resolved {
+                fullname,
+                addrs,
+                port,
+                txt,
+            } => {
+                let instance = fullname
+                    .strip_suffix(service.service_type())
+                    .and_then(|instance| instance.strip_suffix('.'))
+                    .filter(|instance| !instance.is_empty())
+                    .ok_or_else(|| InjectError::WrongServiceType {
+                        fullname: fullname.clone(),
+                        service,
+                    })?;
+                let device_name = instance.rsplit('@').next().unwrap_or(instance);
+                let addrs: Vec<String> = addrs.iter().map(Ipv4Addr::to_string).collect();
+                let service_info = ServiceInfo::new(
+                    service.service_type(),
+                    instance,
+                    &format!("{}.local.", device_name),
+                    addrs.join(",").as_str(),
+                    port,
+                    txt,
+                )
+                .map_err(InjectError::InvalidService)?;
+                DiscoveryEvent::Resolved(service_info)
+            }
+            SyntheticEvent::Removed { fullname } => DiscoveryEvent::Removed { fullname },
+        };
+        Ok(PreparedEvent { service, event })
+    }
+}
+
+#[cfg(any(test, feature = "test-util"))]
+impl DanteDeviceManager {
+    /// Feeds a made up mdns event for one of the Dante services through the same code the discovery threads run for real ones, so cache updates, name parsing and events can be tested without multicast. Works whether or not discovery is running. Resolved services get the host name "<device>.local.".
+    pub fn inject_event(
+        &self,
+        service: DanteService,
+        event: SyntheticEvent,
+    ) -> Result<(), InjectError> {
+        self.inject_prepared_event(event.prepare(service)?);
+        Ok(())
+    }
+
+    /// inject_event() for an event prepared beforehand.
+    pub fn inject_prepared_event(&self, event: PreparedEvent) {
+        self.device_list
+            .lock()
+            .unwrap()
+            .handle_discovery_event(event.service, event.event);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DanteDeviceManager, MAX_CHANNELS_PER_DEVICE};
+
+    const DEVICE: &str = "Soak-Box";
+
+    fn resolve_chan(manager: &DanteDeviceManager, name: &str, id: Option<u16>) {
+        let fullname = format!("{}@{}.{}", name, DEVICE, DanteService::Chan.service_type());
+        let mut txt = HashMap::from([("rate".to_owned(), "48000".to_owned())]);
+        if let Some(id) = id {
+            txt.insert("id".to_owned(), id.to_string());
+        }
+        manager
+            .inject_event(
+                DanteService::Chan,
+                SyntheticEvent::Found {
+                    fullname: fullname.clone(),
+                },
+            )
+            .unwrap();
+        manager
+            .inject_event(
+                DanteService::Chan,
+                SyntheticEvent::Resolved {
+                    fullname,
+                    addrs: vec![Ipv4Addr::new(10, 0, 0, 9)],
+                    port: 4455,
+                    txt,
+                },
+            )
+            .unwrap();
+    }
+
+    fn cached_channels(manager: &DanteDeviceManager) -> Vec<(Option<u16>, String)> {
+        let mut channels: Vec<(Option<u16>, String)> = manager
+            .get_device_info(DEVICE)
+            .unwrap()
+            .channels
+            .into_iter()
+            .map(|channel| (channel.id, channel.name))
+            .collect();
+        channels.sort();
+        channels
+    }
+
+    #[test]
+    fn channel_cache_converges_over_thousands_of_resolutions() {
+        let manager = DanteDeviceManager::new();
+        // 400 channels with an id and 200 without, each resolved five times over, the ones with an id under a new name every pass.
+        for pass in 0..5 {
+            for index in 0..600u16 {
+                match index < 400 {
+                    true => resolve_chan(
+                        &manager,
+                        &format!("In {} v{}", index, pass),
+                        Some(index + 1),
+                    ),
+                    false => resolve_chan(&manager, &format!("Aux {}", index), None),
+                }
+            }
+        }
+        // Half of the ones without an id start advertising one.
+        for index in 400..500u16 {
+            resolve_chan(&manager, &format!("Aux {}", index), Some(index + 1));
+        }
+
+        let mut expected: Vec<(Option<u16>, String)> = (0..600u16)
+            .map(|index| match index {
+                0..=399 => (Some(index + 1), format!("In {} v4", index)),
+                400..=499 => (Some(index + 1), format!("Aux {}", index)),
+                _ => (None, format!("Aux {}", index)),
+            })
+            .collect();
+        expected.sort();
+        assert_eq!(cached_channels(&manager), expected);
+        manager.device_list.lock().unwrap().debug_check_invariants();
+    }
+
+    #[test]
+    fn channel_cache_is_capped() {
+        let manager = DanteDeviceManager::new();
+        for index in 0..MAX_CHANNELS_PER_DEVICE as u16 + 200 {
+            resolve_chan(&manager, &format!("{:04}", index), Some(index + 1));
+        }
+        let channels = cached_channels(&manager);
+        assert_eq!(channels.len(), MAX_CHANNELS_PER_DEVICE);
+        // A channel that's already cached still updates once the cap is hit.
+        resolve_chan(&manager, "Renamed", Some(1));
+        assert_eq!(
+            cached_channels(&manager)[0],
+            (Some(1), "Renamed".to_owned())
+        );
+        assert_eq!(cached_channels(&manager).len(), MAX_CHANNELS_PER_DEVICE);
+    }
+}
@@ -0,0 +1,78 @@
+use crate::{DanteDeviceEvent, DanteDeviceManager};
+use futures_core::Stream;
+use std::collections::VecDeque;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+
+/// Events waiting for a DeviceEventStream to pick them up, and the task to wake when one comes in.
+pub(crate) struct StreamQueue {
+    device_name: String,
+    events: VecDeque<DanteDeviceEvent>,
+    waker: Option<Waker>,
+    closed: bool,
+}
+
+impl StreamQueue {
+    pub(crate) fn new(device_name: &str) -> StreamQueue {
+        StreamQueue {
+            device_name: device_name.to_owned(),
+            events: VecDeque::new(),
+            waker: None,
+            closed: false,
+        }
+    }
+
+    /// Queues the event if it's about the device of the stream.
+    pub(crate) fn push(&mut self, event: &DanteDeviceEvent) {
+        if event.device_name() != Some(self.device_name.as_str()) {
+            return;
+        }
+        self.events.push_back(event.clone());
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+
+    pub(crate) fn close(&mut self) {
+        self.closed = true;
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+/// The events about one device as a futures Stream, see DanteDeviceManager::stream_device_events(). Ends once the manager is gone.
+pub struct DeviceEventStream {
+    queue: Arc<Mutex<StreamQueue>>,
+}
+
+impl Stream for DeviceEventStream {
+    type Item = DanteDeviceEvent;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<DanteDeviceEvent>> {
+        let mut queue = self.queue.lock().unwrap();
+        match queue.events.pop_front() {
+            Some(event) => Poll::Ready(Some(event)),
+            None if queue.closed => Poll::Ready(None),
+            None => {
+                queue.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl DanteDeviceManager {
+    /// Returns every DanteDeviceEvent about the device from now on as a Stream, for select!ing on in async code: channels coming and going, parameter changes, the device going away and so on. Like subscribe_events() nothing is dropped, events queue up until the stream is polled. Dropping the stream unsubscribes.
+    pub fn stream_device_events(&self, device_name: &str) -> DeviceEventStream {
+        DeviceEventStream {
+            queue: self
+                .device_list
+                .lock()
+                .unwrap()
+                .events
+                .subscribe_stream(device_name),
+        }
+    }
+}
@@ -0,0 +1,225 @@
+#[cfg(feature = "async")]
+use crate::event_stream::StreamQueue;
+use crate::{ChannelInfo, ChannelParams, DanteService, RouteCorrection, UnresolvedRoute};
+use std::collections::HashMap;
+use std::net::{Ipv4Addr, SocketAddrV4};
+use std::sync::mpsc::{channel, Receiver, Sender};
+#[cfg(feature = "async")]
+use std::sync::Weak;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Something that happened during discovery. Get these from DanteDeviceManager::subscribe_events().
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DanteDeviceEvent {
+    /// The device was found by one of the services, or came back after being removed. Its services may not have resolved yet.
+    DeviceAdded { device_name: String },
+    /// A service of the device resolved, whether or not anything changed. Handy as a last seen time, since mdns answers for a service come every few minutes while the device is up. A CHAN service resolves once for every channel.
+    ServiceResolved {
+        device_name: String,
+        service: DanteService,
+    },
+    /// The device was found but none of its services resolved an address within the resolve timeout.
+    DeviceUnresolved { device_name: String },
+    /// The device was gone from every mdns service for the removal grace period, see DanteDeviceManager::set_removal_grace_period(). Its cache is kept until purge_stale_caches().
+    DeviceRemoved { device_name: String },
+    /// A service of the device resolved again with different addresses, like after a DHCP renewal. Both lists are sorted.
+    AddressesChanged {
+        device_name: String,
+        old: Vec<Ipv4Addr>,
+        new: Vec<Ipv4Addr>,
+    },
+    /// A CHAN record of the device came in with a different sample rate, encoding or latency than before, like when the device renegotiated latency. A record that only differs in something else, like the channel's name, doesn't count.
+    ChannelParamsChanged {
+        device_name: String,
+        channel_name: String,
+        old: ChannelParams,
+        new: ChannelParams,
+    },
+    /// The TXT properties of a CHAN record that the crate doesn't parse changed, like vendor specific keys. Changes to the parsed ones are ChannelParamsChanged.
+    ChannelPropertiesChanged {
+        device_name: String,
+        channel_name: String,
+        old: HashMap<String, String>,
+        new: HashMap<String, String>,
+    },
+    /// A datagram on the command socket (see DanteDeviceManager::bind_command_socket()) that isn't the answer to a command we sent, like the subscription status notifications some devices push. No format of these has been worked out yet, so payload is the whole datagram, header and all.
+    UnsolicitedMessage {
+        from: SocketAddrV4,
+        payload: Vec<u8>,
+    },
+    /// A service of the device advertised a port no device would listen on, like 0. It's ignored: the device's port for the service is None and commands go to the default port.
+    BogusPort {
+        device_name: String,
+        service: DanteService,
+        port: u16,
+    },
+    /// A RoutingHealer found a route drifted from the desired routing and put it back.
+    RouteCorrected(RouteCorrection),
+    /// The device started advertising a channel over mdns, the same as ChannelEvent::ChannelAdded.
+    ChannelAdded {
+        device_name: String,
+        channel: ChannelInfo,
+    },
+    /// The device stopped advertising the channel with this id, the same as ChannelEvent::ChannelRemoved.
+    ChannelRemoved {
+        device_name: String,
+        channel_id: u16,
+    },
+    /// A subscription's TX device or channel went missing, see DanteDeviceManager::unresolved_routes().
+    RouteUnresolved(UnresolvedRoute),
+    /// An unresolved subscription's TX side is back, or the subscription is gone.
+    RouteResolved {
+        rx_device: String,
+        rx_channel_id: u16,
+    },
+    /// Nothing came in from the Dante network for silent_for, see DanteDeviceManager::set_network_watchdog(). Commands fail with NetworkUnavailable until NetworkRestored.
+    NetworkDown { silent_for: Duration },
+    /// Traffic from the network again after NetworkDown.
+    NetworkRestored { down_for: Duration },
+}
+
+impl DanteDeviceEvent {
+    /// The device the event is about. For an UnsolicitedMessage that's not known, only the address it came from, and the network events aren't about a device.
+    pub fn device_name(&self) -> Option<&str> {
+        match self {
+            DanteDeviceEvent::DeviceAdded { device_name }
+            | DanteDeviceEvent::ServiceResolved { device_name, .. }
+            | DanteDeviceEvent::DeviceUnresolved { device_name }
+            | DanteDeviceEvent::DeviceRemoved { device_name }
+            | DanteDeviceEvent::AddressesChanged { device_name, .. }
+            | DanteDeviceEvent::ChannelParamsChanged { device_name, .. }
+            | DanteDeviceEvent::ChannelPropertiesChanged { device_name, .. }
+            | DanteDeviceEvent::BogusPort { device_name, .. }
+            | DanteDeviceEvent::ChannelAdded { device_name, .. }
+            | DanteDeviceEvent::ChannelRemoved { device_name, .. } => Some(device_name),
+            DanteDeviceEvent::RouteCorrected(correction) => Some(&correction.rx_device),
+            DanteDeviceEvent::RouteUnresolved(route) => Some(&route.rx_device),
+            DanteDeviceEvent::RouteResolved { rx_device, .. } => Some(rx_device),
+            DanteDeviceEvent::UnsolicitedMessage { .. }
+            | DanteDeviceEvent::NetworkDown { .. }
+            | DanteDeviceEvent::NetworkRestored { .. } => None,
+        }
+    }
+}
+
+/// The senders of everyone subscribed to events. Subscribers that hung up are dropped on the next emit.
+#[derive(Default)]
+pub(crate) struct EventSenders {
+    senders: Vec<Sender<DanteDeviceEvent>>,
+    #[cfg(feature = "async")]
+    streams: Vec<Weak<Mutex<StreamQueue>>>,
+}
+
+impl EventSenders {
+    pub(crate) fn subscribe(&mut self) -> Receiver<DanteDeviceEvent> {
+        let (sender, receiver) = channel();
+        self.senders.push(sender);
+        receiver
+    }
+
+    /// Whether anyone subscribed, though they may have hung up since the last emit.
+    pub(crate) fn has_subscribers(&self) -> bool {
+        #[cfg(feature = "async")]
+        if !self.streams.is_empty() {
+            return true;
+        }
+        !self.senders.is_empty()
+    }
+
+    pub(crate) fn emit(&mut self, event: DanteDeviceEvent) {
+        self.senders
+            .retain(|sender| sender.send(event.clone()).is_ok());
+        #[cfg(feature = "async")]
+        self.streams.retain(|queue| match queue.upgrade() {
+            Some(queue) => {
+                queue.lock().unwrap().push(&event);
+                true
+            }
+            None => false,
+        });
+    }
+
+    #[cfg(feature = "async")]
+    pub(crate) fn subscribe_stream(&mut self, device_name: &str) -> Arc<Mutex<StreamQueue>> {
+        let queue = Arc::new(Mutex::new(StreamQueue::new(device_name)));
+        self.streams.push(Arc::downgrade(&queue));
+        queue
+    }
+}
+
+/// Ends the streams, since nothing is going to be emitted anymore.
+#[cfg(feature = "async")]
+impl Drop for EventSenders {
+    fn drop(&mut self) {
+        for queue in self.streams.iter().filter_map(Weak::upgrade) {
+            queue.lock().unwrap().close();
+        }
+    }
+}
+
+/// A change to the channels a device advertises over mdns. Get these from DanteDeviceManager::watch_device_channels().
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ChannelEvent {
+    ChannelAdded(ChannelInfo),
+    /// The id of a channel that's no longer advertised. Channels that never advertised an id aren't reported when they go away.
+    ChannelRemoved(u16),
+}
+
+type ChannelCallback = Box<dyn Fn(ChannelEvent) + Send>;
+
+/// Channel watch callbacks by device name.
+#[derive(Default)]
+pub(crate) struct ChannelWatchers {
+    next_id: u64,
+    callbacks: HashMap<String, Vec<(u64, ChannelCallback)>>,
+}
+
+impl ChannelWatchers {
+    pub(crate) fn emit(&self, device_name: &str, event: ChannelEvent) {
+        for (_, callback) in self.callbacks.get(device_name).into_iter().flatten() {
+            callback(event.clone());
+        }
+    }
+}
+
+/// Keeps a channel watch going. The callback is removed when this is dropped.
+pub struct WatchHandle {
+    watchers: Arc<Mutex<ChannelWatchers>>,
+    device_name: String,
+    id: u64,
+}
+
+impl WatchHandle {
+    pub(crate) fn new(
+        watchers: &Arc<Mutex<ChannelWatchers>>,
+        device_name: &str,
+        callback: ChannelCallback,
+    ) -> WatchHandle {
+        let mut watchers_lock = watchers.lock().unwrap();
+        let id = watchers_lock.next_id;
+        watchers_lock.next_id += 1;
+        watchers_lock
+            .callbacks
+            .entry(device_name.to_owned())
+            .or_default()
+            .push((id, callback));
+        WatchHandle {
+            watchers: watchers.clone(),
+            device_name: device_name.to_owned(),
+            id,
+        }
+    }
+}
+
+impl Drop for WatchHandle {
+    fn drop(&mut self) {
+        let mut watchers = self.watchers.lock().unwrap();
+        if let Some(callbacks) = watchers.callbacks.get_mut(&self.device_name) {
+            callbacks.retain(|(id, _)| *id != self.id);
+            if callbacks.is_empty() {
+                watchers.callbacks.remove(&self.device_name);
+            }
+        }
+    }
+}
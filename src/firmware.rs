@@ -0,0 +1,247 @@
+use crate::{DanteDeviceManager, DeviceInfo, QueryError, COMMAND_DEVICEINFO};
+use log::{debug, warn};
+use std::net::Ipv4Addr;
+use std::thread::sleep;
+use std::time::Duration;
+
+/// Time between two device info queries of firmware_report(), so a big network doesn't get them all at once.
+const FIRMWARE_QUERY_PACING: Duration = Duration::from_millis(50);
+
+/// Where what a FirmwareEntry says came from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum FirmwareSource {
+    /// Discovery already knew the model and version, the device wasn't queried.
+    FromCache,
+    /// Discovery was missing something, so the device was queried and it answered.
+    Queried,
+    /// Discovery was missing something and the device didn't answer the query.
+    Unreachable,
+}
+
+/// What's known about the hardware and Dante software of a device, see DanteDeviceManager::firmware_report().
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FirmwareEntry {
+    pub device_name: String,
+    pub addresses: Vec<Ipv4Addr>,
+    /// CMC "mf" property.
+    pub manufacturer: Option<String>,
+    /// CMC "model" property.
+    pub model: Option<String>,
+    /// ARC "router_vers" property, the version of the Dante software on the device.
+    pub router_vers: Option<String>,
+    /// router_vers split at the dots, for comparing versions.
+    pub version: Option<Vec<u16>>,
+    /// The raw response to the device info query (1003) when the device was queried. Its layout hasn't been worked out yet, but the platform and software version should be in there somewhere.
+    pub device_info_response: Option<Vec<u8>>,
+    pub source: FirmwareSource,
+}
+
+impl FirmwareEntry {
+    fn from_device_info(device_info: DeviceInfo) -> FirmwareEntry {
+        // Missing CMC properties are kept as "N/A" in the cache.
+        let known = |value: Option<String>| value.filter(|value| value != "N/A");
+        FirmwareEntry {
+            version: device_info
+                .arc_capabilities
+                .map(|arc_capabilities| arc_capabilities.version),
+            device_name: device_info.name,
+            addresses: device_info.addresses,
+            manufacturer: known(device_info.manufacturer),
+            model: known(device_info.model),
+            router_vers: known(device_info.router_vers),
+            device_info_response: None,
+            source: FirmwareSource::FromCache,
+        }
+    }
+
+    fn is_complete(&self) -> bool {
+        self.model.is_some() && self.version.is_some()
+    }
+}
+
+/// Devices of the same model running the same Dante version, see group_firmware().
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FirmwareGroup {
+    pub model: Option<String>,
+    pub router_vers: Option<String>,
+    pub device_names: Vec<String>,
+}
+
+/// Groups the entries of a firmware_report() by model and version, in the order of the report. Handy for planning upgrades, since each group is one model on one version.
+pub fn group_firmware(entries: &[FirmwareEntry]) -> Vec<FirmwareGroup> {
+    let mut groups: Vec<FirmwareGroup> = Vec::new();
+    for entry in entries {
+        match groups.last_mut() {
+            Some(group) if group.model == entry.model && group.router_vers == entry.router_vers => {
+                group.device_names.push(entry.device_name.clone())
+            }
+            _ => groups.push(FirmwareGroup {
+                model: entry.model.clone(),
+                router_vers: entry.router_vers.clone(),
+                device_names: vec![entry.device_name.clone()],
+            }),
+        }
+    }
+    groups
+}
+
+impl DanteDeviceManager {
+    /// Reports the model and Dante software version of every discovered device, sorted by model, then version, then name, with whatever isn't known last. Both come from discovery, a device missing either is sent the device info query so it's at least known whether it's there, one every 50ms. That query's response isn't decoded yet, so it's handed back raw in device_info_response, and uptime isn't known at all until it is. A device that doesn't answer is marked Unreachable and keeps what discovery knew.
+    pub fn firmware_report(&mut self) -> Vec<FirmwareEntry> {
+        let mut entries: Vec<FirmwareEntry> = self
+            .get_device_infos()
+            .into_iter()
+            .map(FirmwareEntry::from_device_info)
+            .collect();
+
+        let mut first_query = true;
+        for entry in entries.iter_mut().filter(|entry| !entry.is_complete()) {
+            if !first_query {
+                sleep(FIRMWARE_QUERY_PACING);
+            }
+            first_query = false;
+            match self.query_device_info(&entry.device_name) {
+                Ok(response) => {
+                    debug!(
+                        "Device info of {}: {}",
+                        entry.device_name,
+                        hex::encode(&response)
+                    );
+                    entry.device_info_response = Some(response);
+                    entry.source = FirmwareSource::Queried;
+                }
+                Err(error) => {
+                    warn!(
+                        "Couldn't query device info of {}: {}",
+                        entry.device_name, error
+                    );
+                    entry.source = FirmwareSource::Unreachable;
+                }
+            }
+        }
+
+        entries.sort_by(|a, b| {
+            (a.model.is_none(), &a.model, a.version.is_none(), &a.version)
+                .cmp(&(b.model.is_none(), &b.model, b.version.is_none(), &b.version))
+                .then_with(|| a.device_name.cmp(&b.device_name))
+        });
+        entries
+    }
+
+    fn query_device_info(&mut self, device_name: &str) -> Result<Vec<u8>, QueryError> {
+        let (device_ip, port) = {
+            let device_list = self.device_list.lock().unwrap();
+            (
+                device_list.get_device_ip(device_name)?,
+                device_list.get_arc_command_port(device_name),
+            )
+        };
+        let command = self.make_dante_command(COMMAND_DEVICEINFO, &[])?;
+        Self::query_address(
+            &self.metrics,
+            &self.packet_capture,
+            self.transport(),
+            &device_ip,
+            port,
+            &command,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ArcCapabilities;
+
+    fn device_info(name: &str, model: &str, router_vers: &str) -> DeviceInfo {
+        DeviceInfo {
+            name: name.to_owned(),
+            hostname: None,
+            dbc_connected: true,
+            cmc_connected: true,
+            arc_connected: true,
+            chan_connected: true,
+            untracked_services: Vec::new(),
+            unresolved: false,
+            manually_added: false,
+            addresses: vec![Ipv4Addr::new(10, 0, 0, 1)],
+            arc_port: Some(4440),
+            arc_addresses: Some(vec![Ipv4Addr::new(10, 0, 0, 1)]),
+            dbc_port: None,
+            cmc_port: None,
+            bogus_ports: Vec::new(),
+            id: None,
+            manufacturer: Some("Audinate".to_owned()),
+            model: Some(model.to_owned()),
+            router_vers: Some(router_vers.to_owned()),
+            router_info: None,
+            arc_capabilities: ArcCapabilities::parse(router_vers),
+            detected_version: None,
+            version_override: None,
+            channel_count_hint: None,
+            channels: Vec::new(),
+            channel_name_conflicts: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn entry_of_brooklyn_device() {
+        let entry =
+            FirmwareEntry::from_device_info(device_info("Stage-Box", "Brooklyn II", "4.4.1.3"));
+        assert_eq!(entry.model.as_deref(), Some("Brooklyn II"));
+        assert_eq!(entry.router_vers.as_deref(), Some("4.4.1.3"));
+        assert_eq!(entry.version, Some(vec![4, 4, 1, 3]));
+        assert_eq!(entry.source, FirmwareSource::FromCache);
+        assert!(entry.is_complete());
+    }
+
+    #[test]
+    fn entry_of_ultimo_device() {
+        let entry =
+            FirmwareEntry::from_device_info(device_info("AVIO-USB", "ADP-USB-AU-2X2", "4.2.1.3"));
+        assert_eq!(entry.version, Some(vec![4, 2, 1, 3]));
+        assert!(entry.is_complete());
+    }
+
+    #[test]
+    fn entry_of_dvs_without_cmc_properties() {
+        // The cache keeps missing CMC properties as "N/A", the report leaves them out.
+        let entry = FirmwareEntry::from_device_info(device_info("Laptop-DVS", "N/A", "N/A"));
+        assert_eq!(entry.model, None);
+        assert_eq!(entry.router_vers, None);
+        assert_eq!(entry.version, None);
+        assert!(!entry.is_complete());
+    }
+
+    #[test]
+    fn groups_follow_report_order() {
+        let entries: Vec<FirmwareEntry> = [
+            ("Amp-1", "Brooklyn II", "4.4.1.3"),
+            ("Amp-2", "Brooklyn II", "4.4.1.3"),
+            ("AVIO-1", "ADP-USB-AU-2X2", "4.2.1.3"),
+            ("Amp-3", "Brooklyn II", "4.4.1.3"),
+        ]
+        .into_iter()
+        .map(|(name, model, router_vers)| {
+            FirmwareEntry::from_device_info(device_info(name, model, router_vers))
+        })
+        .collect();
+        let groups = group_firmware(&entries);
+        let device_names: Vec<&[String]> = groups
+            .iter()
+            .map(|group| group.device_names.as_slice())
+            .collect();
+        assert_eq!(
+            device_names,
+            vec![
+                &["Amp-1".to_owned(), "Amp-2".to_owned()][..],
+                &["AVIO-1".to_owned()][..],
+                &["Amp-3".to_owned()][..],
+            ]
+        );
+        assert_eq!(groups[1].router_vers.as_deref(), Some("4.2.1.3"));
+    }
+}
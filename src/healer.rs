@@ -0,0 +1,552 @@
+use crate::{
+    as_dante_name, CancellationToken, ChannelRef, ClearSubscriptionError, DanteDeviceEvent,
+    DanteDeviceManager, MakeSubscriptionError, Partial, QueryError, Route, RoutingMatrix,
+    SubscriptionEntry,
+};
+use log::{debug, info, warn};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{sleep, JoinHandle};
+use std::time::{Duration, Instant, SystemTime};
+
+/// How long corrections are kept around for RoutingHealer::corrections_since().
+const CORRECTION_HISTORY: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// A route that keeps failing is tried at most every this many passes.
+const MAX_BACKOFF_PASSES: u32 = 32;
+
+/// A route the healer found drifted from the desired routing and put back.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RouteCorrection {
+    pub timestamp: Instant,
+    pub wall_time: SystemTime,
+    pub rx_device: String,
+    pub rx_channel: ChannelRef,
+    /// What the read-back said the channel was subscribed to, None if it wasn't.
+    pub found: Option<(String, String)>,
+    /// What it was set back to, None if it was cleared.
+    pub applied: Option<(String, String)>,
+}
+
+/// Time between the devices of a healer pass or of RoutingHealer::apply_routing_matrix(), so a big matrix doesn't read back and command every device at once, and discovery and other callers get the manager in between.
+const HEALER_DEVICE_PACING: Duration = Duration::from_millis(50);
+
+#[derive(Default)]
+struct HealerState {
+    stopped: AtomicBool,
+    paused: AtomicBool,
+    /// apply_routing_matrix() calls in progress.
+    applying: AtomicUsize,
+    desired: Mutex<RoutingMatrix>,
+    corrections: Mutex<VecDeque<RouteCorrection>>,
+}
+
+impl HealerState {
+    /// Whether the healer should stay out of the way, for pause() or an apply_routing_matrix() in progress.
+    fn is_held(&self) -> bool {
+        self.paused.load(Ordering::SeqCst) || self.applying.load(Ordering::SeqCst) > 0
+    }
+
+    /// Holds the healer off until the returned guard is dropped, also when an apply_routing_matrix() panics part way.
+    fn hold_for_apply(&self) -> ApplyGuard<'_> {
+        self.applying.fetch_add(1, Ordering::SeqCst);
+        ApplyGuard { state: self }
+    }
+
+    fn record(&self, corrections: Vec<RouteCorrection>) {
+        let mut history = self.corrections.lock().unwrap();
+        history.extend(corrections);
+        while history
+            .front()
+            .is_some_and(|correction| correction.timestamp.elapsed() > CORRECTION_HISTORY)
+        {
+            history.pop_front();
+        }
+    }
+}
+
+/// An apply_routing_matrix() in progress, see HealerState::hold_for_apply().
+struct ApplyGuard<'a> {
+    state: &'a HealerState,
+}
+
+impl Drop for ApplyGuard<'_> {
+    fn drop(&mut self) {
+        self.state.applying.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// The routes of a matrix by RX device, in the order of the matrix.
+fn routes_by_device(matrix: &RoutingMatrix) -> Vec<(String, Vec<Route>)> {
+    let mut devices: Vec<(String, Vec<Route>)> = Vec::new();
+    for route in matrix.routes() {
+        match devices.last_mut() {
+            Some((rx_device, routes)) if *rx_device == route.rx_device => routes.push(route),
+            _ => devices.push((route.rx_device.clone(), vec![route])),
+        }
+    }
+    devices
+}
+
+/// Why a route of RoutingHealer::apply_routing_matrix() wasn't applied.
+#[derive(thiserror::Error, Debug)]
+pub enum ApplyRouteError {
+    #[error("couldn't read back the subscriptions of the rx device")]
+    ReadBack(#[source] QueryError),
+    #[error("the rx device has no channel {0:?}")]
+    ChannelNotFound(ChannelRef),
+    /// The route names an RX channel more than one channel of the device is called.
+    #[error("more than one rx channel is called {0:?}")]
+    AmbiguousChannel(ChannelRef),
+    /// The address or Dante version of the RX device isn't known.
+    #[error("couldn't resolve the rx device")]
+    Resolve(#[source] MakeSubscriptionError),
+    #[error("couldn't subscribe the rx channel")]
+    Subscribe(#[source] MakeSubscriptionError),
+    #[error("couldn't clear the rx channel")]
+    Clear(#[source] ClearSubscriptionError),
+}
+
+/// Keeps the routing of the network at a desired RoutingMatrix, for unattended installs where a device reboot or someone with Dante Controller can undo a route. Every interval it reads back the devices in the matrix and re-applies the routes that drifted, emitting a DanteDeviceEvent::RouteCorrected for each. A route that can't be read back or applied is tried less and less often, down to every 32nd pass, until it works again.
+///
+/// The healer locks the manager for one device at a time and waits 50ms between devices, so discovery and other callers aren't held up for a whole pass. Changing routes through apply_routing_matrix() keeps it out of the way until the batch is done, and pause() keeps it out for longer, like while someone is making changes by hand. Each device is read back at most once per pass and gets at most one command per route, so it doesn't add more traffic than the routes it corrects. The healer stops when stop() is called or it's dropped.
+pub struct RoutingHealer {
+    state: Arc<HealerState>,
+    manager: Arc<Mutex<DanteDeviceManager>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl RoutingHealer {
+    pub fn spawn(
+        manager: Arc<Mutex<DanteDeviceManager>>,
+        desired: RoutingMatrix,
+        interval: Duration,
+    ) -> RoutingHealer {
+        let state = Arc::new(HealerState {
+            desired: Mutex::new(desired),
+            ..HealerState::default()
+        });
+        let thread_state = state.clone();
+        let thread_manager = manager.clone();
+        let thread = std::thread::spawn(move || {
+            debug!("Starting routing healer thread");
+            let mut backoff: HashMap<(String, ChannelRef), (u32, u32)> = HashMap::new();
+            let mut last_pass: Option<Instant> = None;
+            while !thread_state.stopped.load(Ordering::SeqCst) {
+                let due = last_pass.is_none_or(|last_pass| last_pass.elapsed() >= interval);
+                if !due || thread_state.is_held() {
+                    sleep(Duration::from_millis(100));
+                    continue;
+                }
+                last_pass = Some(Instant::now());
+                let desired = thread_state.desired.lock().unwrap().clone();
+                for (index, (rx_device, routes)) in
+                    routes_by_device(&desired).into_iter().enumerate()
+                {
+                    // The rest of the pass waits for the next one.
+                    if thread_state.stopped.load(Ordering::SeqCst) || thread_state.is_held() {
+                        break;
+                    }
+                    if index > 0 {
+                        sleep(HEALER_DEVICE_PACING);
+                    }
+                    let corrections = thread_manager.lock().unwrap().heal_device_routing(
+                        &rx_device,
+                        routes,
+                        &mut backoff,
+                    );
+                    thread_state.record(corrections);
+                }
+            }
+        });
+        RoutingHealer {
+            state,
+            manager,
+            thread: Some(thread),
+        }
+    }
+
+    /// Stops correcting routes until resume() is called. A device that's being corrected finishes.
+    pub fn pause(&self) {
+        self.state.paused.store(true, Ordering::SeqCst);
+    }
+
+    pub fn resume(&self) {
+        self.state.paused.store(false, Ordering::SeqCst);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.state.paused.load(Ordering::SeqCst)
+    }
+
+    /// Applies every route of a matrix, subscribing the RX channels that have a route and clearing the cleared ones, with the healer held off until the whole batch is done so it doesn't put routes back halfway through. The routes then become part of the routing the healer keeps, replacing the ones it had for the same RX channels. RX channels are looked up in a fresh read-back of their device, by id or by name. Devices are done one after the other with the same pacing as a healer pass, and a device stops at its first route that fails without stopping the others. Returns how many routes of each RX device were applied, by name.
+    pub fn apply_routing_matrix(
+        &self,
+        matrix: &RoutingMatrix,
+    ) -> BTreeMap<String, Result<u16, ApplyRouteError>> {
+        self.apply_routing_matrix_cancellable(matrix, &CancellationToken::new())
+            .value
+    }
+
+    /// apply_routing_matrix() that stops before the next RX device once cancel is cancelled. The devices it didn't get to are left out of the result, and their routes don't become part of the routing the healer keeps.
+    pub fn apply_routing_matrix_cancellable(
+        &self,
+        matrix: &RoutingMatrix,
+        cancel: &CancellationToken,
+    ) -> Partial<BTreeMap<String, Result<u16, ApplyRouteError>>> {
+        let _held = self.state.hold_for_apply();
+        let mut results = BTreeMap::new();
+        let mut cancelled = false;
+        for (index, (rx_device, routes)) in routes_by_device(matrix).into_iter().enumerate() {
+            if index > 0 {
+                sleep(HEALER_DEVICE_PACING);
+            }
+            if cancel.is_cancelled() {
+                cancelled = true;
+                break;
+            }
+            {
+                let mut desired = self.state.desired.lock().unwrap();
+                for route in &routes {
+                    match &route.tx {
+                        Some((tx_device, tx_channel)) => desired.set_route(
+                            &route.rx_device,
+                            route.rx_channel.clone(),
+                            tx_device,
+                            tx_channel,
+                        ),
+                        None => desired.clear_route(&route.rx_device, route.rx_channel.clone()),
+                    }
+                }
+            }
+            let result = self
+                .manager
+                .lock()
+                .unwrap()
+                .apply_device_routing(&rx_device, routes);
+            results.insert(rx_device, result);
+        }
+        Partial {
+            value: results,
+            cancelled,
+        }
+    }
+
+    /// Every correction made in the last period, oldest first. Corrections are kept for 24 hours.
+    pub fn corrections_since(&self, period: Duration) -> Vec<RouteCorrection> {
+        self.state
+            .corrections
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|correction| correction.timestamp.elapsed() <= period)
+            .cloned()
+            .collect()
+    }
+
+    /// Stops the healer and waits for a device that's being corrected to finish.
+    pub fn stop(mut self) {
+        self.stop_and_join();
+    }
+
+    fn stop_and_join(&mut self) {
+        self.state.stopped.store(true, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                warn!("Routing healer thread panicked");
+            }
+        }
+    }
+}
+
+impl Drop for RoutingHealer {
+    fn drop(&mut self) {
+        self.stop_and_join();
+    }
+}
+
+/// The read-back entry of the RX channel of a route.
+fn find_rx_channel<'a>(
+    subscriptions: &'a [SubscriptionEntry],
+    rx_channel: &ChannelRef,
+) -> Result<&'a SubscriptionEntry, ApplyRouteError> {
+    let mut entries = subscriptions
+        .iter()
+        .filter(|subscription| match rx_channel {
+            ChannelRef::Id(id) => subscription.rx_channel_id == *id,
+            ChannelRef::Name(name) => subscription.rx_channel_name == *name,
+        });
+    match (entries.next(), entries.next()) {
+        (Some(entry), None) => Ok(entry),
+        (Some(_), Some(_)) => Err(ApplyRouteError::AmbiguousChannel(rx_channel.clone())),
+        (None, _) => Err(ApplyRouteError::ChannelNotFound(rx_channel.clone())),
+    }
+}
+
+impl DanteDeviceManager {
+    /// The part of a healer pass for one RX device, routes being the routes of the desired matrix for it. backoff holds (failures, passes to skip) of the routes that failed.
+    fn heal_device_routing(
+        &mut self,
+        rx_device: &str,
+        routes: Vec<Route>,
+        backoff: &mut HashMap<(String, ChannelRef), (u32, u32)>,
+    ) -> Vec<RouteCorrection> {
+        let mut read_back: Option<Option<Vec<SubscriptionEntry>>> = None;
+        let mut corrections = Vec::new();
+        for route in routes {
+            let key = (route.rx_device.clone(), route.rx_channel.clone());
+            if let Some((_, skip)) = backoff.get_mut(&key) {
+                if *skip > 0 {
+                    *skip -= 1;
+                    continue;
+                }
+            }
+
+            let subscriptions =
+                read_back.get_or_insert_with(|| match self.get_subscriptions(rx_device, true) {
+                    Ok(subscriptions) => Some(subscriptions.value),
+                    Err(error) => {
+                        warn!("Routing healer couldn't read back {}: {}", rx_device, error);
+                        None
+                    }
+                });
+            let Some(subscriptions) = subscriptions else {
+                Self::back_off(backoff, key);
+                continue;
+            };
+            let entry = match find_rx_channel(subscriptions, &route.rx_channel) {
+                Ok(entry) => entry.clone(),
+                Err(error) => {
+                    warn!("Routing healer can't correct {}: {}", rx_device, error);
+                    Self::back_off(backoff, key);
+                    continue;
+                }
+            };
+
+            let found = entry.tx_device.clone().zip(entry.tx_channel.clone());
+            if found == route.tx {
+                backoff.remove(&key);
+                continue;
+            }
+
+            if let Err(error) = self.apply_route(rx_device, entry.rx_channel_id, route.tx.as_ref())
+            {
+                warn!(
+                    "Routing healer couldn't set {} channel {}: {}",
+                    rx_device, entry.rx_channel_id, error
+                );
+                Self::back_off(backoff, key);
+                continue;
+            }
+            backoff.remove(&key);
+            info!(
+                "Routing healer put back {} channel {:?}: found {:?}, applied {:?}",
+                route.rx_device, route.rx_channel, found, route.tx
+            );
+            let correction = RouteCorrection {
+                timestamp: Instant::now(),
+                wall_time: SystemTime::now(),
+                rx_device: route.rx_device,
+                rx_channel: route.rx_channel,
+                found,
+                applied: route.tx,
+            };
+            self.device_list
+                .lock()
+                .unwrap()
+                .events
+                .emit(DanteDeviceEvent::RouteCorrected(correction.clone()));
+            corrections.push(correction);
+        }
+        corrections
+    }
+
+    /// The part of RoutingHealer::apply_routing_matrix() for one RX device. Stops at the first route that fails. Returns how many were applied.
+    fn apply_device_routing(
+        &mut self,
+        rx_device: &str,
+        routes: Vec<Route>,
+    ) -> Result<u16, ApplyRouteError> {
+        let subscriptions = self
+            .get_subscriptions(rx_device, true)
+            .map_err(ApplyRouteError::ReadBack)?
+            .value;
+        let mut applied = 0;
+        for route in routes {
+            let rx_channel_id = find_rx_channel(&subscriptions, &route.rx_channel)?.rx_channel_id;
+            self.apply_route(rx_device, rx_channel_id, route.tx.as_ref())?;
+            applied += 1;
+        }
+        Ok(applied)
+    }
+
+    /// Subscribes the RX channel to tx, or clears it when tx is None.
+    fn apply_route(
+        &mut self,
+        rx_device: &str,
+        rx_channel_id: u16,
+        tx: Option<&(String, String)>,
+    ) -> Result<(), ApplyRouteError> {
+        let (rx_device_ip, version) = self
+            .resolve_rx_device(rx_device)
+            .map_err(ApplyRouteError::Resolve)?;
+        match tx {
+            Some((tx_device, tx_channel)) => self
+                .make_subscription_unchecked(
+                    &version,
+                    &rx_device_ip,
+                    rx_channel_id,
+                    as_dante_name(tx_device).map_err(ApplyRouteError::Subscribe)?,
+                    as_dante_name(tx_channel).map_err(ApplyRouteError::Subscribe)?,
+                )
+                .map_err(ApplyRouteError::Subscribe),
+            None => self
+                .clear_subscription(&version, &rx_device_ip, rx_channel_id)
+                .map_err(ApplyRouteError::Clear),
+        }
+    }
+
+    /// Doubles the passes a failing route is skipped for, up to MAX_BACKOFF_PASSES.
+    fn back_off(
+        backoff: &mut HashMap<(String, ChannelRef), (u32, u32)>,
+        key: (String, ChannelRef),
+    ) {
+        let (failures, skip) = backoff.entry(key).or_insert((0, 0));
+        *failures += 1;
+        *skip = (1u32 << (*failures).min(5)).min(MAX_BACKOFF_PASSES) - 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn routes_are_grouped_by_rx_device() {
+        let mut matrix = RoutingMatrix::new();
+        matrix.set_route("Amp", ChannelRef::Id(2), "Console", "Main R");
+        matrix.set_route("Recorder", ChannelRef::Id(1), "Console", "Main L");
+        matrix.clear_route("Amp", ChannelRef::Id(1));
+        let devices: Vec<(String, usize)> = routes_by_device(&matrix)
+            .into_iter()
+            .map(|(rx_device, routes)| (rx_device, routes.len()))
+            .collect();
+        assert_eq!(
+            devices,
+            vec![("Amp".to_owned(), 2), ("Recorder".to_owned(), 1)]
+        );
+    }
+
+    #[test]
+    fn apply_routing_matrix_reports_each_device_and_updates_desired() {
+        let manager = Arc::new(Mutex::new(DanteDeviceManager::new()));
+        let mut desired = RoutingMatrix::new();
+        desired.set_route("Nowhere", ChannelRef::Id(1), "Console", "Main L");
+        desired.set_route("Nowhere", ChannelRef::Id(2), "Console", "Main R");
+        let healer = RoutingHealer::spawn(manager, desired, Duration::from_secs(3600));
+
+        let mut matrix = RoutingMatrix::new();
+        matrix.set_route("Nowhere", ChannelRef::Id(1), "Playback", "Left");
+        matrix.clear_route("Elsewhere", ChannelRef::Name("In 1".to_owned()));
+        let results = healer.apply_routing_matrix(&matrix);
+
+        assert_eq!(results.len(), 2);
+        for (rx_device, result) in &results {
+            assert!(
+                matches!(
+                    result,
+                    Err(ApplyRouteError::ReadBack(QueryError::DeviceNotFound(device_name)))
+                        if device_name == rx_device
+                ),
+                "{}: {:?}",
+                rx_device,
+                result
+            );
+        }
+        assert!(!healer.state.is_held());
+        let desired = healer.state.desired.lock().unwrap().clone();
+        assert_eq!(
+            desired.get("Nowhere", &ChannelRef::Id(1)),
+            Some(&("Playback".to_owned(), "Left".to_owned()))
+        );
+        assert_eq!(
+            desired.get("Nowhere", &ChannelRef::Id(2)),
+            Some(&("Console".to_owned(), "Main R".to_owned()))
+        );
+        assert_eq!(desired.len(), 3);
+        healer.stop();
+    }
+
+    #[test]
+    fn cancelled_apply_routing_matrix_leaves_desired_alone() {
+        let manager = Arc::new(Mutex::new(DanteDeviceManager::new()));
+        let healer = RoutingHealer::spawn(manager, RoutingMatrix::new(), Duration::from_secs(3600));
+        let mut matrix = RoutingMatrix::new();
+        matrix.set_route("Nowhere", ChannelRef::Id(1), "Playback", "Left");
+        matrix.clear_route("Elsewhere", ChannelRef::Id(1));
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+        let results = healer.apply_routing_matrix_cancellable(&matrix, &cancel);
+        assert!(results.cancelled);
+        assert!(results.value.is_empty());
+        assert!(!healer.state.is_held());
+        assert_eq!(healer.state.desired.lock().unwrap().len(), 0);
+        healer.stop();
+    }
+
+    #[test]
+    fn panic_while_applying_releases_the_healer() {
+        let manager = Arc::new(Mutex::new(DanteDeviceManager::new()));
+        let healer = RoutingHealer::spawn(
+            manager.clone(),
+            RoutingMatrix::new(),
+            Duration::from_secs(3600),
+        );
+        // Poison the manager, so applying panics on locking it.
+        let _ = std::thread::spawn(move || {
+            let _manager = manager.lock().unwrap();
+            panic!("poisoning the manager");
+        })
+        .join();
+        let mut matrix = RoutingMatrix::new();
+        matrix.set_route("Nowhere", ChannelRef::Id(1), "Playback", "Left");
+        let applied = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            healer.apply_routing_matrix(&matrix)
+        }));
+        assert!(applied.is_err());
+        assert!(!healer.state.is_held());
+    }
+
+    #[test]
+    fn find_rx_channel_by_id_and_name() {
+        let entry = |rx_channel_id: u16, rx_channel_name: &str| SubscriptionEntry {
+            rx_channel_id,
+            rx_channel_name: rx_channel_name.to_owned(),
+            tx_device: None,
+            tx_channel: None,
+            status: 0,
+        };
+        let subscriptions = vec![entry(1, "In"), entry(2, "In"), entry(3, "Aux")];
+        assert_eq!(
+            find_rx_channel(&subscriptions, &ChannelRef::Id(2))
+                .unwrap()
+                .rx_channel_id,
+            2
+        );
+        assert_eq!(
+            find_rx_channel(&subscriptions, &ChannelRef::Name("Aux".to_owned()))
+                .unwrap()
+                .rx_channel_id,
+            3
+        );
+        assert!(matches!(
+            find_rx_channel(&subscriptions, &ChannelRef::Name("In".to_owned())),
+            Err(ApplyRouteError::AmbiguousChannel(_))
+        ));
+        assert!(matches!(
+            find_rx_channel(&subscriptions, &ChannelRef::Id(4)),
+            Err(ApplyRouteError::ChannelNotFound(ChannelRef::Id(4)))
+        ));
+    }
+}
@@ -1,31 +1,220 @@
+mod audio_group;
+mod cancellation;
+mod capture;
+mod command_socket;
+mod compatibility;
+mod device_info;
+mod device_model;
+mod discovery_event;
+#[cfg(feature = "async")]
+mod event_stream;
+mod events;
+mod firmware;
+mod healer;
+mod lock_probe;
+mod metrics;
+mod network_graph;
+mod network_health;
+mod other_services;
+mod query_cache;
+mod reachability;
+mod reader;
+mod reconnect;
+mod redundancy;
+mod routing;
+mod routing_matrix;
+#[cfg(feature = "aes67")]
+mod sap;
+#[cfg(feature = "aes67")]
+mod sdp;
+mod snapshot;
+mod tags;
+mod topology;
+mod unicast_dns;
+mod unresolved;
+
+#[cfg(feature = "serde")]
+pub use crate::audio_group::AudioGroupLoadError;
+pub use crate::audio_group::{AudioGroup, AudioGroupError, GroupRoute};
+pub use crate::cancellation::{CancellationToken, Partial};
+use crate::capture::PacketCapture;
+pub use crate::capture::{
+    pcap_header, to_pcap_file, PacketCaptureHandle, PacketDirection, PacketRecord,
+};
+pub use crate::command_socket::AddressTranslator;
+use crate::command_socket::{is_receive_timeout, send_with_backoff, CommandSocket, Transport};
+pub use crate::compatibility::VersionCompatibility;
+pub use crate::device_info::{
+    ArcCapabilities, CompactDeviceInfo, DeviceInfo, ServiceStatus, VerboseDeviceInfo,
+};
+pub use crate::device_model::DeviceModelInfo;
+use crate::discovery_event::DiscoveryEvent;
+#[cfg(any(test, feature = "test-util"))]
+pub use crate::discovery_event::{InjectError, PreparedEvent, SyntheticEvent};
+#[cfg(feature = "async")]
+pub use crate::event_stream::DeviceEventStream;
+pub use crate::events::{ChannelEvent, DanteDeviceEvent, WatchHandle};
+use crate::events::{ChannelWatchers, EventSenders};
+pub use crate::firmware::{group_firmware, FirmwareEntry, FirmwareGroup, FirmwareSource};
+pub use crate::healer::{ApplyRouteError, RouteCorrection, RoutingHealer};
+#[cfg(any(test, feature = "test-util"))]
+use crate::lock_probe::LockProbe;
+use crate::metrics::Metrics;
+pub use crate::metrics::{CommandLatency, LatencySample, MetricsSnapshot};
+pub use crate::network_graph::GraphFormat;
+use crate::network_health::{NetworkWatchdog, WatchdogState};
+pub use crate::other_services::{OtherService, ServiceResolver};
+pub use crate::query_cache::{Freshness, QueryResult};
+pub use crate::reachability::{AddressProbe, REACHABILITY_TTL};
+pub use crate::reader::ParseError;
+use crate::reader::Reader;
+use crate::reconnect::BrowseRetry;
+pub use crate::reconnect::{ReconnectPolicy, ServiceHealth, ServicesStatus};
+pub use crate::redundancy::RedundancyHeuristic;
+pub use crate::routing::{PassiveSubscriptionEntry, SubscriptionConflict};
+pub use crate::routing_matrix::{ChannelRef, Route, RouteChange, RouteChangeKind, RoutingMatrix};
+#[cfg(feature = "aes67")]
+pub use crate::sdp::{parse_sdp, Aes67Stream, SdpError};
+pub use crate::snapshot::{ImportError, NetworkSnapshot, SnapshotDetail};
+use crate::tags::TagKey;
+use crate::topology::AddressSighting;
+pub use crate::topology::{DuplicateNameEntry, Severity, TopologyWarning};
+pub use crate::unicast_dns::ProbeError;
+pub use crate::unresolved::{UnresolvedReason, UnresolvedRoute};
 use crate::DanteDeviceEncoding::{PCM16, PCM24, PCM32};
 use ascii::AsciiStr;
-use bytes::BytesMut;
-use log::{debug, error, info, warn};
-use mdns_sd::{ServiceDaemon, ServiceEvent};
-use std::collections::{HashMap, HashSet};
-use std::error::Error;
-use std::fmt::{Debug, Display, Formatter, Write};
-use std::hash::{Hash, Hasher};
-use std::net::{Ipv4Addr, UdpSocket};
+use bytes::{Bytes, BytesMut};
+use log::{debug, error, info, log_enabled, warn, Level};
+use mdns_sd::{ServiceDaemon, ServiceEvent, ServiceInfo};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt::{Debug, Display, Formatter};
+use std::io::Write;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::Receiver;
 use std::sync::{Arc, Mutex};
-use std::thread::sleep;
-use std::time::Duration;
+use std::thread::{sleep, JoinHandle};
+use std::time::{Duration, Instant};
+
+const CMC_SERVICE: &str = "_netaudio-cmc._udp.local.";
+const DBC_SERVICE: &str = "_netaudio-dbc._udp.local.";
+const ARC_SERVICE: &str = "_netaudio-arc._udp.local.";
+const CHAN_SERVICE: &str = "_netaudio-chan._udp.local.";
+
+/// The four mdns services Dante devices advertise.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum DanteService {
+    Dbc,
+    Cmc,
+    Arc,
+    Chan,
+}
+
+impl DanteService {
+    pub const ALL: [DanteService; 4] = [
+        DanteService::Dbc,
+        DanteService::Cmc,
+        DanteService::Arc,
+        DanteService::Chan,
+    ];
+
+    /// The mdns service type, like "_netaudio-arc._udp.local.".
+    pub fn service_type(&self) -> &'static str {
+        match self {
+            DanteService::Dbc => DBC_SERVICE,
+            DanteService::Cmc => CMC_SERVICE,
+            DanteService::Arc => ARC_SERVICE,
+            DanteService::Chan => CHAN_SERVICE,
+        }
+    }
+}
+
+impl Display for DanteService {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            DanteService::Dbc => "DBC",
+            DanteService::Cmc => "CMC",
+            DanteService::Arc => "ARC",
+            DanteService::Chan => "CHAN",
+        })
+    }
+}
+
+/// Port ARC commands are sent to when the device's ARC port hasn't been discovered.
+const DEFAULT_ARC_PORT: u16 = 4440;
+
+/// Dante devices don't listen on well-known ports, so a port below this in an mdns record is bogus. Some virtual devices advertise 0.
+const MIN_DEVICE_PORT: u16 = 1024;
+
+/// None for a port no device would really listen on, see MIN_DEVICE_PORT.
+fn port_if_valid(port: u16) -> Option<u16> {
+    (port >= MIN_DEVICE_PORT).then_some(port)
+}
+
+/// More channels than any Dante device has. Anything past this is a misbehaving device and is ignored rather than cached.
+const MAX_CHANNELS_PER_DEVICE: usize = 1024;
+
+/// Channels listed in each of get_device_descriptions(). Dante Via or a virtual soundcard can have hundreds, and a description that long is no use for reading.
+pub const DESCRIPTION_MAX_CHANNELS: usize = 64;
+
+/// How long to wait for a device to answer a query, unless set otherwise with set_response_timeout(). Devices answer within a few milliseconds on a healthy network.
+pub const DEFAULT_RESPONSE_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// How long a read-back that timed out waits for a refresh to turn up new addresses before giving up.
+const REFRESH_WAIT: Duration = Duration::from_secs(3);
+
+/// How long a found device may go without any of its services resolving before it's flagged unresolved.
+const DEFAULT_RESOLVE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long the cache of a disconnected device is kept by purge_stale_caches().
+const DEFAULT_CACHE_TTL: Duration = Duration::from_secs(5 * 60);
+
+/// How long a device that's gone from every service stays in the list, see set_removal_grace_period().
+const DEFAULT_REMOVAL_GRACE: Duration = Duration::from_secs(5);
+
+// CHAN TXT properties that have a field in CHANInfo. Everything else is in ChannelInfo::unknown_properties(), and so is an "en" that isn't a number.
+const CHAN_PARSED_PROPERTIES: [&str; 5] = ["id", "rate", "en", "latency_ns", "nchan"];
+
+/// The CHAN TXT properties that aren't parsed into a field, see CHAN_PARSED_PROPERTIES.
+fn unknown_chan_properties(properties: &HashMap<String, String>) -> HashMap<String, String> {
+    properties
+        .iter()
+        .filter(|(key, value)| {
+            !CHAN_PARSED_PROPERTIES.contains(&key.as_str())
+                || (key.as_str() == "en" && value.parse::<u8>().is_err())
+        })
+        .map(|(key, value)| (key.clone(), value.clone()))
+        .collect()
+}
+
+/// Largest command the manager sends by default, see set_max_datagram_size(). Leaves room for IP and UDP headers under a 1500 byte MTU, since devices don't reliably reassemble fragmented commands.
+const DEFAULT_MAX_DATAGRAM_SIZE: usize = 1400;
+
+/// How long read-back subscription data is reused before the device is queried again.
+const DEFAULT_ROUTING_MAX_AGE: Duration = Duration::from_secs(5);
 
-const CMC_SERVICE: &'static str = "_netaudio-cmc._udp.local.";
-const DBC_SERVICE: &'static str = "_netaudio-dbc._udp.local.";
-const ARC_SERVICE: &'static str = "_netaudio-arc._udp.local.";
-const CHAN_SERVICE: &'static str = "_netaudio-chan._udp.local.";
+/// How long channel counts and names queried from a device are reused. These only change along with the device's channels, which discovery notices.
+const DEFAULT_QUERY_MAX_AGE: Duration = Duration::from_secs(60);
 
+// Not all of these are wired up yet.
+#[allow(dead_code)]
 const DEVICE_CONTROL_PORT: u32 = 8800;
 const DEVICE_HEARTBEAT_PORT: u32 = 8708;
+#[allow(dead_code)]
 const DEVICE_INFO_PORT: u32 = 8702;
+#[allow(dead_code)]
 const DEVICE_INFO_SRC_PORT1: u32 = 1029;
+#[allow(dead_code)]
 const DEVICE_INFO_SRC_PORT2: u32 = 1030;
 
+// Clock settings like preferred master, QoS (DSCP), latency, sample rate and encoding settings go here, and so does the query for the rates and encodings a device supports, but I haven't captured what the commands look like yet.
+#[allow(dead_code)]
 const DEVICE_SETTINGS_PORT: u32 = 8700;
 
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum DanteVersion {
     Dante4_4_1_3,
     Dante4_2_1_3,
@@ -58,37 +247,168 @@ impl DanteVersion {
             _ => None,
         }
     }
+
+    /// Length of the arguments of a subscription command up to where the names start, 266 bytes for 4.4.1.3 and 322 for 4.2.1.3.
+    pub fn subscription_payload_size(&self) -> usize {
+        self.get_commands().subscription_layout.name_area_start
+    }
+
+    /// How many zeros a subscription command has between its fixed fields and the names, 248 bytes for 4.4.1.3 and 314 for 4.2.1.3. What the space is for isn't known, the devices just want it there.
+    pub fn packet_alignment_bytes(&self) -> usize {
+        let layout = self.get_commands().subscription_layout;
+        layout.name_area_start - layout.fixed_fields_length()
+    }
 }
 
 struct DanteVersionCommands {
     command_subscription: [u8; 2],
+    subscription_layout: SubscriptionLayout,
 }
 
 // Command IDs for different Dante Versions.
 const DANTECOMMANDS_4_4_1_3: DanteVersionCommands = DanteVersionCommands {
     command_subscription: [0x34, 0x10],
+    subscription_layout: SubscriptionLayout {
+        prefix: &[0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x08, 0x00, 0x20, 0x01],
+        channel_suffix: &[0x00, 0x03],
+        name_area_start: 266,
+    },
 };
 const DANTECOMMANDS_4_2_1_3: DanteVersionCommands = DanteVersionCommands {
     command_subscription: [0x30, 0x10],
+    subscription_layout: SubscriptionLayout {
+        prefix: &[0x10, 0x01],
+        channel_suffix: &[],
+        name_area_start: 322,
+    },
 };
 
+/// Where things go in the arguments of a subscription command of a Dante version: prefix, the RX channel id, channel_suffix, the offsets of the TX channel and device names, then zeros up to name_area_start where the names go, nul terminated. Offsets count from the start of the command, header included. Clearing a subscription is the same command with both offsets 0 and no names.
+struct SubscriptionLayout {
+    prefix: &'static [u8],
+    channel_suffix: &'static [u8],
+    /// Where the names start in the arguments.
+    name_area_start: usize,
+}
+
+impl SubscriptionLayout {
+    /// Length of everything before the zeros: prefix, RX channel id, channel_suffix and the two name offsets.
+    const fn fixed_fields_length(&self) -> usize {
+        self.prefix.len() + 2 + self.channel_suffix.len() + 4
+    }
+
+    /// The arguments subscribing the RX channel to tx (device, channel), or clearing it when tx is None.
+    fn args(&self, rx_channel_id: u16, tx: Option<(&AsciiStr, &AsciiStr)>) -> BytesMut {
+        let mut command_buffer = BytesMut::with_capacity(self.name_area_start + 64);
+        command_buffer.extend_from_slice(self.prefix);
+        command_buffer.extend_from_slice(&rx_channel_id.to_be_bytes());
+        command_buffer.extend_from_slice(self.channel_suffix);
+        let tx_channel_offset = (COMMAND_HEADER_LENGTH + self.name_area_start) as u16;
+        match tx {
+            Some((_, tx_channel)) => {
+                let tx_device_offset = tx_channel_offset + tx_channel.len() as u16 + 1;
+                command_buffer.extend_from_slice(&tx_channel_offset.to_be_bytes());
+                command_buffer.extend_from_slice(&tx_device_offset.to_be_bytes());
+            }
+            None => command_buffer.extend_from_slice(&[0x00; 4]),
+        }
+        command_buffer.resize(self.name_area_start, 0x00);
+        if let Some((tx_device, tx_channel)) = tx {
+            command_buffer.extend_from_slice(tx_channel.as_bytes());
+            command_buffer.extend_from_slice(&[0x00]);
+            command_buffer.extend_from_slice(tx_device.as_bytes());
+            command_buffer.extend_from_slice(&[0x00]);
+        }
+        command_buffer
+    }
+}
+
+// resize() in SubscriptionLayout::args() would cut the fixed fields short if they didn't fit before the names.
+const _: () = assert!(
+    DANTECOMMANDS_4_4_1_3
+        .subscription_layout
+        .fixed_fields_length()
+        <= DANTECOMMANDS_4_4_1_3.subscription_layout.name_area_start
+);
+const _: () = assert!(
+    DANTECOMMANDS_4_2_1_3
+        .subscription_layout
+        .fixed_fields_length()
+        <= DANTECOMMANDS_4_2_1_3.subscription_layout.name_area_start
+);
+
+/// Length of the header make_dante_command() puts in front of the arguments. Responses have one of the same length.
+const COMMAND_HEADER_LENGTH: usize = 10;
+
+// Version independent command IDs.
+const COMMAND_CHANNELCOUNT: [u8; 2] = [0x10, 0x00];
+const COMMAND_RXCHANNELNAMES: [u8; 2] = [0x30, 0x00];
+const COMMAND_TXCHANNELS: [u8; 2] = [0x20, 0x00];
+/// Should have the Dante platform and software version somewhere in its response, see firmware_report().
+const COMMAND_DEVICEINFO: [u8; 2] = 1003u16.to_be_bytes();
+
 // Still need to figure these out.
 /*
-const COMMAND_CHANNELCOUNT: [u8; 2] = 1000u16.to_be_bytes();
-const COMMAND_DEVICEINFO: [u8; 2] = 1003u16.to_be_bytes();
 const COMMAND_DEVICENAME: [u8; 2] = 1002u16.to_be_bytes();
-const COMMAND_RXCHANNELNAMES: [u8; 2] = 3000u16.to_be_bytes();
 const COMMAND_TXCHANNELNAMES: [u8; 2] = 2010u16.to_be_bytes();
 const COMMAND_SETRXCHANNELNAME: [u8; 2] = 12289u16.to_be_bytes();
 const COMMAND_SETTXCHANNELNAME: [u8; 2] = 8211u16.to_be_bytes();
 const COMMAND_SETDEVICENAME: [u8; 2] = 4097u16.to_be_bytes();
  */
 
-#[derive(Clone)]
-enum DanteDeviceEncoding {
+/// The sample encoding of a channel, from the "en" property of its CHAN record.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum DanteDeviceEncoding {
     PCM16,
     PCM24,
     PCM32,
+    /// An "en" value this crate doesn't know, like some AES67 interop channels advertise. Kept as is so it's not mistaken for a channel that doesn't say.
+    Other(u8),
+}
+
+impl DanteDeviceEncoding {
+    /// From the value of the "en" property.
+    fn from_en(en: u8) -> DanteDeviceEncoding {
+        match en {
+            16 => PCM16,
+            24 => PCM24,
+            32 => PCM32,
+            other => DanteDeviceEncoding::Other(other),
+        }
+    }
+}
+
+impl Display for DanteDeviceEncoding {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PCM16 => f.write_str("PCM16"),
+            PCM24 => f.write_str("PCM24"),
+            PCM32 => f.write_str("PCM32"),
+            DanteDeviceEncoding::Other(en) => write!(f, "{}", en),
+        }
+    }
+}
+
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+#[error("\"{0}\" isn't an encoding, expected PCM16, PCM24, PCM32 or an \"en\" value")]
+pub struct ParseEncodingError(String);
+
+/// Parses what Display gives, "PCM16", "PCM24" and "PCM32", as well as raw "en" values like "24" or "8".
+impl FromStr for DanteDeviceEncoding {
+    type Err = ParseEncodingError;
+
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        match string {
+            "PCM16" => Ok(PCM16),
+            "PCM24" => Ok(PCM24),
+            "PCM32" => Ok(PCM32),
+            _ => string
+                .parse()
+                .map(DanteDeviceEncoding::from_en)
+                .map_err(|_| ParseEncodingError(string.to_owned())),
+        }
+    }
 }
 
 #[derive(Clone)]
@@ -97,6 +417,15 @@ struct DBCInfo {
     port: u16,
 }
 
+impl From<&ServiceInfo> for DBCInfo {
+    fn from(service_info: &ServiceInfo) -> Self {
+        DBCInfo {
+            addresses: service_info.get_addresses().to_owned(),
+            port: service_info.get_port().to_owned(),
+        }
+    }
+}
+
 #[derive(Clone)]
 struct CMCInfo {
     addresses: HashSet<Ipv4Addr>,
@@ -104,6 +433,32 @@ struct CMCInfo {
     id: String,
     manufacturer: String,
     model: String,
+    /// "nchan" property, the number of channels the device has.
+    channel_count_hint: Option<u16>,
+}
+
+impl From<&ServiceInfo> for CMCInfo {
+    fn from(service_info: &ServiceInfo) -> Self {
+        CMCInfo {
+            addresses: service_info.get_addresses().to_owned(),
+            port: service_info.get_port().to_owned(),
+            id: match service_info.get_property("id") {
+                Some(id_property) => id_property.val_str().to_owned(),
+                None => "N/A".to_string(),
+            },
+            manufacturer: match service_info.get_property("mf") {
+                Some(mf_property) => mf_property.val_str().to_owned(),
+                None => "N/A".to_string(),
+            },
+            model: match service_info.get_property("model") {
+                Some(model_property) => model_property.val_str().to_owned(),
+                None => "N/A".to_string(),
+            },
+            channel_count_hint: service_info
+                .get_property_val_str("nchan")
+                .and_then(|nchan| nchan.parse().ok()),
+        }
+    }
 }
 
 #[derive(Clone)]
@@ -114,50 +469,232 @@ struct ARCInfo {
     router_info: String,
 }
 
-#[derive(Clone)]
+impl From<&ServiceInfo> for ARCInfo {
+    fn from(service_info: &ServiceInfo) -> Self {
+        ARCInfo {
+            addresses: service_info.get_addresses().to_owned(),
+            port: service_info.get_port().to_owned(),
+            router_vers: match service_info.get_property("router_vers") {
+                Some(router_vers_property) => router_vers_property.val_str().to_owned(),
+                None => "N/A".to_string(),
+            },
+            router_info: match service_info.get_property("router_info") {
+                Some(router_info_property) => router_info_property.val_str().to_owned(),
+                None => "N/A".to_string(),
+            },
+        }
+    }
+}
+
+#[derive(Clone, PartialEq)]
 struct CHANInfo {
     name: String,
     id: Option<u16>,
     sample_rate: Option<u32>,
     encoding: Option<DanteDeviceEncoding>,
     latency: Option<Duration>,
+    /// "nchan" property, the number of channels the device has.
+    channel_count_hint: Option<u16>,
+    /// Every TXT property of the record as it came in, parsed or not.
+    extra_properties: HashMap<String, String>,
 }
 
-impl PartialEq<Self> for CHANInfo {
-    fn eq(&self, other: &Self) -> bool {
-        self.id == other.id
+impl CHANInfo {
+    /// chan_name comes from the fullname, see split_chan_fullname().
+    fn from_service_info(chan_name: &str, service_info: &ServiceInfo) -> CHANInfo {
+        CHANInfo {
+            name: chan_name.to_owned(),
+            id: service_info.get_property("id").and_then(|id_property| {
+                let id = id_property.val_str().parse().ok();
+                if id.is_none() {
+                    warn!(
+                        "Ignoring unparseable chan id {:?} of {}",
+                        id_property.val_str(),
+                        service_info.get_fullname()
+                    );
+                }
+                id
+            }),
+            sample_rate: match service_info.get_property("rate") {
+                Some(rate_property) => rate_property.val_str().parse().ok(),
+                None => None,
+            },
+            encoding: service_info
+                .get_property("en")
+                .and_then(|encoding_property| {
+                    let encoding = encoding_property
+                        .val_str()
+                        .parse()
+                        .ok()
+                        .map(DanteDeviceEncoding::from_en);
+                    if encoding.is_none() {
+                        warn!(
+                            "Unparseable chan encoding {:?} of {} kept in extra properties",
+                            encoding_property.val_str(),
+                            service_info.get_fullname()
+                        );
+                    }
+                    encoding
+                }),
+            latency: match service_info.get_property("latency_ns") {
+                Some(latency_property) => latency_property
+                    .val_str()
+                    .parse()
+                    .ok()
+                    .map(Duration::from_nanos),
+                None => None,
+            },
+            channel_count_hint: service_info
+                .get_property_val_str("nchan")
+                .and_then(|nchan| nchan.parse().ok()),
+            extra_properties: service_info
+                .get_properties()
+                .iter()
+                .map(|property| (property.key().to_owned(), property.val_str().to_owned()))
+                .collect(),
+        }
+    }
+
+    /// Whether from_service_info() would make this same CHANInfo out of the record, without building one. Every field is parsed from the TXT properties, which are all in extra_properties, so the name and those are all there is to compare. A record with a key in it twice never matches, from_service_info() sorts those out.
+    fn matches_service_info(&self, chan_name: &str, service_info: &ServiceInfo) -> bool {
+        let properties = service_info.get_properties();
+        self.name == chan_name
+            && properties.iter().count() == self.extra_properties.len()
+            && properties.iter().all(|property| {
+                self.extra_properties
+                    .get(property.key())
+                    .is_some_and(|value| value == property.val_str())
+            })
     }
-}
 
-impl Eq for CHANInfo {}
+    fn params(&self) -> ChannelParams {
+        ChannelParams {
+            sample_rate: self.sample_rate,
+            encoding: self.encoding,
+            latency: self.latency,
+        }
+    }
 
-impl Hash for CHANInfo {
-    fn hash<H: Hasher>(&self, state: &mut H) {
-        self.id.hash(state);
+    /// CHAN records advertise TX channels. The canonical name isn't in the record, see DeviceDiscoveryCache::tx_channel_info().
+    fn to_channel_info(&self, routing: Option<ChannelRouting>) -> ChannelInfo {
+        ChannelInfo {
+            id: self.id,
+            name: self.name.clone(),
+            canonical: None,
+            direction: ChannelDirection::Tx,
+            sample_rate: self.sample_rate,
+            encoding: self.encoding,
+            latency: self.latency,
+            routing,
+            extra_properties: self.extra_properties.clone(),
+            source: ChannelSource::Mdns,
+        }
     }
 }
 
-#[derive(Debug)]
-struct DeviceAlreadyPresent {}
+/// Channels are keyed by id, falling back to the name for channels that don't advertise one.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+enum ChannelKey {
+    Id(u16),
+    Name(String),
+}
 
-impl Display for DeviceAlreadyPresent {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "Device not present.")
-    }
+/// The CHAN records of a device.
+#[derive(Default)]
+struct ChannelCache {
+    channels: HashMap<ChannelKey, CHANInfo>,
+    /// Set once the device hits MAX_CHANNELS_PER_DEVICE, so the warning is only logged once.
+    overflowed: bool,
+    /// When a CHAN record of the device last resolved, see refresh_chan_cache().
+    last_resolved_at: Option<Instant>,
 }
 
-impl std::error::Error for DeviceAlreadyPresent {}
+impl ChannelCache {
+    fn iter(&self) -> impl Iterator<Item = &CHANInfo> {
+        self.channels.values()
+    }
+
+    fn len(&self) -> usize {
+        self.channels.len()
+    }
+
+    /// The cached channel a record with this id and name would replace, see update().
+    fn find(&self, id: Option<u16>, name: &str) -> Option<&CHANInfo> {
+        let by_name = || self.channels.get(&ChannelKey::Name(name.to_owned()));
+        match id {
+            Some(id) => self.channels.get(&ChannelKey::Id(id)).or_else(by_name),
+            None => self
+                .channels
+                .values()
+                .find(|known| known.id.is_some() && known.name == name)
+                .or_else(by_name),
+        }
+    }
+
+    /// Adds or replaces a channel. A channel that was cached by name is moved over once it advertises an id, and a record without an id for a channel already known by id keeps the id. Returns whether it's a channel that wasn't cached before.
+    fn update(&mut self, device_name: &str, info: CHANInfo) -> bool {
+        let mut was_named = false;
+        let key = match info.id {
+            Some(id) => {
+                was_named = self
+                    .channels
+                    .remove(&ChannelKey::Name(info.name.clone()))
+                    .is_some();
+                ChannelKey::Id(id)
+            }
+            None => {
+                if let Some(known) = self
+                    .channels
+                    .values_mut()
+                    .find(|known| known.id.is_some() && known.name == info.name)
+                {
+                    *known = CHANInfo {
+                        id: known.id,
+                        ..info
+                    };
+                    return false;
+                }
+                ChannelKey::Name(info.name.clone())
+            }
+        };
+
+        if !self.channels.contains_key(&key) && self.channels.len() >= MAX_CHANNELS_PER_DEVICE {
+            if !self.overflowed {
+                warn!(
+                    "{} advertises more than {} channels, ignoring the rest",
+                    device_name, MAX_CHANNELS_PER_DEVICE
+                );
+                self.overflowed = true;
+            }
+            return false;
+        }
+        self.channels.insert(key, info).is_none() && !was_named
+    }
+
+    /// Removes every channel with the given name. Returns the ids of the removed channels that had one.
+    fn remove_by_name(&mut self, name: &str) -> Vec<u16> {
+        let mut removed_ids = Vec::new();
+        self.channels.retain(|_, channel| {
+            if channel.name != name {
+                return true;
+            }
+            removed_ids.extend(channel.id);
+            false
+        });
+        removed_ids
+    }
+}
 
 #[derive(Debug)]
-struct DeviceNotPresent {}
+struct DeviceAlreadyPresent {}
 
-impl Display for DeviceNotPresent {
+impl Display for DeviceAlreadyPresent {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         write!(f, "Device not present.")
     }
 }
 
-impl std::error::Error for DeviceNotPresent {}
+impl std::error::Error for DeviceAlreadyPresent {}
 
 #[derive(Debug)]
 struct DeviceStatus {
@@ -165,6 +702,13 @@ struct DeviceStatus {
     connected_cmc: bool,
     connected_arc: bool,
     connected_chan: bool,
+    found_at: Instant,
+    /// Set when none of the services resolved an address within the resolve timeout.
+    unresolved: bool,
+    /// When the device went from its last service, while it waits out the removal grace period.
+    gone_since: Option<Instant>,
+    /// Put in the list by update_device_list() instead of discovery.
+    manually_added: bool,
 }
 
 impl DeviceStatus {
@@ -174,20 +718,91 @@ impl DeviceStatus {
             connected_cmc: false,
             connected_arc: false,
             connected_chan: false,
+            found_at: Instant::now(),
+            unresolved: false,
+            gone_since: None,
+            manually_added: false,
+        }
+    }
+
+    /// The flag for whether the device is connected to a service's discovery.
+    fn connected_mut(&mut self, service: DanteService) -> &mut bool {
+        match service {
+            DanteService::Dbc => &mut self.connected_dbc,
+            DanteService::Cmc => &mut self.connected_cmc,
+            DanteService::Arc => &mut self.connected_arc,
+            DanteService::Chan => &mut self.connected_chan,
+        }
+    }
+
+    fn any_connected(&self) -> bool {
+        self.connected_dbc || self.connected_cmc || self.connected_arc || self.connected_chan
+    }
+}
+
+/// A value fetched from a device along with when it was fetched.
+#[derive(Clone)]
+struct Timestamped<T> {
+    value: T,
+    fetched_at: Instant,
+}
+
+impl<T> Timestamped<T> {
+    fn new(value: T) -> Self {
+        Timestamped {
+            value,
+            fetched_at: Instant::now(),
         }
     }
+
+    fn is_fresh(&self, max_age: Duration) -> bool {
+        self.fetched_at.elapsed() < max_age
+    }
 }
 
+/// Everything discovered about a device. Caches outlive their device: when a device disconnects from every service it's removed from the device list, but its cache is kept so that a device that comes back (a reboot, an mdns flap) picks up where it left off. purge_stale_caches() removes caches of devices that have been gone for too long.
 struct DeviceDiscoveryCache {
     dbc_info: Option<DBCInfo>,
     cmc_info: Option<CMCInfo>,
     arc_info: Option<ARCInfo>,
-    chan_info: HashSet<CHANInfo>,
+    chan_info: ChannelCache,
+    /// Last subscription read-back of the device's RX channels.
+    subscriptions: Option<Timestamped<Vec<SubscriptionEntry>>>,
+    /// Last (TX, RX) channel count query.
+    channel_counts: Option<Timestamped<(u16, u16)>>,
+    /// Last TX channel names query, as (id, name).
+    tx_channel_names: Option<Timestamped<Vec<(u16, String)>>>,
+    /// When the device was removed from the device list, None while it's connected.
+    disconnected_at: Option<Instant>,
+    /// Host name from the A record, without ".local.". Usually the same as the device (instance) name, but not for a device that was renamed and hasn't rebooted.
+    hostname: Option<String>,
+    /// Every address set the device's services resolved to lately, for spotting two devices with the same name.
+    address_sightings: Vec<AddressSighting>,
+    /// Version the device's commands are built for no matter what its ARC record says, see DanteDeviceManager::set_device_version_override().
+    version_override: Option<DanteVersion>,
 }
 
 struct DanteDeviceList {
-    devices: HashMap<String, DeviceStatus>,
-    caches: HashMap<String, DeviceDiscoveryCache>,
+    devices: HashMap<Arc<str>, DeviceStatus>,
+    caches: HashMap<Arc<str>, DeviceDiscoveryCache>,
+    events: EventSenders,
+    channel_watchers: Arc<Mutex<ChannelWatchers>>,
+    /// Services the last start_discovery() didn't browse for.
+    untracked_services: Vec<DanteService>,
+    /// What extra browse targets found, by fullname.
+    other_services: BTreeMap<String, OtherService>,
+    #[cfg(feature = "aes67")]
+    aes67_streams: sap::Aes67Streams,
+    /// When a service last resolved, for telling when discovery has settled.
+    last_resolved_at: Option<Instant>,
+    /// How long a device that's gone from every service is kept, see finalize_removals().
+    removal_grace: Duration,
+    /// Subscriptions whose TX side is missing, by RX device and channel id. See refresh_unresolved_routes().
+    unresolved_routes: HashMap<(String, u16), UnresolvedRoute>,
+    /// See DanteDeviceManager::tag_device().
+    tags: BTreeMap<TagKey, BTreeSet<String>>,
+    /// Last probe of each address, the round trip time or None when it didn't answer. See DanteDeviceManager::probe_addresses().
+    reachability: HashMap<Ipv4Addr, Timestamped<Option<Duration>>>,
 }
 
 impl DanteDeviceList {
@@ -197,34 +812,61 @@ impl DanteDeviceList {
             return Err(DeviceAlreadyPresent {});
         }
 
+        // The name is allocated once and shared by both maps. A device that comes back reuses the one its cache still has.
+        let device_name: Arc<str> = match self.caches.get_key_value(new_device_name) {
+            Some((device_name, _)) => device_name.clone(),
+            None => Arc::from(new_device_name),
+        };
         self.devices
-            .insert(new_device_name.to_owned(), DeviceStatus::new());
-
-        // Create a cache for the device as well if there isn't already one.
-        if !self.caches.contains_key(new_device_name) {
-            self.caches.insert(
-                new_device_name.to_owned(),
-                DeviceDiscoveryCache {
-                    dbc_info: None,
-                    cmc_info: None,
-                    arc_info: None,
-                    chan_info: HashSet::new(),
-                },
-            );
+            .insert(device_name.clone(), DeviceStatus::new());
+
+        // Create a cache for the device as well if there isn't already one. A device that comes back keeps its old cache.
+        match self.caches.get_mut(new_device_name) {
+            Some(cache) => cache.disconnected_at = None,
+            None => {
+                self.caches.insert(
+                    device_name,
+                    DeviceDiscoveryCache {
+                        dbc_info: None,
+                        cmc_info: None,
+                        arc_info: None,
+                        chan_info: ChannelCache::default(),
+                        subscriptions: None,
+                        channel_counts: None,
+                        tx_channel_names: None,
+                        disconnected_at: None,
+                        hostname: None,
+                        address_sightings: Vec::new(),
+                        version_override: None,
+                    },
+                );
+            }
         }
+        self.invalidate_subscriptions_to(new_device_name);
 
         Ok(())
     }
 
-    fn try_add_device(&mut self, new_device_name: &str) {
-        // Explicitly throw away error. If we already had one, Ok. If we make one, also Ok.
-        let _ = self.add_device(new_device_name);
-    }
-
     fn device_connected(&self, device_name: &str) -> bool {
         self.devices.contains_key(device_name)
     }
 
+    fn channel_name_exist(&self, device_name: &str, chan_name: &str) -> bool {
+        if !(self.device_connected(device_name)) {
+            return false;
+        }
+        match self.caches.get(device_name) {
+            Some(cache) => cache
+                .chan_info
+                .iter()
+                .any(|chan_info| chan_info.name == chan_name),
+            None => {
+                error!("Cache doesn't exist despite device being connected!");
+                false
+            }
+        }
+    }
+
     fn channel_id_exist(&self, device_name: &str, chan_id: u16) -> bool {
         if !(self.device_connected(device_name)) {
             return false;
@@ -241,6 +883,7 @@ impl DanteDeviceList {
         }
     }
 
+    #[allow(dead_code)]
     fn get_channel_name_from_id(&self, device_name: &str, chan_id: u16) -> Option<&str> {
         if !(self.device_connected(device_name)) {
             return None;
@@ -286,811 +929,3956 @@ impl DanteDeviceList {
         Some(device_ips)
     }
 
+    /// Every address the device's services resolved to, sorted, whether or not it's still connected.
+    fn cached_addresses(&self, device_name: &str) -> Vec<Ipv4Addr> {
+        let mut addresses: Vec<Ipv4Addr> = match self.caches.get(device_name) {
+            Some(cache) => [
+                cache.arc_info.as_ref().map(|info| &info.addresses),
+                cache.dbc_info.as_ref().map(|info| &info.addresses),
+                cache.cmc_info.as_ref().map(|info| &info.addresses),
+            ]
+            .into_iter()
+            .flatten()
+            .flatten()
+            .copied()
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect(),
+            None => Vec::new(),
+        };
+        addresses.sort();
+        addresses
+    }
+
+    /// Returns cached_addresses() if the service resolving to addresses may change them, None if it resolved to the same ones as last time. Services resolve again every few minutes, mostly to the same addresses, so this keeps that from allocating.
+    fn addresses_before_change(
+        &self,
+        device_name: &str,
+        service: DanteService,
+        addresses: &HashSet<Ipv4Addr>,
+    ) -> Option<Vec<Ipv4Addr>> {
+        let cache = self.caches.get(device_name)?;
+        let cached = match service {
+            DanteService::Dbc => cache.dbc_info.as_ref().map(|info| &info.addresses),
+            DanteService::Cmc => cache.cmc_info.as_ref().map(|info| &info.addresses),
+            DanteService::Arc => cache.arc_info.as_ref().map(|info| &info.addresses),
+            DanteService::Chan => None,
+        };
+        match cached == Some(addresses) {
+            true => None,
+            false => Some(self.cached_addresses(device_name)),
+        }
+    }
+
+    /// Emits AddressesChanged if the device's addresses differ from old. A device resolving for the first time doesn't count.
+    fn emit_if_addresses_changed(&mut self, device_name: &str, old: Vec<Ipv4Addr>) {
+        let new = self.cached_addresses(device_name);
+        if !old.is_empty() && old != new {
+            info!(
+                "Addresses of {} changed from {:?} to {:?}",
+                device_name, old, new
+            );
+            self.events.emit(DanteDeviceEvent::AddressesChanged {
+                device_name: device_name.to_owned(),
+                old,
+                new,
+            });
+            // It may have rebooted on the way.
+            self.invalidate_query_results(device_name);
+        }
+    }
+
+    /// The address to send commands for a device to. When a device resolved to several, the lowest is used so it's always the same one.
+    fn get_device_ip(&self, device_name: &str) -> Result<Ipv4Addr, QueryError> {
+        self.get_device_ips(device_name)
+            .ok_or_else(|| QueryError::DeviceNotFound(device_name.to_owned()))?
+            .into_iter()
+            .min()
+            .ok_or_else(|| QueryError::DeviceUnresolved(device_name.to_owned()))
+    }
+
+    /// Names of the connected devices that resolved to an address, sorted. Usually just the one.
+    fn get_devices_by_ip(&self, ip: &Ipv4Addr) -> Vec<String> {
+        let mut device_names: Vec<String> = self
+            .devices
+            .keys()
+            .filter(|device_name| match self.get_device_ips(device_name) {
+                Some(device_ips) => device_ips.contains(ip),
+                None => false,
+            })
+            .map(|device_name| device_name.to_string())
+            .collect();
+        device_names.sort();
+        device_names
+    }
+
+    /// Updates the host name a device's services resolved to.
+    fn update_hostname(&mut self, device_name: &str, hostname: &str) {
+        let hostname = cutoff_address(hostname, None);
+        if let Some(cache) = self.caches.get_mut(device_name) {
+            if cache.hostname.as_deref() != Some(hostname) {
+                if hostname != device_name {
+                    info!(
+                        "Device \"{}\" resolves to host name \"{}\"",
+                        device_name, hostname
+                    );
+                }
+                cache.hostname = Some(hostname.to_owned());
+            }
+        }
+    }
+
+    /// Names of connected devices whose host name is hostname, sorted. The ".local." suffix is optional.
+    fn get_devices_by_hostname(&self, hostname: &str) -> Vec<String> {
+        let hostname = hostname.strip_suffix(".local.").unwrap_or(hostname);
+        let mut device_names: Vec<String> = self
+            .devices
+            .keys()
+            .filter(|device_name| {
+                self.caches
+                    .get(*device_name)
+                    .and_then(|cache| cache.hostname.as_deref())
+                    == Some(hostname)
+            })
+            .map(|device_name| device_name.to_string())
+            .collect();
+        device_names.sort();
+        device_names
+    }
+
     /// Updates the dbc info of device in the list with a specific name.
     fn update_dbc(&mut self, device_name: &str, info: DBCInfo) {
+        let old_addresses =
+            self.addresses_before_change(device_name, DanteService::Dbc, &info.addresses);
+        self.check_advertised_port(device_name, DanteService::Dbc, info.port);
+        self.record_resolved_addresses(device_name, &info.addresses);
         self.caches
             .get_mut(device_name)
             .expect("Tried updating cache of device that doesn't exist")
             .dbc_info = Some(info);
+        self.last_resolved_at = Some(Instant::now());
+        if let Some(old_addresses) = old_addresses {
+            self.emit_if_addresses_changed(device_name, old_addresses);
+        }
         debug!("update_dbc for {}", device_name);
     }
 
     /// Updates the cmc info of device in the list with a specific name.
     fn update_cmc(&mut self, device_name: &str, info: CMCInfo) {
+        let old_addresses =
+            self.addresses_before_change(device_name, DanteService::Cmc, &info.addresses);
+        self.check_advertised_port(device_name, DanteService::Cmc, info.port);
+        self.record_resolved_addresses(device_name, &info.addresses);
         self.caches
             .get_mut(device_name)
             .expect("Tried updating cache of device that doesn't exist")
             .cmc_info = Some(info);
+        self.last_resolved_at = Some(Instant::now());
+        if let Some(old_addresses) = old_addresses {
+            self.emit_if_addresses_changed(device_name, old_addresses);
+        }
         debug!("update_cmc for {}", device_name);
     }
 
     /// Updates the arc info of device in the list with a specific name.
     fn update_arc(&mut self, device_name: &str, info: ARCInfo) {
+        let old_addresses =
+            self.addresses_before_change(device_name, DanteService::Arc, &info.addresses);
+        self.check_advertised_port(device_name, DanteService::Arc, info.port);
+        self.record_resolved_addresses(device_name, &info.addresses);
         self.caches
             .get_mut(device_name)
             .expect("Tried updating cache of device that doesn't exist")
             .arc_info = Some(info);
+        self.last_resolved_at = Some(Instant::now());
+        if let Some(old_addresses) = old_addresses {
+            self.emit_if_addresses_changed(device_name, old_addresses);
+        }
         debug!("update_arc for {}", device_name);
     }
 
-    /// Updates the cmc info of device in the list with a specific name.
+    /// Updates the chan info of a channel of device in the list with a specific name.
     fn update_chan(&mut self, device_name: &str, info: CHANInfo) {
-        self.caches
+        let chan_info = &mut self
+            .caches
             .get_mut(device_name)
             .expect("Tried updating cache of device that doesn't exist")
-            .chan_info
-            .replace(info);
+            .chan_info;
+        let old = chan_info.find(info.id, &info.name);
+        // CHAN records go around again every few minutes, almost always unchanged.
+        if old.is_some_and(|old| *old == info) {
+            self.touch_chan(device_name);
+            return;
+        }
+        let channel_name = info.name.clone();
+        let new_params = info.params();
+        // Only the unknown properties make a ChannelPropertiesChanged, the parsed ones are in the params.
+        let changed_extra_properties = old
+            .filter(|old| old.extra_properties != info.extra_properties)
+            .map(|old| {
+                (
+                    unknown_chan_properties(&old.extra_properties),
+                    unknown_chan_properties(&info.extra_properties),
+                )
+            })
+            .filter(|(old, new)| old != new);
+        let old = old.map(|old| (old.params(), old.name != info.name));
+        let (id, name) = (info.id, channel_name.clone());
+        let is_new = chan_info.update(device_name, info);
+        chan_info.last_resolved_at = Some(Instant::now());
+        let renamed = old.is_some_and(|(_, renamed)| renamed);
+        let old_params = old.map(|(old_params, _)| old_params);
+        if is_new || renamed || old_params.is_some_and(|old_params| old_params != new_params) {
+            self.invalidate_channel_queries(device_name);
+        }
+        if let Some(old_params) = old_params.filter(|old_params| *old_params != new_params) {
+            info!(
+                "Channel {} of {} changed from {:?} to {:?}",
+                channel_name, device_name, old_params, new_params
+            );
+            self.events.emit(DanteDeviceEvent::ChannelParamsChanged {
+                device_name: device_name.to_owned(),
+                channel_name: channel_name.clone(),
+                old: old_params,
+                new: new_params,
+            });
+        }
+        if let Some((old_extra_properties, new_extra_properties)) = changed_extra_properties {
+            debug!(
+                "TXT properties of channel {} of {} changed from {:?} to {:?}",
+                channel_name, device_name, old_extra_properties, new_extra_properties
+            );
+            self.events
+                .emit(DanteDeviceEvent::ChannelPropertiesChanged {
+                    device_name: device_name.to_owned(),
+                    channel_name,
+                    old: old_extra_properties,
+                    new: new_extra_properties,
+                });
+        }
+        let added = match is_new {
+            true => self.caches.get(device_name).and_then(|cache| {
+                cache
+                    .chan_info
+                    .find(id, &name)
+                    .map(|chan_info| cache.tx_channel_info(chan_info, None))
+            }),
+            false => None,
+        };
+        if let Some(added) = added {
+            self.channel_watchers
+                .lock()
+                .unwrap()
+                .emit(device_name, ChannelEvent::ChannelAdded(added.clone()));
+            self.events.emit(DanteDeviceEvent::ChannelAdded {
+                device_name: device_name.to_owned(),
+                channel: added,
+            });
+        }
+        self.last_resolved_at = Some(Instant::now());
         debug!("update_chan for {}", device_name);
     }
 
-    fn connect_dbc(&mut self, device_name: &str) {
-        self.try_add_device(device_name);
-        self.devices
-            .get_mut(device_name)
-            .expect("Just tried to add device, should be able to get it")
-            .connected_dbc = true;
-        debug!("Connected to dbc discovery.");
+    /// Removes a channel that's no longer advertised. The device is only disconnected from chan discovery once its last channel is gone.
+    fn remove_chan(&mut self, device_name: &str, chan_name: &str) {
+        let (channels_removed, channels_left) = match self.caches.get_mut(device_name) {
+            Some(cache) => {
+                let channels_before = cache.chan_info.len();
+                let removed_ids = cache.chan_info.remove_by_name(chan_name);
+                let watchers = self.channel_watchers.lock().unwrap();
+                for id in removed_ids {
+                    watchers.emit(device_name, ChannelEvent::ChannelRemoved(id));
+                    self.events.emit(DanteDeviceEvent::ChannelRemoved {
+                        device_name: device_name.to_owned(),
+                        channel_id: id,
+                    });
+                }
+                (
+                    cache.chan_info.len() < channels_before,
+                    cache.chan_info.len(),
+                )
+            }
+            None => (false, 0),
+        };
+        if channels_removed {
+            self.invalidate_channel_queries(device_name);
+        }
+        debug!("remove_chan {} for {}", chan_name, device_name);
+        if channels_left == 0 {
+            self.disconnect_service(device_name, DanteService::Chan);
+        }
     }
 
-    fn connect_cmc(&mut self, device_name: &str) {
-        self.try_add_device(device_name);
-        self.devices
+    /// Stores a fresh subscription read-back for a device.
+    fn update_subscriptions(&mut self, device_name: &str, subscriptions: Vec<SubscriptionEntry>) {
+        self.caches
             .get_mut(device_name)
-            .expect("Just tried to add device, should be able to get it")
-            .connected_cmc = true;
-        debug!("Connected to cmc discovery.");
+            .expect("Tried updating cache of device that doesn't exist")
+            .subscriptions = Some(Timestamped::new(subscriptions));
+        self.refresh_unresolved_routes();
+        debug!("update_subscriptions for {}", device_name);
     }
 
-    fn connect_arc(&mut self, device_name: &str) {
-        self.try_add_device(device_name);
-        self.devices
-            .get_mut(device_name)
-            .expect("Just tried to add device, should be able to get it")
-            .connected_arc = true;
-        debug!("Connected to arc discovery.");
+    /// Drops the subscription read-back of any device with the given address, forcing the next read to query the device.
+    fn invalidate_subscriptions_for_ip(&mut self, ip: &Ipv4Addr) {
+        for device_name in self.get_devices_by_ip(ip) {
+            if let Some(cache) = self.caches.get_mut(device_name.as_str()) {
+                cache.subscriptions = None;
+            }
+        }
     }
 
-    fn connect_chan(&mut self, device_name: &str) {
-        self.try_add_device(device_name);
-        self.devices
-            .get_mut(device_name)
-            .expect("Just tried to add device, should be able to get it")
-            .connected_chan = true;
-        debug!("Connected to chan discovery.");
+    /// Whether a device at the address could have the RX channel. Only false when a device there has RX channel ids known from get_rx_channel_ids() and the channel isn't one of them, so devices discovery hasn't seen are given the benefit of the doubt.
+    fn may_have_rx_channel(&self, ip: &Ipv4Addr, rx_channel_id: u16) -> bool {
+        self.get_devices_by_ip(ip)
+            .iter()
+            .map(|device_name| self.get_rx_channel_ids(device_name))
+            .all(|rx_channel_ids| {
+                rx_channel_ids.is_empty() || rx_channel_ids.contains(&rx_channel_id)
+            })
     }
 
-    fn disconnect_dbc(&mut self, device_name: &str) {
-        self.devices
-            .get_mut(device_name)
-            .expect("If we're calling disconnect, we should still have the device in the list.")
-            .connected_dbc = false;
-        self.check_remove(device_name)
-            .expect("If we're calling disconnect, we should still have the device in the list.");
-        debug!("Disconnected from dbc discovery");
+    /// Flags devices that were found longer than timeout ago but still have no addresses, emitting an event for each. Devices that have since resolved get the flag cleared. Returns whether any device was newly flagged.
+    fn check_unresolved(&mut self, timeout: Duration) -> bool {
+        let mut newly_unresolved = Vec::new();
+        for (device_name, status) in self.devices.iter_mut() {
+            let resolved = self
+                .caches
+                .get(device_name)
+                .map(|cache| {
+                    [
+                        cache.dbc_info.as_ref().map(|info| &info.addresses),
+                        cache.cmc_info.as_ref().map(|info| &info.addresses),
+                        cache.arc_info.as_ref().map(|info| &info.addresses),
+                    ]
+                    .into_iter()
+                    .flatten()
+                    .any(|addresses| !addresses.is_empty())
+                })
+                .unwrap_or(false);
+
+            if resolved {
+                if status.unresolved {
+                    info!("{} resolved after being flagged unresolved", device_name);
+                }
+                status.unresolved = false;
+            } else if !status.unresolved && status.found_at.elapsed() > timeout {
+                status.unresolved = true;
+                newly_unresolved.push(device_name.to_string());
+            }
+        }
+
+        let any_unresolved = !newly_unresolved.is_empty();
+        for device_name in newly_unresolved {
+            warn!("{} was found but hasn't resolved an address", device_name);
+            self.events
+                .emit(DanteDeviceEvent::DeviceUnresolved { device_name });
+        }
+        any_unresolved
     }
 
-    fn disconnect_cmc(&mut self, device_name: &str) {
-        self.devices
-            .get_mut(device_name)
-            .expect("If we're calling disconnect, we should still have the device in the list.")
-            .connected_cmc = false;
-        self.check_remove(device_name)
-            .expect("If we're calling disconnect, we should still have the device in the list.");
-        debug!("Disconnected from cmc discovery");
+    fn detect_subscription_conflicts(&self) -> Vec<SubscriptionConflict> {
+        let subscriptions: HashMap<String, Vec<SubscriptionEntry>> = self
+            .devices
+            .keys()
+            .filter_map(|device_name| {
+                let cache = self.caches.get(device_name)?;
+                let subscriptions = cache.subscriptions.as_ref()?;
+                Some((device_name.to_string(), subscriptions.value.clone()))
+            })
+            .collect();
+        routing::find_subscription_conflicts(&subscriptions)
     }
 
-    fn disconnect_arc(&mut self, device_name: &str) {
-        self.devices
-            .get_mut(device_name)
-            .expect("If we're calling disconnect, we should still have the device in the list.")
-            .connected_arc = false;
-        self.check_remove(device_name)
-            .expect("If we're calling disconnect, we should still have the device in the list.");
-        debug!("Disconnected from arc discovery");
+    fn get_arc_port(&self, device_name: &str) -> Option<u16> {
+        self.caches
+            .get(device_name)?
+            .arc_info
+            .as_ref()
+            .and_then(|arc_info| port_if_valid(arc_info.port))
     }
 
-    fn disconnect_chan(&mut self, device_name: &str) {
-        self.devices
-            .get_mut(device_name)
-            .expect("If we're calling disconnect, we should still have the device in the list.")
-            .connected_chan = false;
-        self.check_remove(device_name)
-            .expect("If we're calling disconnect, we should still have the device in the list.");
-        debug!("Disconnected from chan discovery");
-    }
-
-    /// Checks if a device should be removed (all the discovery types have been removed), and deletes if if that's the case.
-    /// Errors when the device name isn't a device in the list.
-    fn check_remove(&mut self, device_name: &str) -> Result<(), DeviceNotPresent> {
-        match self.devices.get(device_name) {
-            Some(device_status) => {
-                if !(device_status.connected_dbc
-                    || device_status.connected_cmc
-                    || device_status.connected_arc
-                    || device_status.connected_chan)
-                {
-                    self.devices.remove(device_name);
-                }
+    /// The port a service of the device advertised, bogus or not. get_arc_port() and friends leave out bogus ones.
+    fn get_advertised_port(&self, device_name: &str, service: DanteService) -> Option<u16> {
+        let cache = self.caches.get(device_name)?;
+        match service {
+            DanteService::Dbc => cache.dbc_info.as_ref().map(|dbc_info| dbc_info.port),
+            DanteService::Cmc => cache.cmc_info.as_ref().map(|cmc_info| cmc_info.port),
+            DanteService::Arc => cache.arc_info.as_ref().map(|arc_info| arc_info.port),
+            DanteService::Chan => None,
+        }
+    }
 
-                Ok(())
-            }
-            None => Err(DeviceNotPresent {}),
+    /// The port ARC commands for the device go to: its ARC port, or DEFAULT_ARC_PORT when that's unknown or bogus.
+    fn get_arc_command_port(&self, device_name: &str) -> u16 {
+        if let Some(advertised) = self
+            .get_advertised_port(device_name, DanteService::Arc)
+            .filter(|port| port_if_valid(*port).is_none())
+        {
+            debug!(
+                "{} advertises ARC port {}, using {} instead",
+                device_name, advertised, DEFAULT_ARC_PORT
+            );
         }
+        self.get_arc_port(device_name).unwrap_or(DEFAULT_ARC_PORT)
     }
 
-    fn new() -> Self {
-        DanteDeviceList {
-            devices: HashMap::new(),
-            caches: HashMap::new(),
+    /// The port ARC commands for the device at an address go to, see get_arc_command_port(). DEFAULT_ARC_PORT when no discovered device has the address, like for devices that were never discovered.
+    fn get_arc_command_port_by_ip(&self, ip: &Ipv4Addr) -> u16 {
+        match self.get_devices_by_ip(ip).first() {
+            Some(device_name) => self.get_arc_command_port(device_name),
+            None => {
+                debug!(
+                    "No discovered device at {}, using ARC port {}",
+                    ip, DEFAULT_ARC_PORT
+                );
+                DEFAULT_ARC_PORT
+            }
         }
     }
-}
 
-/// Cutoff the address from a hostname. Address default is "local."
-fn cutoff_address<'a>(hostname: &'a str, address: Option<&'a str>) -> &'a str {
-    let cutoff_string = ".".to_string() + address.unwrap_or("local.");
-    match hostname.strip_suffix(&cutoff_string) {
-        None => {
-            warn!(
-                "Device \"{}\" doesn't end with \"{}\". This is abnormal.",
-                hostname, cutoff_string
-            );
-            hostname
+    /// Warns about a bogus port the first time a service of the device advertises it.
+    fn check_advertised_port(&mut self, device_name: &str, service: DanteService, port: u16) {
+        if port_if_valid(port).is_some()
+            || self.get_advertised_port(device_name, service) == Some(port)
+        {
+            return;
         }
-        Some(stripped) => stripped,
+        warn!(
+            "{} advertises {} port {}, ignoring it",
+            device_name, service, port
+        );
+        self.events.emit(DanteDeviceEvent::BogusPort {
+            device_name: device_name.to_owned(),
+            service,
+            port,
+        });
     }
-}
 
-#[derive(thiserror::Error, Debug)]
-pub enum MakeSubscriptionError {
-    #[error("error sending udp packet")]
-    ConnectionFailed,
-}
-#[derive(thiserror::Error, Debug)]
-pub enum ClearSubscriptionError {
-    #[error("error sending udp packet")]
-    ConnectionFailed,
-}
+    fn get_dbc_port(&self, device_name: &str) -> Option<u16> {
+        self.caches
+            .get(device_name)?
+            .dbc_info
+            .as_ref()
+            .and_then(|dbc_info| port_if_valid(dbc_info.port))
+    }
 
-/// A Dante Device Manager stores information related to interacting with dante devices. Right now, it stores mdns information found from start_discovery() and a sequence ID. Currently, the control of dante devices is separate from the discovery of them. I found that for some devices on the network, mdns discovery can be slow or not happen at all, so I switched to using direct ip addresses and channel numbers/names (essentially exactly the information that is needed to send the udp packet to make the connection). In the case of make_subscription() and clear_subscription(), the only state changed by DanteDeviceManager is a sequence ID, which is an incrementing 16-bit integer, though whether this is really needed is suspect.
-pub struct DanteDeviceManager {
-    device_list: Arc<Mutex<DanteDeviceList>>,
-    running: Arc<Mutex<bool>>,
-    current_command_sequence_id: u16,
-}
+    fn get_cmc_port(&self, device_name: &str) -> Option<u16> {
+        self.caches
+            .get(device_name)?
+            .cmc_info
+            .as_ref()
+            .and_then(|cmc_info| port_if_valid(cmc_info.port))
+    }
 
-impl DanteDeviceManager {
-    /// Spawns the discovery service in a separate thread. Call stop_discovery() to end it.
-    pub fn start_discovery(&self) -> Result<(), Box<dyn std::error::Error>> {
-        info!("Starting discovery");
-        *self.running.lock().unwrap() = true;
+    /// The "nchan" hint of the CMC record, falling back to the CHAN records.
+    fn get_channel_count_hint(&self, device_name: &str) -> Option<u16> {
+        let cache = self.caches.get(device_name)?;
+        cache
+            .cmc_info
+            .as_ref()
+            .and_then(|cmc_info| cmc_info.channel_count_hint)
+            .or_else(|| {
+                cache
+                    .chan_info
+                    .iter()
+                    .filter_map(|chan_info| chan_info.channel_count_hint)
+                    .max()
+            })
+    }
 
-        // Spawn threads equal to the number of different addresses we are discovering on.
-        let mdns = ServiceDaemon::new().expect("Failed to create mdns service daemon!");
+    /// Names more than one channel of a direction of the device has, going by the CHAN records and the last TX channel names query for TX channels, and the last subscription read-back for RX channels. Sorted by direction and name.
+    fn channel_name_conflicts(&self, device_name: &str) -> Vec<ChannelNameConflict> {
+        let Some(cache) = self.caches.get(device_name) else {
+            return Vec::new();
+        };
+        let mut names: BTreeMap<(bool, &str), BTreeSet<u16>> = BTreeMap::new();
+        let tx_channels = cache
+            .chan_info
+            .iter()
+            .filter_map(|chan_info| Some((chan_info.id?, chan_info.name.as_str())))
+            .chain(
+                cache
+                    .tx_channel_names
+                    .iter()
+                    .flat_map(|tx_channel_names| tx_channel_names.value.iter())
+                    .map(|(id, name)| (*id, name.as_str())),
+            );
+        for (id, name) in tx_channels {
+            names.entry((true, name)).or_default().insert(id);
+        }
+        for subscription in cache
+            .subscriptions
+            .iter()
+            .flat_map(|subscriptions| subscriptions.value.iter())
+        {
+            names
+                .entry((false, subscription.rx_channel_name.as_str()))
+                .or_default()
+                .insert(subscription.rx_channel_id);
+        }
+        names
+            .into_iter()
+            .filter(|(_, ids)| ids.len() > 1)
+            .map(|((tx, name), ids)| ChannelNameConflict {
+                direction: match tx {
+                    true => ChannelDirection::Tx,
+                    false => ChannelDirection::Rx,
+                },
+                name: name.to_owned(),
+                ids: ids.into_iter().collect(),
+            })
+            .collect()
+    }
 
-        // Discovery for DBC
-        let dbc_receiver = mdns
-            .browse(DBC_SERVICE)
-            .unwrap_or_else(|_| panic!("Failed to browse for {}", DBC_SERVICE));
-
-        // Fresh Arcs to move into thread.
-        let device_list_dbc = self.device_list.clone();
-        let running_dbc = self.running.clone();
-
-        let dbc_thread = std::thread::spawn(move || {
-            debug!("Starting discovery thread");
-            while *running_dbc.lock().unwrap() {
-                while let Ok(event) = dbc_receiver.try_recv() {
-                    match event {
-                        ServiceEvent::SearchStarted(service_type) => {
-                            debug!("DBC Search Started: {}", &service_type);
-                        }
-                        ServiceEvent::ServiceFound(service_type, fullname) => {
-                            debug!("DBC Search Found: {}, {}", &service_type, &fullname);
-                            let device_name = cutoff_address(&fullname, Some(DBC_SERVICE));
+    /// Labels more than one of the device's CHAN records have, sorted.
+    fn duplicate_chan_names(&self, device_name: &str) -> Vec<String> {
+        let Some(cache) = self.caches.get(device_name) else {
+            return Vec::new();
+        };
+        let mut counts: BTreeMap<&str, usize> = BTreeMap::new();
+        for chan_info in cache.chan_info.iter() {
+            *counts.entry(chan_info.name.as_str()).or_default() += 1;
+        }
+        counts
+            .into_iter()
+            .filter(|(_, count)| *count > 1)
+            .map(|(name, _)| name.to_owned())
+            .collect()
+    }
 
-                            let mut device_list_lock = device_list_dbc
-                                .lock()
-                                .expect("Cannot get mutex lock of DanteDevices");
+    /// Works out whether tx_channel is the label or the canonical name of a TX channel of the device, trying the labels from the CHAN records first like the device does. Fails with AmbiguousChannelName when more than one channel has it as that kind of name. None when it's neither as far as is known, which includes devices that aren't known at all, since subscriptions to them are made blind anyway.
+    fn resolve_tx_channel_name(
+        &self,
+        tx_device: &str,
+        tx_channel: &str,
+    ) -> Result<Option<ChannelNameKind>, MakeSubscriptionError> {
+        let Some(cache) = self.caches.get(tx_device) else {
+            return Ok(None);
+        };
+        let labelled: BTreeSet<u16> = cache
+            .chan_info
+            .iter()
+            .filter(|chan_info| chan_info.name == tx_channel)
+            .filter_map(|chan_info| chan_info.id)
+            .collect();
+        let canonical: BTreeSet<u16> = cache
+            .tx_channel_names
+            .iter()
+            .flat_map(|tx_channel_names| tx_channel_names.value.iter())
+            .filter(|(_, name)| name == tx_channel)
+            .map(|(id, _)| *id)
+            .collect();
+        let (kind, ids) = match (labelled.is_empty(), canonical.is_empty()) {
+            (false, _) => (ChannelNameKind::Label, labelled),
+            (true, false) => (ChannelNameKind::Canonical, canonical),
+            (true, true) => return Ok(None),
+        };
+        match ids.len() {
+            1 => Ok(Some(kind)),
+            _ => Err(MakeSubscriptionError::AmbiguousChannelName {
+                name: tx_channel.to_owned(),
+                candidates: ids.into_iter().collect(),
+            }),
+        }
+    }
 
-                            device_list_lock.connect_dbc(device_name);
-                        }
-                        ServiceEvent::ServiceResolved(service_info) => {
-                            info!("DBC Service Resolved: {:?}", &service_info);
-                            let device_name =
-                                cutoff_address(service_info.get_fullname(), Some(DBC_SERVICE));
-                            let mut device_list_lock = device_list_dbc
-                                .lock()
-                                .expect("Cannot get mutex lock of DanteDevices");
-                            device_list_lock.update_dbc(
-                                device_name,
-                                DBCInfo {
-                                    addresses: service_info.get_addresses().to_owned(),
-                                    port: service_info.get_port().to_owned(),
-                                },
-                            );
-                        }
-                        ServiceEvent::ServiceRemoved(service_type, fullname) => {
-                            info!("DBC Service Removed: a:{}, b:{}", &service_type, &fullname);
-                            let mut device_list_lock = device_list_dbc.lock().unwrap();
-                            device_list_lock
-                                .disconnect_dbc(cutoff_address(&fullname, Some(DBC_SERVICE)));
-                        }
-                        ServiceEvent::SearchStopped(service_type) => {
-                            error!("DBC Search Stopped: {}", &service_type);
-                        }
-                    }
-                }
-                sleep(Duration::from_millis(100));
-            }
-        });
+    /// Which subscription commands the device takes: its version override if it has one, otherwise going by its ARC record. None when it has neither or it's a version the crate can't talk to.
+    fn get_dante_version(&self, device_name: &str) -> Option<DanteVersion> {
+        let cache = self.caches.get(device_name)?;
+        cache
+            .version_override
+            .or_else(|| self.get_detected_version(device_name))
+    }
 
-        // Discovery for CMC
-        let cmc_receiver = mdns
-            .browse(CMC_SERVICE)
-            .unwrap_or_else(|_| panic!("Failed to browse for {}", CMC_SERVICE));
-
-        // Fresh Arcs to move into thread.
-        let device_list_cmc = self.device_list.clone();
-        let running_cmc = self.running.clone();
-
-        let cmc_thread = std::thread::spawn(move || {
-            debug!("Starting discovery thread");
-            while *running_cmc.lock().unwrap() {
-                while let Ok(event) = cmc_receiver.try_recv() {
-                    match event {
-                        ServiceEvent::SearchStarted(service_type) => {
-                            debug!("CMC Search Started: {}", &service_type);
-                        }
-                        ServiceEvent::ServiceFound(service_type, fullname) => {
-                            debug!("CMC Search Found: {}, {}", &service_type, &fullname);
-                            let device_name = cutoff_address(&fullname, Some(CMC_SERVICE));
+    /// The version going by the device's ARC record alone.
+    fn get_detected_version(&self, device_name: &str) -> Option<DanteVersion> {
+        self.caches
+            .get(device_name)?
+            .arc_info
+            .as_ref()
+            .and_then(|arc_info| ArcCapabilities::parse(&arc_info.router_vers))
+            .and_then(|capabilities| capabilities.dante_version())
+    }
 
-                            let mut device_list_lock = device_list_cmc
-                                .lock()
-                                .expect("Cannot get mutex lock of DanteDevices");
+    /// The "id" property of the CMC record, None when the device has no CMC record or it didn't have an id.
+    fn get_device_cmc_id(&self, device_name: &str) -> Option<&str> {
+        self.caches
+            .get(device_name)?
+            .cmc_info
+            .as_ref()
+            .map(|cmc_info| cmc_info.id.as_str())
+            .filter(|id| *id != "N/A")
+    }
 
-                            device_list_lock.connect_cmc(device_name);
-                        }
-                        ServiceEvent::ServiceResolved(service_info) => {
-                            info!("CMC Service Resolved: {:?}", &service_info);
-                            let device_name =
-                                cutoff_address(service_info.get_fullname(), Some(CMC_SERVICE));
-                            let mut device_list_lock = device_list_cmc
-                                .lock()
-                                .expect("Cannot get mutex lock of DanteDevices");
-                            device_list_lock.update_cmc(
-                                device_name,
-                                CMCInfo {
-                                    addresses: service_info.get_addresses().to_owned(),
-                                    port: service_info.get_port().to_owned(),
-                                    id: match service_info.get_property("id") {
-                                        Some(id_property) => id_property.val_str().to_owned(),
-                                        None => "N/A".to_string(),
-                                    },
-                                    manufacturer: match service_info.get_property("mf") {
-                                        Some(mf_property) => mf_property.val_str().to_owned(),
-                                        None => "N/A".to_string(),
-                                    },
-                                    model: match service_info.get_property("model") {
-                                        Some(model_property) => model_property.val_str().to_owned(),
-                                        None => "N/A".to_string(),
-                                    },
-                                },
-                            );
-                        }
-                        ServiceEvent::ServiceRemoved(service_type, fullname) => {
-                            info!("CMC Service Removed: a:{}, b:{}", &service_type, &fullname);
-                            let mut device_list_lock = device_list_cmc.lock().unwrap();
-                            device_list_lock
-                                .disconnect_cmc(cutoff_address(&fullname, Some(CMC_SERVICE)));
-                        }
-                        ServiceEvent::SearchStopped(service_type) => {
-                            error!("CMC Search Stopped: {}", &service_type);
-                        }
-                    }
-                }
-                sleep(Duration::from_millis(100));
-            }
-        });
+    /// The connected device whose CMC record has this id. When the old name of a renamed device is still around both have it, then the one found last wins.
+    fn get_device_name_by_id(&self, id: &str) -> Option<&str> {
+        self.devices
+            .iter()
+            .filter(|(device_name, _)| self.get_device_cmc_id(device_name) == Some(id))
+            .max_by_key(|(_, status)| status.found_at)
+            .map(|(device_name, _)| &**device_name)
+    }
 
-        // Discovery for ARC
-        let arc_receiver = mdns
-            .browse(ARC_SERVICE)
-            .unwrap_or_else(|_| panic!("Failed to browse for {}", ARC_SERVICE));
-
-        // Fresh Arcs to move into thread.
-        let device_list_arc = self.device_list.clone();
-        let running_arc = self.running.clone();
-
-        let arc_thread = std::thread::spawn(move || {
-            debug!("Starting discovery thread");
-            while *running_arc.lock().unwrap() {
-                while let Ok(event) = arc_receiver.try_recv() {
-                    match event {
-                        ServiceEvent::SearchStarted(service_type) => {
-                            debug!("ARC Search Started: {}", &service_type);
-                        }
-                        ServiceEvent::ServiceFound(service_type, fullname) => {
-                            debug!("ARC Search Found: {}, {}", &service_type, &fullname);
-                            let device_name = cutoff_address(&fullname, Some(ARC_SERVICE));
+    /// RX channel ids from the last subscription read-back, or 1 up to the channel count hint when the device hasn't been read back yet.
+    fn get_rx_channel_ids(&self, device_name: &str) -> Vec<u16> {
+        match self
+            .caches
+            .get(device_name)
+            .and_then(|cache| cache.subscriptions.as_ref())
+        {
+            Some(subscriptions) => subscriptions
+                .value
+                .iter()
+                .map(|subscription| subscription.rx_channel_id)
+                .collect(),
+            None => (1..=self.get_channel_count_hint(device_name).unwrap_or(0)).collect(),
+        }
+    }
 
-                            let mut device_list_lock = device_list_arc
-                                .lock()
-                                .expect("Cannot get mutex lock of DanteDevices");
+    /// A service of the device was found. Adds the device, and a cache for it, if it isn't in the list yet.
+    fn connect_service(&mut self, device_name: &str, service: DanteService) {
+        // Already being in the list is fine, that's the device's other services.
+        if self.add_device(device_name).is_ok() {
+            self.events.emit(DanteDeviceEvent::DeviceAdded {
+                device_name: device_name.to_owned(),
+            });
+        }
+        let device_status = self
+            .devices
+            .get_mut(device_name)
+            .expect("Just tried to add device, should be able to get it");
+        *device_status.connected_mut(service) = true;
+        // Discovery found it after all, from now on it's a discovered device.
+        device_status.manually_added = false;
+        if device_status.gone_since.take().is_some() {
+            debug!("{} came back within the removal grace period", device_name);
+        }
+        debug!("Connected to {} discovery.", service);
+        self.debug_check_invariants();
+    }
 
-                            device_list_lock.connect_arc(device_name);
-                        }
-                        ServiceEvent::ServiceResolved(service_info) => {
-                            info!("ARC Service Resolved: {:?}", &service_info);
-                            let device_name =
-                                cutoff_address(service_info.get_fullname(), Some(ARC_SERVICE));
-                            let mut device_list_lock = device_list_arc
-                                .lock()
-                                .expect("Cannot get mutex lock of DanteDevices");
-                            device_list_lock.update_arc(
-                                device_name,
-                                ARCInfo {
-                                    addresses: service_info.get_addresses().to_owned(),
-                                    port: service_info.get_port().to_owned(),
-                                    router_vers: match service_info.get_property("router_vers") {
-                                        Some(router_vers_property) => {
-                                            router_vers_property.val_str().to_owned()
-                                        }
-                                        None => "N/A".to_string(),
-                                    },
-                                    router_info: match service_info.get_property("router_info") {
-                                        Some(router_info_property) => {
-                                            router_info_property.val_str().to_owned()
-                                        }
-                                        None => "N/A".to_string(),
-                                    },
-                                },
-                            );
-                        }
-                        ServiceEvent::ServiceRemoved(service_type, fullname) => {
-                            info!("ARC Service Removed: a:{}, b:{}", &service_type, &fullname);
-                            let mut device_list_lock = device_list_arc.lock().unwrap();
-                            device_list_lock
-                                .disconnect_arc(cutoff_address(&fullname, Some(ARC_SERVICE)));
-                        }
-                        ServiceEvent::SearchStopped(service_type) => {
-                            error!("ARC Search Stopped: {}", &service_type);
-                        }
-                    }
-                }
-                sleep(Duration::from_millis(100));
+    /// A service of the device was removed. Once the device is gone from every service it's removed from the list, keeping its cache, but only after the removal grace period so a device that flaps its mdns announcements isn't removed and added back every time. mdns can report a removal for a device that isn't in the list, after a flap or a reconnect, which is ignored.
+    fn disconnect_service(&mut self, device_name: &str, service: DanteService) {
+        let Some(device_status) = self.devices.get_mut(device_name) else {
+            debug!(
+                "{} discovery removed {}, which isn't in the list",
+                service, device_name
+            );
+            return;
+        };
+        *device_status.connected_mut(service) = false;
+        if !device_status.any_connected() {
+            if self.removal_grace.is_zero() {
+                self.remove_device(device_name);
+            } else if device_status.gone_since.is_none() {
+                device_status.gone_since = Some(Instant::now());
             }
-        });
+        }
+        debug!("Disconnected from {} discovery", service);
+        self.debug_check_invariants();
+    }
 
-        // Discovery for CHAN
-        let chan_receiver = mdns
-            .browse(CHAN_SERVICE)
-            .unwrap_or_else(|_| panic!("Failed to browse for {}", CHAN_SERVICE));
-
-        // Fresh Arcs to move into thread.
-        let device_list_chan = self.device_list.clone();
-        let running_chan = self.running.clone();
-
-        let chan_thread = std::thread::spawn(move || {
-            debug!("Starting discovery thread");
-            while *running_chan.lock().unwrap() {
-                while let Ok(event) = chan_receiver.try_recv() {
-                    match event {
-                        ServiceEvent::SearchStarted(service_type) => {
-                            debug!("CHAN Search Started: {}", &service_type);
-                        }
-                        ServiceEvent::ServiceFound(service_type, fullname) => {
-                            debug!("CHAN Search Found: {}, {}", &service_type, &fullname);
-                            let (chan_name, full_name) = fullname
-                                .split_once("@")
-                                .expect("CHAN fullname without \"@\" unexpected.");
-                            let device_name = cutoff_address(full_name, Some(CHAN_SERVICE));
-
-                            let mut device_list_lock = device_list_chan
-                                .lock()
-                                .expect("Cannot get mutex lock of DanteDevices");
-
-                            device_list_lock.connect_chan(device_name);
-                        }
-                        ServiceEvent::ServiceResolved(service_info) => {
-                            info!("CHAN Service Resolved: {:?}", &service_info);
-                            let (chan_name, full_name) = service_info
-                                .get_fullname()
-                                .split_once("@")
-                                .expect("CHAN fullname without \"@\" unexpected.");
-                            let device_name = cutoff_address(full_name, Some(CHAN_SERVICE));
-                            let mut device_list_lock = device_list_chan
-                                .lock()
-                                .expect("Cannot get mutex lock of DanteDevices");
-                            device_list_lock.update_chan(
-                                device_name,
-                                CHANInfo {
-                                    name: chan_name.to_owned(),
-                                    id: service_info.get_property("id").map(|id_property| {
-                                        id_property
-                                            .val_str()
-                                            .to_owned()
-                                            .parse()
-                                            .expect("Couldn't parse chan service id")
-                                    }),
-                                    sample_rate: match service_info.get_property("rate") {
-                                        Some(rate_property) => rate_property.val_str().parse().ok(),
-                                        None => None,
-                                    },
-                                    encoding: match service_info.get_property("en") {
-                                        Some(encoding_property) => {
-                                            match encoding_property.val_str() {
-                                                "16" => Some(PCM16),
-                                                "24" => Some(PCM24),
-                                                "32" => Some(PCM32),
-                                                &_ => None,
-                                            }
-                                        }
-                                        None => None,
-                                    },
-                                    latency: match service_info.get_property("latency_ns") {
-                                        Some(latency_property) => latency_property
-                                            .val_str()
-                                            .parse()
-                                            .ok()
-                                            .map(Duration::from_nanos),
-                                        None => None,
-                                    },
-                                },
-                            );
-                        }
-                        ServiceEvent::ServiceRemoved(service_type, fullname) => {
-                            info!("CHAN Service Removed: a:{}, b:{}", &service_type, &fullname);
-                            let (chan_name, full_name) = fullname
-                                .split_once("@")
-                                .expect("CHAN fullname without \"@\" unexpected.");
-                            let device_name = cutoff_address(full_name, Some(CHAN_SERVICE));
-
-                            let mut device_list_lock = device_list_chan.lock().unwrap();
-                            device_list_lock.disconnect_chan(device_name);
-                        }
-                        ServiceEvent::SearchStopped(service_type) => {
-                            error!("CHAN Search Stopped: {}", &service_type);
-                        }
-                    }
-                }
-                sleep(Duration::from_millis(100));
-            }
-        });
+    /// Removes the devices that have been gone from every service for the removal grace period.
+    fn finalize_removals(&mut self) {
+        let gone: Vec<String> = self
+            .devices
+            .iter()
+            .filter(|(_, status)| {
+                status
+                    .gone_since
+                    .is_some_and(|gone_since| gone_since.elapsed() >= self.removal_grace)
+            })
+            .map(|(device_name, _)| device_name.to_string())
+            .collect();
+        for device_name in gone {
+            self.remove_device(&device_name);
+        }
+        self.debug_check_invariants();
+    }
 
-        Ok(())
+    fn remove_device(&mut self, device_name: &str) {
+        self.devices.remove(device_name);
+        if let Some(cache) = self.caches.get_mut(device_name) {
+            cache.disconnected_at = Some(Instant::now());
+        }
+        self.invalidate_query_results(device_name);
+        info!("{} is gone", device_name);
+        self.events.emit(DanteDeviceEvent::DeviceRemoved {
+            device_name: device_name.to_owned(),
+        });
     }
 
-    fn get_new_command_sequence_id(&mut self) -> u16 {
-        let return_id = self.current_command_sequence_id;
-        self.current_command_sequence_id += 1;
-        return_id
+    /// The DBC service of the device resolved. Connects it first, so a resolve that comes in before the service was found or after it was removed still has a cache to go to.
+    fn resolve_dbc(&mut self, device_name: &str, hostname: &str, info: DBCInfo) {
+        self.connect_service(device_name, DanteService::Dbc);
+        self.update_hostname(device_name, hostname);
+        self.update_dbc(device_name, info);
+        self.emit_service_resolved(device_name, DanteService::Dbc);
+        self.debug_check_invariants();
     }
 
-    fn make_dante_command(&mut self, command: [u8; 2], command_args: &[u8]) -> BytesMut {
-        let mut buffer = bytes::BytesMut::new();
-        buffer.extend_from_slice(&[0x28, 0x30]);
-        assert_eq!(buffer.len(), 2);
-        buffer.extend_from_slice(&((command_args.len() + 10) as u16).to_be_bytes());
-        assert_eq!(buffer.len(), 4);
-        buffer.extend_from_slice(&self.get_new_command_sequence_id().to_be_bytes());
-        assert_eq!(buffer.len(), 6);
-        buffer.extend(command);
-        assert_eq!(buffer.len(), 8);
-        buffer.extend_from_slice(&[0x00, 0x00]);
-        assert_eq!(buffer.len(), 10);
-        buffer.extend_from_slice(&command_args);
-        buffer
+    /// Same as resolve_dbc() for the CMC service.
+    fn resolve_cmc(&mut self, device_name: &str, hostname: &str, info: CMCInfo) {
+        self.connect_service(device_name, DanteService::Cmc);
+        self.update_hostname(device_name, hostname);
+        self.update_cmc(device_name, info);
+        self.emit_service_resolved(device_name, DanteService::Cmc);
+        self.debug_check_invariants();
     }
 
-    fn send_bytes_to_addresses(
-        addresses: &HashSet<Ipv4Addr>,
-        port: u16,
-        bytes: &[u8],
-    ) -> Result<(), Box<dyn Error>> {
-        let socket = UdpSocket::bind("0.0.0.0:0")?;
-        for address in addresses {
-            debug!(
-                "Sent bytes {:?} to {}:{}",
-                hex::encode(bytes),
-                address,
-                port
-            );
-            socket.send_to(bytes, (*address, port))?;
-        }
-        Ok(())
+    /// Same as resolve_dbc() for the ARC service.
+    fn resolve_arc(&mut self, device_name: &str, hostname: &str, info: ARCInfo) {
+        self.connect_service(device_name, DanteService::Arc);
+        self.update_hostname(device_name, hostname);
+        self.update_arc(device_name, info);
+        self.emit_service_resolved(device_name, DanteService::Arc);
+        self.debug_check_invariants();
     }
 
-    fn send_bytes_to_address(
-        address: &Ipv4Addr,
-        port: u16,
-        bytes: &[u8],
-    ) -> Result<(), Box<dyn Error>> {
-        let socket = UdpSocket::bind("0.0.0.0:0")?;
-
-        debug!(
-            "Sent bytes {:?} to {}:{}",
-            hex::encode(bytes),
-            address,
-            port
-        );
-        socket.send_to(bytes, (*address, port))?;
+    /// Same as resolve_dbc() for a CHAN service of the device.
+    fn resolve_chan(&mut self, device_name: &str, hostname: &str, info: CHANInfo) {
+        self.connect_service(device_name, DanteService::Chan);
+        self.update_hostname(device_name, hostname);
+        self.update_chan(device_name, info);
+        self.emit_service_resolved(device_name, DanteService::Chan);
+        self.debug_check_invariants();
+    }
 
-        Ok(())
+    /// A CHAN service resolved to a record that's already cached, see chan_record_unchanged(). Same as resolve_chan(), without making a CHANInfo only to find it's the one in the cache.
+    fn resolve_unchanged_chan(&mut self, device_name: &str, hostname: &str) {
+        self.connect_service(device_name, DanteService::Chan);
+        self.update_hostname(device_name, hostname);
+        self.touch_chan(device_name);
+        self.emit_service_resolved(device_name, DanteService::Chan);
+        self.debug_check_invariants();
     }
 
-    /// Makes a dante subscription on a device. Dante subscriptions are "stored" on the receiver side, where a transmitter device name and transmitter device channel name are associated with a specific channel number on the receiver side. The arguments for this function are exactly the arguments needed to construct the udp packet. Also, there is no need to start_discovery() beforehand, the two functionalities are separate.
-    pub fn make_subscription(
-        &mut self,
-        version: &DanteVersion,
-        rx_device_ip: &Ipv4Addr,
-        rx_channel_id: u16,
-        tx_device: &AsciiStr,
-        tx_channel: &AsciiStr,
-    ) -> Result<(), MakeSubscriptionError> {
-        let tx_device_name_buffer = tx_device.as_bytes();
-        let tx_channel_name_buffer = tx_channel.as_bytes();
-
-        let port: u16 = 4440;
-
-        let mut command_buffer = BytesMut::new();
-
-        match version {
-            DanteVersion::Dante4_4_1_3 => {
-                command_buffer.extend_from_slice(&[
-                    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x08, 0x00, 0x20, 0x01,
-                ]);
-                assert_eq!(command_buffer.len(), 10);
-                command_buffer.extend_from_slice(&rx_channel_id.to_be_bytes());
-                assert_eq!(command_buffer.len(), 12);
-                command_buffer.extend_from_slice(&[0x00, 0x03, 0x01, 0x14]);
-                assert_eq!(command_buffer.len(), 16);
-                let end_pos: u16 = (276 + tx_channel_name_buffer.len() + 1) as u16;
-                command_buffer.extend_from_slice(&end_pos.to_be_bytes());
-                assert_eq!(command_buffer.len(), 18);
-                command_buffer.extend_from_slice(&vec![0x00; 248]);
-                assert_eq!(command_buffer.len(), 266);
-                command_buffer.extend_from_slice(tx_channel_name_buffer);
-                command_buffer.extend_from_slice(&[0x00]);
-                command_buffer.extend_from_slice(tx_device_name_buffer);
-                command_buffer.extend_from_slice(&[0x00]);
-            }
-            DanteVersion::Dante4_2_1_3 => {
-                command_buffer.extend_from_slice(&[0x10, 0x01]);
-                assert_eq!(command_buffer.len(), 2);
-                command_buffer.extend_from_slice(&rx_channel_id.to_be_bytes());
-                assert_eq!(command_buffer.len(), 4);
-                command_buffer.extend_from_slice(&[0x01, 0x4C]);
-                assert_eq!(command_buffer.len(), 6);
-                let end_pos: u16 = (332 + tx_channel_name_buffer.len() + 1) as u16;
-                command_buffer.extend_from_slice(&end_pos.to_be_bytes());
-                assert_eq!(command_buffer.len(), 8);
-                command_buffer.extend_from_slice(&vec![0x00; 314]);
-                assert_eq!(command_buffer.len(), 322);
-                command_buffer.extend_from_slice(tx_channel_name_buffer);
-                command_buffer.extend_from_slice(&[0x00]);
-                command_buffer.extend_from_slice(tx_device_name_buffer);
-                command_buffer.extend_from_slice(&[0x00]);
-            }
-        }
-
-        match Self::send_bytes_to_address(
-            rx_device_ip,
-            port,
-            &self.make_dante_command(version.get_commands().command_subscription, &command_buffer),
-        ) {
-            Ok(_) => Ok(()),
-            Err(_) => Err(MakeSubscriptionError::ConnectionFailed),
-        }
+    /// Whether the cache of the device already has the CHAN record, so resolving it again changes nothing. Doesn't allocate, this is most of what discovery sees of a big network.
+    fn chan_record_unchanged(
+        &self,
+        device_name: &str,
+        chan_name: &str,
+        service_info: &ServiceInfo,
+    ) -> bool {
+        let id = service_info
+            .get_property_val_str("id")
+            .and_then(|id| id.parse().ok());
+        self.caches
+            .get(device_name)
+            .and_then(|cache| cache.chan_info.find(id, chan_name))
+            .is_some_and(|old| old.matches_service_info(chan_name, service_info))
     }
 
-    /// Clears a dante device subscription. Essentially the same as make_subscription except with an empty transmitter name and transmitter channel name.
-    pub fn clear_subscription(
-        &mut self,
-        version: &DanteVersion,
-        rx_device_ip: &Ipv4Addr,
-        rx_channel_id: u16,
-    ) -> Result<(), MakeSubscriptionError> {
-        let mut command_buffer = BytesMut::new();
-
-        match version {
-            DanteVersion::Dante4_4_1_3 => {
-                command_buffer.extend_from_slice(&[
-                    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x08, 0x00, 0x20, 0x01,
-                ]);
-                assert_eq!(command_buffer.len(), 10);
-                command_buffer.extend_from_slice(&rx_channel_id.to_be_bytes());
-                assert_eq!(command_buffer.len(), 12);
-                command_buffer.extend_from_slice(&[0x00, 0x03, 0x00, 0x00, 0x00, 0x00]);
-                assert_eq!(command_buffer.len(), 18);
-                command_buffer.extend_from_slice(&vec![0x00; 248]);
-                assert_eq!(command_buffer.len(), 266);
-            }
-            DanteVersion::Dante4_2_1_3 => {
-                command_buffer.extend_from_slice(&[0x10, 0x01]);
-                assert_eq!(command_buffer.len(), 2);
-                command_buffer.extend_from_slice(&rx_channel_id.to_be_bytes());
-                assert_eq!(command_buffer.len(), 4);
-                command_buffer.extend_from_slice(&vec![0x00; 318]);
-                assert_eq!(command_buffer.len(), 322);
-            }
-        }
-
-        let port: u16 = 4440;
-
-        match Self::send_bytes_to_address(
-            rx_device_ip,
-            port,
-            &self.make_dante_command(version.get_commands().command_subscription, &command_buffer),
-        ) {
-            Ok(_) => Ok(()),
-            Err(_) => Err(MakeSubscriptionError::ConnectionFailed),
+    /// A CHAN record of the device resolved again unchanged.
+    fn touch_chan(&mut self, device_name: &str) {
+        let now = Instant::now();
+        if let Some(cache) = self.caches.get_mut(device_name) {
+            cache.chan_info.last_resolved_at = Some(now);
         }
+        self.last_resolved_at = Some(now);
     }
 
-    /// Returns whether dante mdns discovery is running
-    pub fn is_running(&self) -> bool {
-        *self.running.lock().unwrap()
+    /// Emits ServiceResolved, if anyone is listening. Services resolve again every few minutes, so the event isn't even made up when nobody is.
+    fn emit_service_resolved(&mut self, device_name: &str, service: DanteService) {
+        if self.events.has_subscribers() {
+            self.events.emit(DanteDeviceEvent::ServiceResolved {
+                device_name: device_name.to_owned(),
+                service,
+            });
+        }
     }
 
-    /// Stops mdns discovery
-    pub fn stop_discovery(&self) {
-        *self.running.lock().unwrap() = false;
+    /// Checks that every device in the list has a cache, and that a cache is marked disconnected exactly when its device isn't in the list. Only in debug builds, after each change discovery makes to the list.
+    fn debug_check_invariants(&self) {
+        if !cfg!(debug_assertions) {
+            return;
+        }
+        for device_name in self.devices.keys() {
+            match self.caches.get(device_name) {
+                Some(cache) => debug_assert!(
+                    cache.disconnected_at.is_none(),
+                    "Cache of connected device {} is marked disconnected",
+                    device_name
+                ),
+                None => panic!("Device {} has no cache", device_name),
+            }
+        }
+        for (device_name, cache) in &self.caches {
+            debug_assert!(
+                cache.disconnected_at.is_some() || self.devices.contains_key(device_name),
+                "Cache of {} isn't marked disconnected but the device isn't in the list",
+                device_name
+            );
+        }
     }
 
-    /// Returns a list of all the mdns dante device names that were found on the network.
-    pub fn get_device_names(&self) -> Vec<String> {
-        self.device_list
-            .lock()
-            .unwrap()
-            .devices
-            .keys()
-            .map(|device| device.to_owned())
-            .collect()
+    /// Removes the caches of devices that have been disconnected for longer than ttl.
+    fn purge_stale_caches(&mut self, ttl: Duration) {
+        self.caches
+            .retain(|device_name, cache| match cache.disconnected_at {
+                Some(disconnected_at) if disconnected_at.elapsed() > ttl => {
+                    debug!("Purging cache of {}", device_name);
+                    false
+                }
+                _ => true,
+            });
+        self.debug_check_invariants();
     }
 
-    /// Returns a list descriptions of all the mdns dante device names that were found on the network.
-    pub fn get_device_descriptions(&self) -> Vec<String> {
-        let device_list = self.device_list.lock().unwrap();
-        let device_info_map = device_list.devices.iter().map(|(device, status)| {
-            (
-                device,
-                status,
-                device_list
-                    .caches
-                    .get(device)
-                    .expect("Should have a cache for any given connected device."),
-            )
-        });
-        device_info_map.into_iter()
-            .map(|(device, status, cache)| {
-                let mut info = format!(
-                    "{}:\ndbc status: {}\ncmc status: {}\narc status: {}\nchan status: {}\nid: {}\nmanufacturer: {}\nmodel: {}\nrouter_vers: {}\nrouter_info: {}\nARC port: {}\nIP: {}",
-                    device,
-                    match status.connected_dbc {
-                        true => "Connected",
-                        false => "Disconnected",
-                    },
-                    match status.connected_cmc {
-                        true => "Connected",
-                        false => "Disconnected",
-                    },
-                    match status.connected_arc {
-                        true => "Connected",
-                        false => "Disconnected",
-                    },
-                    match status.connected_chan {
-                        true => "Connected",
-                        false => "Disconnected",
-                    },
-                    match &cache.cmc_info {
-                        Some(cmc_info) => {cmc_info.id.to_owned()}
-                        None => "N/A".to_string()
-                    },
-                    match &cache.cmc_info {
-                        Some(cmc_info) => {cmc_info.manufacturer.to_owned()}
-                        None => "N/A".to_string()
-                    },
-                    match &cache.cmc_info {
-                        Some(cmc_info) => {cmc_info.model.to_owned()}
-                        None => "N/A".to_string()
-                    },
-                    match &cache.arc_info {
-                        Some(arc_info) => {arc_info.router_vers.to_owned()}
-                        None => "N/A".to_string()
-                    },
-                    match &cache.arc_info {
-                        Some(arc_info) => {arc_info.router_info.to_owned()}
-                        None => "N/A".to_string()
-                    },
-                    match &cache.arc_info {
-                        Some(arc_info) => {arc_info.port.to_string()}
-                        None => "N/A".to_string()
-                    },
-                    match &cache.arc_info {
-                        Some(arc_info) => {format!("{:?}", &arc_info.addresses)}
-                        None => "N/A".to_string()
-                    }
-                );
-                info += "\nChannels:";
-                let mut chan_info_sorted: Vec<&CHANInfo> = cache.chan_info.iter().collect();
-                chan_info_sorted.sort_by(|x, y| x.id.partial_cmp(&y.id).unwrap());
-                for chan_info in chan_info_sorted {
-                    info += &format!("\n\"{}\"", chan_info.name);
-                }
-                info
-            })
-            .collect()
+    fn new() -> Self {
+        DanteDeviceList {
+            devices: HashMap::new(),
+            caches: HashMap::new(),
+            events: EventSenders::default(),
+            channel_watchers: Arc::default(),
+            untracked_services: Vec::new(),
+            other_services: BTreeMap::new(),
+            #[cfg(feature = "aes67")]
+            aes67_streams: sap::Aes67Streams::new(),
+            last_resolved_at: None,
+            removal_grace: DEFAULT_REMOVAL_GRACE,
+            unresolved_routes: HashMap::new(),
+            tags: BTreeMap::new(),
+            reachability: HashMap::new(),
+        }
     }
+}
 
-    pub fn new() -> Self {
-        DanteDeviceManager {
-            device_list: Arc::new(Mutex::new(DanteDeviceList::new())),
-            running: Arc::new(Mutex::new(false)),
-            current_command_sequence_id: 0,
+/// Cutoff the address from a hostname. Address default is "local."
+fn cutoff_address<'a>(hostname: &'a str, address: Option<&'a str>) -> &'a str {
+    let address = address.unwrap_or("local.");
+    match hostname
+        .strip_suffix(address)
+        .and_then(|stripped| stripped.strip_suffix('.'))
+    {
+        None => {
+            warn!(
+                "Device \"{}\" doesn't end with \".{}\". This is abnormal.",
+                hostname, address
+            );
+            hostname
         }
+        Some(stripped) => stripped,
     }
 }
 
-impl Default for DanteDeviceManager {
-    fn default() -> Self {
-        DanteDeviceManager::new()
+/// Logs a service resolving. Every service of every device resolves again every few minutes, so the whole record, TXT properties and all, is only formatted when debug logging is on.
+pub(crate) fn log_service_resolved(service: &str, service_info: &ServiceInfo) {
+    if log_enabled!(Level::Debug) {
+        debug!("{} Service Resolved: {:?}", service, service_info);
+    } else {
+        info!(
+            "{} Service Resolved: {}",
+            service,
+            service_info.get_fullname()
+        );
     }
 }
 
-/// Print raw data received from mDNS discovery requests at addr.
-fn print_mdns_with_address(addr: &str, poll_time: Duration) {
-    info!("Starting discovery");
-
-    let mdns = ServiceDaemon::new().expect("Failed to create mdns service daemon!");
-    let receiver = mdns
-        .browse(addr)
-        .unwrap_or_else(|_| panic!("Failed to browse for {}", addr));
-
-    let keep_polling = Arc::new(Mutex::new(true));
-    let keep_polling_thread = keep_polling.clone();
-
-    let thread = std::thread::spawn(move || {
-        debug!("Starting discovery thread");
-        while *keep_polling_thread.lock().unwrap() {
-            while let Ok(event) = receiver.try_recv() {
-                match event {
-                    ServiceEvent::SearchStarted(service_name) => {
-                        println!("Search Started: {}", &service_name)
-                    }
-                    ServiceEvent::ServiceFound(service_name, host_service_name) => {
-                        println!("Search Found: {}, {}", &service_name, &host_service_name)
-                    }
-                    ServiceEvent::ServiceResolved(service_info) => {
-                        println!("Service Resolved: {:?}", &service_info);
-                    }
-                    ServiceEvent::ServiceRemoved(a, b) => {
-                        println!("Service Removed: {}, {}", &a, &b)
-                    }
-                    ServiceEvent::SearchStopped(a) => {
-                        println!("Search Stopped: {}", &a)
-                    }
-                }
-            }
-            sleep(Duration::from_millis(100));
+/// Splits a CHAN service fullname ("channel@device._netaudio-chan._udp.local.") into the channel and device names. None, with a warning, for a name without the "@".
+fn split_chan_fullname(fullname: &str) -> Option<(&str, &str)> {
+    match fullname.split_once('@') {
+        Some((chan_name, full_name)) => {
+            Some((chan_name, cutoff_address(full_name, Some(CHAN_SERVICE))))
         }
-    });
-
-    sleep(poll_time);
-
-    *keep_polling.lock().unwrap() = false;
+        None => {
+            warn!(
+                "Ignoring CHAN service without \"@\" in its name: {}",
+                fullname
+            );
+            None
+        }
+    }
+}
 
-    thread.join().unwrap();
+/// Frames a control command: the 0x28 0x30 magic, the total length, the sequence ID, the command ID and two zero bytes, followed by args. This is what every command the crate sends looks like, so it's here for trying out commands the crate doesn't know yet. Devices answer with the same sequence ID, so keep them unique among the commands in flight.
+pub fn make_dante_command(sequence_id: u16, command: [u8; 2], args: &[u8]) -> Bytes {
+    let mut buffer = BytesMut::new();
+    buffer.extend_from_slice(&[0x28, 0x30]);
+    assert_eq!(buffer.len(), 2);
+    buffer.extend_from_slice(&((args.len() + COMMAND_HEADER_LENGTH) as u16).to_be_bytes());
+    assert_eq!(buffer.len(), 4);
+    buffer.extend_from_slice(&sequence_id.to_be_bytes());
+    assert_eq!(buffer.len(), 6);
+    buffer.extend(command);
+    assert_eq!(buffer.len(), 8);
+    buffer.extend_from_slice(&[0x00, 0x00]);
+    assert_eq!(buffer.len(), COMMAND_HEADER_LENGTH);
+    buffer.extend_from_slice(args);
+    buffer.freeze()
 }
 
-/// Print raw data received from mDNS discovery requests to the "_netaudio-cmc._udp.local." address.
-pub fn print_cmc(poll_time: Duration) {
-    print_mdns_with_address(CMC_SERVICE, poll_time);
+/// A response to a control command, split into its header fields. See parse_dante_response().
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DanteResponse<'a> {
+    pub total_length: u16,
+    /// The sequence ID of the command this answers.
+    pub sequence_id: u16,
+    pub command: [u8; 2],
+    /// Raw status code. What the codes mean hasn't been worked out yet.
+    pub status: u16,
+    pub payload: &'a [u8],
 }
 
-/// Print raw data received from mDNS discovery requests to the "_netaudio-dbc._udp.local." address.
-pub fn print_dbc(poll_time: Duration) {
+/// Splits a response to a control command into its header and payload, the other way around from make_dante_command(). Responses have the same 10 byte header as commands, with the status where commands have zeros.
+pub fn parse_dante_response(buf: &[u8]) -> Result<DanteResponse<'_>, ParseError> {
+    let mut reader = Reader::new(buf);
+    if reader.read_bytes(2)? != [0x28, 0x30] {
+        return Err(ParseError::InvalidMagic);
+    }
+    let total_length = reader.read_u16()?;
+    let sequence_id = reader.read_u16()?;
+    let command = reader.read_bytes(2)?;
+    let status = reader.read_u16()?;
+    if total_length as usize != buf.len() {
+        return Err(ParseError::LengthMismatch {
+            header: total_length,
+            actual: buf.len(),
+        });
+    }
+    Ok(DanteResponse {
+        total_length,
+        sequence_id,
+        command: [command[0], command[1]],
+        status,
+        payload: reader.read_bytes(buf.len() - 10)?,
+    })
+}
+
+/// Checks that a name can go into a command. Names are kept as UTF-8 everywhere else, since that's what mdns and read-backs hand us and some manufacturers ship names with umlauts, but commands carry names as ascii bytes. Dante Controller only lets you pick ascii names to begin with (letters, digits and '-' for devices, and anything printable but '=', '.' and '@' for channels, as far as I can tell), so a name that fails this was set by something other than Dante Controller and the device can't be subscribed to by that name. Nothing is transliterated, since "Bühne" and "Buhne" are different devices as far as Dante is concerned.
+pub fn as_dante_name(name: &str) -> Result<&AsciiStr, MakeSubscriptionError> {
+    AsciiStr::from_ascii(name).map_err(|_| MakeSubscriptionError::NonAsciiName {
+        name: name.to_owned(),
+        offending: name.chars().filter(|c| !c.is_ascii()).collect(),
+    })
+}
+
+/// Splits a "device/channel" path.
+fn parse_channel_path(path: &str) -> Result<(&str, &str), MakeSubscriptionError> {
+    match path.split_once('/') {
+        Some((device, channel))
+            if !device.is_empty() && !channel.is_empty() && !channel.contains('/') =>
+        {
+            Ok((device, channel))
+        }
+        _ => Err(MakeSubscriptionError::InvalidChannelPath(path.to_owned())),
+    }
+}
+
+/// Parses one page of the response to COMMAND_RXCHANNELNAMES. Each channel is a 20 byte record starting at byte 12. Layout of a record as far as I can tell:
+/// 0: channel id, 6: tx channel name offset, 8: tx device name offset, 10: rx channel name offset, 14: subscription status.
+/// A tx device offset of 0 means the channel isn't subscribed, and a tx channel offset of 0 means the tx channel has the same name as the rx channel.
+fn parse_rx_channels_page(
+    response: &[u8],
+    channel_count: usize,
+) -> Result<Vec<SubscriptionEntry>, QueryError> {
+    let mut reader = Reader::new(response);
+    let mut entries = Vec::with_capacity(channel_count);
+    for index in 0..channel_count {
+        let rx_channel_id = reader.seek(12 + index * 20).read_u16()?;
+        let tx_channel_offset = reader.skip(4)?.read_u16()?;
+        let tx_device_offset = reader.read_u16()?;
+        let rx_channel_offset = reader.read_u16()?;
+        let status = reader.skip(2)?.read_u16()?;
+
+        let rx_channel_name = reader.label_at(rx_channel_offset as usize)?;
+        let (tx_device, tx_channel) = match tx_device_offset {
+            0 => (None, None),
+            _ => {
+                let tx_device = reader.label_at(tx_device_offset as usize)?;
+                let tx_channel = match tx_channel_offset {
+                    0 => rx_channel_name.clone(),
+                    _ => reader.label_at(tx_channel_offset as usize)?,
+                };
+                (Some(tx_device), Some(tx_channel))
+            }
+        };
+
+        entries.push(SubscriptionEntry {
+            rx_channel_id,
+            rx_channel_name,
+            tx_device,
+            tx_channel,
+            status,
+        });
+    }
+    Ok(entries)
+}
+
+/// Parses one page of the response to COMMAND_TXCHANNELS into (channel id, name). Each channel is an 8 byte record starting at byte 12, with the channel id at 0 and the name offset at 4, as far as I can tell.
+fn parse_tx_channels_page(
+    response: &[u8],
+    channel_count: usize,
+) -> Result<Vec<(u16, String)>, QueryError> {
+    let mut reader = Reader::new(response);
+    let mut channels = Vec::with_capacity(channel_count);
+    for index in 0..channel_count {
+        let channel_id = reader.seek(12 + index * 8).read_u16()?;
+        let name_offset = reader.skip(2)?.read_u16()?;
+        channels.push((channel_id, reader.label_at(name_offset as usize)?));
+    }
+    Ok(channels)
+}
+
+/// Why a command sent to several addresses at once, like by make_subscription_broadcast(), got to none of them: what sending to each address failed with, in the order they were tried.
+#[derive(Debug)]
+pub struct SendError {
+    pub failures: Vec<(Ipv4Addr, std::io::Error)>,
+}
+
+impl Display for SendError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if self.failures.is_empty() {
+            return write!(f, "there was no address to send to");
+        }
+        write!(f, "couldn't send to")?;
+        for (index, (address, error)) in self.failures.iter().enumerate() {
+            let separator = if index == 0 { " " } else { ", " };
+            write!(f, "{}{} ({})", separator, address, error)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for SendError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        let (_, error) = self.failures.first()?;
+        Some(error)
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum MakeSubscriptionError {
+    /// The device at the address is known not to have this RX channel, going by its last read-back or channel count hint.
+    #[error("rx channel {0} is out of range for the device")]
+    ChannelOutOfRange(u16),
+    /// RX channel ids start at 1, devices reject 0.
+    #[error("rx channel ids start at 1, 0 isn't one")]
+    ZeroChannelId,
+    /// The command couldn't be sent to any of the addresses it went to.
+    #[error("error sending udp packet")]
+    ConnectionFailed(#[source] SendError),
+    /// A channel path that isn't "device/channel" with both parts non-empty.
+    #[error("invalid channel path \"{0}\", expected \"device/channel\"")]
+    InvalidChannelPath(String),
+    #[error("rx channel \"{0}\" not found")]
+    ChannelNotFound(String),
+    /// More than one channel of the device has the name, see ChannelNameConflict.
+    #[error("more than one channel is called \"{name}\": {candidates:?}")]
+    AmbiguousChannelName { name: String, candidates: Vec<u16> },
+    /// The device's ARC record doesn't advertise a Dante version this crate knows.
+    #[error("unknown dante version for device \"{0}\"")]
+    UnknownVersion(String),
+    /// Dante names are ascii, see as_dante_name().
+    #[error("name \"{name}\" has characters that aren't ascii: {offending:?}")]
+    NonAsciiName { name: String, offending: Vec<char> },
+    #[error("couldn't look up the rx device")]
+    Query(#[from] QueryError),
+    /// See QueryError::PayloadTooLarge.
+    #[error("command is {size} bytes, {} over the {limit} byte limit", size - limit)]
+    PayloadTooLarge { size: usize, limit: usize },
+    /// See QueryError::NetworkUnavailable.
+    #[error("the dante network is unavailable")]
+    NetworkUnavailable,
+}
+#[derive(thiserror::Error, Debug)]
+pub enum ClearSubscriptionError {
+    /// For clearing by device name, when discovery doesn't know the device. clear_subscription() itself takes an address, so it never returns this.
+    #[error("device \"{0}\" not found")]
+    DeviceNotFound(String),
+    /// The device at the address is known not to have this RX channel, going by its last read-back or channel count hint.
+    #[error("rx channel {0} not found")]
+    ChannelNotFound(u16),
+    /// See MakeSubscriptionError::ZeroChannelId.
+    #[error("rx channel ids start at 1, 0 isn't one")]
+    ZeroChannelId,
+    #[error("error sending udp packet")]
+    ConnectionFailed(#[source] std::io::Error),
+    /// See QueryError::PayloadTooLarge.
+    #[error("command is {size} bytes, {} over the {limit} byte limit", size - limit)]
+    PayloadTooLarge { size: usize, limit: usize },
+    /// See QueryError::NetworkUnavailable.
+    #[error("the dante network is unavailable")]
+    NetworkUnavailable,
+    /// For clearing by device name, when the device couldn't be read back.
+    #[error("couldn't look up the device")]
+    Query(#[from] QueryError),
+    /// For clearing by device name, see MakeSubscriptionError::UnknownVersion.
+    #[error("unknown dante version for device \"{0}\"")]
+    UnknownVersion(String),
+}
+
+/// Why a command wasn't framed, see DanteDeviceManager::make_dante_command(). Turned into the variant of the same name of whichever error the caller returns.
+enum CommandRefused {
+    PayloadTooLarge { size: usize, limit: usize },
+    NetworkUnavailable,
+}
+
+impl From<CommandRefused> for QueryError {
+    fn from(refused: CommandRefused) -> Self {
+        match refused {
+            CommandRefused::PayloadTooLarge { size, limit } => {
+                QueryError::PayloadTooLarge { size, limit }
+            }
+            CommandRefused::NetworkUnavailable => QueryError::NetworkUnavailable,
+        }
+    }
+}
+
+impl From<CommandRefused> for MakeSubscriptionError {
+    fn from(refused: CommandRefused) -> Self {
+        match refused {
+            CommandRefused::PayloadTooLarge { size, limit } => {
+                MakeSubscriptionError::PayloadTooLarge { size, limit }
+            }
+            CommandRefused::NetworkUnavailable => MakeSubscriptionError::NetworkUnavailable,
+        }
+    }
+}
+
+impl ClearSubscriptionError {
+    /// A failed lookup or read-back of a device to clear by name, with an unknown device as DeviceNotFound rather than Query(DeviceNotFound).
+    fn from_lookup(error: QueryError) -> Self {
+        match error {
+            QueryError::DeviceNotFound(device_name) => {
+                ClearSubscriptionError::DeviceNotFound(device_name)
+            }
+            error => ClearSubscriptionError::Query(error),
+        }
+    }
+}
+
+impl From<CommandRefused> for ClearSubscriptionError {
+    fn from(refused: CommandRefused) -> Self {
+        match refused {
+            CommandRefused::PayloadTooLarge { size, limit } => {
+                ClearSubscriptionError::PayloadTooLarge { size, limit }
+            }
+            CommandRefused::NetworkUnavailable => ClearSubscriptionError::NetworkUnavailable,
+        }
+    }
+}
+
+/// Why a subscription command didn't go out, see DanteDeviceManager::send_subscription_command(). make_subscription() and clear_subscription() share the checks and sending, and turn this into their own error.
+enum SubscriptionCommandError {
+    ZeroChannelId,
+    ChannelNotFound(u16),
+    Refused(CommandRefused),
+    SendFailed(SendError),
+}
+
+impl From<CommandRefused> for SubscriptionCommandError {
+    fn from(refused: CommandRefused) -> Self {
+        SubscriptionCommandError::Refused(refused)
+    }
+}
+
+impl From<SubscriptionCommandError> for MakeSubscriptionError {
+    fn from(error: SubscriptionCommandError) -> Self {
+        match error {
+            SubscriptionCommandError::ZeroChannelId => MakeSubscriptionError::ZeroChannelId,
+            SubscriptionCommandError::ChannelNotFound(rx_channel_id) => {
+                MakeSubscriptionError::ChannelOutOfRange(rx_channel_id)
+            }
+            SubscriptionCommandError::Refused(refused) => refused.into(),
+            SubscriptionCommandError::SendFailed(error) => {
+                MakeSubscriptionError::ConnectionFailed(error)
+            }
+        }
+    }
+}
+
+impl From<SubscriptionCommandError> for ClearSubscriptionError {
+    fn from(error: SubscriptionCommandError) -> Self {
+        match error {
+            SubscriptionCommandError::ZeroChannelId => ClearSubscriptionError::ZeroChannelId,
+            SubscriptionCommandError::ChannelNotFound(rx_channel_id) => {
+                ClearSubscriptionError::ChannelNotFound(rx_channel_id)
+            }
+            SubscriptionCommandError::Refused(refused) => refused.into(),
+            // Clearing only goes to one address, so there's one failure.
+            SubscriptionCommandError::SendFailed(error) => {
+                let error = error.failures.into_iter().next().map_or_else(
+                    || std::io::Error::other("nothing was sent"),
+                    |(_, error)| error,
+                );
+                ClearSubscriptionError::ConnectionFailed(error)
+            }
+        }
+    }
+}
+#[derive(thiserror::Error, Debug)]
+pub enum CopyRoutingError {
+    #[error("couldn't read back the subscriptions of {device}")]
+    ReadBack {
+        device: String,
+        #[source]
+        source: QueryError,
+    },
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum DiscoveryError {
+    /// The mdns daemon couldn't start, usually because there's no usable multicast interface (containers, some VPNs). Everything that works on addresses directly still works.
+    #[error("failed to start the mdns daemon")]
+    DaemonInit(#[source] mdns_sd::Error),
+    #[error("failed to browse for {service}")]
+    Browse {
+        service: String,
+        #[source]
+        source: mdns_sd::Error,
+    },
+    /// Couldn't listen for SAP announcements, usually because another program has the port.
+    #[cfg(feature = "aes67")]
+    #[error("failed to listen for SAP announcements")]
+    SapListener(#[source] std::io::Error),
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum QueryError {
+    #[error("device \"{0}\" not found")]
+    DeviceNotFound(String),
+    #[error("device \"{0}\" was discovered but has no resolved address")]
+    DeviceUnresolved(String),
+    #[error("error sending or receiving udp packet")]
+    ConnectionFailed(#[source] std::io::Error),
+    #[error("device did not respond in time")]
+    Timeout,
+    #[error("device sent a malformed response")]
+    InvalidResponse,
+    /// The command would be a bigger datagram than set_max_datagram_size() allows, so it wasn't sent.
+    #[error("command is {size} bytes, {} over the {limit} byte limit", size - limit)]
+    PayloadTooLarge { size: usize, limit: usize },
+    /// The network watchdog has the network down, so nothing was sent, see DanteDeviceManager::set_network_watchdog().
+    #[error("the dante network is unavailable")]
+    NetworkUnavailable,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum RefreshError {
+    #[error("device \"{0}\" not found")]
+    DeviceNotFound(String),
+    #[error("discovery isn't running")]
+    DiscoveryNotRunning,
+    #[error("no CHAN record resolved in time")]
+    Timeout,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum WaitError {
+    #[error("device \"{0}\" not found")]
+    DeviceNotFound(String),
+    #[error("timed out waiting")]
+    Timeout,
+    #[error("cancelled while waiting")]
+    Cancelled,
+}
+
+/// The subscription of a single RX channel, as read back from the device.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SubscriptionEntry {
+    pub rx_channel_id: u16,
+    pub rx_channel_name: String,
+    /// Transmitter device name, or None if the channel isn't subscribed to anything.
+    pub tx_device: Option<String>,
+    /// Transmitter channel name, or None if the channel isn't subscribed to anything.
+    pub tx_channel: Option<String>,
+    /// Raw subscription status code as reported by the device.
+    pub status: u16,
+}
+
+/// Routing state of a channel. RX channels carry their current subscription, TX channels carry how many RX channels on the network are subscribed to them.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ChannelRouting {
+    Rx {
+        /// (tx device, tx channel), or None if the channel isn't subscribed.
+        subscription: Option<(String, String)>,
+    },
+    Tx {
+        subscriber_count: u16,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ChannelDirection {
+    Rx,
+    Tx,
+}
+
+/// A channel of a device. Sample rate, encoding and latency are only known for channels advertised over mdns.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ChannelInfo {
+    pub id: Option<u16>,
+    /// The label of the channel, what it's called in Dante Controller and the CHAN record. For channels without a CHAN record, whatever name the device was queried for.
+    pub name: String,
+    /// The name the channel has no matter what it's labelled, like "01". Subscriptions take it as well as the label, so it still works for a channel whose label was cleared or changed. Only known for TX channels, once the device's TX channel names were queried.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub canonical: Option<String>,
+    pub direction: ChannelDirection,
+    pub sample_rate: Option<u32>,
+    pub encoding: Option<DanteDeviceEncoding>,
+    pub latency: Option<Duration>,
+    /// None when the routing of the channel wasn't read back.
+    pub routing: Option<ChannelRouting>,
+    /// Every TXT property of the CHAN record as it came in, the ones parsed into the fields above included. unknown_properties() has just the ones the crate doesn't know about.
+    pub extra_properties: HashMap<String, String>,
+    pub source: ChannelSource,
+}
+
+/// Which of a channel's names a name given to a routing call turned out to be, see ChannelInfo::canonical.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ChannelNameKind {
+    Label,
+    Canonical,
+}
+
+/// Where what's known about a channel came from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ChannelSource {
+    /// A CHAN record.
+    Mdns,
+    /// Queried from the device.
+    Query,
+    /// Only the id is known, the device didn't say what the channel is called. The name is empty, so these can only be routed by id.
+    Placeholder,
+}
+
+impl ChannelInfo {
+    /// A channel known by id and name only.
+    fn bare(
+        id: u16,
+        name: String,
+        direction: ChannelDirection,
+        source: ChannelSource,
+    ) -> ChannelInfo {
+        ChannelInfo {
+            id: Some(id),
+            name,
+            canonical: None,
+            direction,
+            sample_rate: None,
+            encoding: None,
+            latency: None,
+            routing: None,
+            extra_properties: HashMap::new(),
+            source,
+        }
+    }
+
+    /// The TXT properties of the CHAN record that the crate doesn't know about, like vendor specific keys, and an "en" it couldn't parse.
+    pub fn unknown_properties(&self) -> HashMap<String, String> {
+        unknown_chan_properties(&self.extra_properties)
+    }
+}
+
+/// What a CHAN record says about the audio of a channel, see DanteDeviceEvent::ChannelParamsChanged.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ChannelParams {
+    pub sample_rate: Option<u32>,
+    pub encoding: Option<DanteDeviceEncoding>,
+    pub latency: Option<Duration>,
+}
+
+/// Channels of one direction of a device that go by the same name, which happens after sloppy renaming. Subscribing by such a name is ambiguous, the device picks one of them, so the name based functions refuse to and return MakeSubscriptionError::AmbiguousChannelName instead.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ChannelNameConflict {
+    pub direction: ChannelDirection,
+    pub name: String,
+    /// Ids of the channels with the name, sorted.
+    pub ids: Vec<u16>,
+}
+
+/// Result of get_channels_with_routing(). fetched_at is the time of the oldest read-back that went into the result. When a device couldn't be queried its last known read-back is used and stale is set, so the data is still shown but shouldn't be trusted.
+#[derive(Clone, Debug)]
+pub struct ChannelsWithRouting {
+    pub channels: Vec<ChannelInfo>,
+    pub fetched_at: Instant,
+    pub stale: bool,
+    /// The scan was cancelled part way, so subscriber counts may be low.
+    pub cancelled: bool,
+}
+
+/// What start_discovery() started, so it can be torn down completely.
+#[derive(Default)]
+struct DiscoveryThreads {
+    threads: Vec<JoinHandle<()>>,
+    daemons: Vec<ServiceDaemon>,
+}
+
+/// Returned by start_discovery(), for stopping discovery and waiting for its threads without going through the manager, like from another thread while the manager is locked. Dropping it leaves discovery running. It shares the threads with the manager, so stop_and_join() and DanteDeviceManager::stop_discovery_and_wait() do the same thing, and a handle kept around from before a restart stops the discovery that's running now.
+pub struct DiscoveryHandle {
+    running: Arc<Mutex<bool>>,
+    discovery: Arc<Mutex<DiscoveryThreads>>,
+}
+
+impl DiscoveryHandle {
+    /// Stops discovery, then waits for the discovery threads to finish and shuts down the mdns daemons, so nothing touches the device list or the network anymore once it returns.
+    pub fn stop_and_join(self) {
+        *self.running.lock().unwrap() = false;
+        let discovery = std::mem::take(&mut *self.discovery.lock().unwrap());
+        for thread in discovery.threads {
+            if thread.join().is_err() {
+                error!("Discovery thread panicked");
+            }
+        }
+        for daemon in discovery.daemons {
+            if let Err(error) = daemon.shutdown() {
+                error!("Failed to shut down mdns daemon: {}", error);
+            }
+        }
+    }
+}
+
+impl Debug for DiscoveryHandle {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DiscoveryHandle")
+            .field("running", &*self.running.lock().unwrap())
+            .finish_non_exhaustive()
+    }
+}
+
+/// A Dante Device Manager stores information related to interacting with dante devices. Right now, it stores mdns information found from start_discovery() and a sequence ID. Currently, the control of dante devices is separate from the discovery of them. I found that for some devices on the network, mdns discovery can be slow or not happen at all, so I switched to using direct ip addresses and channel numbers/names (essentially exactly the information that is needed to send the udp packet to make the connection). In the case of make_subscription() and clear_subscription(), the only state changed by DanteDeviceManager is a sequence ID, which is an incrementing 16-bit integer, though whether this is really needed is suspect.
+pub struct DanteDeviceManager {
+    device_list: Arc<Mutex<DanteDeviceList>>,
+    running: Arc<Mutex<bool>>,
+    discovery: Arc<Mutex<DiscoveryThreads>>,
+    metrics: Arc<Metrics>,
+    packet_capture: Arc<PacketCapture>,
+    reconnect_policy: ReconnectPolicy,
+    redundancy_heuristic: RedundancyHeuristic,
+    discovery_services: HashSet<DanteService>,
+    /// Extra service types to browse for, see add_browse_target().
+    browse_targets: Vec<(String, ServiceResolver)>,
+    #[cfg(feature = "aes67")]
+    aes67_discovery: bool,
+    /// Services reconnect_discovery() was called for, picked up by their discovery threads.
+    reconnect_requests: Arc<Mutex<HashSet<DanteService>>>,
+    /// Services whose mdns search stopped, see get_services_status().
+    stopped_searches: Arc<Mutex<HashMap<DanteService, String>>>,
+    /// Starts the mdns daemon discovery runs on. Always ServiceDaemon::new() outside of tests, which swap it to see how discovery copes with mdns failing.
+    mdns_daemon: fn() -> Result<ServiceDaemon, mdns_sd::Error>,
+    current_command_sequence_id: u16,
+    /// See set_max_datagram_size().
+    max_datagram_size: usize,
+    /// The socket commands go out on, see bind_command_socket(). None sends every command from a socket of its own.
+    command_socket: Option<CommandSocket>,
+    /// See set_address_translator().
+    address_translator: Option<Box<AddressTranslator>>,
+    routing_max_age: Duration,
+    query_max_age: Duration,
+    resolve_timeout: Duration,
+    response_timeout: Duration,
+    chan_refresh_timeout: Duration,
+    /// Bumped to make every discovery thread browse again.
+    rebrowse_generation: Arc<AtomicUsize>,
+    /// See set_network_watchdog().
+    network_watchdog_window: Option<Duration>,
+    /// Set while the network watchdog has the network down.
+    network_down: Arc<AtomicBool>,
+    cache_ttl: Duration,
+    /// See take_read_lock_max_hold().
+    #[cfg(any(test, feature = "test-util"))]
+    lock_probe: LockProbe,
+}
+
+impl DanteDeviceManager {
+    /// Spawns the discovery service in a separate thread. Call stop_discovery() to end it, or stop_and_join() on the returned handle to also wait for it to exit. Fails if mdns can't be started on this machine, in which case the manager can still be used with addresses directly.
+    pub fn start_discovery(&self) -> Result<DiscoveryHandle, DiscoveryError> {
+        info!("Starting discovery");
+
+        // Spawn threads equal to the number of different addresses we are discovering on.
+        let mdns = (self.mdns_daemon)().map_err(DiscoveryError::DaemonInit)?;
+        let browse = |service: &str| {
+            mdns.browse(service).map_err(|source| {
+                if let Err(error) = mdns.shutdown() {
+                    error!("Failed to shut down mdns daemon: {}", error);
+                }
+                DiscoveryError::Browse {
+                    service: service.to_owned(),
+                    source,
+                }
+            })
+        };
+        let tracked = |service| self.discovery_services.contains(&service);
+        let mut receivers = HashMap::new();
+        for service in DanteService::ALL
+            .into_iter()
+            .filter(|service| tracked(*service))
+        {
+            receivers.insert(service, browse(service.service_type())?);
+        }
+
+        let mut other_receivers = Vec::new();
+        for (service_type, resolver) in &self.browse_targets {
+            other_receivers.push((
+                service_type.clone(),
+                browse(service_type)?,
+                resolver.clone(),
+            ));
+        }
+        #[cfg(feature = "aes67")]
+        let sap_socket = match self.aes67_discovery {
+            true => Some(sap::bind_sap_socket().map_err(|error| {
+                if let Err(error) = mdns.shutdown() {
+                    error!("Failed to shut down mdns daemon: {}", error);
+                }
+                DiscoveryError::SapListener(error)
+            })?),
+            false => None,
+        };
+
+        self.device_list.lock().unwrap().untracked_services = DanteService::ALL
+            .into_iter()
+            .filter(|service| !tracked(*service))
+            .collect();
+        self.stopped_searches.lock().unwrap().clear();
+        *self.running.lock().unwrap() = true;
+
+        let rebrowse_generation = self.rebrowse_generation.clone();
+        let mut threads = Vec::new();
+
+        // Discovery for DBC
+        if let Some(mut dbc_receiver) = receivers.remove(&DanteService::Dbc) {
+            // Fresh Arcs to move into thread.
+            let device_list_dbc = self.device_list.clone();
+            let metrics_dbc = self.metrics.clone();
+            let running_dbc = self.running.clone();
+            let mdns_dbc = mdns.clone();
+            let rebrowse_dbc = rebrowse_generation.clone();
+            let mut retry_dbc = BrowseRetry::new(
+                DanteService::Dbc,
+                self.reconnect_policy,
+                self.reconnect_requests.clone(),
+                self.stopped_searches.clone(),
+            );
+
+            threads.push(std::thread::spawn(move || {
+                debug!("Starting discovery thread");
+                let mut browse_generation = rebrowse_dbc.load(Ordering::SeqCst);
+                while *running_dbc.lock().unwrap() {
+                    // Browsing again re-queries the network, which gets stuck devices to resolve.
+                    // Also browse again if the search stopped or a reconnect was asked for.
+                    if browse_generation != rebrowse_dbc.load(Ordering::SeqCst)
+                        || retry_dbc.take_due()
+                    {
+                        browse_generation = rebrowse_dbc.load(Ordering::SeqCst);
+                        match mdns_dbc.browse(DBC_SERVICE) {
+                            Ok(receiver) => dbc_receiver = receiver,
+                            Err(error) => {
+                                error!("Failed to browse again for {}: {}", DBC_SERVICE, error)
+                            }
+                        }
+                    }
+                    while let Ok(event) = dbc_receiver.try_recv() {
+                        metrics_dbc.record_discovery_event(&event);
+                        match event {
+                            ServiceEvent::SearchStarted(service_type) => {
+                                debug!("DBC Search Started: {}", &service_type);
+                                retry_dbc.search_started();
+                            }
+                            ServiceEvent::ServiceFound(service_type, fullname) => {
+                                debug!("DBC Search Found: {}, {}", &service_type, &fullname);
+                                device_list_dbc
+                                    .lock()
+                                    .expect("Cannot get mutex lock of DanteDevices")
+                                    .handle_discovery_event(
+                                        DanteService::Dbc,
+                                        DiscoveryEvent::Found { fullname },
+                                    );
+                            }
+                            ServiceEvent::ServiceResolved(service_info) => {
+                                log_service_resolved("DBC", &service_info);
+                                retry_dbc.search_working();
+                                device_list_dbc
+                                    .lock()
+                                    .expect("Cannot get mutex lock of DanteDevices")
+                                    .handle_discovery_event(
+                                        DanteService::Dbc,
+                                        DiscoveryEvent::Resolved(service_info),
+                                    );
+                            }
+                            ServiceEvent::ServiceRemoved(service_type, fullname) => {
+                                info!("DBC Service Removed: a:{}, b:{}", &service_type, &fullname);
+                                device_list_dbc.lock().unwrap().handle_discovery_event(
+                                    DanteService::Dbc,
+                                    DiscoveryEvent::Removed { fullname },
+                                );
+                            }
+                            ServiceEvent::SearchStopped(service_type) => {
+                                error!("DBC Search Stopped: {}", &service_type);
+                                retry_dbc.search_stopped(service_type);
+                            }
+                        }
+                    }
+                    sleep(Duration::from_millis(100));
+                }
+            }));
+        }
+
+        // Discovery for CMC
+        if let Some(mut cmc_receiver) = receivers.remove(&DanteService::Cmc) {
+            // Fresh Arcs to move into thread.
+            let device_list_cmc = self.device_list.clone();
+            let metrics_cmc = self.metrics.clone();
+            let running_cmc = self.running.clone();
+            let mdns_cmc = mdns.clone();
+            let rebrowse_cmc = rebrowse_generation.clone();
+            let mut retry_cmc = BrowseRetry::new(
+                DanteService::Cmc,
+                self.reconnect_policy,
+                self.reconnect_requests.clone(),
+                self.stopped_searches.clone(),
+            );
+
+            threads.push(std::thread::spawn(move || {
+                debug!("Starting discovery thread");
+                let mut browse_generation = rebrowse_cmc.load(Ordering::SeqCst);
+                while *running_cmc.lock().unwrap() {
+                    // Browsing again re-queries the network, which gets stuck devices to resolve.
+                    // Also browse again if the search stopped or a reconnect was asked for.
+                    if browse_generation != rebrowse_cmc.load(Ordering::SeqCst)
+                        || retry_cmc.take_due()
+                    {
+                        browse_generation = rebrowse_cmc.load(Ordering::SeqCst);
+                        match mdns_cmc.browse(CMC_SERVICE) {
+                            Ok(receiver) => cmc_receiver = receiver,
+                            Err(error) => {
+                                error!("Failed to browse again for {}: {}", CMC_SERVICE, error)
+                            }
+                        }
+                    }
+                    while let Ok(event) = cmc_receiver.try_recv() {
+                        metrics_cmc.record_discovery_event(&event);
+                        match event {
+                            ServiceEvent::SearchStarted(service_type) => {
+                                debug!("CMC Search Started: {}", &service_type);
+                                retry_cmc.search_started();
+                            }
+                            ServiceEvent::ServiceFound(service_type, fullname) => {
+                                debug!("CMC Search Found: {}, {}", &service_type, &fullname);
+                                device_list_cmc
+                                    .lock()
+                                    .expect("Cannot get mutex lock of DanteDevices")
+                                    .handle_discovery_event(
+                                        DanteService::Cmc,
+                                        DiscoveryEvent::Found { fullname },
+                                    );
+                            }
+                            ServiceEvent::ServiceResolved(service_info) => {
+                                log_service_resolved("CMC", &service_info);
+                                retry_cmc.search_working();
+                                device_list_cmc
+                                    .lock()
+                                    .expect("Cannot get mutex lock of DanteDevices")
+                                    .handle_discovery_event(
+                                        DanteService::Cmc,
+                                        DiscoveryEvent::Resolved(service_info),
+                                    );
+                            }
+                            ServiceEvent::ServiceRemoved(service_type, fullname) => {
+                                info!("CMC Service Removed: a:{}, b:{}", &service_type, &fullname);
+                                device_list_cmc.lock().unwrap().handle_discovery_event(
+                                    DanteService::Cmc,
+                                    DiscoveryEvent::Removed { fullname },
+                                );
+                            }
+                            ServiceEvent::SearchStopped(service_type) => {
+                                error!("CMC Search Stopped: {}", &service_type);
+                                retry_cmc.search_stopped(service_type);
+                            }
+                        }
+                    }
+                    sleep(Duration::from_millis(100));
+                }
+            }));
+        }
+
+        // Discovery for ARC
+        if let Some(mut arc_receiver) = receivers.remove(&DanteService::Arc) {
+            // Fresh Arcs to move into thread.
+            let device_list_arc = self.device_list.clone();
+            let metrics_arc = self.metrics.clone();
+            let running_arc = self.running.clone();
+            let mdns_arc = mdns.clone();
+            let rebrowse_arc = rebrowse_generation.clone();
+            let mut retry_arc = BrowseRetry::new(
+                DanteService::Arc,
+                self.reconnect_policy,
+                self.reconnect_requests.clone(),
+                self.stopped_searches.clone(),
+            );
+
+            threads.push(std::thread::spawn(move || {
+                debug!("Starting discovery thread");
+                let mut browse_generation = rebrowse_arc.load(Ordering::SeqCst);
+                while *running_arc.lock().unwrap() {
+                    // Browsing again re-queries the network, which gets stuck devices to resolve.
+                    // Also browse again if the search stopped or a reconnect was asked for.
+                    if browse_generation != rebrowse_arc.load(Ordering::SeqCst)
+                        || retry_arc.take_due()
+                    {
+                        browse_generation = rebrowse_arc.load(Ordering::SeqCst);
+                        match mdns_arc.browse(ARC_SERVICE) {
+                            Ok(receiver) => arc_receiver = receiver,
+                            Err(error) => {
+                                error!("Failed to browse again for {}: {}", ARC_SERVICE, error)
+                            }
+                        }
+                    }
+                    while let Ok(event) = arc_receiver.try_recv() {
+                        metrics_arc.record_discovery_event(&event);
+                        match event {
+                            ServiceEvent::SearchStarted(service_type) => {
+                                debug!("ARC Search Started: {}", &service_type);
+                                retry_arc.search_started();
+                            }
+                            ServiceEvent::ServiceFound(service_type, fullname) => {
+                                debug!("ARC Search Found: {}, {}", &service_type, &fullname);
+                                device_list_arc
+                                    .lock()
+                                    .expect("Cannot get mutex lock of DanteDevices")
+                                    .handle_discovery_event(
+                                        DanteService::Arc,
+                                        DiscoveryEvent::Found { fullname },
+                                    );
+                            }
+                            ServiceEvent::ServiceResolved(service_info) => {
+                                log_service_resolved("ARC", &service_info);
+                                retry_arc.search_working();
+                                device_list_arc
+                                    .lock()
+                                    .expect("Cannot get mutex lock of DanteDevices")
+                                    .handle_discovery_event(
+                                        DanteService::Arc,
+                                        DiscoveryEvent::Resolved(service_info),
+                                    );
+                            }
+                            ServiceEvent::ServiceRemoved(service_type, fullname) => {
+                                info!("ARC Service Removed: a:{}, b:{}", &service_type, &fullname);
+                                device_list_arc.lock().unwrap().handle_discovery_event(
+                                    DanteService::Arc,
+                                    DiscoveryEvent::Removed { fullname },
+                                );
+                            }
+                            ServiceEvent::SearchStopped(service_type) => {
+                                error!("ARC Search Stopped: {}", &service_type);
+                                retry_arc.search_stopped(service_type);
+                            }
+                        }
+                    }
+                    sleep(Duration::from_millis(100));
+                }
+            }));
+        }
+
+        // Discovery for CHAN
+        if let Some(mut chan_receiver) = receivers.remove(&DanteService::Chan) {
+            // Fresh Arcs to move into thread.
+            let device_list_chan = self.device_list.clone();
+            let metrics_chan = self.metrics.clone();
+            let running_chan = self.running.clone();
+            let mdns_chan = mdns.clone();
+            let rebrowse_chan = rebrowse_generation.clone();
+            let mut retry_chan = BrowseRetry::new(
+                DanteService::Chan,
+                self.reconnect_policy,
+                self.reconnect_requests.clone(),
+                self.stopped_searches.clone(),
+            );
+
+            threads.push(std::thread::spawn(move || {
+                debug!("Starting discovery thread");
+                let mut browse_generation = rebrowse_chan.load(Ordering::SeqCst);
+                while *running_chan.lock().unwrap() {
+                    // Browsing again re-queries the network, which gets stuck devices to resolve.
+                    // Also browse again if the search stopped or a reconnect was asked for.
+                    if browse_generation != rebrowse_chan.load(Ordering::SeqCst)
+                        || retry_chan.take_due()
+                    {
+                        browse_generation = rebrowse_chan.load(Ordering::SeqCst);
+                        match mdns_chan.browse(CHAN_SERVICE) {
+                            Ok(receiver) => chan_receiver = receiver,
+                            Err(error) => {
+                                error!("Failed to browse again for {}: {}", CHAN_SERVICE, error)
+                            }
+                        }
+                    }
+                    while let Ok(event) = chan_receiver.try_recv() {
+                        metrics_chan.record_discovery_event(&event);
+                        match event {
+                            ServiceEvent::SearchStarted(service_type) => {
+                                debug!("CHAN Search Started: {}", &service_type);
+                                retry_chan.search_started();
+                            }
+                            ServiceEvent::ServiceFound(service_type, fullname) => {
+                                debug!("CHAN Search Found: {}, {}", &service_type, &fullname);
+                                device_list_chan
+                                    .lock()
+                                    .expect("Cannot get mutex lock of DanteDevices")
+                                    .handle_discovery_event(
+                                        DanteService::Chan,
+                                        DiscoveryEvent::Found { fullname },
+                                    );
+                            }
+                            ServiceEvent::ServiceResolved(service_info) => {
+                                log_service_resolved("CHAN", &service_info);
+                                retry_chan.search_working();
+                                device_list_chan
+                                    .lock()
+                                    .expect("Cannot get mutex lock of DanteDevices")
+                                    .handle_discovery_event(
+                                        DanteService::Chan,
+                                        DiscoveryEvent::Resolved(service_info),
+                                    );
+                            }
+                            ServiceEvent::ServiceRemoved(service_type, fullname) => {
+                                info!("CHAN Service Removed: a:{}, b:{}", &service_type, &fullname);
+                                device_list_chan.lock().unwrap().handle_discovery_event(
+                                    DanteService::Chan,
+                                    DiscoveryEvent::Removed { fullname },
+                                );
+                            }
+                            ServiceEvent::SearchStopped(service_type) => {
+                                error!("CHAN Search Stopped: {}", &service_type);
+                                retry_chan.search_stopped(service_type);
+                            }
+                        }
+                    }
+                    sleep(Duration::from_millis(100));
+                }
+            }));
+        }
+
+        for (service_type, receiver, resolver) in other_receivers {
+            threads.push(self.spawn_browse_thread(
+                service_type,
+                receiver,
+                resolver,
+                mdns.clone(),
+                rebrowse_generation.clone(),
+            ));
+        }
+
+        #[cfg(feature = "aes67")]
+        if let Some(sap_socket) = sap_socket {
+            threads.push(sap::spawn_sap_listener(
+                sap_socket,
+                self.device_list.clone(),
+                self.running.clone(),
+            ));
+        }
+
+        let mut watchdog = self.network_watchdog_window.map(|window| WatchdogState {
+            watchdog: NetworkWatchdog::new(window, Instant::now()),
+            metrics: self.metrics.clone(),
+            network_down: self.network_down.clone(),
+        });
+        if watchdog.is_some() {
+            match network_health::bind_heartbeat_socket() {
+                Ok(socket) => threads.push(network_health::spawn_heartbeat_listener(
+                    socket,
+                    self.metrics.clone(),
+                    self.running.clone(),
+                )),
+                // The watchdog never arms without heartbeats, so this leaves it off rather than failing discovery.
+                Err(error) => warn!(
+                    "Couldn't listen for heartbeats, the network watchdog stays off: {}",
+                    error
+                ),
+            }
+        }
+
+        // Watches for devices that were found but never resolved, and removes the ones that have been gone for the removal grace period. Also runs the network watchdog.
+        let device_list_watch = self.device_list.clone();
+        let running_watch = self.running.clone();
+        let resolve_timeout = self.resolve_timeout;
+
+        threads.push(std::thread::spawn(move || {
+            debug!("Starting unresolved device watch thread");
+            while *running_watch.lock().unwrap() {
+                let mut device_list = device_list_watch
+                    .lock()
+                    .expect("Cannot get mutex lock of DanteDevices");
+                device_list.finalize_removals();
+                device_list.refresh_unresolved_routes();
+                let any_unresolved = device_list.check_unresolved(resolve_timeout);
+                if let Some(event) = watchdog.as_mut().and_then(WatchdogState::check) {
+                    device_list.events.emit(event);
+                }
+                drop(device_list);
+                if any_unresolved {
+                    rebrowse_generation.fetch_add(1, Ordering::SeqCst);
+                }
+                sleep(Duration::from_millis(100));
+            }
+        }));
+
+        let mut discovery = self.discovery.lock().unwrap();
+        discovery.threads.extend(threads);
+        discovery.daemons.push(mdns);
+
+        Ok(self.discovery_handle())
+    }
+
+    fn discovery_handle(&self) -> DiscoveryHandle {
+        DiscoveryHandle {
+            running: self.running.clone(),
+            discovery: self.discovery.clone(),
+        }
+    }
+
+    /// Like start_discovery(), but stops discovery by itself once deadline has passed and then calls on_complete. For the usual "scan the network for 5 seconds, then look at what was found". Join the returned handle to wait for discovery to stop. If stop_discovery() is called before the deadline, the handle finishes early and on_complete is still called.
+    pub fn start_discovery_with_deadline(
+        &self,
+        deadline: Duration,
+        on_complete: Option<Box<dyn FnOnce() + Send>>,
+    ) -> Result<JoinHandle<()>, DiscoveryError> {
+        self.start_discovery()?;
+
+        let running_deadline = self.running.clone();
+        let stop_at = Instant::now() + deadline;
+
+        Ok(std::thread::spawn(move || {
+            while *running_deadline.lock().unwrap() {
+                let now = Instant::now();
+                if now >= stop_at {
+                    info!("Discovery deadline reached, stopping discovery");
+                    *running_deadline.lock().unwrap() = false;
+                    break;
+                }
+                sleep((stop_at - now).min(Duration::from_millis(100)));
+            }
+            if let Some(on_complete) = on_complete {
+                on_complete();
+            }
+        }))
+    }
+
+    fn get_new_command_sequence_id(&mut self) -> u16 {
+        let return_id = self.current_command_sequence_id;
+        self.current_command_sequence_id = self.current_command_sequence_id.wrapping_add(1);
+        return_id
+    }
+
+    /// Frames a command with the next sequence ID, refusing one that would be bigger than the datagram size limit. The length field of the header is 16 bits, so nothing bigger than that goes out whatever the limit. Every command is refused while the network watchdog has the network down, see set_network_watchdog().
+    fn make_dante_command(
+        &mut self,
+        command: [u8; 2],
+        command_args: &[u8],
+    ) -> Result<Bytes, CommandRefused> {
+        if !self.network_available() {
+            return Err(CommandRefused::NetworkUnavailable);
+        }
+        let size = COMMAND_HEADER_LENGTH + command_args.len();
+        let limit = self.max_datagram_size.min(u16::MAX as usize);
+        if size > limit {
+            warn!(
+                "Not sending command {}, it's {} bytes and the limit is {}",
+                hex::encode(command),
+                size,
+                limit
+            );
+            return Err(CommandRefused::PayloadTooLarge { size, limit });
+        }
+        Ok(make_dante_command(
+            self.get_new_command_sequence_id(),
+            command,
+            command_args,
+        ))
+    }
+
+    fn transport(&self) -> Transport<'_> {
+        Transport {
+            command_socket: self.command_socket.as_ref(),
+            translator: self.address_translator.as_deref(),
+            response_timeout: self.response_timeout,
+        }
+    }
+
+    /// Sends bytes to every target, going on to the next when one fails. Fails only when every target did, with what each failed with.
+    fn send_bytes_to_addresses(
+        metrics: &Metrics,
+        capture: &PacketCapture,
+        transport: Transport,
+        targets: &[(Ipv4Addr, u16)],
+        bytes: &[u8],
+    ) -> Result<(), SendError> {
+        let mut failures = Vec::new();
+        for (address, port) in targets {
+            if let Err(error) =
+                Self::send_bytes_to_address(metrics, capture, transport, address, *port, bytes)
+            {
+                failures.push((*address, error));
+            }
+        }
+        if failures.len() < targets.len() {
+            for (address, error) in &failures {
+                warn!(
+                    "Sending to {} failed, it went out elsewhere: {}",
+                    address, error
+                );
+            }
+            return Ok(());
+        }
+        Err(SendError { failures })
+    }
+
+    fn send_bytes_to_address(
+        metrics: &Metrics,
+        capture: &PacketCapture,
+        transport: Transport,
+        address: &Ipv4Addr,
+        port: u16,
+        bytes: &[u8],
+    ) -> std::io::Result<()> {
+        let target = transport.target(*address, port);
+        if let Some(command_socket) = transport.command_socket {
+            return command_socket.send(address, target, bytes, transport.response_timeout);
+        }
+
+        let socket = Transport::bind_throwaway(&target)?;
+
+        debug!("Sent bytes {:?} to {}", hex::encode(bytes), target);
+        send_with_backoff(metrics, || socket.send_to(bytes, target))?;
+        metrics.record_command_sent(bytes.len());
+        capture.record(PacketDirection::Outbound, *address, port, bytes);
+
+        Ok(())
+    }
+
+    /// Makes a dante subscription on a device. Dante subscriptions are "stored" on the receiver side, where a transmitter device name and transmitter device channel name are associated with a specific channel number on the receiver side. The arguments for this function are exactly the arguments needed to construct the udp packet. Also, there is no need to start_discovery() beforehand, the two functionalities are separate.
+    ///
+    /// When the device at rx_device_ip has been discovered and its RX channels are known, a channel it doesn't have is rejected with ChannelOutOfRange before anything is sent. Use make_subscription_unchecked() to send anyway.
+    ///
+    /// This only reaches the device at the one address. For a discovered device on a redundant network, make_subscription_broadcast() reaches it on both. The command goes to the ARC port of the device discovered at the address, or to the default 4440 when none was.
+    pub fn make_subscription(
+        &mut self,
+        version: &DanteVersion,
+        rx_device_ip: &Ipv4Addr,
+        rx_channel_id: u16,
+        tx_device: &AsciiStr,
+        tx_channel: &AsciiStr,
+    ) -> Result<(), MakeSubscriptionError> {
+        let rx_device_ips = HashSet::from([*rx_device_ip]);
+        self.check_rx_channel(&rx_device_ips, rx_channel_id, true)?;
+        self.send_subscription_command(
+            version,
+            &rx_device_ips,
+            rx_channel_id,
+            Some((tx_device, tx_channel)),
+        )?;
+        Ok(())
+    }
+
+    /// make_subscription() without checking the RX channel against what discovery knows, for when that knowledge is wrong or out of date. Channel 0 is still rejected, no device has it.
+    pub fn make_subscription_unchecked(
+        &mut self,
+        version: &DanteVersion,
+        rx_device_ip: &Ipv4Addr,
+        rx_channel_id: u16,
+        tx_device: &AsciiStr,
+        tx_channel: &AsciiStr,
+    ) -> Result<(), MakeSubscriptionError> {
+        let rx_device_ips = HashSet::from([*rx_device_ip]);
+        self.check_rx_channel(&rx_device_ips, rx_channel_id, false)?;
+        self.send_subscription_command(
+            version,
+            &rx_device_ips,
+            rx_channel_id,
+            Some((tx_device, tx_channel)),
+        )?;
+        Ok(())
+    }
+
+    /// make_subscription() for a discovered device, sending the command to every address it resolved to at once. On a redundant network a device has a primary and a secondary address, and the subscription gets through as long as either network does, so this is the one to use in production. An address the command can't be sent to doesn't stop the others, this only fails with ConnectionFailed when it couldn't be sent to any, saying why for each. Fails with DeviceNotFound or DeviceUnresolved if discovery doesn't know an address for the device, and with ChannelOutOfRange like make_subscription().
+    pub fn make_subscription_broadcast(
+        &mut self,
+        version: &DanteVersion,
+        rx_device_name: &str,
+        rx_channel_id: u16,
+        tx_device: &AsciiStr,
+        tx_channel: &AsciiStr,
+    ) -> Result<(), MakeSubscriptionError> {
+        let rx_device_ips = {
+            let device_list = self.device_list.lock().unwrap();
+            let rx_device_ips = device_list
+                .get_device_ips(rx_device_name)
+                .ok_or_else(|| QueryError::DeviceNotFound(rx_device_name.to_owned()))?;
+            if rx_device_ips.is_empty() {
+                return Err(QueryError::DeviceUnresolved(rx_device_name.to_owned()).into());
+            }
+            rx_device_ips
+        };
+        self.check_rx_channel(&rx_device_ips, rx_channel_id, true)?;
+        self.send_subscription_command(
+            version,
+            &rx_device_ips,
+            rx_channel_id,
+            Some((tx_device, tx_channel)),
+        )?;
+        Ok(())
+    }
+
+    /// make_subscription() that tries again up to retries times, retry_delay apart, when the command couldn't be sent. Any other error, like the channel being out of range, won't go away by trying again and is returned right away.
+    #[allow(clippy::too_many_arguments)]
+    pub fn subscribe_with_retry(
+        &mut self,
+        retries: u32,
+        retry_delay: Duration,
+        version: &DanteVersion,
+        rx_device_ip: &Ipv4Addr,
+        rx_channel_id: u16,
+        tx_device: &AsciiStr,
+        tx_channel: &AsciiStr,
+    ) -> Result<(), MakeSubscriptionError> {
+        let mut attempt = 0;
+        loop {
+            match self.make_subscription(
+                version,
+                rx_device_ip,
+                rx_channel_id,
+                tx_device,
+                tx_channel,
+            ) {
+                Err(MakeSubscriptionError::ConnectionFailed(_)) if attempt < retries => {
+                    attempt += 1;
+                    warn!(
+                        "Subscribing rx channel {} at {} failed, retrying ({}/{})",
+                        rx_channel_id, rx_device_ip, attempt, retries
+                    );
+                    sleep(retry_delay);
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Subscribes an RX channel to a TX channel, both given as "device/channel" paths like they're written in most Dante documentation. Unlike make_subscription() this needs the RX device to have been discovered, since its address, Dante version and channel ids are looked up by name.
+    ///
+    /// The TX channel can be given by its label or its canonical name, for devices whose labels were cleared. Returns which one it was, or None when the TX device or channel isn't known, in which case the name is sent as is and it's up to the device.
+    pub fn subscribe_by_channel_path(
+        &mut self,
+        rx_path: &str,
+        tx_path: &str,
+    ) -> Result<Option<ChannelNameKind>, MakeSubscriptionError> {
+        let (rx_device, rx_channel) = parse_channel_path(rx_path)?;
+        let (tx_device, tx_channel) = parse_channel_path(tx_path)?;
+        let tx_device = as_dante_name(tx_device)?;
+        let tx_channel = as_dante_name(tx_channel)?;
+
+        let name_kind = self
+            .device_list
+            .lock()
+            .unwrap()
+            .resolve_tx_channel_name(tx_device.as_str(), tx_channel.as_str())?;
+        if name_kind == Some(ChannelNameKind::Canonical) {
+            info!(
+                "\"{}\" isn't a label of {}, subscribing to it as a canonical name",
+                tx_channel, tx_device
+            );
+        }
+        let (rx_device_ip, version, rx_channel_id) =
+            self.resolve_rx_channel(rx_device, rx_channel)?;
+
+        self.make_subscription(
+            &version,
+            &rx_device_ip,
+            rx_channel_id,
+            tx_device,
+            tx_channel,
+        )?;
+        Ok(name_kind)
+    }
+
+    /// Looks up the address and Dante version of a discovered RX device, and the id of its RX channel with the given name.
+    fn resolve_rx_channel(
+        &mut self,
+        rx_device: &str,
+        rx_channel: &str,
+    ) -> Result<(Ipv4Addr, DanteVersion, u16), MakeSubscriptionError> {
+        let (subscriptions, _) = self.get_subscriptions_cached(rx_device, false)?;
+        let candidates: Vec<u16> = subscriptions
+            .value
+            .iter()
+            .filter(|subscription| subscription.rx_channel_name == rx_channel)
+            .map(|subscription| subscription.rx_channel_id)
+            .collect();
+        let rx_channel_id = match candidates[..] {
+            [] => {
+                return Err(MakeSubscriptionError::ChannelNotFound(
+                    rx_channel.to_owned(),
+                ))
+            }
+            [rx_channel_id] => rx_channel_id,
+            _ => {
+                return Err(MakeSubscriptionError::AmbiguousChannelName {
+                    name: rx_channel.to_owned(),
+                    candidates,
+                })
+            }
+        };
+        let (rx_device_ip, version) = self.resolve_rx_device(rx_device)?;
+        Ok((rx_device_ip, version, rx_channel_id))
+    }
+
+    /// Looks up the address and Dante version of a discovered RX device.
+    fn resolve_rx_device(
+        &self,
+        rx_device: &str,
+    ) -> Result<(Ipv4Addr, DanteVersion), MakeSubscriptionError> {
+        let device_list = self.device_list.lock().unwrap();
+        let rx_device_ip = device_list.get_device_ip(rx_device)?;
+        let version = device_list
+            .get_dante_version(rx_device)
+            .ok_or_else(|| MakeSubscriptionError::UnknownVersion(rx_device.to_owned()))?;
+        Ok((rx_device_ip, version))
+    }
+
+    /// Replays the subscriptions of one discovered device on another, for swapping out a device. Each subscribed RX channel of the source goes to the target RX channel with the same name, or failing that the same id. Channels the target doesn't have and subscriptions that fail are logged and skipped. Returns how many subscriptions were copied.
+    pub fn copy_routing_between_devices(
+        &mut self,
+        version: &DanteVersion,
+        source_device: &str,
+        target_device: &str,
+    ) -> Result<u16, CopyRoutingError> {
+        Ok(self
+            .copy_routing_between_devices_cancellable(
+                version,
+                source_device,
+                target_device,
+                &CancellationToken::new(),
+            )?
+            .value)
+    }
+
+    /// copy_routing_between_devices() that stops before the next subscription once cancel is cancelled, returning how many it copied until then.
+    pub fn copy_routing_between_devices_cancellable(
+        &mut self,
+        version: &DanteVersion,
+        source_device: &str,
+        target_device: &str,
+        cancel: &CancellationToken,
+    ) -> Result<Partial<u16>, CopyRoutingError> {
+        let read_back = |manager: &mut Self, device: &str| {
+            manager
+                .get_subscriptions_cached(device, false)
+                .map(|(subscriptions, _)| subscriptions.value)
+                .map_err(|source| CopyRoutingError::ReadBack {
+                    device: device.to_owned(),
+                    source,
+                })
+        };
+        let source_subscriptions = read_back(self, source_device)?;
+        let target_channels = read_back(self, target_device)?;
+        let target_ip = self
+            .device_list
+            .lock()
+            .unwrap()
+            .get_device_ip(target_device)
+            .map_err(|source| CopyRoutingError::ReadBack {
+                device: target_device.to_owned(),
+                source,
+            })?;
+
+        let mut copied = 0;
+        for subscription in source_subscriptions {
+            if cancel.is_cancelled() {
+                return Ok(Partial {
+                    value: copied,
+                    cancelled: true,
+                });
+            }
+            let (Some(tx_device), Some(tx_channel)) =
+                (subscription.tx_device, subscription.tx_channel)
+            else {
+                continue;
+            };
+            let mut by_name = target_channels
+                .iter()
+                .filter(|target| target.rx_channel_name == subscription.rx_channel_name);
+            let target_channel = match (by_name.next(), by_name.next()) {
+                (Some(target), None) => Some(target),
+                (Some(_), Some(_)) => {
+                    warn!(
+                        "{} has more than one RX channel called \"{}\", not copying its subscription",
+                        target_device, subscription.rx_channel_name
+                    );
+                    continue;
+                }
+                (None, _) => None,
+            };
+            let target_channel = target_channel.or_else(|| {
+                target_channels
+                    .iter()
+                    .find(|target| target.rx_channel_id == subscription.rx_channel_id)
+            });
+            let Some(target_channel) = target_channel else {
+                warn!(
+                    "{} has no RX channel matching \"{}\" ({}), not copying its subscription",
+                    target_device, subscription.rx_channel_name, subscription.rx_channel_id
+                );
+                continue;
+            };
+            let (tx_device_ascii, tx_channel_ascii) =
+                match (as_dante_name(&tx_device), as_dante_name(&tx_channel)) {
+                    (Ok(tx_device_ascii), Ok(tx_channel_ascii)) => {
+                        (tx_device_ascii, tx_channel_ascii)
+                    }
+                    (Err(error), _) | (_, Err(error)) => {
+                        warn!(
+                            "Not copying subscription to {}/{}: {}",
+                            tx_device, tx_channel, error
+                        );
+                        continue;
+                    }
+                };
+            match self.make_subscription(
+                version,
+                &target_ip,
+                target_channel.rx_channel_id,
+                tx_device_ascii,
+                tx_channel_ascii,
+            ) {
+                Ok(()) => copied += 1,
+                Err(error) => warn!(
+                    "Failed to copy subscription of \"{}\" to {}: {}",
+                    subscription.rx_channel_name, target_device, error
+                ),
+            }
+        }
+        Ok(Partial {
+            value: copied,
+            cancelled: false,
+        })
+    }
+
+    /// Clears every subscribed RX channel of a discovered device, going by a fresh read-back. Stops at the first channel that can't be cleared. Returns how many were cleared.
+    pub fn clear_device_subscriptions(
+        &mut self,
+        device_name: &str,
+    ) -> Result<u16, ClearSubscriptionError> {
+        let (subscriptions, _) = self
+            .get_subscriptions_cached(device_name, true)
+            .map_err(ClearSubscriptionError::from_lookup)?;
+        let (device_ip, version) = {
+            let device_list = self.device_list.lock().unwrap();
+            let version = device_list
+                .get_dante_version(device_name)
+                .ok_or_else(|| ClearSubscriptionError::UnknownVersion(device_name.to_owned()))?;
+            let device_ip = device_list
+                .get_device_ip(device_name)
+                .map_err(ClearSubscriptionError::from_lookup)?;
+            (device_ip, version)
+        };
+        let mut cleared = 0;
+        for subscription in subscriptions
+            .value
+            .iter()
+            .filter(|subscription| subscription.tx_device.is_some())
+        {
+            self.clear_subscription(&version, &device_ip, subscription.rx_channel_id)?;
+            cleared += 1;
+        }
+        Ok(cleared)
+    }
+
+    /// Clears a dante device subscription. Essentially the same as make_subscription except with an empty transmitter name and transmitter channel name.
+    pub fn clear_subscription(
+        &mut self,
+        version: &DanteVersion,
+        rx_device_ip: &Ipv4Addr,
+        rx_channel_id: u16,
+    ) -> Result<(), ClearSubscriptionError> {
+        let rx_device_ips = HashSet::from([*rx_device_ip]);
+        self.check_rx_channel(&rx_device_ips, rx_channel_id, true)?;
+        self.send_subscription_command(version, &rx_device_ips, rx_channel_id, None)?;
+        Ok(())
+    }
+
+    /// What make_subscription() and clear_subscription() check before sending anything. Devices reject RX channel 0, and with check_discovery a channel that discovery knows a device at one of the addresses doesn't have is rejected as well.
+    fn check_rx_channel(
+        &self,
+        rx_device_ips: &HashSet<Ipv4Addr>,
+        rx_channel_id: u16,
+        check_discovery: bool,
+    ) -> Result<(), SubscriptionCommandError> {
+        if rx_channel_id == 0 {
+            return Err(SubscriptionCommandError::ZeroChannelId);
+        }
+        let device_list = self.device_list.lock().unwrap();
+        if check_discovery
+            && !rx_device_ips
+                .iter()
+                .all(|ip| device_list.may_have_rx_channel(ip, rx_channel_id))
+        {
+            return Err(SubscriptionCommandError::ChannelNotFound(rx_channel_id));
+        }
+        Ok(())
+    }
+
+    /// Sends the subscription command for the RX channel to every address, subscribing it to tx (device, channel) or clearing it when tx is None. The subscription read-backs of the devices there are dropped once it's out, since they no longer hold.
+    fn send_subscription_command(
+        &mut self,
+        version: &DanteVersion,
+        rx_device_ips: &HashSet<Ipv4Addr>,
+        rx_channel_id: u16,
+        tx: Option<(&AsciiStr, &AsciiStr)>,
+    ) -> Result<(), SubscriptionCommandError> {
+        let command_buffer = version
+            .get_commands()
+            .subscription_layout
+            .args(rx_channel_id, tx);
+        let command =
+            self.make_dante_command(version.get_commands().command_subscription, &command_buffer)?;
+        let targets: Vec<(Ipv4Addr, u16)> = {
+            let device_list = self.device_list.lock().unwrap();
+            device_list
+                .order_by_reachability(rx_device_ips)
+                .into_iter()
+                .map(|ip| (ip, device_list.get_arc_command_port_by_ip(&ip)))
+                .collect()
+        };
+        Self::send_bytes_to_addresses(
+            &self.metrics,
+            &self.packet_capture,
+            self.transport(),
+            &targets,
+            &command,
+        )
+        .map_err(SubscriptionCommandError::SendFailed)?;
+        let mut device_list = self.device_list.lock().unwrap();
+        for rx_device_ip in rx_device_ips {
+            device_list.invalidate_subscriptions_for_ip(rx_device_ip);
+        }
+        Ok(())
+    }
+
+    /// Sends bytes to a device and waits for the response carrying the same sequence ID. Anything else arriving on a socket of its own is ignored, garbled datagrams included, on the command socket it's left to the receive thread.
+    fn query_address(
+        metrics: &Metrics,
+        capture: &PacketCapture,
+        transport: Transport,
+        address: &Ipv4Addr,
+        port: u16,
+        bytes: &[u8],
+    ) -> Result<Vec<u8>, QueryError> {
+        let target = transport.target(*address, port);
+        if let Some(command_socket) = transport.command_socket {
+            return command_socket.query(address, target, bytes, transport.response_timeout);
+        }
+
+        let socket = Transport::bind_throwaway(&target).map_err(QueryError::ConnectionFailed)?;
+
+        debug!("Sent bytes {:?} to {}", hex::encode(bytes), target);
+        send_with_backoff(metrics, || socket.send_to(bytes, target))
+            .map_err(QueryError::ConnectionFailed)?;
+        metrics.record_command_sent(bytes.len());
+        capture.record(PacketDirection::Outbound, *address, port, bytes);
+
+        let sequence_id = command_socket::sequence_id(bytes);
+        let sent_at = Instant::now();
+        let deadline = sent_at + transport.response_timeout;
+        let mut buffer = [0u8; 2048];
+        loop {
+            // Without a read timeout recv_from() would wait forever for a device that's gone. It's what's left until the deadline, so datagrams that aren't the answer don't push the deadline back.
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                metrics.record_command_timed_out();
+                return Err(QueryError::Timeout);
+            }
+            socket
+                .set_read_timeout(Some(remaining))
+                .map_err(QueryError::ConnectionFailed)?;
+            match socket.recv_from(&mut buffer) {
+                Ok((length, from)) => {
+                    let response = &buffer[..length];
+                    debug!("Received bytes {:?} from {}", hex::encode(response), from);
+                    metrics.record_received(length);
+                    // Answers through a translated address are recorded as coming from the device.
+                    if from.ip() == target.ip() {
+                        capture.record(PacketDirection::Inbound, *address, port, response);
+                    } else if let SocketAddr::V4(from) = from {
+                        capture.record(PacketDirection::Inbound, *from.ip(), from.port(), response);
+                    }
+                    if from.ip() == target.ip()
+                        && sequence_id.is_some()
+                        && command_socket::sequence_id(response) == sequence_id
+                    {
+                        metrics.record_command_acked(*address, sent_at.elapsed());
+                        return Ok(response.to_vec());
+                    }
+                }
+                Err(error) if is_receive_timeout(&error) => {
+                    metrics.record_command_timed_out();
+                    return Err(QueryError::Timeout);
+                }
+                Err(error) => return Err(QueryError::ConnectionFailed(error)),
+            }
+        }
+    }
+
+    /// Returns the (tx, rx) channel counts of a device.
+    fn query_channel_counts(
+        &mut self,
+        device_ip: &Ipv4Addr,
+        port: u16,
+    ) -> Result<(u16, u16), QueryError> {
+        let command = self.make_dante_command(COMMAND_CHANNELCOUNT, &[])?;
+        let response = Self::query_address(
+            &self.metrics,
+            &self.packet_capture,
+            self.transport(),
+            device_ip,
+            port,
+            &command,
+        )?;
+        let response = parse_dante_response(&response)?;
+        let mut reader = Reader::new(response.payload);
+        reader.seek(2);
+        Ok((reader.read_u16()?, reader.read_u16()?))
+    }
+
+    fn list_subscriptions_at(
+        &mut self,
+        device_ip: &Ipv4Addr,
+        port: u16,
+    ) -> Result<Vec<SubscriptionEntry>, QueryError> {
+        let (_, rx_count) = self.query_channel_counts(device_ip, port)?;
+
+        // Channels come back 16 to a page.
+        let mut entries = Vec::with_capacity(rx_count as usize);
+        for page in 0..rx_count.div_ceil(16) {
+            let first_channel = page * 16 + 1;
+            let mut args = vec![0x00, 0x01];
+            args.extend_from_slice(&first_channel.to_be_bytes());
+            args.extend_from_slice(&[0x00, 0x00]);
+            let command = self.make_dante_command(COMMAND_RXCHANNELNAMES, &args)?;
+            let response = Self::query_address(
+                &self.metrics,
+                &self.packet_capture,
+                self.transport(),
+                device_ip,
+                port,
+                &command,
+            )?;
+            let page_count = (rx_count - page * 16).min(16) as usize;
+            entries.extend(parse_rx_channels_page(&response, page_count)?);
+        }
+        Ok(entries)
+    }
+
+    /// Queries the names of a device's TX channels, 16 to a page like the RX ones.
+    fn list_tx_channels_at(
+        &mut self,
+        device_ip: &Ipv4Addr,
+        port: u16,
+        tx_count: u16,
+    ) -> Result<Vec<(u16, String)>, QueryError> {
+        let mut channels = Vec::with_capacity(tx_count as usize);
+        for page in 0..tx_count.div_ceil(16) {
+            let first_channel = page * 16 + 1;
+            let mut args = vec![0x00, 0x01];
+            args.extend_from_slice(&first_channel.to_be_bytes());
+            args.extend_from_slice(&[0x00, 0x00]);
+            let command = self.make_dante_command(COMMAND_TXCHANNELS, &args)?;
+            let response = Self::query_address(
+                &self.metrics,
+                &self.packet_capture,
+                self.transport(),
+                device_ip,
+                port,
+                &command,
+            )?;
+            let page_count = (tx_count - page * 16).min(16) as usize;
+            channels.extend(parse_tx_channels_page(&response, page_count)?);
+        }
+        Ok(channels)
+    }
+
+    /// Lists every TX channel of a discovered device, for devices that only advertise some of their channels over mdns. Channels with a CHAN record come from it, the rest are queried from the device, and when the device doesn't answer that query they're filled in as placeholders so they can still be routed by id. ChannelInfo::source says which is which.
+    ///
+    /// max_count is how many TX channels the device has. When None it's queried from the device.
+    pub fn probe_tx_channels(
+        &mut self,
+        device_name: &str,
+        max_count: Option<u16>,
+    ) -> Result<Vec<ChannelInfo>, QueryError> {
+        let (device_ip, port, mut channels, mut unnumbered) = {
+            let device_list = self.device_list.lock().unwrap();
+            let device_ip = device_list.get_device_ip(device_name)?;
+            let port = device_list.get_arc_command_port(device_name);
+            let mut channels = BTreeMap::new();
+            let mut unnumbered = Vec::new();
+            if let Some(cache) = device_list.caches.get(device_name) {
+                for chan_info in cache.chan_info.iter() {
+                    match chan_info.id {
+                        Some(id) => {
+                            channels.insert(id, cache.tx_channel_info(chan_info, None));
+                        }
+                        None => unnumbered.push(chan_info.to_channel_info(None)),
+                    }
+                }
+            }
+            (device_ip, port, channels, unnumbered)
+        };
+
+        let tx_count = match max_count {
+            Some(max_count) => max_count,
+            None => self.get_channel_counts(device_name, false)?.value.0,
+        };
+
+        // The cached names are only good for the channel count the device gave.
+        let queried = match max_count {
+            Some(_) => self.list_tx_channels_at(&device_ip, port, tx_count),
+            None => self
+                .get_tx_channel_names(device_name, false)
+                .map(|tx_channel_names| tx_channel_names.value),
+        };
+        match queried {
+            Ok(queried) => {
+                for (id, name) in queried {
+                    let channel = channels.entry(id).or_insert_with(|| {
+                        ChannelInfo::bare(
+                            id,
+                            name.clone(),
+                            ChannelDirection::Tx,
+                            ChannelSource::Query,
+                        )
+                    });
+                    channel.canonical = Some(name);
+                }
+            }
+            Err(error) => warn!(
+                "Couldn't query the TX channels of {}, using placeholders: {}",
+                device_name, error
+            ),
+        }
+        for id in 1..=tx_count {
+            channels.entry(id).or_insert_with(|| {
+                ChannelInfo::bare(
+                    id,
+                    String::new(),
+                    ChannelDirection::Tx,
+                    ChannelSource::Placeholder,
+                )
+            });
+        }
+
+        let mut channels: Vec<ChannelInfo> = channels.into_values().collect();
+        unnumbered.sort_by(|a, b| a.name.cmp(&b.name));
+        channels.append(&mut unnumbered);
+        Ok(channels)
+    }
+
+    /// Reads back the current subscription of every RX channel of a device. Like make_subscription(), this doesn't need discovery to be running, but when the device was discovered the query goes to its ARC port.
+    pub fn list_subscriptions(
+        &mut self,
+        rx_device_ip: &Ipv4Addr,
+    ) -> Result<Vec<SubscriptionEntry>, QueryError> {
+        let port = self
+            .device_list
+            .lock()
+            .unwrap()
+            .get_arc_command_port_by_ip(rx_device_ip);
+        self.list_subscriptions_at(rx_device_ip, port)
+    }
+
+    /// Returns the subscription read-back of a discovered device, querying the device only if the cached read-back is older than the routing max age or force_refresh is set. If the query fails but an older read-back exists, that is returned as stale.
+    fn get_subscriptions_cached(
+        &mut self,
+        device_name: &str,
+        force_refresh: bool,
+    ) -> Result<(Timestamped<Vec<SubscriptionEntry>>, Freshness), QueryError> {
+        let (mut device_ips, port, cached) = {
+            let device_list = self.device_list.lock().unwrap();
+            let device_ips = device_list
+                .get_device_ips(device_name)
+                .ok_or_else(|| QueryError::DeviceNotFound(device_name.to_owned()))?;
+            if device_ips.is_empty() {
+                return Err(QueryError::DeviceUnresolved(device_name.to_owned()));
+            }
+            let cached = device_list
+                .caches
+                .get(device_name)
+                .and_then(|cache| cache.subscriptions.clone());
+            if let Some(cached) = &cached {
+                if !force_refresh && cached.is_fresh(self.routing_max_age) {
+                    return Ok((cached.clone(), Freshness::Cached));
+                }
+            }
+            (
+                device_ips,
+                device_list.get_arc_command_port(device_name),
+                cached,
+            )
+        };
+
+        let mut last_error = QueryError::DeviceNotFound(device_name.to_owned());
+        let mut refreshed = false;
+        loop {
+            // Addresses that answered a probe lately first, so a dead one doesn't cost a timeout every time.
+            let ordered_ips = self
+                .device_list
+                .lock()
+                .unwrap()
+                .order_by_reachability(&device_ips);
+            for device_ip in &ordered_ips {
+                match self.list_subscriptions_at(device_ip, port) {
+                    Ok(mut subscriptions) => {
+                        // Devices refer to themselves as "." when subscribed to their own channels.
+                        for subscription in &mut subscriptions {
+                            if subscription.tx_device.as_deref() == Some(".") {
+                                subscription.tx_device = Some(device_name.to_owned());
+                            }
+                        }
+                        let mut device_list = self.device_list.lock().unwrap();
+                        if device_list.device_connected(device_name) {
+                            device_list.update_subscriptions(device_name, subscriptions.clone());
+                        }
+                        return Ok((Timestamped::new(subscriptions), Freshness::Fresh));
+                    }
+                    Err(error) => {
+                        warn!(
+                            "Reading subscriptions of {} at {} failed: {}",
+                            device_name, device_ip, error
+                        );
+                        last_error = error;
+                    }
+                }
+            }
+
+            // The device may have changed address. Only new addresses are tried, once.
+            if refreshed || !matches!(last_error, QueryError::Timeout) {
+                break;
+            }
+            refreshed = true;
+            match self.refresh_and_wait(device_name, &device_ips) {
+                Some(new_ips) => device_ips = new_ips,
+                None => break,
+            }
+        }
+
+        match cached {
+            Some(cached) => Ok((cached, Freshness::Stale)),
+            None => Err(last_error),
+        }
+    }
+
+    /// Returns the channels of a discovered device annotated with their routing. RX channels carry their current subscription, TX channels carry the number of RX channels across all discovered devices subscribed to them. Read-backs are reused for set_routing_max_age(), so calling this repeatedly won't query devices more often than that. If a device can't be queried, its last known read-back is used and the result is marked stale.
+    pub fn get_channels_with_routing(
+        &mut self,
+        device_name: &str,
+    ) -> Result<ChannelsWithRouting, QueryError> {
+        self.get_channels_with_routing_cancellable(device_name, &CancellationToken::new())
+    }
+
+    /// get_channels_with_routing() that stops scanning other devices once cancel is cancelled, returning what it has with cancelled set.
+    pub fn get_channels_with_routing_cancellable(
+        &mut self,
+        device_name: &str,
+        cancel: &CancellationToken,
+    ) -> Result<ChannelsWithRouting, QueryError> {
+        let (own_subscriptions, freshness) = self.get_subscriptions_cached(device_name, false)?;
+        let mut stale = freshness == Freshness::Stale;
+        let mut fetched_at = own_subscriptions.fetched_at;
+
+        // Count subscribers of our TX channels by scanning everyone's subscriptions.
+        let mut subscriber_counts: HashMap<String, u16> = HashMap::new();
+        let mut cancelled = false;
+        for other_device in self.get_device_names() {
+            if cancel.is_cancelled() {
+                cancelled = true;
+                break;
+            }
+            let subscriptions = if other_device == device_name {
+                own_subscriptions.clone()
+            } else {
+                match self.get_subscriptions_cached(&other_device, false) {
+                    Ok((subscriptions, freshness)) => {
+                        stale |= freshness == Freshness::Stale;
+                        subscriptions
+                    }
+                    Err(error) => {
+                        warn!(
+                            "Couldn't read subscriptions of {}, subscriber counts may be low: {}",
+                            other_device, error
+                        );
+                        stale = true;
+                        continue;
+                    }
+                }
+            };
+            fetched_at = fetched_at.min(subscriptions.fetched_at);
+            for subscription in subscriptions.value {
+                if let (Some(tx_device), Some(tx_channel)) =
+                    (subscription.tx_device, subscription.tx_channel)
+                {
+                    if tx_device == device_name {
+                        *subscriber_counts.entry(tx_channel).or_insert(0) += 1;
+                    }
+                }
+            }
+        }
+
+        let mut channels: Vec<ChannelInfo> = own_subscriptions
+            .value
+            .into_iter()
+            .map(|subscription| ChannelInfo {
+                routing: Some(ChannelRouting::Rx {
+                    subscription: subscription.tx_device.zip(subscription.tx_channel),
+                }),
+                ..ChannelInfo::bare(
+                    subscription.rx_channel_id,
+                    subscription.rx_channel_name,
+                    ChannelDirection::Rx,
+                    ChannelSource::Query,
+                )
+            })
+            .collect();
+
+        let device_list = self.device_list.lock().unwrap();
+        if let Some(cache) = device_list.caches.get(device_name) {
+            let mut tx_channels: Vec<&CHANInfo> = cache.chan_info.iter().collect();
+            tx_channels.sort_by_key(|chan_info| (chan_info.id.is_none(), chan_info.id));
+            channels.extend(tx_channels.into_iter().map(|chan_info| {
+                cache.tx_channel_info(
+                    chan_info,
+                    Some(ChannelRouting::Tx {
+                        subscriber_count: subscriber_counts
+                            .get(&chan_info.name)
+                            .copied()
+                            .unwrap_or(0),
+                    }),
+                )
+            }));
+        }
+
+        Ok(ChannelsWithRouting {
+            channels,
+            fetched_at,
+            stale,
+            cancelled,
+        })
+    }
+
+    /// Reads back the routing of every discovered device into a RoutingMatrix, keyed by RX channel name. Read-backs younger than the routing max age are reused, and a device that can't be queried is in there with its last read-back, or left out if it has none.
+    pub fn get_routing_matrix(&mut self) -> RoutingMatrix {
+        for device_name in self.get_device_names() {
+            if let Err(error) = self.get_subscriptions_cached(&device_name, false) {
+                warn!(
+                    "Couldn't read back subscriptions of {}: {}",
+                    device_name, error
+                );
+            }
+        }
+        self.device_list.lock().unwrap().cached_routing_matrix()
+    }
+
+    /// get_routing_matrix() for one device: the routes of its RX channels and the routes of other devices subscribed to its TX channels. Only the device itself is read back, if its read-back is older than the routing max age. Subscriptions of other devices to it come from their last read-backs, so they're only there for devices that were read back before.
+    pub fn get_routing_for_device(&mut self, device_name: &str) -> RoutingMatrix {
+        if let Err(error) = self.get_subscriptions_cached(device_name, false) {
+            warn!(
+                "Couldn't read back subscriptions of {}: {}",
+                device_name, error
+            );
+        }
+        self.device_list
+            .lock()
+            .unwrap()
+            .cached_routing_matrix()
+            .filter_by_device(device_name)
+    }
+
+    /// Looks for routing loops and RX channels doubled up on the same TX channel. Only looks at the last subscription read-back of each device (get_channels_with_routing() reads back every discovered device), devices that were never read back are left out.
+    pub fn detect_subscription_conflicts(&self) -> Vec<SubscriptionConflict> {
+        self.device_list
+            .lock()
+            .unwrap()
+            .detect_subscription_conflicts()
+    }
+
+    /// Lists the subscriptions of every device without sending anything. mdns records don't say what's subscribed to what, so this goes by the last read-back of each device, checked against the CHAN records of the sources, with a confidence for how far to trust each entry. Devices that were never read back are left out.
+    pub fn enumerate_subscriptions_passive(&self) -> Vec<PassiveSubscriptionEntry> {
+        self.device_list
+            .lock()
+            .unwrap()
+            .enumerate_subscriptions_passive(self.routing_max_age)
+    }
+
+    /// Checks a discovered device for problems: not resolving, channels at different sample rates and duplicate channel names. Returns nothing for unknown devices.
+    pub fn diagnose_device(&self, device_name: &str) -> Vec<TopologyWarning> {
+        self.device_list
+            .lock()
+            .unwrap()
+            .diagnose_device(device_name)
+    }
+
+    /// Runs diagnose_device() on every discovered device and then checks the network as a whole: every device at the same sample rate and latency, no device names that only differ by case, and no subscription conflicts (see detect_subscription_conflicts()). Errors are also logged.
+    /// Clock master election isn't checked, since nothing reads clock status from devices yet.
+    pub fn validate_network_topology(&self) -> Vec<TopologyWarning> {
+        let warnings = self.device_list.lock().unwrap().validate_network_topology();
+        for warning in &warnings {
+            if warning.severity == Severity::Error {
+                error!("{}", warning);
+            }
+        }
+        warnings
+    }
+
+    /// Sets how long read-back subscription data is reused before devices are queried again. Defaults to 5 seconds.
+    pub fn set_routing_max_age(&mut self, max_age: Duration) {
+        self.routing_max_age = max_age;
+    }
+
+    /// Returns the names of devices that were found but didn't resolve an address within the resolve timeout. Discovery browses again whenever a device gets flagged, and the flag clears once the device resolves.
+    pub fn get_unresolved_devices(&self) -> Vec<String> {
+        self.device_list
+            .lock()
+            .unwrap()
+            .devices
+            .iter()
+            .filter(|(_, status)| status.unresolved)
+            .map(|(device_name, _)| device_name.to_string())
+            .collect()
+    }
+
+    /// Sets how long a found device may go without resolving before it's flagged unresolved. Defaults to 10 seconds. Takes effect the next time discovery is started.
+    pub fn set_resolve_timeout(&mut self, timeout: Duration) {
+        self.resolve_timeout = timeout;
+    }
+
+    /// Sets how long a query waits for the device to answer before failing with QueryError::Timeout. Defaults to DEFAULT_RESPONSE_TIMEOUT. Worth raising for devices behind a slow link, like over a VPN.
+    pub fn set_response_timeout(&mut self, timeout: Duration) {
+        self.response_timeout = timeout;
+    }
+
+    /// Same as set_response_timeout(), for setting it up along with creating the manager.
+    pub fn response_timeout(mut self, timeout: Duration) -> Self {
+        self.response_timeout = timeout;
+        self
+    }
+
+    /// Frees the cached discovery data of devices that have been disconnected for longer than the cache TTL. The cache of a device that disconnects is otherwise kept forever so it can be reused if the device comes back, which adds up in long running processes on busy networks.
+    pub fn purge_stale_caches(&self) {
+        self.device_list
+            .lock()
+            .unwrap()
+            .purge_stale_caches(self.cache_ttl);
+    }
+
+    /// Sets how long the cache of a disconnected device survives purge_stale_caches(). Defaults to 5 minutes.
+    pub fn set_cache_ttl(&mut self, ttl: Duration) {
+        self.cache_ttl = ttl;
+    }
+
+    /// Sets the size of the biggest command the manager sends, header included. Bigger ones fail with PayloadTooLarge before anything is sent, saying how big they were. Defaults to 1400 bytes, which fits a 1500 byte MTU without fragmenting. None of the commands the crate sends come anywhere near that yet, it's there for bigger ones to come and for networks with a smaller MTU, like over a VPN.
+    pub fn set_max_datagram_size(&mut self, size: usize) {
+        self.max_datagram_size = size;
+    }
+
+    /// Sets how long a device that's gone from every mdns service stays in the list before it's removed and DanteDeviceEvent::DeviceRemoved goes out. A device that comes back in the meantime stays as if nothing happened, which rides out the announcement flaps of devices behind wireless bridges. Defaults to 5 seconds, zero removes devices right away. Devices are only removed while discovery is running.
+    pub fn set_removal_grace_period(&self, grace: Duration) {
+        self.device_list.lock().unwrap().removal_grace = grace;
+    }
+
+    /// Returns a receiver that gets every DanteDeviceEvent from now on. Dropping the receiver unsubscribes.
+    pub fn subscribe_events(&self) -> Receiver<DanteDeviceEvent> {
+        self.device_list.lock().unwrap().events.subscribe()
+    }
+
+    /// Calls callback whenever the device starts or stops advertising a channel over mdns while discovery is running, like when a sample rate change makes it renegotiate its channels. The watch stops when the returned handle is dropped. The callback runs on a discovery thread with the device list locked, so it mustn't call back into the manager.
+    pub fn watch_device_channels(
+        &self,
+        device_name: &str,
+        callback: impl Fn(ChannelEvent) + Send + 'static,
+    ) -> WatchHandle {
+        let watchers = self.device_list.lock().unwrap().channel_watchers.clone();
+        WatchHandle::new(&watchers, device_name, Box::new(callback))
+    }
+
+    /// Sets which mdns services discovery browses for, all four by default. Inventory only needs CMC and routing needs ARC and CHAN, and leaving out CHAN cuts down mdns traffic a lot on networks with many channels. Services left out show as ServiceStatus::NotTracked. Takes effect the next time discovery is started.
+    pub fn set_discovery_services(&mut self, services: &[DanteService]) {
+        self.discovery_services = services.iter().copied().collect();
+    }
+
+    /// Starts recording every datagram sent to and received from devices on control sockets, for replay tests and for looking at in Wireshark with to_pcap_file(). mdns traffic isn't included. Several captures can run at once.
+    pub fn enable_packet_capture(&self) -> PacketCaptureHandle {
+        PacketCaptureHandle::new(&self.packet_capture)
+    }
+
+    /// Sends every command from one socket bound to port, instead of a new socket per command, and keeps reading it in the background. Devices answer (and some push subscription status) to the port that last commanded them, so with a port known in advance firewalls can let that traffic in, and whatever comes in that isn't the answer to a command shows up as a DanteDeviceEvent::UnsolicitedMessage. Port 0 lets the OS pick one. Returns the port bound. A socket bound earlier is closed first.
+    pub fn bind_command_socket(&mut self, port: u16) -> std::io::Result<u16> {
+        self.command_socket = None;
+        let command_socket = CommandSocket::bind(
+            port,
+            self.device_list.clone(),
+            self.metrics.clone(),
+            self.packet_capture.clone(),
+        )?;
+        let port = command_socket.port();
+        info!("Bound command socket to port {}", port);
+        self.command_socket = Some(command_socket);
+        Ok(port)
+    }
+
+    /// Closes the socket from bind_command_socket(), going back to a socket per command.
+    pub fn unbind_command_socket(&mut self) {
+        self.command_socket = None;
+    }
+
+    /// The port of the socket from bind_command_socket(), None when commands go out from a socket of their own.
+    pub fn command_port(&self) -> Option<u16> {
+        self.command_socket.as_ref().map(CommandSocket::port)
+    }
+
+    /// Sends commands for a device somewhere else than its address, for devices behind a NAT or a UDP relay (like a jump host into the Dante VLAN). translator gets the address and port a command is for and returns where to send it. Answers are matched back to the device they're for by the address they were sent to, so a relay has to answer from the address it was sent to. Discovery doesn't go through the translator: mdns doesn't get through a relay, so remote devices have to be addressed directly, with make_subscription() and the like taking the device's own address. The socket from bind_command_socket() only sends to IPv4 addresses.
+    pub fn set_address_translator(
+        &mut self,
+        translator: impl Fn(Ipv4Addr, u16) -> (IpAddr, u16) + Send + Sync + 'static,
+    ) {
+        self.address_translator = Some(Box::new(translator));
+    }
+
+    /// Goes back to sending commands to devices' own addresses.
+    pub fn clear_address_translator(&mut self) {
+        self.address_translator = None;
+    }
+
+    /// Makes discovery query the network again for the device, for when it changed address (a DHCP renewal) and the cache still has the old one. Services that resolve again replace their cached addresses, so addresses that no longer appear are dropped, and an AddressesChanged event is emitted if the set changed. Returns right away, the cache is updated as answers come in. mdns can only re-query whole service types, so this refreshes every device just like refresh_all().
+    pub fn refresh_device(&self, device_name: &str) -> Result<(), QueryError> {
+        if !self
+            .device_list
+            .lock()
+            .unwrap()
+            .devices
+            .contains_key(device_name)
+        {
+            return Err(QueryError::DeviceNotFound(device_name.to_owned()));
+        }
+        self.refresh_all();
+        Ok(())
+    }
+
+    /// Makes discovery query the network again for the CHAN records of a device, and waits for one of them to come back, for picking up renamed, added or removed channels without waiting for the device to announce them. mdns can only re-query whole service types, so this re-queries the CHAN records of every device, but leaves the other services alone unlike refresh_device(). Waits up to the CHAN refresh timeout, see set_chan_refresh_timeout(). A channel that went away is only dropped from the cache once mdns says it's gone, so a record coming back doesn't mean every stale channel is gone.
+    pub fn refresh_chan_cache(&self, device_name: &str) -> Result<(), RefreshError> {
+        if !self.is_running() {
+            return Err(RefreshError::DiscoveryNotRunning);
+        }
+        if !self
+            .device_list
+            .lock()
+            .unwrap()
+            .devices
+            .contains_key(device_name)
+        {
+            return Err(RefreshError::DeviceNotFound(device_name.to_owned()));
+        }
+
+        let start = Instant::now();
+        info!("Refreshing CHAN records of {}", device_name);
+        self.reconnect_discovery(DanteService::Chan);
+        loop {
+            let resolved = self
+                .device_list
+                .lock()
+                .unwrap()
+                .caches
+                .get(device_name)
+                .and_then(|cache| cache.chan_info.last_resolved_at)
+                .is_some_and(|last_resolved_at| last_resolved_at >= start);
+            if resolved {
+                return Ok(());
+            }
+            if start.elapsed() >= self.chan_refresh_timeout {
+                return Err(RefreshError::Timeout);
+            }
+            sleep(Duration::from_millis(50));
+        }
+    }
+
+    /// Sets how long refresh_chan_cache() waits for a CHAN record to come back. Defaults to 3 seconds.
+    pub fn set_chan_refresh_timeout(&mut self, timeout: Duration) {
+        self.chan_refresh_timeout = timeout;
+    }
+
+    /// Makes discovery query the network again for every device. Does nothing while discovery isn't running.
+    pub fn refresh_all(&self) {
+        if self.is_running() {
+            info!("Refreshing all devices");
+            self.rebrowse_generation.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    /// Refreshes the device and waits up to REFRESH_WAIT for it to resolve to addresses that aren't in stale. Returns the new addresses, or None if discovery isn't running or none turned up.
+    fn refresh_and_wait(
+        &self,
+        device_name: &str,
+        stale: &HashSet<Ipv4Addr>,
+    ) -> Option<HashSet<Ipv4Addr>> {
+        if !self.is_running() {
+            return None;
+        }
+        self.refresh_device(device_name).ok()?;
+        let give_up_at = Instant::now() + REFRESH_WAIT;
+        while Instant::now() < give_up_at {
+            sleep(Duration::from_millis(100));
+            let new_ips: HashSet<Ipv4Addr> = self
+                .device_list
+                .lock()
+                .unwrap()
+                .get_device_ips(device_name)?
+                .difference(stale)
+                .copied()
+                .collect();
+            if !new_ips.is_empty() {
+                info!("{} moved to {:?}, trying again", device_name, new_ips);
+                return Some(new_ips);
+            }
+        }
+        None
+    }
+
+    /// Makes discovery also browse for another mdns service type, like "_netaudio-ddp._udp.local." or whatever Dante Via endpoints advertise. What it finds shows up in get_other_services(). Takes effect the next time discovery is started.
+    pub fn add_browse_target(&mut self, service_type: &str) {
+        self.add_browse_target_with_resolver(service_type, OtherService::from_service_info);
+    }
+
+    /// add_browse_target() with a resolver that decides what's kept of each resolved service, returning None to skip it.
+    pub fn add_browse_target_with_resolver(
+        &mut self,
+        service_type: &str,
+        resolver: impl Fn(&ServiceInfo) -> Option<OtherService> + Send + Sync + 'static,
+    ) {
+        self.browse_targets
+            .push((service_type.to_owned(), Arc::new(resolver)));
+    }
+
+    /// Returns what the extra browse targets found, sorted by fullname.
+    pub fn get_other_services(&self) -> Vec<OtherService> {
+        self.device_list
+            .lock()
+            .unwrap()
+            .other_services
+            .values()
+            .cloned()
+            .collect()
+    }
+
+    /// Makes discovery also listen for SAP announcements of AES67 streams, for networks that mix Dante with other AES67 gear. Needs UDP port 9875, which start_discovery() fails with SapListener if it can't get. Takes effect the next time discovery is started.
+    #[cfg(feature = "aes67")]
+    pub fn set_aes67_discovery(&mut self, enabled: bool) {
+        self.aes67_discovery = enabled;
+    }
+
+    /// Returns the AES67 streams announced over SAP in the last hour, sorted by origin.
+    #[cfg(feature = "aes67")]
+    pub fn get_aes67_streams(&self) -> Vec<Aes67Stream> {
+        self.device_list
+            .lock()
+            .unwrap()
+            .aes67_streams
+            .values()
+            .map(|(stream, _)| stream.clone())
+            .collect()
+    }
+
+    /// Returns names that more than one device on the network uses, which makes mdns and routing to them unreliable. The device list is keyed by name so such devices look like one, but they give themselves away by resolving to different addresses back and forth. A device that moved to a new address isn't reported.
+    pub fn check_duplicate_device_names(&self) -> Vec<DuplicateNameEntry> {
+        self.device_list.lock().unwrap().duplicate_device_names()
+    }
+
+    /// Returns the current discovery and control traffic counters.
+    pub fn metrics(&self) -> MetricsSnapshot {
+        let devices_known = self.device_list.lock().unwrap().devices.len();
+        self.metrics.snapshot(devices_known)
+    }
+
+    /// Returns the highest latency any channel of the device advertises over mdns, the delay to compensate for when lining its audio up with other devices. None if the device isn't known or no channel advertised a latency.
+    pub fn max_latency(&self, device_name: &str) -> Option<Duration> {
+        self.device_list
+            .lock()
+            .unwrap()
+            .caches
+            .get(device_name)?
+            .chan_info
+            .iter()
+            .filter_map(|chan_info| chan_info.latency)
+            .max()
+    }
+
+    /// Returns every TXT property of the CHAN record of a channel as it came in, including the ones parsed into ChannelInfo fields, for properties the crate doesn't know about yet. None if the device or channel isn't known.
+    pub fn get_chan_info_raw(
+        &self,
+        device_name: &str,
+        channel_id: u16,
+    ) -> Option<HashMap<String, String>> {
+        self.device_list
+            .lock()
+            .unwrap()
+            .caches
+            .get(device_name)?
+            .chan_info
+            .iter()
+            .find(|chan_info| chan_info.id == Some(channel_id))
+            .map(|chan_info| chan_info.extra_properties.clone())
+    }
+
+    /// Returns the encoding the CHAN record of a channel advertises. None if the device or channel isn't known, or the record didn't say.
+    pub fn get_channel_encoding(
+        &self,
+        device_name: &str,
+        channel_id: u16,
+    ) -> Option<DanteDeviceEncoding> {
+        self.device_list
+            .lock()
+            .unwrap()
+            .caches
+            .get(device_name)?
+            .chan_info
+            .iter()
+            .find(|chan_info| chan_info.id == Some(channel_id))?
+            .encoding
+    }
+
+    /// get_channel_encoding() for every channel of the device at once, by channel id. Channels without an id or encoding are left out.
+    pub fn get_all_channel_encodings(
+        &self,
+        device_name: &str,
+    ) -> HashMap<u16, DanteDeviceEncoding> {
+        match self.device_list.lock().unwrap().caches.get(device_name) {
+            Some(cache) => cache
+                .chan_info
+                .iter()
+                .filter_map(|chan_info| Some((chan_info.id?, chan_info.encoding?)))
+                .collect(),
+            None => HashMap::new(),
+        }
+    }
+
+    /// Returns round trip times of the last commands the device answered at any of its addresses, or None if it hasn't answered any yet. Commands are timed from when they leave the socket, so this is how long the network and the device take and a rising p95 is worth looking into.
+    pub fn get_command_latency(&self, device_name: &str) -> Option<CommandLatency> {
+        let device_ips = self
+            .device_list
+            .lock()
+            .unwrap()
+            .get_device_ips(device_name)?;
+        self.metrics.command_latency(device_ips)
+    }
+
+    /// Sets what discovery threads do when mdns stops their search. Defaults to browsing again with exponential backoff from 1 second up to a minute. Takes effect the next time discovery is started.
+    pub fn set_discovery_reconnect_policy(&mut self, policy: ReconnectPolicy) {
+        self.reconnect_policy = policy;
+    }
+
+    /// Makes the discovery thread of a service browse again, for when its search stopped and the reconnect policy isn't bringing it back. Does nothing while discovery isn't running.
+    pub fn reconnect_discovery(&self, service: DanteService) {
+        if self.is_running() {
+            self.reconnect_requests.lock().unwrap().insert(service);
+        }
+    }
+
+    /// reconnect_discovery() for DBC.
+    pub fn reconnect_dbc_discovery(&self) {
+        self.reconnect_discovery(DanteService::Dbc);
+    }
+
+    /// reconnect_discovery() for CMC.
+    pub fn reconnect_cmc_discovery(&self) {
+        self.reconnect_discovery(DanteService::Cmc);
+    }
+
+    /// reconnect_discovery() for ARC.
+    pub fn reconnect_arc_discovery(&self) {
+        self.reconnect_discovery(DanteService::Arc);
+    }
+
+    /// reconnect_discovery() for CHAN.
+    pub fn reconnect_chan_discovery(&self) {
+        self.reconnect_discovery(DanteService::Chan);
+    }
+
+    /// Returns whether mdns discovery can work on this machine, so UIs can grey out discovery when it can't. True while discovery is running, otherwise this starts and stops an mdns daemon to find out.
+    pub fn discovery_available(&self) -> bool {
+        if self.is_running() {
+            return true;
+        }
+        match (self.mdns_daemon)() {
+            Ok(mdns) => {
+                if let Err(error) = mdns.shutdown() {
+                    error!("Failed to shut down mdns daemon: {}", error);
+                }
+                true
+            }
+            Err(error) => {
+                warn!("mdns discovery unavailable: {}", error);
+                false
+            }
+        }
+    }
+
+    /// Returns whether dante mdns discovery is running
+    pub fn is_running(&self) -> bool {
+        *self.running.lock().unwrap()
+    }
+
+    /// Stops mdns discovery. The discovery threads notice within a poll cycle and may still be running when this returns, see stop_discovery_and_wait().
+    pub fn stop_discovery(&self) {
+        *self.running.lock().unwrap() = false;
+    }
+
+    /// stop_discovery(), then waits for the discovery threads to finish and shuts down the mdns daemons, so nothing touches the device list or the network anymore once it returns. The same as DiscoveryHandle::stop_and_join(). Discovery can be started again afterwards.
+    pub fn stop_discovery_and_wait(&self) {
+        self.discovery_handle().stop_and_join();
+    }
+
+    /// Waits until no service has resolved for settle_time, or until max_time has passed since start. Returns whether it was cancelled before that.
+    fn wait_for_settle(
+        &self,
+        start: Instant,
+        settle_time: Duration,
+        max_time: Duration,
+        cancel: &CancellationToken,
+    ) -> bool {
+        loop {
+            let now = Instant::now();
+            let last_resolved_at = self
+                .device_list
+                .lock()
+                .unwrap()
+                .last_resolved_at
+                .map_or(start, |last_resolved_at| last_resolved_at.max(start));
+            if now - last_resolved_at >= settle_time || now - start >= max_time {
+                return false;
+            }
+            if cancel.sleep(Duration::from_millis(50)) {
+                return true;
+            }
+        }
+    }
+
+    /// Runs discovery for timeout and returns the devices that fully resolved in that time, sorted by name: the ones with an address and an ARC record, which is what sending them commands takes. Devices still half resolved when the time is up are left out. Discovery is stopped again before this returns, also when it fails to start. If discovery was already running it's left running, and this returns what's known right away, without waiting.
+    ///
+    /// Returns DeviceInfo, which is what every other API here describes a device with, and an error when mdns can't be started rather than an empty list that looks like an empty network.
+    pub fn try_get_devices_timeout(
+        &self,
+        timeout: Duration,
+    ) -> Result<Vec<DeviceInfo>, DiscoveryError> {
+        Ok(self
+            .try_get_devices_timeout_cancellable(timeout, &CancellationToken::new())?
+            .value)
+    }
+
+    /// try_get_devices_timeout() that stops discovery early once cancel is cancelled, returning the devices that fully resolved until then.
+    pub fn try_get_devices_timeout_cancellable(
+        &self,
+        timeout: Duration,
+        cancel: &CancellationToken,
+    ) -> Result<Partial<Vec<DeviceInfo>>, DiscoveryError> {
+        let mut cancelled = false;
+        if !self.is_running() {
+            let discovery = match self.start_discovery() {
+                Ok(discovery) => discovery,
+                Err(error) => {
+                    // start_discovery() shuts its daemon down when it fails, this only makes sure no thread is left behind.
+                    self.stop_discovery_and_wait();
+                    return Err(error);
+                }
+            };
+            cancelled = cancel.sleep(timeout);
+            discovery.stop_and_join();
+        }
+        Ok(Partial {
+            value: self
+                .get_device_infos()
+                .into_iter()
+                .filter(|info| !info.unresolved && !info.addresses.is_empty() && info.arc_connected)
+                .collect(),
+            cancelled,
+        })
+    }
+
+    /// Returns the ARC port a device resolved to over mdns. This is the port control commands go to, and isn't always the default 4440.
+    pub fn get_device_arc_port(&self, device_name: &str) -> Option<u16> {
+        self.device_list.lock().unwrap().get_arc_port(device_name)
+    }
+
+    /// Returns the DBC port a device resolved to over mdns.
+    pub fn get_device_dbc_port(&self, device_name: &str) -> Option<u16> {
+        self.device_list.lock().unwrap().get_dbc_port(device_name)
+    }
+
+    /// Returns the CMC port a device resolved to over mdns.
+    pub fn get_device_cmc_port(&self, device_name: &str) -> Option<u16> {
+        self.device_list.lock().unwrap().get_cmc_port(device_name)
+    }
+
+    /// Returns whether a device has an RX channel, going by get_rx_channel_ids(). None when the RX channels of the device aren't known.
+    pub fn rx_channel_exists(&self, device_name: &str, rx_channel_id: u16) -> Option<bool> {
+        let rx_channel_ids = self.get_rx_channel_ids(device_name);
+        match rx_channel_ids.is_empty() {
+            true => None,
+            false => Some(rx_channel_ids.contains(&rx_channel_id)),
+        }
+    }
+
+    /// Returns the name of the device that resolved to an address, for working out which device a response came from. If several devices claim the address, the first by name is returned.
+    pub fn get_device_by_ip(&self, ip: &Ipv4Addr) -> Option<String> {
+        self.device_list
+            .lock()
+            .unwrap()
+            .get_devices_by_ip(ip)
+            .into_iter()
+            .next()
+    }
+
+    /// Returns the name of the device whose services resolve to a host name. Devices are always keyed on their (instance) name, which is what Dante shows, so this is for going the other way from an A record. If several devices share the host name, the first by name is returned.
+    pub fn get_device_by_hostname(&self, hostname: &str) -> Option<String> {
+        self.device_list
+            .lock()
+            .unwrap()
+            .get_devices_by_hostname(hostname)
+            .into_iter()
+            .next()
+    }
+
+    /// Returns a list of all the mdns dante device names that were found on the network.
+    pub fn get_device_names(&self) -> Vec<String> {
+        self.read_device_list()
+            .devices
+            .keys()
+            .map(|device| device.to_string())
+            .collect()
+    }
+
+    /// Returns the RX channel ids of a device without querying it. These come from the last subscription read-back, or before the device has been read back, from the channel count the device hints at in its mdns records. Empty if neither is known.
+    pub fn get_rx_channel_ids(&self, device_name: &str) -> Vec<u16> {
+        self.device_list
+            .lock()
+            .unwrap()
+            .get_rx_channel_ids(device_name)
+    }
+
+    /// Whether a discovered device advertises a channel with this name over mdns, without querying it. Only channels with a CHAN record count, so this is for checking names before sending commands, not for listing what the device has (see probe_tx_channels() for that).
+    pub fn channel_exists_by_name(&self, device_name: &str, channel_name: &str) -> bool {
+        self.device_list
+            .lock()
+            .unwrap()
+            .channel_name_exist(device_name, channel_name)
+    }
+
+    /// channel_exists_by_name() by channel id.
+    pub fn channel_exists_by_id(&self, device_name: &str, channel_id: u16) -> bool {
+        self.device_list
+            .lock()
+            .unwrap()
+            .channel_id_exist(device_name, channel_id)
+    }
+
+    /// Waits until a discovered device advertises a channel with this name over mdns and returns it. After a channel is renamed the new name only shows up once its CHAN record has gone around again, so this is for waiting on that. Fails with DeviceNotFound if discovery doesn't know the device, and with Timeout if the channel doesn't show up in time.
+    pub fn wait_for_channel(
+        &self,
+        device_name: &str,
+        channel_name: &str,
+        timeout: Duration,
+    ) -> Result<ChannelInfo, WaitError> {
+        self.wait_for_channel_cancellable(
+            device_name,
+            channel_name,
+            timeout,
+            &CancellationToken::new(),
+        )
+    }
+
+    /// wait_for_channel() that gives up with Cancelled once cancel is cancelled.
+    pub fn wait_for_channel_cancellable(
+        &self,
+        device_name: &str,
+        channel_name: &str,
+        timeout: Duration,
+        cancel: &CancellationToken,
+    ) -> Result<ChannelInfo, WaitError> {
+        let start = Instant::now();
+        loop {
+            {
+                let device_list = self.device_list.lock().unwrap();
+                if !device_list.device_connected(device_name) {
+                    return Err(WaitError::DeviceNotFound(device_name.to_owned()));
+                }
+                let channel = device_list.caches.get(device_name).and_then(|cache| {
+                    cache
+                        .chan_info
+                        .iter()
+                        .find(|chan_info| chan_info.name == channel_name)
+                        .map(|chan_info| cache.tx_channel_info(chan_info, None))
+                });
+                if let Some(channel) = channel {
+                    return Ok(channel);
+                }
+            }
+            if start.elapsed() >= timeout {
+                return Err(WaitError::Timeout);
+            }
+            if cancel.sleep(Duration::from_millis(50)) {
+                return Err(WaitError::Cancelled);
+            }
+        }
+    }
+
+    /// Returns the CMC "id" of a device, an identifier that stays the same when the device is renamed or changes address. None if the device isn't known or its CMC record didn't say.
+    pub fn get_device_id(&self, device_name: &str) -> Option<String> {
+        self.device_list
+            .lock()
+            .unwrap()
+            .get_device_cmc_id(device_name)
+            .map(str::to_owned)
+    }
+
+    /// Returns the name a device currently goes by from its CMC "id", see get_device_id().
+    pub fn get_device_name_by_id(&self, id: &str) -> Option<String> {
+        self.device_list
+            .lock()
+            .unwrap()
+            .get_device_name_by_id(id)
+            .map(str::to_owned)
+    }
+
+    /// Whether more than one channel of a direction of the device is known to have the same name, see channel_name_conflicts().
+    pub fn has_channel_name_conflicts(&self, device_name: &str) -> bool {
+        !self.channel_name_conflicts(device_name).is_empty()
+    }
+
+    /// Returns the names more than one channel of a direction of the device has. TX channels are known from the CHAN records and the TX channel names query, RX channels from the last subscription read-back, so a device that hasn't been queried may have conflicts this doesn't know about.
+    pub fn channel_name_conflicts(&self, device_name: &str) -> Vec<ChannelNameConflict> {
+        self.device_list
+            .lock()
+            .unwrap()
+            .channel_name_conflicts(device_name)
+    }
+
+    /// Returns the TX channel names more than one of the device's CHAN records have, sorted, for checking before renaming channels. Dante wants TX channel names to be unique on a device, so this should come back empty. Unlike channel_name_conflicts() it only goes by what the device advertises, without querying it first.
+    pub fn verify_channel_names_unique(&self, device_name: &str) -> Vec<String> {
+        self.device_list
+            .lock()
+            .unwrap()
+            .duplicate_chan_names(device_name)
+    }
+
+    /// verify_channel_names_unique() for every discovered device, leaving out the ones without duplicates.
+    pub fn verify_all_channel_names_unique(&self) -> HashMap<String, Vec<String>> {
+        let device_list = self.device_list.lock().unwrap();
+        device_list
+            .devices
+            .keys()
+            .map(|device_name| {
+                (
+                    device_name.to_string(),
+                    device_list.duplicate_chan_names(device_name),
+                )
+            })
+            .filter(|(_, duplicates)| !duplicates.is_empty())
+            .collect()
+    }
+
+    /// Returns a snapshot of what discovery knows about a device, or None if it isn't in the list.
+    pub fn get_device_info(&self, device_name: &str) -> Option<DeviceInfo> {
+        self.read_device_list().get_device_info(device_name)
+    }
+
+    /// Returns a snapshot of every device found on the network, sorted by name. The device list is locked for one device at a time, so discovery carries on in between on a big network. A device that goes away meanwhile is left out.
+    pub fn get_device_infos(&self) -> Vec<DeviceInfo> {
+        let mut device_names = self.get_device_names();
+        device_names.sort();
+        device_names
+            .iter()
+            .filter_map(|device_name| self.get_device_info(device_name))
+            .collect()
+    }
+
+    /// Returns a list descriptions of all the mdns dante device names that were found on the network. Each description is the `{}` format of DeviceInfo, with the channel names cut off after DESCRIPTION_MAX_CHANNELS channels. get_device_info() has all of them.
+    pub fn get_device_descriptions(&self) -> Vec<String> {
+        self.get_device_infos()
+            .iter()
+            .map(|device_info| device_info.description(DESCRIPTION_MAX_CHANNELS))
+            .collect()
+    }
+
+    pub fn new() -> Self {
+        DanteDeviceManager {
+            device_list: Arc::new(Mutex::new(DanteDeviceList::new())),
+            running: Arc::new(Mutex::new(false)),
+            discovery: Arc::new(Mutex::new(DiscoveryThreads::default())),
+            metrics: Arc::new(Metrics::default()),
+            packet_capture: Arc::new(PacketCapture::default()),
+            reconnect_policy: ReconnectPolicy::default(),
+            redundancy_heuristic: RedundancyHeuristic::default(),
+            discovery_services: HashSet::from(DanteService::ALL),
+            browse_targets: Vec::new(),
+            #[cfg(feature = "aes67")]
+            aes67_discovery: false,
+            reconnect_requests: Arc::new(Mutex::new(HashSet::new())),
+            stopped_searches: Arc::default(),
+            mdns_daemon: ServiceDaemon::new,
+            current_command_sequence_id: 0,
+            command_socket: None,
+            address_translator: None,
+            routing_max_age: DEFAULT_ROUTING_MAX_AGE,
+            query_max_age: DEFAULT_QUERY_MAX_AGE,
+            resolve_timeout: DEFAULT_RESOLVE_TIMEOUT,
+            response_timeout: DEFAULT_RESPONSE_TIMEOUT,
+            chan_refresh_timeout: REFRESH_WAIT,
+            rebrowse_generation: Arc::default(),
+            network_watchdog_window: None,
+            network_down: Arc::default(),
+            cache_ttl: DEFAULT_CACHE_TTL,
+            #[cfg(any(test, feature = "test-util"))]
+            lock_probe: LockProbe::default(),
+            max_datagram_size: DEFAULT_MAX_DATAGRAM_SIZE,
+        }
+    }
+}
+
+impl Default for DanteDeviceManager {
+    fn default() -> Self {
+        DanteDeviceManager::new()
+    }
+}
+
+/// Browses for the service type addr (like DanteService::Cmc.service_type()) for poll_time and returns every raw mdns event that came in, in order. Fails like start_discovery() if mdns can't be started on this machine.
+pub fn collect_mdns_with_address(
+    addr: &str,
+    poll_time: Duration,
+) -> Result<Vec<ServiceEvent>, DiscoveryError> {
+    info!("Collecting mdns events for {}", addr);
+
+    let mdns = ServiceDaemon::new().map_err(DiscoveryError::DaemonInit)?;
+    let receiver = mdns.browse(addr).map_err(|source| {
+        if let Err(error) = mdns.shutdown() {
+            error!("Failed to shut down mdns daemon: {}", error);
+        }
+        DiscoveryError::Browse {
+            service: addr.to_owned(),
+            source,
+        }
+    })?;
+
+    let deadline = Instant::now() + poll_time;
+    let mut events = Vec::new();
+    while let Some(remaining) = deadline.checked_duration_since(Instant::now()) {
+        match receiver.recv_timeout(remaining) {
+            Ok(event) => events.push(event),
+            Err(_) => break,
+        }
+    }
+
+    if let Err(error) = mdns.shutdown() {
+        error!("Failed to shut down mdns daemon: {}", error);
+    }
+    Ok(events)
+}
+
+/// Writes events from collect_mdns_with_address() to sink, one line each.
+pub fn write_mdns_events(events: &[ServiceEvent], sink: &mut impl Write) -> std::io::Result<()> {
+    for event in events {
+        match event {
+            ServiceEvent::SearchStarted(service_name) => {
+                writeln!(sink, "Search Started: {}", service_name)?
+            }
+            ServiceEvent::ServiceFound(service_name, host_service_name) => writeln!(
+                sink,
+                "Search Found: {}, {}",
+                service_name, host_service_name
+            )?,
+            ServiceEvent::ServiceResolved(service_info) => {
+                writeln!(sink, "Service Resolved: {:?}", service_info)?
+            }
+            ServiceEvent::ServiceRemoved(a, b) => writeln!(sink, "Service Removed: {}, {}", a, b)?,
+            ServiceEvent::SearchStopped(a) => writeln!(sink, "Search Stopped: {}", a)?,
+        }
+    }
+    Ok(())
+}
+
+/// Print raw data received from mDNS discovery requests at addr.
+fn print_mdns_with_address(addr: &str, poll_time: Duration) {
+    let events = match collect_mdns_with_address(addr, poll_time) {
+        Ok(events) => events,
+        Err(error) => {
+            error!("Failed to collect mdns events for {}: {}", addr, error);
+            return;
+        }
+    };
+    if let Err(error) = write_mdns_events(&events, &mut std::io::stdout().lock()) {
+        error!("Failed to print mdns events: {}", error);
+    }
+}
+
+/// Entry points for the targets in fuzz/. Only built under cargo fuzz.
+#[cfg(fuzzing)]
+#[doc(hidden)]
+pub mod fuzzing {
+    /// Parses a response to the RX channel names query, with the first byte as the channel count. Must not panic for any input.
+    pub fn parse_rx_channels_page(data: &[u8]) {
+        if let Some((channel_count, response)) = data.split_first() {
+            let _ = crate::parse_rx_channels_page(response, *channel_count as usize);
+        }
+    }
+
+    /// Same as parse_rx_channels_page() for the TX channels query.
+    pub fn parse_tx_channels_page(data: &[u8]) {
+        if let Some((channel_count, response)) = data.split_first() {
+            let _ = crate::parse_tx_channels_page(response, *channel_count as usize);
+        }
+    }
+
+    /// Resolves a made up service the way the discovery threads do, into a fresh device list. The first byte picks the service, the rest is lines of text: the instance name, then "key=value" TXT properties. Must not panic for any input.
+    pub fn resolve_service(data: &[u8]) {
+        use crate::*;
+        use std::collections::HashMap;
+
+        let Some((service, text)) = data.split_first() else {
+            return;
+        };
+        let service = DanteService::ALL[*service as usize % DanteService::ALL.len()];
+        let text = String::from_utf8_lossy(text);
+        let mut lines = text.lines();
+        let instance = lines.next().unwrap_or_default();
+        let properties: HashMap<String, String> = lines
+            .filter_map(|line| line.split_once('='))
+            .map(|(key, value)| (key.to_owned(), value.to_owned()))
+            .collect();
+        let Ok(service_info) = ServiceInfo::new(
+            service.service_type(),
+            instance,
+            "fuzz.local.",
+            Ipv4Addr::new(192, 168, 1, 10),
+            4440,
+            properties,
+        ) else {
+            return;
+        };
+
+        let mut device_list = DanteDeviceList::new();
+        let fullname = service_info.get_fullname();
+        let device_name = match service {
+            DanteService::Chan => match split_chan_fullname(fullname) {
+                Some((_, device_name)) => device_name,
+                None => return,
+            },
+            _ => cutoff_address(fullname, Some(service.service_type())),
+        };
+        let device_name = device_name.to_owned();
+        device_list.handle_discovery_event(service, DiscoveryEvent::Resolved(service_info));
+        let _ = device_list.get_device_info(&device_name);
+    }
+
+    /// Splits a made up control response into header and payload, like every answer to a command is. Checks that whatever parses is framed the way the header says: the length is the packet's and the payload is what follows the header. Must not panic for any input.
+    pub fn parse_dante_response(data: &[u8]) {
+        if let Ok(response) = crate::parse_dante_response(data) {
+            assert_eq!(response.total_length as usize, data.len());
+            assert_eq!(response.payload, &data[10..]);
+            assert_eq!(
+                crate::command_socket::sequence_id(data),
+                Some(response.sequence_id)
+            );
+        }
+    }
+
+    /// Parses a made up DNS response the way probe_device() does its answers. Must not panic for any input.
+    pub fn parse_dns_response(data: &[u8]) {
+        let _ = crate::unicast_dns::parse_response(data);
+    }
+
+    /// Runs the network watchdog through made up time, one byte per step: the low six bits are how many tenths of a second pass, bit 6 is traffic coming in and bit 7 a heartbeat. Checks that it never goes down before a heartbeat or before the window passed in silence, and that down and restored take turns. Must not panic for any input.
+    pub fn network_watchdog(data: &[u8]) {
+        use crate::network_health::NetworkWatchdog;
+        use crate::*;
+
+        let window = Duration::from_secs(2);
+        let start = Instant::now();
+        let mut watchdog = NetworkWatchdog::new(window, start);
+        let mut now = start;
+        let mut last_traffic_at = None;
+        let mut heard_heartbeat = false;
+        let mut down = false;
+        for step in data {
+            now += Duration::from_millis((step & 0x3f) as u64 * 100);
+            if step & 0xc0 != 0 {
+                last_traffic_at = Some(now);
+            }
+            heard_heartbeat |= step & 0x80 != 0;
+            match watchdog.update(now, last_traffic_at, heard_heartbeat) {
+                Some(DanteDeviceEvent::NetworkDown { silent_for }) => {
+                    assert!(heard_heartbeat && !down);
+                    assert!(silent_for >= window);
+                    assert_eq!(
+                        silent_for,
+                        now - last_traffic_at.unwrap_or(start).max(start)
+                    );
+                    down = true;
+                }
+                Some(DanteDeviceEvent::NetworkRestored { .. }) => {
+                    assert!(down);
+                    assert_eq!(last_traffic_at, Some(now));
+                    down = false;
+                }
+                Some(event) => panic!("unexpected {:?}", event),
+                None => {}
+            }
+        }
+    }
+
+    /// Builds subscription commands of every Dante version from made up names and checks that the offsets in them point at the names. The first two bytes are the RX channel id, the rest is "device/channel".
+    pub fn subscription_command(data: &[u8]) {
+        use crate::*;
+
+        let Some((rx_channel_id, path)) = data.split_first_chunk::<2>() else {
+            return;
+        };
+        let rx_channel_id = u16::from_be_bytes(*rx_channel_id);
+        // Names are at most 31 characters and never have a nul in them.
+        if path.len() > 64 || path.contains(&0) {
+            return;
+        }
+        let Ok(path) = AsciiStr::from_ascii(path) else {
+            return;
+        };
+        let Some((tx_device, tx_channel)) = path.as_str().split_once('/') else {
+            return;
+        };
+        let (tx_device, tx_channel) = (
+            as_dante_name(tx_device).unwrap(),
+            as_dante_name(tx_channel).unwrap(),
+        );
+        for version in [DanteVersion::Dante4_4_1_3, DanteVersion::Dante4_2_1_3] {
+            let layout = version.get_commands().subscription_layout;
+            let command = make_dante_command(
+                0,
+                version.get_commands().command_subscription,
+                &layout.args(rx_channel_id, Some((tx_device, tx_channel))),
+            );
+            let name_at = |offset_at: usize| {
+                let offset = u16::from_be_bytes([command[offset_at], command[offset_at + 1]]);
+                let name = &command[offset as usize..];
+                &name[..name.iter().position(|byte| *byte == 0).unwrap()]
+            };
+            let offsets_at =
+                COMMAND_HEADER_LENGTH + layout.prefix.len() + 2 + layout.channel_suffix.len();
+            assert_eq!(name_at(offsets_at), tx_channel.as_bytes());
+            assert_eq!(name_at(offsets_at + 2), tx_device.as_bytes());
+            assert_eq!(
+                command.len(),
+                COMMAND_HEADER_LENGTH
+                    + layout.name_area_start
+                    + tx_channel.len()
+                    + tx_device.len()
+                    + 2
+            );
+        }
+    }
+
+    /// Runs a made up sequence of discovery transitions on a fresh device list, two bytes each: the first picks the transition and service, the second the device and its address. Every transition takes the device list lock once, so any interleaving of the discovery threads and commands is one of these sequences. The invariants of the list are checked after each. Must not panic for any input.
+    pub fn device_list_transitions(data: &[u8]) {
+        use crate::*;
+        use std::collections::HashMap;
+
+        const DEVICE_NAMES: [&str; 3] = ["alpha", "bravo", "charlie"];
+
+        let mut device_list = DanteDeviceList::new();
+        for transition in data.chunks_exact(2) {
+            let service = DanteService::ALL[(transition[0] & 0x03) as usize];
+            let device_name = DEVICE_NAMES[transition[1] as usize % DEVICE_NAMES.len()];
+            let Ok(service_info) = ServiceInfo::new(
+                service.service_type(),
+                device_name,
+                "fuzz.local.",
+                Ipv4Addr::new(192, 168, 1, transition[1]),
+                4440,
+                HashMap::<String, String>::new(),
+            ) else {
+                return;
+            };
+            let hostname = service_info.get_hostname();
+            match transition[0] >> 2 {
+                0 => device_list.connect_service(device_name, service),
+                1 => device_list.disconnect_service(device_name, service),
+                2 => match service {
+                    DanteService::Dbc => {
+                        device_list.resolve_dbc(device_name, hostname, (&service_info).into())
+                    }
+                    DanteService::Cmc => {
+                        device_list.resolve_cmc(device_name, hostname, (&service_info).into())
+                    }
+                    DanteService::Arc => {
+                        device_list.resolve_arc(device_name, hostname, (&service_info).into())
+                    }
+                    DanteService::Chan => device_list.resolve_chan(
+                        device_name,
+                        hostname,
+                        CHANInfo::from_service_info("chan", &service_info),
+                    ),
+                },
+                3 => device_list.remove_chan(device_name, "chan"),
+                4 => device_list.purge_stale_caches(Duration::ZERO),
+                6 => {
+                    device_list.removal_grace = Duration::ZERO;
+                    device_list.finalize_removals();
+                }
+                5 => {
+                    if device_list.device_connected(device_name) {
+                        device_list.update_subscriptions(device_name, Vec::new());
+                    }
+                }
+                _ => {
+                    let _ = device_list.get_device_info(device_name);
+                    let _ = device_list.get_device_ip(device_name);
+                }
+            }
+            device_list.debug_check_invariants();
+        }
+    }
+}
+
+/// Discovers the devices on the network in one go. Browses until no service has resolved for settle_time, or max_time has passed, then stops discovery completely and returns what was found, sorted by name. For scripts that would otherwise start discovery, sleep, and read.
+pub fn discover(
+    settle_time: Duration,
+    max_time: Duration,
+) -> Result<Vec<DeviceInfo>, DiscoveryError> {
+    Ok(discover_cancellable(settle_time, max_time, &CancellationToken::new())?.value)
+}
+
+/// discover() that stops browsing early once cancel is cancelled, returning what was found until then.
+pub fn discover_cancellable(
+    settle_time: Duration,
+    max_time: Duration,
+    cancel: &CancellationToken,
+) -> Result<Partial<Vec<DeviceInfo>>, DiscoveryError> {
+    let manager = DanteDeviceManager::new();
+    let start = Instant::now();
+    let discovery = manager.start_discovery()?;
+    let cancelled = manager.wait_for_settle(start, settle_time, max_time, cancel);
+    discovery.stop_and_join();
+    Ok(Partial {
+        value: manager.get_device_infos(),
+        cancelled,
+    })
+}
+
+/// Print raw data received from mDNS discovery requests to the "_netaudio-cmc._udp.local." address.
+pub fn print_cmc(poll_time: Duration) {
+    print_mdns_with_address(CMC_SERVICE, poll_time);
+}
+
+/// Print raw data received from mDNS discovery requests to the "_netaudio-dbc._udp.local." address.
+pub fn print_dbc(poll_time: Duration) {
     print_mdns_with_address(DBC_SERVICE, poll_time);
 }
 
@@ -1103,3 +4891,880 @@ pub fn print_arc(poll_time: Duration) {
 pub fn print_chan(poll_time: Duration) {
     print_mdns_with_address(CHAN_SERVICE, poll_time);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn command_sequence_id_wraps_around() {
+        let mut manager = DanteDeviceManager::new();
+        manager.current_command_sequence_id = u16::MAX;
+        assert_eq!(manager.get_new_command_sequence_id(), u16::MAX);
+        assert_eq!(manager.get_new_command_sequence_id(), 0);
+        assert_eq!(manager.get_new_command_sequence_id(), 1);
+    }
+
+    #[test]
+    fn as_dante_name_takes_ascii() {
+        assert_eq!(as_dante_name("Stage-Box").unwrap().as_str(), "Stage-Box");
+        assert_eq!(
+            as_dante_name("Main L (mix)").unwrap().as_str(),
+            "Main L (mix)"
+        );
+        assert_eq!(as_dante_name("").unwrap().as_str(), "");
+    }
+
+    #[test]
+    fn as_dante_name_rejects_umlaut() {
+        match as_dante_name("Bühne-Links") {
+            Err(MakeSubscriptionError::NonAsciiName { name, offending }) => {
+                assert_eq!(name, "Bühne-Links");
+                assert_eq!(offending, vec!['ü']);
+            }
+            result => panic!("expected NonAsciiName, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn as_dante_name_rejects_emoji() {
+        match as_dante_name("Vox 🎤 Ä") {
+            Err(MakeSubscriptionError::NonAsciiName { name, offending }) => {
+                assert_eq!(name, "Vox 🎤 Ä");
+                assert_eq!(offending, vec!['🎤', 'Ä']);
+            }
+            result => panic!("expected NonAsciiName, got {:?}", result),
+        }
+    }
+
+    /// A device at 10.0.0.7 whose CMC record says it has two channels.
+    fn manager_with_two_channel_device() -> DanteDeviceManager {
+        let manager = DanteDeviceManager::new();
+        let fullname = format!("Stage-Box.{}", DanteService::Cmc.service_type());
+        manager
+            .inject_event(
+                DanteService::Cmc,
+                SyntheticEvent::Resolved {
+                    fullname,
+                    addrs: vec![Ipv4Addr::new(10, 0, 0, 7)],
+                    port: 8800,
+                    txt: HashMap::from([("nchan".to_owned(), "2".to_owned())]),
+                },
+            )
+            .unwrap();
+        manager
+    }
+
+    /// A subscription command as the version specific code before SubscriptionLayout built it: header with sequence id 1, the fixed fields, the zeros and the names.
+    fn golden_subscription_packet(
+        header: [u8; 10],
+        fixed_fields: &[u8],
+        zeros: usize,
+        names: &[u8],
+    ) -> Vec<u8> {
+        let mut packet = header.to_vec();
+        packet.extend_from_slice(fixed_fields);
+        packet.resize(packet.len() + zeros, 0x00);
+        packet.extend_from_slice(names);
+        packet
+    }
+
+    fn subscription_packet(version: DanteVersion, tx: Option<(&str, &str)>) -> Vec<u8> {
+        let tx = tx.map(|(tx_device, tx_channel)| {
+            (
+                as_dante_name(tx_device).unwrap(),
+                as_dante_name(tx_channel).unwrap(),
+            )
+        });
+        let commands = version.get_commands();
+        let args = commands.subscription_layout.args(5, tx);
+        make_dante_command(1, commands.command_subscription, &args).to_vec()
+    }
+
+    #[test]
+    fn subscribe_packet_4_4_1_3_matches_golden() {
+        let golden = golden_subscription_packet(
+            [0x28, 0x30, 0x01, 0x21, 0x00, 0x01, 0x34, 0x10, 0x00, 0x00],
+            &[
+                0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x08, 0x00, 0x20, 0x01, 0x00, 0x05, 0x00, 0x03,
+                0x01, 0x14, 0x01, 0x17,
+            ],
+            248,
+            b"01\0Stage-Box\0",
+        );
+        assert_eq!(
+            subscription_packet(DanteVersion::Dante4_4_1_3, Some(("Stage-Box", "01"))),
+            golden
+        );
+    }
+
+    #[test]
+    fn subscribe_packet_4_2_1_3_matches_golden() {
+        let golden = golden_subscription_packet(
+            [0x28, 0x30, 0x01, 0x59, 0x00, 0x01, 0x30, 0x10, 0x00, 0x00],
+            &[0x10, 0x01, 0x00, 0x05, 0x01, 0x4C, 0x01, 0x4F],
+            314,
+            b"01\0Stage-Box\0",
+        );
+        assert_eq!(
+            subscription_packet(DanteVersion::Dante4_2_1_3, Some(("Stage-Box", "01"))),
+            golden
+        );
+    }
+
+    #[test]
+    fn clear_packet_4_4_1_3_matches_golden() {
+        let golden = golden_subscription_packet(
+            [0x28, 0x30, 0x01, 0x14, 0x00, 0x01, 0x34, 0x10, 0x00, 0x00],
+            &[
+                0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x08, 0x00, 0x20, 0x01, 0x00, 0x05, 0x00, 0x03,
+                0x00, 0x00, 0x00, 0x00,
+            ],
+            248,
+            b"",
+        );
+        assert_eq!(
+            subscription_packet(DanteVersion::Dante4_4_1_3, None),
+            golden
+        );
+    }
+
+    #[test]
+    fn clear_packet_4_2_1_3_matches_golden() {
+        let golden = golden_subscription_packet(
+            [0x28, 0x30, 0x01, 0x4C, 0x00, 0x01, 0x30, 0x10, 0x00, 0x00],
+            &[0x10, 0x01, 0x00, 0x05],
+            318,
+            b"",
+        );
+        assert_eq!(
+            subscription_packet(DanteVersion::Dante4_2_1_3, None),
+            golden
+        );
+    }
+
+    #[test]
+    fn unknown_properties_leave_out_parsed_ones() {
+        let mut channel = ChannelInfo::bare(
+            1,
+            "01".to_owned(),
+            ChannelDirection::Tx,
+            ChannelSource::Mdns,
+        );
+        channel.extra_properties = HashMap::from([
+            ("id".to_owned(), "1".to_owned()),
+            ("rate".to_owned(), "48000".to_owned()),
+            ("en".to_owned(), "float".to_owned()),
+            ("vendor".to_owned(), "x".to_owned()),
+        ]);
+        assert_eq!(
+            channel.unknown_properties(),
+            HashMap::from([
+                ("en".to_owned(), "float".to_owned()),
+                ("vendor".to_owned(), "x".to_owned()),
+            ])
+        );
+    }
+
+    /// A device with 512 channels resolved in a scrambled order, every eighth without an id.
+    fn manager_with_512_channel_device() -> DanteDeviceManager {
+        let manager = manager_with_two_channel_device();
+        for step in 0..512u32 {
+            let index = (step * 197) % 512;
+            let mut txt = HashMap::from([("rate".to_owned(), "48000".to_owned())]);
+            if index % 8 != 0 {
+                txt.insert("id".to_owned(), (index + 1).to_string());
+            }
+            let fullname = format!(
+                "ch{:03}@Stage-Box.{}",
+                511 - index,
+                DanteService::Chan.service_type()
+            );
+            manager
+                .inject_event(
+                    DanteService::Chan,
+                    SyntheticEvent::Resolved {
+                        fullname,
+                        addrs: vec![Ipv4Addr::new(10, 0, 0, 7)],
+                        port: 4455,
+                        txt,
+                    },
+                )
+                .unwrap();
+        }
+        manager
+    }
+
+    #[test]
+    fn get_device_infos_orders_512_channels() {
+        let manager = manager_with_512_channel_device();
+        let infos = manager.get_device_infos();
+        assert_eq!(infos.len(), 1);
+        let channels = &infos[0].channels;
+        assert_eq!(channels.len(), 512);
+        let (numbered, unnumbered) = channels.split_at(448);
+        let ids: Vec<u16> = numbered.iter().map(|channel| channel.id.unwrap()).collect();
+        let mut sorted_ids = ids.clone();
+        sorted_ids.sort();
+        assert_eq!(ids, sorted_ids);
+        assert!(unnumbered.iter().all(|channel| channel.id.is_none()));
+        assert!(unnumbered
+            .windows(2)
+            .all(|pair| pair[0].name < pair[1].name));
+        assert_eq!(manager.get_device_infos(), infos);
+    }
+
+    #[test]
+    fn get_device_descriptions_cut_off_512_channels() {
+        let manager = manager_with_512_channel_device();
+        let descriptions = manager.get_device_descriptions();
+        assert_eq!(descriptions.len(), 1);
+        let description = &descriptions[0];
+        let channel_lines: Vec<&str> = description
+            .lines()
+            .skip_while(|line| *line != "Channels:")
+            .skip(1)
+            .collect();
+        assert_eq!(channel_lines.len(), DESCRIPTION_MAX_CHANNELS + 1);
+        // Channel id 2 is index 1, named ch510.
+        assert_eq!(channel_lines[0], "\"ch510\"");
+        assert_eq!(
+            channel_lines[DESCRIPTION_MAX_CHANNELS],
+            format!("  ... and {} more", 512 - DESCRIPTION_MAX_CHANNELS)
+        );
+        assert_eq!(manager.get_device_descriptions(), descriptions);
+    }
+
+    #[test]
+    fn clear_subscription_rejects_channel_zero() {
+        let mut manager = DanteDeviceManager::new();
+        let result =
+            manager.clear_subscription(&DanteVersion::Dante4_4_1_3, &Ipv4Addr::new(10, 0, 0, 7), 0);
+        assert!(matches!(result, Err(ClearSubscriptionError::ZeroChannelId)));
+    }
+
+    #[test]
+    fn clear_subscription_rejects_channel_the_device_does_not_have() {
+        let mut manager = manager_with_two_channel_device();
+        let result =
+            manager.clear_subscription(&DanteVersion::Dante4_4_1_3, &Ipv4Addr::new(10, 0, 0, 7), 3);
+        assert!(matches!(
+            result,
+            Err(ClearSubscriptionError::ChannelNotFound(3))
+        ));
+    }
+
+    #[test]
+    fn clear_device_subscriptions_of_unknown_device() {
+        let mut manager = DanteDeviceManager::new();
+        let result = manager.clear_device_subscriptions("Nowhere");
+        assert!(matches!(
+            result,
+            Err(ClearSubscriptionError::DeviceNotFound(device_name)) if device_name == "Nowhere"
+        ));
+    }
+
+    #[test]
+    fn clear_subscription_while_network_is_down() {
+        let mut manager = manager_with_two_channel_device();
+        manager.network_down.store(true, Ordering::SeqCst);
+        let result =
+            manager.clear_subscription(&DanteVersion::Dante4_4_1_3, &Ipv4Addr::new(10, 0, 0, 7), 1);
+        assert!(matches!(
+            result,
+            Err(ClearSubscriptionError::NetworkUnavailable)
+        ));
+    }
+
+    #[test]
+    fn clear_subscription_over_datagram_limit() {
+        let mut manager = manager_with_two_channel_device();
+        manager.set_max_datagram_size(16);
+        let result =
+            manager.clear_subscription(&DanteVersion::Dante4_4_1_3, &Ipv4Addr::new(10, 0, 0, 7), 1);
+        assert!(matches!(
+            result,
+            Err(ClearSubscriptionError::PayloadTooLarge { limit: 16, .. })
+        ));
+    }
+
+    #[test]
+    fn clear_subscription_send_failure() {
+        let mut manager = DanteDeviceManager::new();
+        // Sending to the broadcast address without SO_BROADCAST is refused by the OS.
+        let result =
+            manager.clear_subscription(&DanteVersion::Dante4_4_1_3, &Ipv4Addr::BROADCAST, 1);
+        assert!(matches!(
+            result,
+            Err(ClearSubscriptionError::ConnectionFailed(_))
+        ));
+    }
+
+    #[test]
+    fn query_times_out_when_the_device_never_answers() {
+        // Bound but never read, so the query goes out and nothing comes back.
+        let silent_device = std::net::UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let port = silent_device.local_addr().unwrap().port();
+        let response_timeout = Duration::from_millis(50);
+        let mut manager = DanteDeviceManager::new().response_timeout(response_timeout);
+        let started_at = Instant::now();
+        assert!(matches!(
+            manager.query_channel_counts(&Ipv4Addr::LOCALHOST, port),
+            Err(QueryError::Timeout)
+        ));
+        assert!(started_at.elapsed() >= response_timeout);
+        assert!(started_at.elapsed() < Duration::from_secs(5));
+        assert_eq!(manager.metrics().commands_timed_out, 1);
+    }
+
+    #[test]
+    fn subscription_commands_go_to_the_arc_port_of_the_device() {
+        let relayed_device = std::net::UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        relayed_device
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let port = relayed_device.local_addr().unwrap().port();
+        let mut manager = DanteDeviceManager::new();
+        manager
+            .inject_event(
+                DanteService::Arc,
+                SyntheticEvent::Resolved {
+                    fullname: format!("Relayed.{}", DanteService::Arc.service_type()),
+                    addrs: vec![Ipv4Addr::LOCALHOST],
+                    port,
+                    txt: HashMap::new(),
+                },
+            )
+            .unwrap();
+        manager
+            .make_subscription_unchecked(
+                &DanteVersion::Dante4_4_1_3,
+                &Ipv4Addr::LOCALHOST,
+                1,
+                as_dante_name("Stage-Box").unwrap(),
+                as_dante_name("01").unwrap(),
+            )
+            .unwrap();
+        let mut buffer = [0u8; 2048];
+        let (length, _) = relayed_device.recv_from(&mut buffer).unwrap();
+        let commands = DanteVersion::Dante4_4_1_3.get_commands();
+        let tx = (
+            as_dante_name("Stage-Box").unwrap(),
+            as_dante_name("01").unwrap(),
+        );
+        let args = commands.subscription_layout.args(1, Some(tx));
+        assert_eq!(
+            &buffer[..length],
+            &make_dante_command(0, commands.command_subscription, &args)[..]
+        );
+    }
+
+    /// A discovered device at a local port that never answers, with a read-back of three RX channels, two of them subscribed, that's older than the routing max age.
+    fn manager_with_silent_device(silent_device: &std::net::UdpSocket) -> DanteDeviceManager {
+        let mut manager = DanteDeviceManager::new().response_timeout(Duration::from_millis(50));
+        manager.set_routing_max_age(Duration::ZERO);
+        manager
+            .inject_event(
+                DanteService::Arc,
+                SyntheticEvent::Resolved {
+                    fullname: format!("Silent.{}", DanteService::Arc.service_type()),
+                    addrs: vec![Ipv4Addr::LOCALHOST],
+                    port: silent_device.local_addr().unwrap().port(),
+                    txt: HashMap::new(),
+                },
+            )
+            .unwrap();
+        let subscriptions = (1..=3)
+            .map(|rx_channel_id| SubscriptionEntry {
+                rx_channel_id,
+                rx_channel_name: format!("{:02}", rx_channel_id),
+                tx_device: (rx_channel_id < 3).then(|| "Stage-Box".to_owned()),
+                tx_channel: (rx_channel_id < 3).then(|| format!("{:02}", rx_channel_id)),
+                status: 0,
+            })
+            .collect();
+        manager
+            .device_list
+            .lock()
+            .unwrap()
+            .update_subscriptions("Silent", subscriptions);
+        manager
+    }
+
+    #[test]
+    fn count_active_subscriptions_of_an_unreachable_device_is_stale() {
+        let silent_device = std::net::UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let mut manager = manager_with_silent_device(&silent_device);
+        let count = manager.count_active_subscriptions("Silent").unwrap();
+        assert_eq!(count.value, 2);
+        assert_eq!(count.freshness, Freshness::Stale);
+        assert!(matches!(
+            manager.count_active_subscriptions("Missing"),
+            Err(QueryError::DeviceNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn count_available_rx_channels_of_an_unreachable_device_is_stale() {
+        let silent_device = std::net::UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let mut manager = manager_with_silent_device(&silent_device);
+        let count = manager.count_available_rx_channels("Silent").unwrap();
+        assert_eq!(count.value, 1);
+        assert_eq!(count.freshness, Freshness::Stale);
+        manager.set_routing_max_age(Duration::from_secs(60));
+        let count = manager.count_available_rx_channels("Silent").unwrap();
+        assert_eq!(count.value, 1);
+        assert_eq!(count.freshness, Freshness::Cached);
+    }
+
+    #[test]
+    fn command_latency_covers_every_address_of_the_device() {
+        let primary = Ipv4Addr::new(10, 0, 0, 7);
+        let secondary = Ipv4Addr::new(10, 1, 0, 7);
+        let manager = DanteDeviceManager::new();
+        manager
+            .inject_event(
+                DanteService::Arc,
+                SyntheticEvent::Resolved {
+                    fullname: format!("Redundant.{}", DanteService::Arc.service_type()),
+                    addrs: vec![primary, secondary],
+                    port: 4440,
+                    txt: HashMap::new(),
+                },
+            )
+            .unwrap();
+        assert_eq!(manager.get_command_latency("Redundant"), None);
+        // Only the secondary answers.
+        for millis in 1..=3 {
+            manager
+                .metrics
+                .record_command_acked(secondary, Duration::from_millis(millis));
+        }
+        let latency = manager.get_command_latency("Redundant").unwrap();
+        assert_eq!(latency.samples.len(), 3);
+        assert_eq!(latency.max, Duration::from_millis(3));
+        manager
+            .metrics
+            .record_command_acked(primary, Duration::from_millis(4));
+        let latency = manager.get_command_latency("Redundant").unwrap();
+        assert_eq!(latency.samples.len(), 4);
+        assert_eq!(
+            latency.samples.last().unwrap().round_trip,
+            Duration::from_millis(4)
+        );
+    }
+
+    #[test]
+    fn parse_dante_response_splits_the_header() {
+        let packet = [
+            0x28, 0x30, 0x00, 0x0e, 0x12, 0x34, 0x10, 0x00, 0x00, 0x01, 0x00, 0x02, 0x00, 0x04,
+        ];
+        assert_eq!(
+            parse_dante_response(&packet),
+            Ok(DanteResponse {
+                total_length: 14,
+                sequence_id: 0x1234,
+                command: [0x10, 0x00],
+                status: 1,
+                payload: &[0x00, 0x02, 0x00, 0x04],
+            })
+        );
+        // A command is framed the same way, with a zero status.
+        let command = make_dante_command(7, COMMAND_CHANNELCOUNT, &[0xab]);
+        let parsed = parse_dante_response(&command).unwrap();
+        assert_eq!(parsed.sequence_id, 7);
+        assert_eq!(parsed.status, 0);
+        assert_eq!(parsed.payload, &[0xab]);
+    }
+
+    #[test]
+    fn parse_dante_response_rejects_broken_packets() {
+        assert_eq!(
+            parse_dante_response(&[0x28, 0x30, 0x00, 0x0a, 0x00]),
+            Err(ParseError::TooShort)
+        );
+        assert_eq!(parse_dante_response(&[]), Err(ParseError::TooShort));
+        assert_eq!(
+            parse_dante_response(&[0x27, 0x30, 0x00, 0x0a, 0x00, 0x01, 0x10, 0x00, 0x00, 0x00]),
+            Err(ParseError::InvalidMagic)
+        );
+        assert_eq!(
+            parse_dante_response(&[
+                0x28, 0x30, 0x00, 0x10, 0x00, 0x01, 0x10, 0x00, 0x00, 0x00, 0x00, 0x02
+            ]),
+            Err(ParseError::LengthMismatch {
+                header: 16,
+                actual: 12
+            })
+        );
+    }
+
+    #[test]
+    fn query_ignores_garbled_answers() {
+        let device = std::net::UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let port = device.local_addr().unwrap().port();
+        let responder = std::thread::spawn(move || {
+            let mut buffer = [0u8; 2048];
+            let (length, from) = device.recv_from(&mut buffer).unwrap();
+            let command = parse_dante_response(&buffer[..length]).unwrap();
+            let answer = make_dante_command(
+                command.sequence_id,
+                command.command,
+                &[0x00, 0x00, 0x00, 0x02, 0x00, 0x04],
+            );
+            let mut bad_magic = answer.to_vec();
+            bad_magic[0] = 0x00;
+            let mut bad_length = answer.to_vec();
+            bad_length.push(0x00);
+            device.send_to(&bad_magic, from).unwrap();
+            device.send_to(&bad_length, from).unwrap();
+            device.send_to(&answer, from).unwrap();
+        });
+        let mut manager = DanteDeviceManager::new().response_timeout(Duration::from_secs(5));
+        assert_eq!(
+            manager
+                .query_channel_counts(&Ipv4Addr::LOCALHOST, port)
+                .unwrap(),
+            (2, 4)
+        );
+        responder.join().unwrap();
+    }
+
+    #[test]
+    fn stray_datagrams_dont_push_the_query_deadline_back() {
+        let response_timeout = Duration::from_millis(500);
+        let device = std::net::UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let port = device.local_addr().unwrap().port();
+        let responder = std::thread::spawn(move || {
+            let mut buffer = [0u8; 2048];
+            let (_, from) = device.recv_from(&mut buffer).unwrap();
+            // Not the answer, and late enough that a fresh full timeout after it would take the query well past its deadline.
+            std::thread::sleep(response_timeout * 4 / 5);
+            device.send_to(&[0x00], from).unwrap();
+        });
+        let mut manager = DanteDeviceManager::new().response_timeout(response_timeout);
+        let started_at = Instant::now();
+        assert!(matches!(
+            manager.query_channel_counts(&Ipv4Addr::LOCALHOST, port),
+            Err(QueryError::Timeout)
+        ));
+        assert!(started_at.elapsed() < response_timeout * 8 / 5);
+        responder.join().unwrap();
+    }
+
+    #[test]
+    fn cancelled_wait_for_channel_gives_up() {
+        let manager = manager_with_two_channel_device();
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+        assert!(matches!(
+            manager.wait_for_channel_cancellable(
+                "Stage-Box",
+                "Not There",
+                Duration::from_secs(3600),
+                &cancel
+            ),
+            Err(WaitError::Cancelled)
+        ));
+    }
+
+    #[test]
+    fn cancelled_audio_group_sends_nothing() {
+        let silent_device = std::net::UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        silent_device.set_nonblocking(true).unwrap();
+        let mut manager = manager_with_silent_device(&silent_device);
+        manager.set_routing_max_age(Duration::from_secs(3600));
+        manager
+            .inject_event(
+                DanteService::Arc,
+                SyntheticEvent::Resolved {
+                    fullname: format!("Silent.{}", DanteService::Arc.service_type()),
+                    addrs: vec![Ipv4Addr::LOCALHOST],
+                    port: silent_device.local_addr().unwrap().port(),
+                    txt: HashMap::from([("router_vers".to_owned(), "4.4.1.3".to_owned())]),
+                },
+            )
+            .unwrap();
+        let mut group = AudioGroup::new("Mix");
+        group.add_route("Silent", "01", "Stage-Box", "01");
+        group.add_route("Silent", "02", "Stage-Box", "02");
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+        assert!(matches!(
+            manager.clear_audio_group_cancellable(&group, &cancel),
+            Err(AudioGroupError::Cancelled { sent: 0, failed, .. }) if failed.is_empty()
+        ));
+        let mut buffer = [0u8; 2048];
+        assert_eq!(
+            silent_device.recv_from(&mut buffer).unwrap_err().kind(),
+            std::io::ErrorKind::WouldBlock
+        );
+    }
+
+    /// A discovered device at addrs whose ARC port is the port of socket.
+    fn manager_with_device_at(
+        addrs: Vec<Ipv4Addr>,
+        socket: &std::net::UdpSocket,
+    ) -> DanteDeviceManager {
+        let manager = DanteDeviceManager::new();
+        manager
+            .inject_event(
+                DanteService::Arc,
+                SyntheticEvent::Resolved {
+                    fullname: format!("Redundant.{}", DanteService::Arc.service_type()),
+                    addrs,
+                    port: socket.local_addr().unwrap().port(),
+                    txt: HashMap::new(),
+                },
+            )
+            .unwrap();
+        manager
+    }
+
+    #[test]
+    fn broadcast_subscription_goes_on_after_a_failed_address() {
+        let secondary = std::net::UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        secondary
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        // Sending to the broadcast address without SO_BROADCAST is refused by the OS.
+        let mut manager =
+            manager_with_device_at(vec![Ipv4Addr::BROADCAST, Ipv4Addr::LOCALHOST], &secondary);
+        // Tried last, after the broadcast address failed.
+        manager
+            .device_list
+            .lock()
+            .unwrap()
+            .reachability
+            .insert(Ipv4Addr::LOCALHOST, Timestamped::new(None));
+        manager
+            .make_subscription_broadcast(
+                &DanteVersion::Dante4_4_1_3,
+                "Redundant",
+                1,
+                as_dante_name("Stage-Box").unwrap(),
+                as_dante_name("01").unwrap(),
+            )
+            .unwrap();
+        let mut buffer = [0u8; 2048];
+        assert!(secondary.recv_from(&mut buffer).is_ok());
+    }
+
+    #[test]
+    fn broadcast_subscription_fails_when_every_address_does() {
+        let socket = std::net::UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let mut manager = manager_with_device_at(vec![Ipv4Addr::BROADCAST], &socket);
+        match manager.make_subscription_broadcast(
+            &DanteVersion::Dante4_4_1_3,
+            "Redundant",
+            1,
+            as_dante_name("Stage-Box").unwrap(),
+            as_dante_name("01").unwrap(),
+        ) {
+            Err(MakeSubscriptionError::ConnectionFailed(error)) => {
+                assert_eq!(error.failures.len(), 1);
+                assert_eq!(error.failures[0].0, Ipv4Addr::BROADCAST);
+                assert!(error.to_string().contains("255.255.255.255"));
+            }
+            result => panic!("expected ConnectionFailed, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn start_discovery_without_mdns() {
+        let manager = DanteDeviceManager {
+            mdns_daemon: || Err(mdns_sd::Error::Msg("no multicast interface".to_owned())),
+            ..DanteDeviceManager::new()
+        };
+        let Err(error) = manager.start_discovery() else {
+            panic!("discovery started without mdns");
+        };
+        assert!(matches!(
+            &error,
+            DiscoveryError::DaemonInit(mdns_sd::Error::Msg(message)) if message == "no multicast interface"
+        ));
+        assert!(std::error::Error::source(&error).is_some());
+        assert!(!manager.is_running());
+        assert!(!manager.discovery_available());
+        // Nothing was started, so there's nothing to stop.
+        manager.stop_discovery_and_wait();
+    }
+
+    #[test]
+    fn try_get_devices_timeout_without_mdns() {
+        let manager = DanteDeviceManager {
+            mdns_daemon: || Err(mdns_sd::Error::Msg("no multicast interface".to_owned())),
+            ..DanteDeviceManager::new()
+        };
+        assert!(matches!(
+            manager.try_get_devices_timeout(Duration::from_secs(60)),
+            Err(DiscoveryError::DaemonInit(_))
+        ));
+        assert!(!manager.is_running());
+    }
+
+    #[test]
+    fn try_get_devices_timeout_returns_at_once_while_discovery_runs() {
+        let manager = manager_with_two_channel_device();
+        *manager.running.lock().unwrap() = true;
+        let started = Instant::now();
+        // The device has no ARC record, so it doesn't count as resolved.
+        assert_eq!(
+            manager
+                .try_get_devices_timeout(Duration::from_secs(60))
+                .unwrap(),
+            Vec::new()
+        );
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert!(manager.is_running());
+    }
+
+    /// Threads for each service connecting, resolving and disconnecting the same few devices, one sweeping removals and stale caches, and one reading the list and storing read-backs, the way the discovery threads and command calls share the list. Every transition has to leave it consistent.
+    #[test]
+    fn concurrent_transitions_keep_the_device_list_consistent() {
+        const DEVICE_NAMES: [&str; 3] = ["alpha", "bravo", "charlie"];
+
+        let device_list = Arc::new(Mutex::new(DanteDeviceList::new()));
+        device_list.lock().unwrap().removal_grace = Duration::ZERO;
+        let deadline = Instant::now() + Duration::from_secs(1);
+        let mut threads = Vec::new();
+        for (offset, service) in DanteService::ALL.into_iter().enumerate() {
+            let device_list = device_list.clone();
+            threads.push(std::thread::spawn(move || {
+                let mut round = offset;
+                while Instant::now() < deadline {
+                    let device_name = DEVICE_NAMES[round % DEVICE_NAMES.len()];
+                    let service_info = ServiceInfo::new(
+                        service.service_type(),
+                        device_name,
+                        &format!("{}.local.", device_name),
+                        format!("10.0.0.{}", round % DEVICE_NAMES.len() + 1).as_str(),
+                        4440,
+                        HashMap::from([("id".to_owned(), "1".to_owned())]),
+                    )
+                    .unwrap();
+                    let hostname = service_info.get_hostname();
+                    let mut device_list = device_list.lock().unwrap();
+                    match (round / DEVICE_NAMES.len()) % 3 {
+                        0 => device_list.connect_service(device_name, service),
+                        1 => match service {
+                            DanteService::Dbc => device_list.resolve_dbc(
+                                device_name,
+                                hostname,
+                                (&service_info).into(),
+                            ),
+                            DanteService::Cmc => device_list.resolve_cmc(
+                                device_name,
+                                hostname,
+                                (&service_info).into(),
+                            ),
+                            DanteService::Arc => device_list.resolve_arc(
+                                device_name,
+                                hostname,
+                                (&service_info).into(),
+                            ),
+                            DanteService::Chan => device_list.resolve_chan(
+                                device_name,
+                                hostname,
+                                CHANInfo::from_service_info("01", &service_info),
+                            ),
+                        },
+                        _ => device_list.disconnect_service(device_name, service),
+                    }
+                    device_list.debug_check_invariants();
+                    drop(device_list);
+                    round += 1;
+                }
+            }));
+        }
+        let sweeper_list = device_list.clone();
+        threads.push(std::thread::spawn(move || {
+            while Instant::now() < deadline {
+                let mut device_list = sweeper_list.lock().unwrap();
+                device_list.finalize_removals();
+                device_list.purge_stale_caches(Duration::ZERO);
+                device_list.debug_check_invariants();
+                drop(device_list);
+                std::thread::yield_now();
+            }
+        }));
+        let reader_list = device_list.clone();
+        threads.push(std::thread::spawn(move || {
+            let mut round = 0;
+            while Instant::now() < deadline {
+                let device_name = DEVICE_NAMES[round % DEVICE_NAMES.len()];
+                let mut device_list = reader_list.lock().unwrap();
+                if let Some(info) = device_list.get_device_info(device_name) {
+                    assert_eq!(info.name, device_name);
+                }
+                let _ = device_list.get_device_ip(device_name);
+                if device_list.device_connected(device_name) {
+                    device_list.update_subscriptions(device_name, Vec::new());
+                }
+                device_list.debug_check_invariants();
+                drop(device_list);
+                round += 1;
+            }
+        }));
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        let device_list = device_list.lock().unwrap();
+        device_list.debug_check_invariants();
+        // With no grace period, a device goes as soon as its last service does.
+        for status in device_list.devices.values() {
+            assert!(
+                status.connected_dbc
+                    || status.connected_cmc
+                    || status.connected_arc
+                    || status.connected_chan
+            );
+        }
+    }
+
+    /// More channels than the cache takes, ids repeating and every seventh without one, described and snapshotted every way there is and imported again.
+    #[test]
+    fn large_device_describes_and_snapshots() {
+        let mut device_list = DanteDeviceList::new();
+        for index in 0..MAX_CHANNELS_PER_DEVICE + 100 {
+            let properties = HashMap::from([("index".to_owned(), index.to_string())]);
+            device_list.resolve_chan(
+                "large",
+                "large.local.",
+                CHANInfo {
+                    name: format!("ch{}", index),
+                    id: (index % 7 != 0).then_some((index % 300) as u16),
+                    sample_rate: Some(48000),
+                    encoding: None,
+                    latency: None,
+                    channel_count_hint: Some((MAX_CHANNELS_PER_DEVICE + 100) as u16),
+                    extra_properties: properties,
+                },
+            );
+        }
+        device_list.debug_check_invariants();
+        let info = device_list.get_device_info("large").unwrap();
+        assert!(!info.channels.is_empty());
+        assert!(info.channels.len() <= MAX_CHANNELS_PER_DEVICE);
+        assert!(format!("{}", info).lines().count() > info.channels.len());
+        assert!(format!("{:#}", info).lines().count() > info.channels.len());
+        assert!(!info.compact().to_string().contains('\n'));
+        let description = info.verbose(Some(DESCRIPTION_MAX_CHANNELS)).to_string();
+        assert!(description.lines().count() < format!("{:#}", info).lines().count());
+        assert!(description.ends_with(&format!(
+            "... and {} more",
+            info.channels.len() - DESCRIPTION_MAX_CHANNELS
+        )));
+
+        let manager = DanteDeviceManager::new();
+        *manager.device_list.lock().unwrap() = device_list;
+        for detail in [SnapshotDetail::Full, SnapshotDetail::Summary] {
+            let snapshot = manager.snapshot_with_detail(detail);
+            assert_eq!(snapshot.devices.len(), 1);
+            let imported = DanteDeviceList::from_snapshot(snapshot).unwrap();
+            let imported_info = imported.get_device_info("large").unwrap();
+            assert_eq!(imported_info.channels.len(), info.channels.len());
+        }
+    }
+}
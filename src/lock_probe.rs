@@ -0,0 +1,173 @@
+use crate::{DanteDeviceList, DanteDeviceManager};
+use std::ops::Deref;
+#[cfg(any(test, feature = "test-util"))]
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::MutexGuard;
+#[cfg(any(test, feature = "test-util"))]
+use std::time::{Duration, Instant};
+
+/// How often and for how long read APIs held the device list, see DanteDeviceManager::take_read_lock_max_hold() and take_read_lock_count().
+#[cfg(any(test, feature = "test-util"))]
+#[derive(Default)]
+pub(crate) struct LockProbe {
+    max_hold_nanos: AtomicU64,
+    locks: AtomicU64,
+}
+
+/// The device list locked by a read API, which can only look at it. With the test-util feature, and in the crate's own tests, how long it was held is recorded when it's dropped.
+pub(crate) struct ReadGuard<'a> {
+    guard: MutexGuard<'a, DanteDeviceList>,
+    #[cfg(any(test, feature = "test-util"))]
+    probe: &'a LockProbe,
+    #[cfg(any(test, feature = "test-util"))]
+    locked_at: Instant,
+}
+
+impl Deref for ReadGuard<'_> {
+    type Target = DanteDeviceList;
+
+    fn deref(&self) -> &DanteDeviceList {
+        &self.guard
+    }
+}
+
+#[cfg(any(test, feature = "test-util"))]
+impl Drop for ReadGuard<'_> {
+    fn drop(&mut self) {
+        let held = self.locked_at.elapsed().as_nanos() as u64;
+        self.probe.max_hold_nanos.fetch_max(held, Ordering::Relaxed);
+    }
+}
+
+impl DanteDeviceManager {
+    /// Locks the device list for a read API. Every discovery thread waits while it's held, so read APIs copy what they need through this and do the formatting and sorting after dropping it, and APIs going over every device lock it once per device rather than once for all of them.
+    pub(crate) fn read_device_list(&self) -> ReadGuard<'_> {
+        #[cfg(any(test, feature = "test-util"))]
+        self.lock_probe.locks.fetch_add(1, Ordering::Relaxed);
+        ReadGuard {
+            guard: self.device_list.lock().unwrap(),
+            #[cfg(any(test, feature = "test-util"))]
+            probe: &self.lock_probe,
+            #[cfg(any(test, feature = "test-util"))]
+            locked_at: Instant::now(),
+        }
+    }
+
+    /// The longest a read API (get_device_info(), get_device_infos(), get_device_descriptions(), snapshot(), export_network_graph() and the like) held the device list since the last call, and starts over. For checking that reading a big network doesn't stall discovery.
+    #[cfg(any(test, feature = "test-util"))]
+    pub fn take_read_lock_max_hold(&self) -> Duration {
+        Duration::from_nanos(self.lock_probe.max_hold_nanos.swap(0, Ordering::Relaxed))
+    }
+
+    /// How many times read APIs locked the device list since the last call, and starts over. APIs going over every device lock it once per device, see the read_apis_lock_once_per_device test.
+    #[cfg(any(test, feature = "test-util"))]
+    pub fn take_read_lock_count(&self) -> u64 {
+        self.lock_probe.locks.swap(0, Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{DanteDeviceManager, DanteService, GraphFormat, SnapshotDetail, SyntheticEvent};
+    use std::collections::HashMap;
+    use std::net::Ipv4Addr;
+
+    /// Fills a manager with a made up network through the same code discovery runs.
+    fn manager_with_network(device_count: usize, channel_count: usize) -> DanteDeviceManager {
+        let manager = DanteDeviceManager::new();
+        for device in 0..device_count {
+            let device_name = format!("Hold-{:04}", device);
+            let address = Ipv4Addr::new(10, 1, (device / 250) as u8, (device % 250) as u8 + 1);
+            let mut services = vec![
+                (
+                    DanteService::Cmc,
+                    format!("{}.{}", device_name, DanteService::Cmc.service_type()),
+                    HashMap::from([("nchan".to_owned(), channel_count.to_string())]),
+                ),
+                (
+                    DanteService::Arc,
+                    format!("{}.{}", device_name, DanteService::Arc.service_type()),
+                    HashMap::from([("router_vers".to_owned(), "4.4.1.3".to_owned())]),
+                ),
+            ];
+            for channel in 1..=channel_count {
+                services.push((
+                    DanteService::Chan,
+                    format!(
+                        "{:02}@{}.{}",
+                        channel,
+                        device_name,
+                        DanteService::Chan.service_type()
+                    ),
+                    HashMap::from([
+                        ("id".to_owned(), channel.to_string()),
+                        ("rate".to_owned(), "48000".to_owned()),
+                    ]),
+                ));
+            }
+            for (service, fullname, txt) in services {
+                manager
+                    .inject_event(
+                        service,
+                        SyntheticEvent::Found {
+                            fullname: fullname.clone(),
+                        },
+                    )
+                    .unwrap();
+                manager
+                    .inject_event(
+                        service,
+                        SyntheticEvent::Resolved {
+                            fullname,
+                            addrs: vec![address],
+                            port: 4440,
+                            txt,
+                        },
+                    )
+                    .unwrap();
+            }
+        }
+        manager
+    }
+
+    #[test]
+    fn read_apis_lock_once_per_device() {
+        const DEVICES: u64 = 20;
+        let manager = manager_with_network(DEVICES as usize, 8);
+        // Every discovery thread waits while a read API holds the device list, so the ones going over every device lock it once for the names and then once per device, never for all of them at once.
+        let reads: [(&str, &dyn Fn(), u64); 5] = [
+            (
+                "get_device_infos",
+                &|| drop(manager.get_device_infos()),
+                DEVICES + 1,
+            ),
+            (
+                "get_device_descriptions",
+                &|| drop(manager.get_device_descriptions()),
+                DEVICES + 1,
+            ),
+            (
+                "snapshot",
+                &|| drop(manager.snapshot_with_detail(SnapshotDetail::Full)),
+                // And once more for the routing.
+                DEVICES + 2,
+            ),
+            (
+                "export_network_graph",
+                &|| drop(manager.export_network_graph(GraphFormat::Dot)),
+                1,
+            ),
+            ("get_device_names", &|| drop(manager.get_device_names()), 1),
+        ];
+        for (api, read, expected_locks) in reads {
+            manager.take_read_lock_count();
+            read();
+            assert_eq!(
+                manager.take_read_lock_count(),
+                expected_locks,
+                "{} locked the device list an unexpected number of times",
+                api
+            );
+        }
+    }
+}
@@ -0,0 +1,229 @@
+use mdns_sd::ServiceEvent;
+use std::collections::{HashMap, VecDeque};
+use std::net::Ipv4Addr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
+
+/// How many round trips are kept per device.
+const LATENCY_WINDOW: usize = 64;
+
+/// Counters updated by discovery and the control socket. Shared between the manager and the discovery threads.
+#[derive(Default)]
+pub(crate) struct Metrics {
+    searches_started: AtomicU64,
+    services_found: AtomicU64,
+    services_resolved: AtomicU64,
+    services_removed: AtomicU64,
+    searches_stopped: AtomicU64,
+    commands_sent: AtomicU64,
+    commands_acked: AtomicU64,
+    commands_timed_out: AtomicU64,
+    commands_retried: AtomicU64,
+    send_buffer_full: AtomicU64,
+    bytes_sent: AtomicU64,
+    bytes_received: AtomicU64,
+    /// When anything last came in from the network, see record_traffic().
+    last_traffic_at: Mutex<Option<Instant>>,
+    heard_heartbeat: AtomicBool,
+    /// The last LATENCY_WINDOW round trips of acked commands, per device address.
+    round_trips: Mutex<HashMap<Ipv4Addr, VecDeque<LatencySample>>>,
+}
+
+impl Metrics {
+    pub(crate) fn record_discovery_event(&self, event: &ServiceEvent) {
+        let counter = match event {
+            ServiceEvent::SearchStarted(_) => &self.searches_started,
+            ServiceEvent::ServiceFound(_, _) => &self.services_found,
+            ServiceEvent::ServiceResolved(_) => &self.services_resolved,
+            ServiceEvent::ServiceRemoved(_, _) => &self.services_removed,
+            ServiceEvent::SearchStopped(_) => &self.searches_stopped,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        // Searches starting and stopping are the daemon's own doing, the others are answers from the network.
+        if !matches!(
+            event,
+            ServiceEvent::SearchStarted(_) | ServiceEvent::SearchStopped(_)
+        ) {
+            self.record_traffic();
+        }
+    }
+
+    /// Something came in from the network, for the network watchdog.
+    fn record_traffic(&self) {
+        *self.last_traffic_at.lock().unwrap() = Some(Instant::now());
+    }
+
+    pub(crate) fn record_heartbeat(&self) {
+        self.heard_heartbeat.store(true, Ordering::Relaxed);
+        self.record_traffic();
+    }
+
+    pub(crate) fn last_traffic_at(&self) -> Option<Instant> {
+        *self.last_traffic_at.lock().unwrap()
+    }
+
+    pub(crate) fn heard_heartbeat(&self) -> bool {
+        self.heard_heartbeat.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn record_command_sent(&self, length: usize) {
+        self.commands_sent.fetch_add(1, Ordering::Relaxed);
+        self.bytes_sent.fetch_add(length as u64, Ordering::Relaxed);
+    }
+
+    /// Anything that arrived on the control socket, whether or not it was the response being waited for.
+    pub(crate) fn record_received(&self, length: usize) {
+        self.bytes_received
+            .fetch_add(length as u64, Ordering::Relaxed);
+        self.record_traffic();
+    }
+
+    /// round_trip is from the command leaving the socket to the response arriving, so it's the network and the device and nothing on our side.
+    pub(crate) fn record_command_acked(&self, address: Ipv4Addr, round_trip: Duration) {
+        self.commands_acked.fetch_add(1, Ordering::Relaxed);
+        let mut round_trips = self.round_trips.lock().unwrap();
+        let samples = round_trips.entry(address).or_default();
+        if samples.len() == LATENCY_WINDOW {
+            samples.pop_front();
+        }
+        samples.push_back(LatencySample {
+            at: SystemTime::now(),
+            round_trip,
+        });
+    }
+
+    /// The round trips of commands answered at any of the addresses, since commands to a device on a redundant network go to whichever address answers. The last LATENCY_WINDOW of them, oldest first.
+    pub(crate) fn command_latency(
+        &self,
+        addresses: impl IntoIterator<Item = Ipv4Addr>,
+    ) -> Option<CommandLatency> {
+        let round_trips = self.round_trips.lock().unwrap();
+        let mut samples: Vec<LatencySample> = addresses
+            .into_iter()
+            .filter_map(|address| round_trips.get(&address))
+            .flatten()
+            .copied()
+            .collect();
+        samples.sort_by_key(|sample| sample.at);
+        let skipped = samples.len().saturating_sub(LATENCY_WINDOW);
+        samples.drain(..skipped);
+        CommandLatency::from_samples(samples)
+    }
+
+    pub(crate) fn record_command_timed_out(&self) {
+        self.commands_timed_out.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_command_retried(&self) {
+        self.commands_retried.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_send_buffer_full(&self) {
+        self.send_buffer_full.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn snapshot(&self, devices_known: usize) -> MetricsSnapshot {
+        MetricsSnapshot {
+            searches_started: self.searches_started.load(Ordering::Relaxed),
+            services_found: self.services_found.load(Ordering::Relaxed),
+            services_resolved: self.services_resolved.load(Ordering::Relaxed),
+            services_removed: self.services_removed.load(Ordering::Relaxed),
+            searches_stopped: self.searches_stopped.load(Ordering::Relaxed),
+            devices_known: devices_known as u64,
+            commands_sent: self.commands_sent.load(Ordering::Relaxed),
+            commands_acked: self.commands_acked.load(Ordering::Relaxed),
+            commands_timed_out: self.commands_timed_out.load(Ordering::Relaxed),
+            commands_retried: self.commands_retried.load(Ordering::Relaxed),
+            send_buffer_full: self.send_buffer_full.load(Ordering::Relaxed),
+            bytes_sent: self.bytes_sent.load(Ordering::Relaxed),
+            bytes_received: self.bytes_received.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Point in time copy of the manager's counters, see DanteDeviceManager::metrics(). Everything but devices_known only ever goes up, so rates come from the difference between two snapshots.
+///
+/// Commands that don't wait for a response (make_subscription(), clear_subscription()) count as sent but never as acked or timed out.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MetricsSnapshot {
+    /// mdns discovery events, summed over the four services.
+    pub searches_started: u64,
+    pub services_found: u64,
+    pub services_resolved: u64,
+    pub services_removed: u64,
+    pub searches_stopped: u64,
+    /// Devices currently in the device list.
+    pub devices_known: u64,
+    pub commands_sent: u64,
+    pub commands_acked: u64,
+    pub commands_timed_out: u64,
+    /// Datagrams sent again after the OS had no buffer space for them.
+    pub commands_retried: u64,
+    /// Sends that found the OS's send buffers full. A datagram is sent again a few times before that's an error, so this going up means commands are going out faster than the network takes them and they could do with pacing.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub send_buffer_full: u64,
+    /// Bytes sent and received on control sockets.
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+}
+
+impl MetricsSnapshot {
+    /// Every counter with a snake_case name, for feeding whatever metrics system is in use.
+    pub fn counters(&self) -> [(&'static str, u64); 13] {
+        [
+            ("searches_started", self.searches_started),
+            ("services_found", self.services_found),
+            ("services_resolved", self.services_resolved),
+            ("services_removed", self.services_removed),
+            ("searches_stopped", self.searches_stopped),
+            ("devices_known", self.devices_known),
+            ("commands_sent", self.commands_sent),
+            ("commands_acked", self.commands_acked),
+            ("commands_timed_out", self.commands_timed_out),
+            ("commands_retried", self.commands_retried),
+            ("send_buffer_full", self.send_buffer_full),
+            ("bytes_sent", self.bytes_sent),
+            ("bytes_received", self.bytes_received),
+        ]
+    }
+}
+
+/// One acked command.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LatencySample {
+    /// When the response arrived.
+    pub at: SystemTime,
+    pub round_trip: Duration,
+}
+
+/// Round trip times of the last 64 commands a device answered, see DanteDeviceManager::get_command_latency(). Commands that timed out aren't in here, they show up in MetricsSnapshot::commands_timed_out.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CommandLatency {
+    pub min: Duration,
+    pub avg: Duration,
+    pub max: Duration,
+    pub p95: Duration,
+    /// Oldest first, for graphing.
+    pub samples: Vec<LatencySample>,
+}
+
+impl CommandLatency {
+    fn from_samples(samples: Vec<LatencySample>) -> Option<CommandLatency> {
+        let mut sorted: Vec<Duration> = samples.iter().map(|sample| sample.round_trip).collect();
+        sorted.sort();
+        let total: Duration = sorted.iter().sum();
+        // Nearest rank.
+        let p95_index = (sorted.len() * 95).div_ceil(100).checked_sub(1)?;
+        Some(CommandLatency {
+            min: *sorted.first()?,
+            avg: total / sorted.len() as u32,
+            max: *sorted.last()?,
+            p95: sorted[p95_index],
+            samples,
+        })
+    }
+}
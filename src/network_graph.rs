@@ -0,0 +1,237 @@
+use crate::{ChannelRef, DanteDeviceList, DanteDeviceManager, DanteService};
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+
+/// What export_network_graph() writes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum GraphFormat {
+    /// Graphviz, render with `dot -Tsvg`.
+    Dot,
+    /// A Mermaid flowchart, which GitHub and most wikis render inline.
+    Mermaid,
+    PlantUml,
+}
+
+/// The color of a device in the graph.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum NodeStatus {
+    /// Every service discovery browses for is connected and it has an address.
+    Resolved,
+    /// On the network, but some of its services are missing or it didn't resolve.
+    Partial,
+    /// Not on the network, only known as the TX side of a subscription.
+    Offline,
+}
+
+impl NodeStatus {
+    fn label(&self) -> &'static str {
+        match self {
+            NodeStatus::Resolved => "resolved",
+            NodeStatus::Partial => "partial",
+            NodeStatus::Offline => "offline",
+        }
+    }
+
+    /// Fill color, the same in every format.
+    fn color(&self) -> &'static str {
+        match self {
+            NodeStatus::Resolved => "#a6e3a1",
+            NodeStatus::Partial => "#f9e2af",
+            NodeStatus::Offline => "#cccccc",
+        }
+    }
+}
+
+/// Devices and the subscriptions between them. Edges are by (TX device, RX device), with a "tx channel -> rx channel" line per subscription, so a pair of devices with 32 routes between them is one arrow and not 32.
+struct NetworkGraph {
+    nodes: BTreeMap<String, NodeStatus>,
+    edges: BTreeMap<(String, String), Vec<String>>,
+}
+
+impl DanteDeviceList {
+    fn node_status(&self, device_name: &str) -> NodeStatus {
+        let Some(status) = self.devices.get(device_name) else {
+            return NodeStatus::Offline;
+        };
+        let all_connected = DanteService::ALL
+            .into_iter()
+            .filter(|service| !self.untracked_services.contains(service))
+            .all(|service| match service {
+                DanteService::Dbc => status.connected_dbc,
+                DanteService::Cmc => status.connected_cmc,
+                DanteService::Arc => status.connected_arc,
+                DanteService::Chan => status.connected_chan,
+            });
+        let has_address = self
+            .get_device_ips(device_name)
+            .is_some_and(|addresses| !addresses.is_empty());
+        match all_connected && has_address && !status.unresolved {
+            true => NodeStatus::Resolved,
+            false => NodeStatus::Partial,
+        }
+    }
+
+    fn network_graph(&self) -> NetworkGraph {
+        let mut nodes: BTreeMap<String, NodeStatus> = self
+            .devices
+            .keys()
+            .map(|device_name| (device_name.to_string(), self.node_status(device_name)))
+            .collect();
+        let mut edges: BTreeMap<(String, String), Vec<String>> = BTreeMap::new();
+        for route in self.cached_routing_matrix().routes() {
+            let Some((tx_device, tx_channel)) = route.tx else {
+                continue;
+            };
+            let rx_channel = match route.rx_channel {
+                ChannelRef::Name(name) => name,
+                ChannelRef::Id(id) => id.to_string(),
+            };
+            nodes
+                .entry(tx_device.clone())
+                .or_insert_with(|| self.node_status(&tx_device));
+            edges
+                .entry((tx_device, route.rx_device))
+                .or_default()
+                .push(format!("{} -> {}", tx_channel, rx_channel));
+        }
+        NetworkGraph { nodes, edges }
+    }
+}
+
+/// Quotes for Dot and PlantUML strings.
+fn escape_quoted(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Mermaid labels can't hold quotes, but take HTML entities for them.
+fn escape_mermaid(text: &str) -> String {
+    text.replace('"', "#quot;")
+}
+
+struct GraphWriter<'a> {
+    graph: &'a NetworkGraph,
+    format: GraphFormat,
+}
+
+impl GraphWriter<'_> {
+    /// Mermaid and PlantUML want identifiers for nodes, device names can have anything in them.
+    fn node_ids(&self) -> BTreeMap<&str, String> {
+        self.graph
+            .nodes
+            .keys()
+            .enumerate()
+            .map(|(index, device_name)| (device_name.as_str(), format!("d{}", index)))
+            .collect()
+    }
+
+    fn fmt_dot(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "digraph dante {{")?;
+        writeln!(f, "    rankdir=LR;")?;
+        writeln!(f, "    node [shape=box, style=filled];")?;
+        for (device_name, status) in &self.graph.nodes {
+            writeln!(
+                f,
+                "    \"{}\" [fillcolor=\"{}\", tooltip=\"{}\"];",
+                escape_quoted(device_name),
+                status.color(),
+                status.label()
+            )?;
+        }
+        for ((tx_device, rx_device), labels) in &self.graph.edges {
+            let labels: Vec<String> = labels.iter().map(|label| escape_quoted(label)).collect();
+            writeln!(
+                f,
+                "    \"{}\" -> \"{}\" [label=\"{}\"];",
+                escape_quoted(tx_device),
+                escape_quoted(rx_device),
+                labels.join("\\n")
+            )?;
+        }
+        writeln!(f, "}}")
+    }
+
+    fn fmt_mermaid(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let ids = self.node_ids();
+        writeln!(f, "flowchart LR")?;
+        for status in [
+            NodeStatus::Resolved,
+            NodeStatus::Partial,
+            NodeStatus::Offline,
+        ] {
+            writeln!(f, "    classDef {} fill:{}", status.label(), status.color())?;
+        }
+        for (device_name, status) in &self.graph.nodes {
+            writeln!(
+                f,
+                "    {}[\"{}\"]:::{}",
+                ids[device_name.as_str()],
+                escape_mermaid(device_name),
+                status.label()
+            )?;
+        }
+        for ((tx_device, rx_device), labels) in &self.graph.edges {
+            let labels: Vec<String> = labels.iter().map(|label| escape_mermaid(label)).collect();
+            writeln!(
+                f,
+                "    {} -->|\"{}\"| {}",
+                ids[tx_device.as_str()],
+                labels.join("<br/>"),
+                ids[rx_device.as_str()]
+            )?;
+        }
+        Ok(())
+    }
+
+    fn fmt_plantuml(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let ids = self.node_ids();
+        writeln!(f, "@startuml")?;
+        writeln!(f, "left to right direction")?;
+        for (device_name, status) in &self.graph.nodes {
+            writeln!(
+                f,
+                "rectangle \"{}\" as {} {}",
+                escape_quoted(device_name),
+                ids[device_name.as_str()],
+                status.color()
+            )?;
+        }
+        for ((tx_device, rx_device), labels) in &self.graph.edges {
+            // PlantUML reads \n in a label as a line break.
+            let labels: Vec<String> = labels
+                .iter()
+                .map(|label| label.replace('\\', "\\\\"))
+                .collect();
+            writeln!(
+                f,
+                "{} --> {} : {}",
+                ids[tx_device.as_str()],
+                ids[rx_device.as_str()],
+                labels.join("\\n")
+            )?;
+        }
+        writeln!(f, "@enduml")
+    }
+}
+
+impl Display for GraphWriter<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.format {
+            GraphFormat::Dot => self.fmt_dot(f),
+            GraphFormat::Mermaid => self.fmt_mermaid(f),
+            GraphFormat::PlantUml => self.fmt_plantuml(f),
+        }
+    }
+}
+
+impl DanteDeviceManager {
+    /// Draws the network for documentation or for untangling a routing: devices are nodes, green when every service resolved, yellow when some didn't, gray for TX devices that aren't on the network, and subscriptions are arrows from TX to RX device labeled "tx channel -> rx channel". Only last read-backs are used, nothing is queried, so call get_routing_matrix() first for an up to date graph.
+    pub fn export_network_graph(&self, format: GraphFormat) -> String {
+        let graph = self.read_device_list().network_graph();
+        GraphWriter {
+            graph: &graph,
+            format,
+        }
+        .to_string()
+    }
+}
@@ -0,0 +1,229 @@
+use crate::metrics::Metrics;
+use crate::{DanteDeviceEvent, DanteDeviceManager, DEVICE_HEARTBEAT_PORT};
+use log::{debug, error, info, warn};
+use std::net::{Ipv4Addr, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+/// Dante devices multicast a heartbeat to this group on DEVICE_HEARTBEAT_PORT every second or so, whether or not anything talks to them. Going by captures of other people's networks, not checked against a device here yet.
+const HEARTBEAT_GROUP: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 233);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum NetworkHealth {
+    /// No heartbeat heard yet. mdns and commands can go quiet for a long time on a small network that's working fine, so without heartbeats there's no telling a quiet network from a lost one, and nothing is declared down.
+    Unarmed,
+    Up,
+    Down {
+        since: Instant,
+    },
+}
+
+/// Decides whether the Dante network is reachable from how long it's been since anything came in from it: mdns answers, heartbeats, or answers to commands. See DanteDeviceManager::set_network_watchdog().
+pub(crate) struct NetworkWatchdog {
+    window: Duration,
+    started_at: Instant,
+    health: NetworkHealth,
+}
+
+impl NetworkWatchdog {
+    pub(crate) fn new(window: Duration, now: Instant) -> Self {
+        NetworkWatchdog {
+            window,
+            started_at: now,
+            health: NetworkHealth::Unarmed,
+        }
+    }
+
+    /// Moves the watchdog along to now. Returns the event to emit when the network went down or came back.
+    pub(crate) fn update(
+        &mut self,
+        now: Instant,
+        last_traffic_at: Option<Instant>,
+        heard_heartbeat: bool,
+    ) -> Option<DanteDeviceEvent> {
+        // Traffic from before the watchdog started doesn't say anything about now.
+        let last_heard = last_traffic_at.map_or(self.started_at, |at| at.max(self.started_at));
+        match self.health {
+            NetworkHealth::Unarmed => {
+                if heard_heartbeat {
+                    debug!("Heard a heartbeat, network watchdog armed");
+                    self.health = NetworkHealth::Up;
+                }
+                None
+            }
+            NetworkHealth::Up => {
+                let silent_for = now.saturating_duration_since(last_heard);
+                if silent_for < self.window {
+                    return None;
+                }
+                self.health = NetworkHealth::Down { since: now };
+                Some(DanteDeviceEvent::NetworkDown { silent_for })
+            }
+            NetworkHealth::Down { since } => {
+                if last_heard <= since {
+                    return None;
+                }
+                self.health = NetworkHealth::Up;
+                Some(DanteDeviceEvent::NetworkRestored {
+                    down_for: now.saturating_duration_since(since),
+                })
+            }
+        }
+    }
+}
+
+pub(crate) fn bind_heartbeat_socket() -> std::io::Result<UdpSocket> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, DEVICE_HEARTBEAT_PORT as u16))?;
+    socket.join_multicast_v4(&HEARTBEAT_GROUP, &Ipv4Addr::UNSPECIFIED)?;
+    // Short timeout so the listener notices when discovery stops.
+    socket.set_read_timeout(Some(Duration::from_millis(100)))?;
+    Ok(socket)
+}
+
+/// Spawns a thread that counts every heartbeat as network traffic while running is set. What's in a heartbeat isn't looked at.
+pub(crate) fn spawn_heartbeat_listener(
+    socket: UdpSocket,
+    metrics: Arc<Metrics>,
+    running: Arc<Mutex<bool>>,
+) -> JoinHandle<()> {
+    std::thread::spawn(move || {
+        debug!("Starting heartbeat listener thread");
+        let mut buffer = [0u8; 2048];
+        while *running.lock().unwrap() {
+            match socket.recv_from(&mut buffer) {
+                Ok(_) => metrics.record_heartbeat(),
+                Err(error)
+                    if matches!(
+                        error.kind(),
+                        std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+                    ) => {}
+                Err(error) => {
+                    warn!("Heartbeat listener stopped: {}", error);
+                    return;
+                }
+            }
+        }
+    })
+}
+
+/// What the discovery watch thread needs to run the watchdog.
+pub(crate) struct WatchdogState {
+    pub(crate) watchdog: NetworkWatchdog,
+    pub(crate) metrics: Arc<Metrics>,
+    pub(crate) network_down: Arc<AtomicBool>,
+}
+
+impl WatchdogState {
+    /// One tick of the watch thread. Returns the event to emit, if any.
+    pub(crate) fn check(&mut self) -> Option<DanteDeviceEvent> {
+        let event = self.watchdog.update(
+            Instant::now(),
+            self.metrics.last_traffic_at(),
+            self.metrics.heard_heartbeat(),
+        )?;
+        match &event {
+            DanteDeviceEvent::NetworkDown { silent_for } => {
+                error!(
+                    "Nothing heard from the Dante network for {:?}, commands fail until it's back",
+                    silent_for
+                );
+                self.network_down.store(true, Ordering::SeqCst);
+            }
+            _ => {
+                info!("Dante network is back");
+                self.network_down.store(false, Ordering::SeqCst);
+            }
+        }
+        Some(event)
+    }
+}
+
+impl Drop for WatchdogState {
+    /// Commands shouldn't stay refused once nothing is watching anymore.
+    fn drop(&mut self) {
+        self.network_down.store(false, Ordering::SeqCst);
+    }
+}
+
+impl DanteDeviceManager {
+    /// Watches for the machine losing the Dante network altogether, like the link of its network interface going down. With a window set, once nothing at all has come in from the network for that long (no mdns answers, no device heartbeats, no answers to commands), a DanteDeviceEvent::NetworkDown is emitted and commands fail right away with NetworkUnavailable instead of each timing out. It recovers by itself, with a NetworkRestored event, when anything comes in again.
+    ///
+    /// Devices heartbeat every second or so, which is what makes silence mean something. The watchdog only arms once a heartbeat was heard, so a network whose heartbeats don't reach this machine, or another program holding the heartbeat port, never counts as down. Off by default. Takes effect the next time discovery is started, and stopping discovery lifts the NetworkDown condition.
+    pub fn set_network_watchdog(&mut self, window: Option<Duration>) {
+        self.network_watchdog_window = window;
+    }
+
+    /// False while the network watchdog considers the network lost, see set_network_watchdog().
+    pub fn network_available(&self) -> bool {
+        !self.network_down.load(Ordering::SeqCst)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WINDOW: Duration = Duration::from_secs(2);
+
+    /// A watchdog armed by a heartbeat at start, with nothing heard since.
+    fn armed_watchdog(start: Instant) -> NetworkWatchdog {
+        let mut watchdog = NetworkWatchdog::new(WINDOW, start);
+        assert_eq!(watchdog.update(start, Some(start), true), None);
+        watchdog
+    }
+
+    #[test]
+    fn never_down_before_a_heartbeat() {
+        let start = Instant::now();
+        let mut watchdog = NetworkWatchdog::new(WINDOW, start);
+        for seconds in [1, 2, 10, 600] {
+            let now = start + Duration::from_secs(seconds);
+            assert_eq!(watchdog.update(now, None, false), None);
+        }
+        assert_eq!(watchdog.health, NetworkHealth::Unarmed);
+    }
+
+    #[test]
+    fn down_exactly_at_the_window() {
+        let start = Instant::now();
+        let mut watchdog = armed_watchdog(start);
+        let just_before = start + WINDOW - Duration::from_millis(1);
+        assert_eq!(watchdog.update(just_before, Some(start), true), None);
+        assert_eq!(
+            watchdog.update(start + WINDOW, Some(start), true),
+            Some(DanteDeviceEvent::NetworkDown { silent_for: WINDOW })
+        );
+    }
+
+    #[test]
+    fn restored_on_the_first_traffic_after_going_down() {
+        let start = Instant::now();
+        let mut watchdog = armed_watchdog(start);
+        let down_at = start + WINDOW;
+        assert!(watchdog.update(down_at, Some(start), true).is_some());
+        // Traffic from before going down doesn't bring it back.
+        let later = down_at + Duration::from_secs(1);
+        assert_eq!(watchdog.update(later, Some(down_at), true), None);
+        let traffic_at = later + Duration::from_millis(1);
+        assert_eq!(
+            watchdog.update(traffic_at, Some(traffic_at), true),
+            Some(DanteDeviceEvent::NetworkRestored {
+                down_for: traffic_at - down_at
+            })
+        );
+        assert_eq!(watchdog.health, NetworkHealth::Up);
+    }
+
+    #[test]
+    fn no_second_down_while_down() {
+        let start = Instant::now();
+        let mut watchdog = armed_watchdog(start);
+        assert!(watchdog.update(start + WINDOW, Some(start), true).is_some());
+        for seconds in [3, 4, 60] {
+            let now = start + Duration::from_secs(seconds);
+            assert_eq!(watchdog.update(now, Some(start), true), None);
+        }
+    }
+}
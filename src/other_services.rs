@@ -0,0 +1,118 @@
+use crate::{log_service_resolved, DanteDeviceList, DanteDeviceManager};
+use log::{debug, error, info};
+use mdns_sd::{Receiver, ServiceDaemon, ServiceEvent, ServiceInfo};
+use std::collections::HashMap;
+use std::net::Ipv4Addr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread::{sleep, JoinHandle};
+use std::time::Duration;
+
+/// A service found by an extra browse target, see DanteDeviceManager::add_browse_target().
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct OtherService {
+    /// Like "_netaudio-ddp._udp.local.".
+    pub service_type: String,
+    /// Instance name, which for Audinate services is the device name.
+    pub name: String,
+    /// Sorted.
+    pub addresses: Vec<Ipv4Addr>,
+    pub port: u16,
+    pub properties: HashMap<String, String>,
+}
+
+impl OtherService {
+    /// The resolver used when add_browse_target() isn't given one. Takes everything as it is.
+    pub fn from_service_info(service_info: &ServiceInfo) -> Option<OtherService> {
+        let service_type = service_info.get_type().to_owned();
+        let name = service_info
+            .get_fullname()
+            .strip_suffix(&service_type)
+            .map(|name| name.trim_end_matches('.'))
+            .unwrap_or(service_info.get_fullname())
+            .to_owned();
+        let mut addresses: Vec<Ipv4Addr> = service_info.get_addresses().iter().copied().collect();
+        addresses.sort();
+        Some(OtherService {
+            service_type,
+            name,
+            addresses,
+            port: service_info.get_port(),
+            properties: service_info
+                .get_properties()
+                .iter()
+                .map(|property| (property.key().to_owned(), property.val_str().to_owned()))
+                .collect(),
+        })
+    }
+}
+
+/// Turns a resolved service into what's kept in the device list, or None to ignore it.
+pub type ServiceResolver = Arc<dyn Fn(&ServiceInfo) -> Option<OtherService> + Send + Sync>;
+
+impl DanteDeviceList {
+    fn update_other_service(&mut self, fullname: &str, service: OtherService) {
+        debug!("update_other_service for {}", fullname);
+        self.other_services.insert(fullname.to_owned(), service);
+    }
+
+    fn remove_other_service(&mut self, fullname: &str) {
+        debug!("remove_other_service {}", fullname);
+        self.other_services.remove(fullname);
+    }
+}
+
+impl DanteDeviceManager {
+    /// Spawns a discovery thread for an extra browse target. Much like the ones for the Dante services, but everything it resolves goes through the resolver into the other services of the device list.
+    pub(crate) fn spawn_browse_thread(
+        &self,
+        service_type: String,
+        mut receiver: Receiver<ServiceEvent>,
+        resolver: ServiceResolver,
+        mdns: ServiceDaemon,
+        rebrowse: Arc<AtomicUsize>,
+    ) -> JoinHandle<()> {
+        let device_list = self.device_list.clone();
+        let metrics = self.metrics.clone();
+        let running = self.running.clone();
+        std::thread::spawn(move || {
+            debug!("Starting discovery thread for {}", service_type);
+            let mut browse_generation = rebrowse.load(Ordering::SeqCst);
+            while *running.lock().unwrap() {
+                if browse_generation != rebrowse.load(Ordering::SeqCst) {
+                    browse_generation = rebrowse.load(Ordering::SeqCst);
+                    match mdns.browse(&service_type) {
+                        Ok(new_receiver) => receiver = new_receiver,
+                        Err(error) => {
+                            error!("Failed to browse again for {}: {}", service_type, error)
+                        }
+                    }
+                }
+                while let Ok(event) = receiver.try_recv() {
+                    metrics.record_discovery_event(&event);
+                    match event {
+                        ServiceEvent::ServiceResolved(service_info) => {
+                            log_service_resolved(&service_type, &service_info);
+                            if let Some(service) = resolver(&service_info) {
+                                device_list
+                                    .lock()
+                                    .unwrap()
+                                    .update_other_service(service_info.get_fullname(), service);
+                            }
+                        }
+                        ServiceEvent::ServiceRemoved(_, fullname) => {
+                            info!("{} Service Removed: {}", service_type, &fullname);
+                            device_list.lock().unwrap().remove_other_service(&fullname);
+                        }
+                        ServiceEvent::SearchStopped(_) => {
+                            error!("{} Search Stopped", service_type);
+                        }
+                        ServiceEvent::SearchStarted(_) | ServiceEvent::ServiceFound(_, _) => {}
+                    }
+                }
+                sleep(Duration::from_millis(100));
+            }
+        })
+    }
+}
@@ -0,0 +1,255 @@
+use crate::{
+    CHANInfo, ChannelDirection, ChannelInfo, ChannelRouting, ChannelSource, DanteDeviceList,
+    DanteDeviceManager, DeviceDiscoveryCache, QueryError, SubscriptionEntry, Timestamped,
+};
+use log::{debug, warn};
+use std::collections::HashMap;
+use std::net::Ipv4Addr;
+use std::time::{Duration, Instant};
+
+/// Where a query result came from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Freshness {
+    /// The device was queried for this call.
+    Fresh,
+    /// An earlier answer that's still younger than the max age.
+    Cached,
+    /// The device couldn't be queried, so this is the last answer it gave, however old.
+    Stale,
+}
+
+/// The answer to a query, when the device gave it, and whether it was queried for this call.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct QueryResult<T> {
+    pub value: T,
+    pub fetched_at: Instant,
+    pub freshness: Freshness,
+}
+
+impl<T> QueryResult<T> {
+    fn new(timestamped: Timestamped<T>, freshness: Freshness) -> Self {
+        QueryResult {
+            value: timestamped.value,
+            fetched_at: timestamped.fetched_at,
+            freshness,
+        }
+    }
+}
+
+impl DeviceDiscoveryCache {
+    /// Channel counts and TX channel names, which change when the device's channels do.
+    fn invalidate_channel_queries(&mut self) {
+        self.channel_counts = None;
+        self.tx_channel_names = None;
+    }
+
+    /// Drops every query result, so the next getter asks the device again.
+    pub(crate) fn invalidate_query_results(&mut self) {
+        self.invalidate_channel_queries();
+        self.subscriptions = None;
+    }
+
+    /// A TX channel of the CHAN records as a ChannelInfo, with its canonical name if the last TX channel names query had it.
+    pub(crate) fn tx_channel_info(
+        &self,
+        chan_info: &CHANInfo,
+        routing: Option<ChannelRouting>,
+    ) -> ChannelInfo {
+        ChannelInfo {
+            canonical: self.canonical_tx_channel_name(chan_info.id),
+            ..chan_info.to_channel_info(routing)
+        }
+    }
+
+    /// tx_channel_info() without the TXT properties, which aren't copied at all.
+    pub(crate) fn tx_channel_summary(&self, chan_info: &CHANInfo) -> ChannelInfo {
+        ChannelInfo {
+            id: chan_info.id,
+            name: chan_info.name.clone(),
+            canonical: self.canonical_tx_channel_name(chan_info.id),
+            direction: ChannelDirection::Tx,
+            sample_rate: chan_info.sample_rate,
+            encoding: chan_info.encoding,
+            latency: chan_info.latency,
+            routing: None,
+            extra_properties: HashMap::new(),
+            source: ChannelSource::Mdns,
+        }
+    }
+
+    fn canonical_tx_channel_name(&self, id: Option<u16>) -> Option<String> {
+        self.tx_channel_names
+            .as_ref()?
+            .value
+            .iter()
+            .find(|(channel_id, _)| Some(*channel_id) == id)
+            .map(|(_, name)| name.clone())
+    }
+}
+
+impl DanteDeviceList {
+    /// Drops the subscription read-back of every device subscribed to tx_device. A TX device appearing, going away or changing its channels changes the status of subscriptions to it.
+    pub(crate) fn invalidate_subscriptions_to(&mut self, tx_device: &str) {
+        for (device_name, cache) in &mut self.caches {
+            let subscribed = cache.subscriptions.as_ref().is_some_and(|subscriptions| {
+                subscriptions
+                    .value
+                    .iter()
+                    .any(|subscription| subscription.tx_device.as_deref() == Some(tx_device))
+            });
+            if subscribed {
+                debug!(
+                    "Dropping subscription read-back of {}, {} changed",
+                    device_name, tx_device
+                );
+                cache.subscriptions = None;
+            }
+        }
+    }
+
+    /// The device's channels changed: drops what was queried about them, and the read-backs of devices subscribed to it.
+    pub(crate) fn invalidate_channel_queries(&mut self, device_name: &str) {
+        if let Some(cache) = self.caches.get_mut(device_name) {
+            cache.invalidate_channel_queries();
+        }
+        self.invalidate_subscriptions_to(device_name);
+    }
+
+    /// The device went away or moved: drops everything queried from it, and the read-backs of devices subscribed to it.
+    pub(crate) fn invalidate_query_results(&mut self, device_name: &str) {
+        if let Some(cache) = self.caches.get_mut(device_name) {
+            debug!("Dropping query results of {}", device_name);
+            cache.invalidate_query_results();
+        }
+        self.invalidate_subscriptions_to(device_name);
+    }
+}
+
+impl DanteDeviceManager {
+    /// Reads back the subscriptions of a discovered device. A read-back younger than the routing max age (see set_routing_max_age()) is reused unless force_refresh is set.
+    pub fn get_subscriptions(
+        &mut self,
+        device_name: &str,
+        force_refresh: bool,
+    ) -> Result<QueryResult<Vec<SubscriptionEntry>>, QueryError> {
+        let (subscriptions, freshness) =
+            self.get_subscriptions_cached(device_name, force_refresh)?;
+        Ok(QueryResult::new(subscriptions, freshness))
+    }
+
+    /// How many RX channels of a discovered device are subscribed to something, from a read-back younger than the routing max age, or a fresh one. When the device can't be queried the count is from the last read-back, however old, and marked stale.
+    pub fn count_active_subscriptions(
+        &mut self,
+        device_name: &str,
+    ) -> Result<QueryResult<u16>, QueryError> {
+        self.count_subscriptions(device_name, |subscription| subscription.tx_device.is_some())
+    }
+
+    /// How many more subscriptions a discovered device can take: its RX channels that aren't subscribed to anything, going by the same read-back as count_active_subscriptions(), stale when that one is. The read-back has every RX channel of the device, so this is the RX channel count minus the active subscriptions.
+    pub fn count_available_rx_channels(
+        &mut self,
+        device_name: &str,
+    ) -> Result<QueryResult<u16>, QueryError> {
+        self.count_subscriptions(device_name, |subscription| subscription.tx_device.is_none())
+    }
+
+    fn count_subscriptions(
+        &mut self,
+        device_name: &str,
+        counted: fn(&SubscriptionEntry) -> bool,
+    ) -> Result<QueryResult<u16>, QueryError> {
+        let (subscriptions, freshness) = self.get_subscriptions_cached(device_name, false)?;
+        let count = subscriptions
+            .value
+            .iter()
+            .filter(|subscription| counted(subscription))
+            .count() as u16;
+        Ok(QueryResult {
+            value: count,
+            fetched_at: subscriptions.fetched_at,
+            freshness,
+        })
+    }
+
+    /// Queries the (TX, RX) channel counts of a discovered device. An answer younger than the query max age (see set_query_max_age()) is reused unless force_refresh is set.
+    pub fn get_channel_counts(
+        &mut self,
+        device_name: &str,
+        force_refresh: bool,
+    ) -> Result<QueryResult<(u16, u16)>, QueryError> {
+        self.query_cached(
+            device_name,
+            force_refresh,
+            |cache| &mut cache.channel_counts,
+            |manager, device_ip, port| manager.query_channel_counts(device_ip, port),
+        )
+    }
+
+    /// Queries the (id, name) of every TX channel of a discovered device, including the ones it doesn't advertise over mdns. Cached like get_channel_counts().
+    pub fn get_tx_channel_names(
+        &mut self,
+        device_name: &str,
+        force_refresh: bool,
+    ) -> Result<QueryResult<Vec<(u16, String)>>, QueryError> {
+        let (tx_count, _) = self.get_channel_counts(device_name, force_refresh)?.value;
+        self.query_cached(
+            device_name,
+            force_refresh,
+            |cache| &mut cache.tx_channel_names,
+            |manager, device_ip, port| manager.list_tx_channels_at(device_ip, port, tx_count),
+        )
+    }
+
+    /// Sets how long channel counts and TX channel names queried from a device are reused. Defaults to a minute. Results are dropped early when discovery sees the device's channels change, the device move or the device go away.
+    pub fn set_query_max_age(&mut self, max_age: Duration) {
+        self.query_max_age = max_age;
+    }
+
+    /// Answers from the cache slot picked by slot while it's younger than the query max age, otherwise queries the device and stores the answer. When the device can't be queried the cached answer is returned as stale, if there is one.
+    fn query_cached<T: Clone>(
+        &mut self,
+        device_name: &str,
+        force_refresh: bool,
+        slot: fn(&mut DeviceDiscoveryCache) -> &mut Option<Timestamped<T>>,
+        query: impl FnOnce(&mut Self, &Ipv4Addr, u16) -> Result<T, QueryError>,
+    ) -> Result<QueryResult<T>, QueryError> {
+        let (device_ip, port, cached) = {
+            let mut device_list = self.device_list.lock().unwrap();
+            let device_ip = device_list.get_device_ip(device_name)?;
+            let port = device_list.get_arc_command_port(device_name);
+            let cached = device_list
+                .caches
+                .get_mut(device_name)
+                .and_then(|cache| slot(cache).clone());
+            (device_ip, port, cached)
+        };
+        if let Some(cached) = &cached {
+            if !force_refresh && cached.is_fresh(self.query_max_age) {
+                return Ok(QueryResult::new(cached.clone(), Freshness::Cached));
+            }
+        }
+
+        match query(self, &device_ip, port) {
+            Ok(value) => {
+                let fetched = Timestamped::new(value);
+                let mut device_list = self.device_list.lock().unwrap();
+                if device_list.device_connected(device_name) {
+                    if let Some(cache) = device_list.caches.get_mut(device_name) {
+                        *slot(cache) = Some(fetched.clone());
+                    }
+                }
+                Ok(QueryResult::new(fetched, Freshness::Fresh))
+            }
+            Err(error) => match cached {
+                Some(cached) => {
+                    warn!(
+                        "Querying {} failed, using an older answer: {}",
+                        device_name, error
+                    );
+                    Ok(QueryResult::new(cached, Freshness::Stale))
+                }
+                None => Err(error),
+            },
+        }
+    }
+}
@@ -0,0 +1,98 @@
+use crate::{DanteDeviceList, DanteDeviceManager, QueryError, Timestamped};
+use log::debug;
+use std::collections::HashSet;
+use std::net::Ipv4Addr;
+use std::time::{Duration, Instant};
+
+/// How long a probe result is used for ordering addresses, see DanteDeviceManager::probe_addresses(). Long enough to spare most commands a probe, short enough that a network coming back is noticed soon.
+pub const REACHABILITY_TTL: Duration = Duration::from_secs(30);
+
+/// How one address of a device answered, see DanteDeviceManager::probe_addresses().
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AddressProbe {
+    pub address: Ipv4Addr,
+    /// Round trip time of the probe, None when the address didn't answer within the response timeout.
+    pub rtt: Option<Duration>,
+}
+
+impl AddressProbe {
+    pub fn responded(&self) -> bool {
+        self.rtt.is_some()
+    }
+}
+
+impl DanteDeviceList {
+    fn record_probe(&mut self, probe: &AddressProbe) {
+        self.reachability
+            .insert(probe.address, Timestamped::new(probe.rtt));
+    }
+
+    /// The addresses in the order commands should try them: ones a recent probe got an answer from first, fastest first, then ones that weren't probed lately, then ones that didn't answer. Ties go to the lowest address, so the order is the same from one call to the next.
+    pub(crate) fn order_by_reachability(&self, addresses: &HashSet<Ipv4Addr>) -> Vec<Ipv4Addr> {
+        let mut ordered: Vec<Ipv4Addr> = addresses.iter().copied().collect();
+        ordered.sort_by_key(|address| {
+            match self
+                .reachability
+                .get(address)
+                .filter(|probe| probe.is_fresh(REACHABILITY_TTL))
+            {
+                Some(Timestamped {
+                    value: Some(rtt), ..
+                }) => (0, *rtt, *address),
+                None => (1, Duration::ZERO, *address),
+                Some(_) => (2, Duration::ZERO, *address),
+            }
+        });
+        ordered
+    }
+}
+
+impl DanteDeviceManager {
+    /// Returns the addresses of a discovered device in the order commands try them, see probe_addresses(). None when the device isn't connected.
+    pub fn get_device_ips(&self, device_name: &str) -> Option<Vec<Ipv4Addr>> {
+        let device_list = self.read_device_list();
+        let addresses = device_list.get_device_ips(device_name)?;
+        Some(device_list.order_by_reachability(&addresses))
+    }
+
+    /// Sends a channel count query, about the smallest command a device answers, to every address of a discovered device, one after the other, and returns how each answered. The results are kept for REACHABILITY_TTL, and commands to the device meanwhile try the addresses that answered first. On a redundant network where this machine only reaches the primary network, that saves every read-back from timing out on the secondary address before trying the primary. Empty when the device isn't connected or has no address.
+    pub fn probe_addresses(&mut self, device_name: &str) -> Vec<AddressProbe> {
+        let (addresses, port) = {
+            let device_list = self.device_list.lock().unwrap();
+            let Some(addresses) = device_list.get_device_ips(device_name) else {
+                return Vec::new();
+            };
+            let mut addresses: Vec<Ipv4Addr> = addresses.into_iter().collect();
+            addresses.sort();
+            (addresses, device_list.get_arc_command_port(device_name))
+        };
+
+        let mut probes = Vec::new();
+        for address in addresses {
+            let sent_at = Instant::now();
+            let rtt = match self.query_channel_counts(&address, port) {
+                // A garbled answer is still an answer.
+                Ok(_) | Err(QueryError::InvalidResponse) => Some(sent_at.elapsed()),
+                // Nothing was sent, so this says nothing about the address.
+                Err(
+                    error @ (QueryError::NetworkUnavailable | QueryError::PayloadTooLarge { .. }),
+                ) => {
+                    debug!("Not probing {}: {}", address, error);
+                    continue;
+                }
+                Err(error) => {
+                    debug!("{} of {} didn't answer: {}", address, device_name, error);
+                    None
+                }
+            };
+            probes.push(AddressProbe { address, rtt });
+        }
+
+        let mut device_list = self.device_list.lock().unwrap();
+        for probe in &probes {
+            device_list.record_probe(probe);
+        }
+        probes
+    }
+}
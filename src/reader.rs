@@ -0,0 +1,92 @@
+use crate::QueryError;
+
+/// Why a packet couldn't be parsed.
+#[derive(thiserror::Error, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ParseError {
+    #[error("packet too short")]
+    TooShort,
+    #[error("string runs off the end of the packet")]
+    UnterminatedLabel,
+    /// A response that doesn't start with 0x28 0x30.
+    #[error("packet doesn't start with the control protocol magic")]
+    InvalidMagic,
+    /// The length in the header isn't the length of the packet.
+    #[error("header says {header} bytes but the packet is {actual}")]
+    LengthMismatch { header: u16, actual: usize },
+    /// A DNS name whose compression pointers go in circles or past the packet.
+    #[error("invalid compressed name")]
+    InvalidName,
+}
+
+impl From<ParseError> for QueryError {
+    fn from(_: ParseError) -> Self {
+        QueryError::InvalidResponse
+    }
+}
+
+/// Bounds checked cursor over a packet that came off the network. Everything that parses inbound bytes goes through this instead of indexing, so a corrupt or hostile packet is a ParseError and never a panic.
+pub(crate) struct Reader<'a> {
+    buffer: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn new(buffer: &'a [u8]) -> Self {
+        Reader {
+            buffer,
+            position: 0,
+        }
+    }
+
+    /// Moves to an absolute offset. Reading past the end fails, seeking there doesn't.
+    pub(crate) fn seek(&mut self, position: usize) -> &mut Self {
+        self.position = position;
+        self
+    }
+
+    pub(crate) fn skip(&mut self, count: usize) -> Result<&mut Self, ParseError> {
+        self.read_bytes(count)?;
+        Ok(self)
+    }
+
+    pub(crate) fn read_bytes(&mut self, count: usize) -> Result<&'a [u8], ParseError> {
+        let end = self
+            .position
+            .checked_add(count)
+            .ok_or(ParseError::TooShort)?;
+        let bytes = self
+            .buffer
+            .get(self.position..end)
+            .ok_or(ParseError::TooShort)?;
+        self.position = end;
+        Ok(bytes)
+    }
+
+    pub(crate) fn position(&self) -> usize {
+        self.position
+    }
+
+    pub(crate) fn read_u8(&mut self) -> Result<u8, ParseError> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    /// Reads a big endian u16.
+    pub(crate) fn read_u16(&mut self) -> Result<u16, ParseError> {
+        let bytes: [u8; 2] = self
+            .read_bytes(2)?
+            .try_into()
+            .map_err(|_| ParseError::TooShort)?;
+        Ok(u16::from_be_bytes(bytes))
+    }
+
+    /// Reads the null terminated string at an absolute offset without moving. Responses refer to names by their offset into the packet. Invalid UTF-8 is replaced rather than rejected.
+    pub(crate) fn label_at(&self, offset: usize) -> Result<String, ParseError> {
+        let tail = self.buffer.get(offset..).ok_or(ParseError::TooShort)?;
+        let label = tail
+            .split(|byte| *byte == 0x00)
+            .next()
+            .filter(|label| label.len() < tail.len())
+            .ok_or(ParseError::UnterminatedLabel)?;
+        Ok(String::from_utf8_lossy(label).into_owned())
+    }
+}
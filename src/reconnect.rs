@@ -0,0 +1,157 @@
+use crate::{DanteDeviceManager, DanteService};
+use log::warn;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// What a discovery thread does when mdns stops its search (a SearchStopped event). Set with DanteDeviceManager::set_discovery_reconnect_policy().
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReconnectPolicy {
+    /// Leave the search stopped. reconnect_discovery() can still restart it.
+    Never,
+    /// Browse again after initial, doubling the wait up to max every time the search stops again before finding anything.
+    ExponentialBackoff { initial: Duration, max: Duration },
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        ReconnectPolicy::ExponentialBackoff {
+            initial: Duration::from_secs(1),
+            max: Duration::from_secs(60),
+        }
+    }
+}
+
+/// Tracks when a discovery thread should browse for its service again.
+pub(crate) struct BrowseRetry {
+    service: DanteService,
+    policy: ReconnectPolicy,
+    /// Services reconnect_discovery() was called for.
+    requests: Arc<Mutex<HashSet<DanteService>>>,
+    /// Services whose search is stopped, with the service type mdns said stopped. See DanteDeviceManager::get_services_status().
+    stopped_searches: Arc<Mutex<HashMap<DanteService, String>>>,
+    next_delay: Option<Duration>,
+    retry_at: Option<Instant>,
+}
+
+impl BrowseRetry {
+    pub(crate) fn new(
+        service: DanteService,
+        policy: ReconnectPolicy,
+        requests: Arc<Mutex<HashSet<DanteService>>>,
+        stopped_searches: Arc<Mutex<HashMap<DanteService, String>>>,
+    ) -> Self {
+        BrowseRetry {
+            service,
+            policy,
+            requests,
+            stopped_searches,
+            next_delay: None,
+            retry_at: None,
+        }
+    }
+
+    /// The search stopped, schedules a retry according to the policy.
+    pub(crate) fn search_stopped(&mut self, service_type: String) {
+        self.stopped_searches
+            .lock()
+            .unwrap()
+            .insert(self.service, service_type);
+        if let ReconnectPolicy::ExponentialBackoff { initial, max } = self.policy {
+            let delay = self.next_delay.unwrap_or(initial);
+            warn!(
+                "{} search stopped, browsing again in {:?}",
+                self.service, delay
+            );
+            self.retry_at = Some(Instant::now() + delay);
+            self.next_delay = Some((delay * 2).min(max));
+        }
+    }
+
+    /// mdns (re)started the search. The backoff carries on until it finds something.
+    pub(crate) fn search_started(&mut self) {
+        self.stopped_searches.lock().unwrap().remove(&self.service);
+    }
+
+    /// The search found something, so it's working and the backoff starts over.
+    pub(crate) fn search_working(&mut self) {
+        self.next_delay = None;
+    }
+
+    /// Whether a retry is due or a reconnect was asked for. Either way it's consumed.
+    pub(crate) fn take_due(&mut self) -> bool {
+        let requested = self.requests.lock().unwrap().remove(&self.service);
+        let retry_due = self
+            .retry_at
+            .is_some_and(|retry_at| Instant::now() >= retry_at);
+        if requested || retry_due {
+            self.retry_at = None;
+            return true;
+        }
+        false
+    }
+}
+
+/// How the discovery of one service is doing, see DanteDeviceManager::get_services_status().
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ServiceHealth {
+    /// Discovery is browsing for the service.
+    Running,
+    /// Discovery isn't running, or wasn't started for this service (see set_discovery_services()).
+    Stopped,
+    /// mdns stopped the search for the service type it carries. The reconnect policy or reconnect_discovery() may still bring it back.
+    SearchStopped(String),
+}
+
+/// The health of each of the four discovery services.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ServicesStatus {
+    pub dbc: ServiceHealth,
+    pub cmc: ServiceHealth,
+    pub arc: ServiceHealth,
+    pub chan: ServiceHealth,
+}
+
+impl ServicesStatus {
+    pub fn get(&self, service: DanteService) -> &ServiceHealth {
+        match service {
+            DanteService::Dbc => &self.dbc,
+            DanteService::Cmc => &self.cmc,
+            DanteService::Arc => &self.arc,
+            DanteService::Chan => &self.chan,
+        }
+    }
+
+    /// Whether every service is Running.
+    pub fn all_running(&self) -> bool {
+        DanteService::ALL
+            .into_iter()
+            .all(|service| *self.get(service) == ServiceHealth::Running)
+    }
+}
+
+impl DanteDeviceManager {
+    /// Returns how discovery of each service is doing. A SearchStopped used to only show up as an error in the log, this is for noticing it from code, like for a status light or for calling reconnect_discovery().
+    pub fn get_services_status(&self) -> ServicesStatus {
+        let running = self.is_running();
+        let untracked = self.device_list.lock().unwrap().untracked_services.clone();
+        let stopped_searches = self.stopped_searches.lock().unwrap();
+        let health = |service: DanteService| {
+            if !running || untracked.contains(&service) {
+                ServiceHealth::Stopped
+            } else if let Some(service_type) = stopped_searches.get(&service) {
+                ServiceHealth::SearchStopped(service_type.clone())
+            } else {
+                ServiceHealth::Running
+            }
+        };
+        ServicesStatus {
+            dbc: health(DanteService::Dbc),
+            cmc: health(DanteService::Cmc),
+            arc: health(DanteService::Arc),
+            chan: health(DanteService::Chan),
+        }
+    }
+}
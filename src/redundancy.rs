@@ -0,0 +1,70 @@
+use crate::DanteDeviceManager;
+use std::collections::HashSet;
+use std::net::Ipv4Addr;
+
+/// How the primary and secondary address of a device on a redundant network are told apart, since mdns doesn't say which is which. Set with DanteDeviceManager::set_redundancy_heuristic().
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RedundancyHeuristic {
+    /// The lowest address is the primary. Dante's link local defaults put the primary network in 169.254.0.0/16 and the secondary in 172.31.0.0/16, which this gets right.
+    #[default]
+    LowestIsPrimary,
+    /// The lowest address in the subnet is the primary, for networks with static addresses where the primary doesn't sort lowest. A device with no address in the subnet falls back to LowestIsPrimary.
+    PrimarySubnet { network: Ipv4Addr, prefix_len: u8 },
+}
+
+impl RedundancyHeuristic {
+    fn is_primary(&self, address: &Ipv4Addr) -> bool {
+        match self {
+            RedundancyHeuristic::LowestIsPrimary => false,
+            RedundancyHeuristic::PrimarySubnet {
+                network,
+                prefix_len,
+            } => {
+                let mask = u32::MAX
+                    .checked_shl(32 - (*prefix_len).min(32) as u32)
+                    .unwrap_or(0);
+                u32::from(*address) & mask == u32::from(*network) & mask
+            }
+        }
+    }
+
+    /// Splits a device's addresses into (primary, secondary). A device on a single network has no secondary.
+    fn split(&self, addresses: &HashSet<Ipv4Addr>) -> (Option<Ipv4Addr>, Option<Ipv4Addr>) {
+        let primary = addresses
+            .iter()
+            .filter(|address| self.is_primary(address))
+            .min()
+            .or_else(|| addresses.iter().min())
+            .copied();
+        let secondary = addresses
+            .iter()
+            .filter(|address| Some(**address) != primary)
+            .min()
+            .copied();
+        (primary, secondary)
+    }
+}
+
+impl DanteDeviceManager {
+    /// Returns the address of a discovered device on the primary network, as picked by the redundancy heuristic.
+    pub fn get_device_primary_ip(&self, device_name: &str) -> Option<Ipv4Addr> {
+        self.redundant_ips(device_name).0
+    }
+
+    /// Returns the address of a discovered device on the secondary network, or None for a device that only resolved to one address. See set_redundancy_heuristic() for how it's told apart from the primary.
+    pub fn get_device_secondary_ip(&self, device_name: &str) -> Option<Ipv4Addr> {
+        self.redundant_ips(device_name).1
+    }
+
+    /// Sets how get_device_primary_ip() and get_device_secondary_ip() tell the two networks apart. Defaults to RedundancyHeuristic::LowestIsPrimary.
+    pub fn set_redundancy_heuristic(&mut self, heuristic: RedundancyHeuristic) {
+        self.redundancy_heuristic = heuristic;
+    }
+
+    fn redundant_ips(&self, device_name: &str) -> (Option<Ipv4Addr>, Option<Ipv4Addr>) {
+        match self.device_list.lock().unwrap().get_device_ips(device_name) {
+            Some(addresses) => self.redundancy_heuristic.split(&addresses),
+            None => (None, None),
+        }
+    }
+}
@@ -0,0 +1,212 @@
+use crate::{DanteDeviceList, SubscriptionEntry};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::time::Duration;
+
+/// A suspicious pattern in the routing of the network.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SubscriptionConflict {
+    /// Devices that subscribe to each other in a cycle, which causes silence or feedback. Devices are sorted by name.
+    RoutingLoop { devices: Vec<String> },
+    /// Several RX channels subscribed to the same TX channel. This may be intentional, but is often a mistake.
+    DuplicateSubscription {
+        /// (tx device, tx channel)
+        source: (String, String),
+        /// (rx device, rx channel) of every subscriber, sorted.
+        receivers: Vec<(String, String)>,
+    },
+}
+
+/// Finds routing loops and duplicate subscriptions in the subscriptions of each device, keyed by rx device name.
+/// A device subscribed to its own channels isn't considered a loop.
+pub(crate) fn find_subscription_conflicts(
+    subscriptions: &HashMap<String, Vec<SubscriptionEntry>>,
+) -> Vec<SubscriptionConflict> {
+    // Device level graph, rx device -> the tx devices it listens to.
+    let mut graph: BTreeMap<&str, BTreeSet<&str>> = BTreeMap::new();
+    let mut receivers_by_source: BTreeMap<(&str, &str), Vec<(String, String)>> = BTreeMap::new();
+
+    for (rx_device, entries) in subscriptions {
+        graph.entry(rx_device).or_default();
+        for entry in entries {
+            if let (Some(tx_device), Some(tx_channel)) = (&entry.tx_device, &entry.tx_channel) {
+                if tx_device != rx_device {
+                    graph.entry(rx_device).or_default().insert(tx_device);
+                    graph.entry(tx_device).or_default();
+                }
+                receivers_by_source
+                    .entry((tx_device, tx_channel))
+                    .or_default()
+                    .push((rx_device.to_owned(), entry.rx_channel_name.to_owned()));
+            }
+        }
+    }
+
+    let mut loops: Vec<Vec<String>> = strongly_connected_components(&graph)
+        .into_iter()
+        .filter(|component| component.len() > 1)
+        .map(|component| {
+            let mut devices: Vec<String> = component.into_iter().map(str::to_owned).collect();
+            devices.sort();
+            devices
+        })
+        .collect();
+    loops.sort();
+
+    let mut conflicts: Vec<SubscriptionConflict> = loops
+        .into_iter()
+        .map(|devices| SubscriptionConflict::RoutingLoop { devices })
+        .collect();
+
+    conflicts.extend(
+        receivers_by_source
+            .into_iter()
+            .filter(|(_, receivers)| receivers.len() > 1)
+            .map(|((tx_device, tx_channel), mut receivers)| {
+                receivers.sort();
+                SubscriptionConflict::DuplicateSubscription {
+                    source: (tx_device.to_owned(), tx_channel.to_owned()),
+                    receivers,
+                }
+            }),
+    );
+
+    conflicts
+}
+
+/// Tarjan's algorithm. Any component with more than one device in it contains a cycle.
+fn strongly_connected_components<'a>(
+    graph: &BTreeMap<&'a str, BTreeSet<&'a str>>,
+) -> Vec<Vec<&'a str>> {
+    struct State<'a> {
+        index: usize,
+        indices: HashMap<&'a str, usize>,
+        low_links: HashMap<&'a str, usize>,
+        stack: Vec<&'a str>,
+        on_stack: BTreeSet<&'a str>,
+        components: Vec<Vec<&'a str>>,
+    }
+
+    fn visit<'a>(
+        node: &'a str,
+        graph: &BTreeMap<&'a str, BTreeSet<&'a str>>,
+        state: &mut State<'a>,
+    ) {
+        state.indices.insert(node, state.index);
+        state.low_links.insert(node, state.index);
+        state.index += 1;
+        state.stack.push(node);
+        state.on_stack.insert(node);
+
+        for &next in graph.get(node).into_iter().flatten() {
+            if !state.indices.contains_key(next) {
+                visit(next, graph, state);
+                let low_link = state.low_links[node].min(state.low_links[next]);
+                state.low_links.insert(node, low_link);
+            } else if state.on_stack.contains(next) {
+                let low_link = state.low_links[node].min(state.indices[next]);
+                state.low_links.insert(node, low_link);
+            }
+        }
+
+        if state.low_links[node] == state.indices[node] {
+            let mut component = Vec::new();
+            while let Some(member) = state.stack.pop() {
+                state.on_stack.remove(member);
+                component.push(member);
+                if member == node {
+                    break;
+                }
+            }
+            state.components.push(component);
+        }
+    }
+
+    let mut state = State {
+        index: 0,
+        indices: HashMap::new(),
+        low_links: HashMap::new(),
+        stack: Vec::new(),
+        on_stack: BTreeSet::new(),
+        components: Vec::new(),
+    };
+    for &node in graph.keys() {
+        if !state.indices.contains_key(node) {
+            visit(node, graph, &mut state);
+        }
+    }
+    state.components
+}
+
+/// A subscription worked out without talking to any device, see DanteDeviceManager::enumerate_subscriptions_passive().
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PassiveSubscriptionEntry {
+    pub rx_device: String,
+    pub rx_channel_id: u16,
+    pub rx_channel_name: String,
+    pub tx_device: String,
+    pub tx_channel: String,
+    /// How old the read-back this came from is.
+    pub age: Duration,
+    /// 0 to 1. 1 for a fresh read-back of a source that discovery can see, lower as the read-back ages or when the source isn't advertised over mdns.
+    pub confidence: f32,
+}
+
+impl DanteDeviceList {
+    pub(crate) fn enumerate_subscriptions_passive(
+        &self,
+        max_age: Duration,
+    ) -> Vec<PassiveSubscriptionEntry> {
+        let mut device_names: Vec<&str> = self
+            .devices
+            .keys()
+            .map(|device_name| &**device_name)
+            .collect();
+        device_names.sort();
+
+        let mut entries = Vec::new();
+        for rx_device in device_names {
+            let Some(subscriptions) = self
+                .caches
+                .get(rx_device)
+                .and_then(|cache| cache.subscriptions.as_ref())
+            else {
+                continue;
+            };
+            let age = subscriptions.fetched_at.elapsed();
+            // Full confidence while fresh, falling off with age after that.
+            let age_confidence = match age <= max_age {
+                true => 1.0,
+                false => max_age.as_secs_f32() / age.as_secs_f32(),
+            };
+
+            for subscription in &subscriptions.value {
+                let (Some(tx_device), Some(tx_channel)) =
+                    (&subscription.tx_device, &subscription.tx_channel)
+                else {
+                    continue;
+                };
+                // A source with a matching CHAN record is almost certainly still there.
+                let source_advertised = self.caches.get(tx_device.as_str()).is_some_and(|cache| {
+                    cache
+                        .chan_info
+                        .iter()
+                        .any(|chan_info| &chan_info.name == tx_channel)
+                });
+                entries.push(PassiveSubscriptionEntry {
+                    rx_device: rx_device.to_owned(),
+                    rx_channel_id: subscription.rx_channel_id,
+                    rx_channel_name: subscription.rx_channel_name.clone(),
+                    tx_device: tx_device.to_owned(),
+                    tx_channel: tx_channel.to_owned(),
+                    age,
+                    confidence: match source_advertised {
+                        true => age_confidence,
+                        false => age_confidence / 2.0,
+                    },
+                });
+            }
+        }
+        entries
+    }
+}
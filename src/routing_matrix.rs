@@ -0,0 +1,357 @@
+use crate::{DanteDeviceList, SubscriptionEntry};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// An RX channel by name or by id. The matrix doesn't know a device's channels, so Name("01") and Id(1) are different keys even if they're the same channel.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ChannelRef {
+    Id(u16),
+    Name(String),
+}
+
+/// One entry of a RoutingMatrix. tx is the (tx device, tx channel) the RX channel is subscribed to, None for a channel that should be unsubscribed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Route {
+    pub rx_device: String,
+    pub rx_channel: ChannelRef,
+    pub tx: Option<(String, String)>,
+}
+
+/// How a route differs between two matrices, see RoutingMatrix::diff().
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RouteChange {
+    pub rx_device: String,
+    pub rx_channel: ChannelRef,
+    pub from: Option<(String, String)>,
+    pub to: Option<(String, String)>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RouteChangeKind {
+    Added,
+    Removed,
+    Changed,
+}
+
+impl RouteChange {
+    pub fn kind(&self) -> RouteChangeKind {
+        match (&self.from, &self.to) {
+            (None, _) => RouteChangeKind::Added,
+            (_, None) => RouteChangeKind::Removed,
+            _ => RouteChangeKind::Changed,
+        }
+    }
+}
+
+/// Which TX channel every RX channel listens to. Used for saving and restoring the routing of a network and for working out what has to change to get from one routing to another.
+///
+/// A route can be set, explicitly cleared (the channel should be unsubscribed), or absent (the matrix has nothing to say about the channel). get() and diff() treat cleared and absent the same.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(from = "Vec<Route>", into = "Vec<Route>")
+)]
+pub struct RoutingMatrix {
+    routes: BTreeMap<(String, ChannelRef), Option<(String, String)>>,
+}
+
+impl RoutingMatrix {
+    pub fn new() -> Self {
+        RoutingMatrix::default()
+    }
+
+    pub fn set_route(
+        &mut self,
+        rx_device: &str,
+        rx_channel: ChannelRef,
+        tx_device: &str,
+        tx_channel: &str,
+    ) {
+        self.routes.insert(
+            (rx_device.to_owned(), rx_channel),
+            Some((tx_device.to_owned(), tx_channel.to_owned())),
+        );
+    }
+
+    /// Marks the RX channel as unsubscribed.
+    pub fn clear_route(&mut self, rx_device: &str, rx_channel: ChannelRef) {
+        self.routes.insert((rx_device.to_owned(), rx_channel), None);
+    }
+
+    /// Forgets the RX channel, so the matrix has nothing to say about it.
+    pub fn remove_route(&mut self, rx_device: &str, rx_channel: &ChannelRef) {
+        self.routes
+            .remove(&(rx_device.to_owned(), rx_channel.clone()));
+    }
+
+    /// The (tx device, tx channel) the RX channel is subscribed to.
+    pub fn get(&self, rx_device: &str, rx_channel: &ChannelRef) -> Option<&(String, String)> {
+        self.routes
+            .get(&(rx_device.to_owned(), rx_channel.clone()))?
+            .as_ref()
+    }
+
+    pub fn len(&self) -> usize {
+        self.routes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.routes.is_empty()
+    }
+
+    /// Every route, sorted by RX device and channel.
+    pub fn routes(&self) -> impl Iterator<Item = Route> + '_ {
+        self.routes
+            .iter()
+            .map(|((rx_device, rx_channel), tx)| Route {
+                rx_device: rx_device.clone(),
+                rx_channel: rx_channel.clone(),
+                tx: tx.clone(),
+            })
+    }
+
+    /// The routes of RX channels of device_name and of RX channels subscribed to a TX channel of device_name, for showing the routing of one device.
+    pub fn filter_by_device(&self, device_name: &str) -> RoutingMatrix {
+        RoutingMatrix {
+            routes: self
+                .routes
+                .iter()
+                .filter(|((rx_device, _), tx)| {
+                    rx_device == device_name
+                        || tx
+                            .as_ref()
+                            .is_some_and(|(tx_device, _)| tx_device == device_name)
+                })
+                .map(|(key, tx)| (key.clone(), tx.clone()))
+                .collect(),
+        }
+    }
+
+    /// What has to change to get from this routing to other, sorted by RX device and channel. Channels routed the same in both are left out.
+    pub fn diff(&self, other: &RoutingMatrix) -> Vec<RouteChange> {
+        let keys: BTreeSet<&(String, ChannelRef)> =
+            self.routes.keys().chain(other.routes.keys()).collect();
+        keys.into_iter()
+            .filter_map(|key| {
+                let from = self.routes.get(key).cloned().flatten();
+                let to = other.routes.get(key).cloned().flatten();
+                (from != to).then(|| RouteChange {
+                    rx_device: key.0.clone(),
+                    rx_channel: key.1.clone(),
+                    from,
+                    to,
+                })
+            })
+            .collect()
+    }
+
+    #[cfg(feature = "serde")]
+    pub fn to_json(&self) -> Result<String, serde_json::Error> {
+        serde_json::to_string(self)
+    }
+
+    /// Reads a matrix saved with to_json(), or written by hand as a list of routes.
+    #[cfg(feature = "serde")]
+    pub fn from_json(json: &str) -> Result<RoutingMatrix, serde_json::Error> {
+        serde_json::from_str(json)
+    }
+
+    /// Builds a matrix from read-backs by RX device, like NetworkSnapshot::subscriptions. Channels are keyed by name and unsubscribed channels are added as cleared, see add_subscription().
+    pub fn from_subscriptions(
+        subscriptions: &BTreeMap<String, Vec<SubscriptionEntry>>,
+    ) -> RoutingMatrix {
+        let mut matrix = RoutingMatrix::new();
+        for (rx_device, subscriptions) in subscriptions {
+            matrix.insert_read_back(rx_device, subscriptions);
+        }
+        matrix
+    }
+
+    /// Sets the route of a read-back entry of rx_device, keyed by the RX channel name. SubscriptionEntry doesn't say which device it's from, hence rx_device. An entry of an unsubscribed channel clears the route.
+    pub fn add_subscription(&mut self, rx_device: &str, subscription: &SubscriptionEntry) {
+        self.routes.insert(
+            (
+                rx_device.to_owned(),
+                ChannelRef::Name(subscription.rx_channel_name.clone()),
+            ),
+            subscription
+                .tx_device
+                .clone()
+                .zip(subscription.tx_channel.clone()),
+        );
+    }
+
+    /// remove_route() for an RX channel added by add_subscription(), by name.
+    pub fn remove_subscription(&mut self, rx_device: &str, rx_channel: &str) {
+        self.remove_route(rx_device, &ChannelRef::Name(rx_channel.to_owned()));
+    }
+
+    /// Adds the read-back of an RX device, keyed by channel name. Unsubscribed channels are added as cleared.
+    pub(crate) fn insert_read_back(
+        &mut self,
+        rx_device: &str,
+        subscriptions: &[SubscriptionEntry],
+    ) {
+        for subscription in subscriptions {
+            self.add_subscription(rx_device, subscription);
+        }
+    }
+}
+
+impl From<Vec<Route>> for RoutingMatrix {
+    fn from(routes: Vec<Route>) -> Self {
+        RoutingMatrix {
+            routes: routes
+                .into_iter()
+                .map(|route| ((route.rx_device, route.rx_channel), route.tx))
+                .collect(),
+        }
+    }
+}
+
+impl From<RoutingMatrix> for Vec<Route> {
+    fn from(matrix: RoutingMatrix) -> Self {
+        matrix.routes().collect()
+    }
+}
+
+impl DanteDeviceList {
+    /// The routing of every device from its last read-back.
+    pub(crate) fn cached_routing_matrix(&self) -> RoutingMatrix {
+        let mut matrix = RoutingMatrix::new();
+        for (device_name, cache) in &self.caches {
+            if !self.devices.contains_key(device_name) {
+                continue;
+            }
+            if let Some(subscriptions) = &cache.subscriptions {
+                matrix.insert_read_back(device_name, &subscriptions.value);
+            }
+        }
+        matrix
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tx(device: &str, channel: &str) -> Option<(String, String)> {
+        Some((device.to_owned(), channel.to_owned()))
+    }
+
+    fn matrix() -> RoutingMatrix {
+        let mut matrix = RoutingMatrix::new();
+        matrix.set_route("Amp", ChannelRef::Id(1), "Console", "Main L");
+        matrix.set_route("Amp", ChannelRef::Id(2), "Console", "Main R");
+        matrix.set_route(
+            "Recorder",
+            ChannelRef::Name("01".to_owned()),
+            "Stage-Box",
+            "01",
+        );
+        matrix
+    }
+
+    #[test]
+    fn diff_of_identical_matrices_is_empty() {
+        assert!(matrix().diff(&matrix()).is_empty());
+        assert!(RoutingMatrix::new().diff(&RoutingMatrix::new()).is_empty());
+    }
+
+    #[test]
+    fn diff_with_added_crosspoint() {
+        let mut other = matrix();
+        other.set_route("Amp", ChannelRef::Id(3), "Console", "Sub");
+        let changes = matrix().diff(&other);
+        assert_eq!(
+            changes,
+            vec![RouteChange {
+                rx_device: "Amp".to_owned(),
+                rx_channel: ChannelRef::Id(3),
+                from: None,
+                to: tx("Console", "Sub"),
+            }]
+        );
+        assert_eq!(changes[0].kind(), RouteChangeKind::Added);
+    }
+
+    #[test]
+    fn diff_with_removed_crosspoint() {
+        let mut cleared = matrix();
+        cleared.clear_route("Amp", ChannelRef::Id(2));
+        let mut absent = matrix();
+        absent.remove_route("Amp", &ChannelRef::Id(2));
+        let expected = vec![RouteChange {
+            rx_device: "Amp".to_owned(),
+            rx_channel: ChannelRef::Id(2),
+            from: tx("Console", "Main R"),
+            to: None,
+        }];
+        // Cleared and absent are the same to diff().
+        assert_eq!(matrix().diff(&cleared), expected);
+        assert_eq!(matrix().diff(&absent), expected);
+        assert_eq!(expected[0].kind(), RouteChangeKind::Removed);
+        assert!(cleared.diff(&absent).is_empty());
+    }
+
+    #[test]
+    fn diff_with_changed_crosspoint() {
+        let mut other = matrix();
+        other.set_route("Amp", ChannelRef::Id(1), "Playback", "Main L");
+        let changes = matrix().diff(&other);
+        assert_eq!(
+            changes,
+            vec![RouteChange {
+                rx_device: "Amp".to_owned(),
+                rx_channel: ChannelRef::Id(1),
+                from: tx("Console", "Main L"),
+                to: tx("Playback", "Main L"),
+            }]
+        );
+        assert_eq!(changes[0].kind(), RouteChangeKind::Changed);
+    }
+
+    #[test]
+    fn diff_with_route_moved_to_another_tx_channel() {
+        let mut other = matrix();
+        other.set_route(
+            "Recorder",
+            ChannelRef::Name("01".to_owned()),
+            "Stage-Box",
+            "02",
+        );
+        let changes = other.diff(&matrix());
+        assert_eq!(
+            changes,
+            vec![RouteChange {
+                rx_device: "Recorder".to_owned(),
+                rx_channel: ChannelRef::Name("01".to_owned()),
+                from: tx("Stage-Box", "02"),
+                to: tx("Stage-Box", "01"),
+            }]
+        );
+        assert_eq!(changes[0].kind(), RouteChangeKind::Changed);
+    }
+
+    #[test]
+    fn diff_is_sorted_by_rx_device_and_channel() {
+        let mut other = RoutingMatrix::new();
+        other.set_route("Recorder", ChannelRef::Id(2), "Console", "Main L");
+        other.set_route("Amp", ChannelRef::Id(9), "Console", "Main L");
+        let changes = RoutingMatrix::new().diff(&other);
+        let keys: Vec<(&str, &ChannelRef)> = changes
+            .iter()
+            .map(|change| (change.rx_device.as_str(), &change.rx_channel))
+            .collect();
+        assert_eq!(
+            keys,
+            vec![
+                ("Amp", &ChannelRef::Id(9)),
+                ("Recorder", &ChannelRef::Id(2))
+            ]
+        );
+    }
+}
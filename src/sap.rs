@@ -0,0 +1,112 @@
+use crate::sdp::{parse_sdp, Aes67Stream};
+use crate::DanteDeviceList;
+use log::{debug, warn};
+use std::collections::BTreeMap;
+use std::net::{Ipv4Addr, UdpSocket};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+/// SAP announcements go to this group for the administratively scoped "global" range, which is where AES67 devices announce.
+const SAP_GROUP: Ipv4Addr = Ipv4Addr::new(239, 255, 255, 255);
+const SAP_PORT: u16 = 9875;
+
+/// Streams that haven't been announced for this long are dropped. RFC 2974 says an hour.
+const SAP_STREAM_TIMEOUT: Duration = Duration::from_secs(60 * 60);
+
+/// A SAP packet that could be read.
+struct SapPacket<'a> {
+    deletion: bool,
+    sdp: &'a str,
+}
+
+/// Splits a SAP packet (RFC 2974) into its header flags and SDP payload. None for encrypted or compressed packets, which nobody sends in practice, and for anything malformed.
+fn parse_sap_packet(packet: &[u8]) -> Option<SapPacket<'_>> {
+    let flags = *packet.first()?;
+    let version = flags >> 5;
+    let ipv6_origin = flags & 0x10 != 0;
+    let deletion = flags & 0x04 != 0;
+    let encrypted = flags & 0x02 != 0;
+    let compressed = flags & 0x01 != 0;
+    if version != 1 || encrypted || compressed {
+        return None;
+    }
+    let authentication_length = *packet.get(1)? as usize * 4;
+    let origin_length = if ipv6_origin { 16 } else { 4 };
+    let payload = packet.get(4 + origin_length + authentication_length..)?;
+    let payload = std::str::from_utf8(payload).ok()?;
+    // The payload type is optional, an SDP payload starts with "v=0" either way.
+    let sdp = match payload.split_once('\0') {
+        Some((payload_type, sdp)) if !payload_type.starts_with("v=") => {
+            if payload_type != "application/sdp" {
+                return None;
+            }
+            sdp
+        }
+        _ => payload,
+    };
+    Some(SapPacket { deletion, sdp })
+}
+
+/// The AES67 streams announced over SAP, by (origin address, session id).
+pub(crate) type Aes67Streams = BTreeMap<(String, String), (Aes67Stream, Instant)>;
+
+impl DanteDeviceList {
+    fn handle_sap_packet(&mut self, packet: &[u8]) {
+        let Some(sap_packet) = parse_sap_packet(packet) else {
+            return;
+        };
+        match parse_sdp(sap_packet.sdp) {
+            Ok(stream) => {
+                let key = (stream.origin_address.clone(), stream.session_id.clone());
+                if sap_packet.deletion {
+                    debug!("AES67 stream \"{}\" deleted", stream.session_name);
+                    self.aes67_streams.remove(&key);
+                } else {
+                    self.aes67_streams.insert(key, (stream, Instant::now()));
+                }
+            }
+            Err(error) => debug!("Ignoring SAP announcement: {}", error),
+        }
+        self.aes67_streams
+            .retain(|_, (_, announced_at)| announced_at.elapsed() < SAP_STREAM_TIMEOUT);
+    }
+}
+
+/// Binds the SAP port and joins the SAP group. Fails when something else on this machine already has the port.
+pub(crate) fn bind_sap_socket() -> std::io::Result<UdpSocket> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, SAP_PORT))?;
+    socket.join_multicast_v4(&SAP_GROUP, &Ipv4Addr::UNSPECIFIED)?;
+    // Short timeout so the listener notices when discovery stops.
+    socket.set_read_timeout(Some(Duration::from_millis(100)))?;
+    Ok(socket)
+}
+
+/// Spawns a thread that keeps the device list's AES67 streams up to date while running is set.
+pub(crate) fn spawn_sap_listener(
+    socket: UdpSocket,
+    device_list: Arc<Mutex<DanteDeviceList>>,
+    running: Arc<Mutex<bool>>,
+) -> JoinHandle<()> {
+    std::thread::spawn(move || {
+        debug!("Starting SAP listener thread");
+        let mut buffer = [0u8; 2048];
+        while *running.lock().unwrap() {
+            match socket.recv_from(&mut buffer) {
+                Ok((length, _)) => device_list
+                    .lock()
+                    .unwrap()
+                    .handle_sap_packet(&buffer[..length]),
+                Err(error)
+                    if matches!(
+                        error.kind(),
+                        std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+                    ) => {}
+                Err(error) => {
+                    warn!("SAP listener stopped: {}", error);
+                    return;
+                }
+            }
+        }
+    })
+}
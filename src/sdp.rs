@@ -0,0 +1,294 @@
+use std::net::Ipv4Addr;
+use std::time::Duration;
+
+/// An AES67 (or any RTP audio) stream from an SDP session description, like the ones sent in SAP announcements.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Aes67Stream {
+    /// "s=" line.
+    pub session_name: String,
+    /// Session id and unicast address of the "o=" line, which together identify the stream.
+    pub session_id: String,
+    pub origin_address: String,
+    /// Address the stream is sent to, from the "c=" line of the audio media or of the session.
+    pub destination: Option<Ipv4Addr>,
+    /// TTL from the "c=" line.
+    pub ttl: Option<u8>,
+    /// RTP port from the "m=audio" line.
+    pub port: u16,
+    /// Encoding name of the rtpmap, like "L24".
+    pub encoding: Option<String>,
+    pub sample_rate: Option<u32>,
+    pub channels: Option<u16>,
+    /// "a=ptime", packet time.
+    pub packet_time: Option<Duration>,
+    /// "a=ts-refclk", usually the PTP grandmaster like "ptp=IEEE1588-2008:00-1D-C1-FF-FE-12-34-56:0".
+    pub reference_clock: Option<String>,
+    /// "i=" line, if any.
+    pub description: Option<String>,
+}
+
+#[derive(thiserror::Error, Clone, Debug, PartialEq, Eq)]
+pub enum SdpError {
+    #[error("missing \"{0}=\" line")]
+    MissingLine(char),
+    #[error("malformed \"{0}=\" line")]
+    MalformedLine(char),
+    #[error("no audio media")]
+    NoAudio,
+}
+
+/// The "c=" line of a session or media, "IN IP4 239.69.1.2/32".
+fn parse_connection(value: &str) -> Result<(Option<Ipv4Addr>, Option<u8>), SdpError> {
+    let mut fields = value.split_whitespace();
+    let (Some("IN"), Some(address_type), Some(address)) =
+        (fields.next(), fields.next(), fields.next())
+    else {
+        return Err(SdpError::MalformedLine('c'));
+    };
+    if address_type != "IP4" {
+        // IPv6 streams can't be subscribed to from here anyway.
+        return Ok((None, None));
+    }
+    let mut parts = address.split('/');
+    let destination = parts
+        .next()
+        .and_then(|address| address.parse().ok())
+        .ok_or(SdpError::MalformedLine('c'))?;
+    let ttl = parts.next().and_then(|ttl| ttl.parse().ok());
+    Ok((Some(destination), ttl))
+}
+
+/// Parses an SDP session description into the first audio stream it describes. Unknown lines are skipped, so anything that has an origin, a name and an audio media is accepted.
+pub fn parse_sdp(sdp: &str) -> Result<Aes67Stream, SdpError> {
+    let mut session_name = None;
+    let mut origin = None;
+    let mut description = None;
+    let mut session_connection = None;
+    let mut media_connection = None;
+    // (port, payload type) of the first audio media.
+    let mut audio: Option<(u16, String)> = None;
+    let mut in_audio = false;
+    let mut seen_media = false;
+    let mut rtpmap = None;
+    let mut packet_time = None;
+    let mut session_reference_clock = None;
+    let mut media_reference_clock = None;
+
+    for line in sdp.lines() {
+        let Some((kind, value)) = line.split_once('=') else {
+            continue;
+        };
+        let mut kind_chars = kind.chars();
+        let (Some(kind), None) = (kind_chars.next(), kind_chars.next()) else {
+            continue;
+        };
+        let value = value.trim();
+        match kind {
+            'o' => {
+                let fields: Vec<&str> = value.split_whitespace().collect();
+                let [_, session_id, _, _, _, address] = fields[..] else {
+                    return Err(SdpError::MalformedLine('o'));
+                };
+                origin = Some((session_id.to_owned(), address.to_owned()));
+            }
+            's' => session_name = Some(value.to_owned()),
+            'i' if !seen_media => description = Some(value.to_owned()),
+            'c' if !seen_media => session_connection = Some(parse_connection(value)?),
+            'c' if in_audio => media_connection = Some(parse_connection(value)?),
+            'm' => {
+                in_audio = false;
+                let mut fields = value.split_whitespace();
+                if fields.next() == Some("audio") && audio.is_none() {
+                    let port = fields
+                        .next()
+                        .and_then(|port| port.split('/').next())
+                        .and_then(|port| port.parse().ok())
+                        .ok_or(SdpError::MalformedLine('m'))?;
+                    let payload_type = fields.nth(1).ok_or(SdpError::MalformedLine('m'))?;
+                    audio = Some((port, payload_type.to_owned()));
+                    in_audio = true;
+                }
+                seen_media = true;
+            }
+            'a' => {
+                let (attribute, attribute_value) = value.split_once(':').unwrap_or((value, ""));
+                match attribute {
+                    "ts-refclk" if !seen_media => {
+                        session_reference_clock = Some(attribute_value.to_owned())
+                    }
+                    "ts-refclk" if in_audio => {
+                        media_reference_clock = Some(attribute_value.to_owned())
+                    }
+                    "ptime" if in_audio => {
+                        packet_time =
+                            attribute_value
+                                .parse::<f64>()
+                                .ok()
+                                .and_then(|milliseconds| {
+                                    Duration::try_from_secs_f64(milliseconds / 1000.0).ok()
+                                });
+                    }
+                    "rtpmap" if in_audio => {
+                        if let Some((payload_type, format)) = attribute_value.split_once(' ') {
+                            if Some(payload_type) == audio.as_ref().map(|(_, pt)| pt.as_str()) {
+                                rtpmap = Some(format.to_owned());
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let (session_id, origin_address) = origin.ok_or(SdpError::MissingLine('o'))?;
+    let session_name = session_name.ok_or(SdpError::MissingLine('s'))?;
+    let (port, _) = audio.ok_or(SdpError::NoAudio)?;
+    let (destination, ttl) = media_connection
+        .or(session_connection)
+        .unwrap_or((None, None));
+    // "L24/48000/2", the channel count defaults to 1 when left out.
+    let mut rtpmap_fields = rtpmap.as_deref().unwrap_or_default().split('/');
+    let encoding = rtpmap_fields
+        .next()
+        .filter(|encoding| !encoding.is_empty())
+        .map(str::to_owned);
+    let sample_rate = rtpmap_fields.next().and_then(|rate| rate.parse().ok());
+    let channels = match rtpmap_fields.next() {
+        Some(channels) => channels.parse().ok(),
+        None => encoding.as_ref().map(|_| 1),
+    };
+
+    Ok(Aes67Stream {
+        session_name,
+        session_id,
+        origin_address,
+        destination,
+        ttl,
+        port,
+        encoding,
+        sample_rate,
+        channels,
+        packet_time,
+        reference_clock: media_reference_clock.or(session_reference_clock),
+        description,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// What a Dante device in AES67 mode announces for a multicast flow, CRLF line endings and all.
+    const DANTE_AES67: &str = "v=0\r\n\
+        o=- 1423986 1423994 IN IP4 192.168.1.41\r\n\
+        s=AVIO-Bluetooth : 2\r\n\
+        c=IN IP4 239.69.83.133/32\r\n\
+        t=0 0\r\n\
+        a=keywds:Dante\r\n\
+        m=audio 5004 RTP/AVP 97\r\n\
+        i=2 channels: Left, Right\r\n\
+        a=recvonly\r\n\
+        a=rtpmap:97 L24/48000/2\r\n\
+        a=ptime:1\r\n\
+        a=ts-refclk:ptp=IEEE1588-2008:00-1D-C1-FF-FE-12-34-56:0\r\n\
+        a=mediaclk:direct=0\r\n";
+
+    #[test]
+    fn parses_dante_aes67_announcement() {
+        let stream = parse_sdp(DANTE_AES67).unwrap();
+        assert_eq!(
+            stream,
+            Aes67Stream {
+                session_name: "AVIO-Bluetooth : 2".to_owned(),
+                session_id: "1423986".to_owned(),
+                origin_address: "192.168.1.41".to_owned(),
+                destination: Some(Ipv4Addr::new(239, 69, 83, 133)),
+                ttl: Some(32),
+                port: 5004,
+                encoding: Some("L24".to_owned()),
+                sample_rate: Some(48000),
+                channels: Some(2),
+                packet_time: Some(Duration::from_millis(1)),
+                reference_clock: Some("ptp=IEEE1588-2008:00-1D-C1-FF-FE-12-34-56:0".to_owned()),
+                // The i= line is the media's, not the session's.
+                description: None,
+            }
+        );
+    }
+
+    #[test]
+    fn media_connection_and_ttl_win_over_session() {
+        let sdp = "v=0\n\
+            o=- 7 7 IN IP4 10.0.0.3\n\
+            s=Ravenna\n\
+            i=Stage left\n\
+            c=IN IP4 239.1.1.1/16\n\
+            a=ts-refclk:ptp=IEEE1588-2008:AA-BB-CC-FF-FE-00-00-01:0\n\
+            m=audio 5006/2 RTP/AVP 98\n\
+            c=IN IP4 239.1.1.2/255\n\
+            a=rtpmap:98 L16/96000\n\
+            a=ptime:0.125\n";
+        let stream = parse_sdp(sdp).unwrap();
+        assert_eq!(stream.destination, Some(Ipv4Addr::new(239, 1, 1, 2)));
+        assert_eq!(stream.ttl, Some(255));
+        assert_eq!(stream.port, 5006);
+        assert_eq!(stream.encoding.as_deref(), Some("L16"));
+        assert_eq!(stream.sample_rate, Some(96000));
+        // Left out of the rtpmap, which means one channel.
+        assert_eq!(stream.channels, Some(1));
+        assert_eq!(stream.packet_time, Some(Duration::from_micros(125)));
+        assert_eq!(
+            stream.reference_clock.as_deref(),
+            Some("ptp=IEEE1588-2008:AA-BB-CC-FF-FE-00-00-01:0")
+        );
+        assert_eq!(stream.description.as_deref(), Some("Stage left"));
+    }
+
+    #[test]
+    fn missing_connection_line_leaves_destination_unknown() {
+        let sdp = "v=0\n\
+            o=- 1 1 IN IP4 10.0.0.4\n\
+            s=Unicast\n\
+            m=audio 5004 RTP/AVP 96\n\
+            a=rtpmap:96 L24/48000/8\n";
+        let stream = parse_sdp(sdp).unwrap();
+        assert_eq!(stream.destination, None);
+        assert_eq!(stream.ttl, None);
+        assert_eq!(stream.channels, Some(8));
+    }
+
+    #[test]
+    fn connection_without_ttl_and_ipv6() {
+        let (destination, ttl) = parse_connection("IN IP4 10.0.0.5").unwrap();
+        assert_eq!(destination, Some(Ipv4Addr::new(10, 0, 0, 5)));
+        assert_eq!(ttl, None);
+        assert_eq!(parse_connection("IN IP6 ff15::1").unwrap(), (None, None));
+        assert_eq!(
+            parse_connection("IN IP4 not-an-address/32"),
+            Err(SdpError::MalformedLine('c'))
+        );
+    }
+
+    #[test]
+    fn rejects_incomplete_descriptions() {
+        assert_eq!(
+            parse_sdp("v=0\ns=No origin\nm=audio 5004 RTP/AVP 96\n"),
+            Err(SdpError::MissingLine('o'))
+        );
+        assert_eq!(
+            parse_sdp("v=0\no=- 1 1 IN IP4 10.0.0.4\nm=audio 5004 RTP/AVP 96\n"),
+            Err(SdpError::MissingLine('s'))
+        );
+        assert_eq!(
+            parse_sdp("v=0\no=- 1 1 IN IP4 10.0.0.4\ns=Video\nm=video 5004 RTP/AVP 96\n"),
+            Err(SdpError::NoAudio)
+        );
+        assert_eq!(
+            parse_sdp("v=0\no=- 1 1 IN IP4\ns=Short origin\n"),
+            Err(SdpError::MalformedLine('o'))
+        );
+    }
+}
@@ -0,0 +1,286 @@
+use crate::{
+    ARCInfo, CHANInfo, CMCInfo, ChannelCache, ChannelDirection, DBCInfo, DanteDeviceEvent,
+    DanteDeviceList, DanteDeviceManager, DanteService, DeviceDiscoveryCache, DeviceInfo,
+    DeviceStatus, SubscriptionEntry, Timestamped,
+};
+use log::debug;
+use std::collections::{BTreeMap, HashSet};
+use std::sync::Arc;
+use std::time::Instant;
+
+/// Everything a DanteDeviceManager knows about the network: every discovered device and the last subscription read-back of each.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct NetworkSnapshot {
+    pub devices: Vec<DeviceInfo>,
+    /// Last subscription read-back of each device that has one, by device name.
+    pub subscriptions: BTreeMap<String, Vec<SubscriptionEntry>>,
+    /// Tags of each device that has any, by device name, see DanteDeviceManager::tag_device(). Tags of devices that aren't on the network aren't in here.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub tags: BTreeMap<String, Vec<String>>,
+}
+
+/// How much of each channel a snapshot holds, see DanteDeviceManager::snapshot_with_detail().
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SnapshotDetail {
+    /// Everything, the TXT properties of every channel included.
+    #[default]
+    Full,
+    /// Channels without their extra_properties, which are most of a snapshot of devices with hundreds of channels. get_device_info() still has them.
+    Summary,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum ImportError {
+    #[cfg(feature = "serde")]
+    #[error("invalid json")]
+    InvalidJson(#[from] serde_json::Error),
+    #[error("device \"{0}\" appears more than once")]
+    DuplicateDevice(String),
+}
+
+impl DanteDeviceList {
+    /// The subscriptions and tags of a snapshot of the devices, without the devices themselves.
+    fn snapshot_routing(&self, device_names: &[String]) -> NetworkSnapshot {
+        NetworkSnapshot {
+            devices: Vec::new(),
+            subscriptions: device_names
+                .iter()
+                .filter_map(|device_name| {
+                    let subscriptions = self
+                        .caches
+                        .get(device_name.as_str())?
+                        .subscriptions
+                        .as_ref()?;
+                    Some((device_name.clone(), subscriptions.value.clone()))
+                })
+                .collect(),
+            tags: device_names
+                .iter()
+                .filter_map(|device_name| {
+                    let tags: Vec<String> = self
+                        .device_tags(device_name)
+                        .into_iter()
+                        .map(str::to_owned)
+                        .collect();
+                    (!tags.is_empty()).then(|| (device_name.to_string(), tags))
+                })
+                .collect(),
+        }
+    }
+
+    /// Puts a device in the list from its DeviceInfo, as if it had just been discovered, replacing what was known about a device of the same name.
+    fn insert_device_info(
+        &mut self,
+        device_info: DeviceInfo,
+        subscriptions: Option<Vec<SubscriptionEntry>>,
+    ) {
+        let addresses: HashSet<_> = device_info.addresses.iter().copied().collect();
+        let mut chan_info = ChannelCache::default();
+        for channel in device_info.channels {
+            if channel.direction == ChannelDirection::Tx {
+                chan_info.update(
+                    &device_info.name,
+                    CHANInfo {
+                        name: channel.name,
+                        id: channel.id,
+                        sample_rate: channel.sample_rate,
+                        encoding: channel.encoding,
+                        latency: channel.latency,
+                        channel_count_hint: device_info.channel_count_hint,
+                        extra_properties: channel.extra_properties,
+                    },
+                );
+            }
+        }
+
+        let na = || "N/A".to_string();
+        let cache = DeviceDiscoveryCache {
+            dbc_info: device_info.dbc_port.map(|port| DBCInfo {
+                addresses: addresses.clone(),
+                port,
+            }),
+            cmc_info: device_info.cmc_port.map(|port| CMCInfo {
+                addresses: addresses.clone(),
+                port,
+                id: device_info.id.clone().unwrap_or_else(na),
+                manufacturer: device_info.manufacturer.clone().unwrap_or_else(na),
+                model: device_info.model.clone().unwrap_or_else(na),
+                channel_count_hint: device_info.channel_count_hint,
+            }),
+            // A bogus advertised ARC port is kept as it was, so the router info isn't lost on import.
+            arc_info: device_info
+                .arc_port
+                .or_else(|| {
+                    device_info
+                        .bogus_ports
+                        .iter()
+                        .find(|(service, _)| *service == DanteService::Arc)
+                        .map(|(_, port)| *port)
+                })
+                .map(|port| ARCInfo {
+                    addresses: addresses.clone(),
+                    port,
+                    router_vers: device_info.router_vers.clone().unwrap_or_else(na),
+                    router_info: device_info.router_info.clone().unwrap_or_else(na),
+                }),
+            chan_info,
+            subscriptions: subscriptions.map(Timestamped::new),
+            channel_counts: None,
+            tx_channel_names: None,
+            disconnected_at: None,
+            hostname: device_info.hostname,
+            address_sightings: Vec::new(),
+            version_override: device_info.version_override,
+        };
+
+        let device_name: Arc<str> = Arc::from(device_info.name.as_str());
+        self.devices.insert(
+            device_name.clone(),
+            DeviceStatus {
+                connected_dbc: device_info.dbc_connected,
+                connected_cmc: device_info.cmc_connected,
+                connected_arc: device_info.arc_connected,
+                connected_chan: device_info.chan_connected,
+                found_at: Instant::now(),
+                unresolved: device_info.unresolved,
+                gone_since: None,
+                manually_added: device_info.manually_added,
+            },
+        );
+        self.caches.insert(device_name, cache);
+    }
+
+    /// Rebuilds a device list from a snapshot, as if every device in it had just been discovered.
+    pub(crate) fn from_snapshot(snapshot: NetworkSnapshot) -> Result<Self, ImportError> {
+        let mut device_list = DanteDeviceList::new();
+        let mut subscriptions = snapshot.subscriptions;
+
+        for device_info in snapshot.devices {
+            if device_list.devices.contains_key(device_info.name.as_str()) {
+                return Err(ImportError::DuplicateDevice(device_info.name));
+            }
+            let device_subscriptions = subscriptions.remove(&device_info.name);
+            device_list.insert_device_info(device_info, device_subscriptions);
+        }
+
+        // After the devices, so tags go under the CMC ids.
+        for (device_name, tags) in snapshot.tags {
+            for tag in tags {
+                device_list.tag_device(&device_name, &tag);
+            }
+        }
+
+        device_list.debug_check_invariants();
+        Ok(device_list)
+    }
+
+    /// Adds or replaces the devices as manually added ones, see DanteDeviceManager::update_device_list(). Returns the names of the devices that weren't in the list before.
+    pub(crate) fn add_manual_devices(
+        &mut self,
+        devices: Vec<DeviceInfo>,
+    ) -> Result<Vec<String>, ImportError> {
+        let mut names = HashSet::new();
+        if let Some(device_info) = devices
+            .iter()
+            .find(|device_info| !names.insert(device_info.name.as_str()))
+        {
+            return Err(ImportError::DuplicateDevice(device_info.name.clone()));
+        }
+
+        let mut added = Vec::new();
+        for mut device_info in devices {
+            match self.devices.get(device_info.name.as_str()) {
+                Some(status) if !status.manually_added => {
+                    debug!(
+                        "Not replacing {} with a manually added device, discovery found it",
+                        device_info.name
+                    );
+                    continue;
+                }
+                Some(_) => {}
+                None => added.push(device_info.name.clone()),
+            }
+            device_info.manually_added = true;
+            // A read-back of the device stays good when it's described again.
+            let subscriptions = self
+                .caches
+                .get_mut(device_info.name.as_str())
+                .and_then(|cache| cache.subscriptions.take())
+                .map(|subscriptions| subscriptions.value);
+            self.insert_device_info(device_info, subscriptions);
+        }
+
+        self.debug_check_invariants();
+        Ok(added)
+    }
+}
+
+impl DanteDeviceManager {
+    /// Returns everything the manager knows about the network.
+    pub fn snapshot(&self) -> NetworkSnapshot {
+        self.snapshot_with_detail(SnapshotDetail::Full)
+    }
+
+    /// snapshot(), leaving out the TXT properties of the channels with SnapshotDetail::Summary. Importing a summary works, the devices just come back without those properties.
+    ///
+    /// Like get_device_infos(), the device list is locked for one device at a time, so a device can change between being copied and its subscriptions being copied. Devices that go away meanwhile are left out.
+    pub fn snapshot_with_detail(&self, detail: SnapshotDetail) -> NetworkSnapshot {
+        let mut device_names = self.get_device_names();
+        device_names.sort();
+        let devices: Vec<DeviceInfo> = device_names
+            .iter()
+            .filter_map(|device_name| {
+                self.read_device_list()
+                    .get_device_info_with(device_name, detail)
+            })
+            .collect();
+        let device_names: Vec<String> = devices
+            .iter()
+            .map(|device_info| device_info.name.clone())
+            .collect();
+        NetworkSnapshot {
+            devices,
+            ..self.read_device_list().snapshot_routing(&device_names)
+        }
+    }
+
+    /// Creates a manager holding the devices and subscriptions of a snapshot. The manager isn't running discovery, so nothing in it changes until start_discovery() is called.
+    pub fn from_snapshot(snapshot: NetworkSnapshot) -> Result<DanteDeviceManager, ImportError> {
+        let manager = DanteDeviceManager::new();
+        *manager.device_list.lock().unwrap() = DanteDeviceList::from_snapshot(snapshot)?;
+        Ok(manager)
+    }
+
+    /// Serializes snapshot() to JSON. Useful for tests, configuration management and offline tools.
+    #[cfg(feature = "serde")]
+    pub fn export_to_json(&self) -> Result<String, serde_json::Error> {
+        serde_json::to_string(&self.snapshot())
+    }
+
+    /// Restores a manager from export_to_json() output, see from_snapshot().
+    #[cfg(feature = "serde")]
+    pub fn import_from_json(json: &str) -> Result<DanteDeviceManager, ImportError> {
+        Self::from_snapshot(serde_json::from_str(json)?)
+    }
+
+    /// For when mdns doesn't reach the devices, like a control machine on another subnet over a VPN. Puts the devices in the device list as if discovery had found them, so commands go to the addresses and ports given, and marks them manually_added. A device discovery already found is left as it is, one that was manually added before is replaced, and a manually added device discovery finds later becomes a discovered one. DeviceAdded is emitted for each new device.
+    ///
+    /// Addresses are only kept for the services that have a port, like for discovered devices, so give at least arc_port and the other ports the commands need. Nothing removes manually added devices, and with discovery running, one that has no port at all ends up flagged unresolved.
+    pub fn update_device_list(&self, devices: Vec<DeviceInfo>) -> Result<(), ImportError> {
+        let mut device_list = self.device_list.lock().unwrap();
+        for device_name in device_list.add_manual_devices(devices)? {
+            device_list
+                .events
+                .emit(DanteDeviceEvent::DeviceAdded { device_name });
+        }
+        Ok(())
+    }
+
+    /// update_device_list() from a JSON array of devices in the DeviceInfo format, what export_to_json() has under "devices".
+    #[cfg(feature = "serde")]
+    pub fn update_device_list_from_json(&self, json: &str) -> Result<(), ImportError> {
+        self.update_device_list(serde_json::from_str(json)?)
+    }
+}
@@ -0,0 +1,103 @@
+use crate::{ClearSubscriptionError, DanteDeviceList, DanteDeviceManager};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// What tags of a device are kept under. The CMC id when the device has one, so tags follow it through a rename, or its name until it does.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum TagKey {
+    Id(String),
+    Name(String),
+}
+
+impl DanteDeviceList {
+    fn tag_key(&self, device_name: &str) -> TagKey {
+        match self.get_device_cmc_id(device_name) {
+            Some(id) => TagKey::Id(id.to_owned()),
+            None => TagKey::Name(device_name.to_owned()),
+        }
+    }
+
+    pub(crate) fn tag_device(&mut self, device_name: &str, tag: &str) {
+        let key = self.tag_key(device_name);
+        self.tags.entry(key).or_default().insert(tag.to_owned());
+    }
+
+    /// Tags of the device, by its CMC id and by its name. Tags put on by name before the CMC record resolved are still found this way.
+    pub(crate) fn device_tags(&self, device_name: &str) -> BTreeSet<&str> {
+        let by_id = self
+            .get_device_cmc_id(device_name)
+            .and_then(|id| self.tags.get(&TagKey::Id(id.to_owned())));
+        let by_name = self.tags.get(&TagKey::Name(device_name.to_owned()));
+        by_id
+            .into_iter()
+            .chain(by_name)
+            .flatten()
+            .map(String::as_str)
+            .collect()
+    }
+}
+
+impl DanteDeviceManager {
+    /// Tags a device, for grouping devices like "FOH rack" or "stage left". Tags are only kept by the manager, nothing is written to the device. They're kept under the device's CMC id, so they stay with it when it's renamed. A device whose CMC record hasn't resolved yet is tagged by name, and loses the tag when it's renamed before that. Tagging a device that isn't discovered (yet) is fine.
+    pub fn tag_device(&self, device_name: &str, tag: &str) {
+        self.device_list
+            .lock()
+            .unwrap()
+            .tag_device(device_name, tag);
+    }
+
+    /// Removes a tag from a device, whether it was tagged by CMC id or by name.
+    pub fn untag_device(&self, device_name: &str, tag: &str) {
+        let mut device_list = self.device_list.lock().unwrap();
+        let id = device_list
+            .get_device_cmc_id(device_name)
+            .map(str::to_owned);
+        let keys = id
+            .map(TagKey::Id)
+            .into_iter()
+            .chain([TagKey::Name(device_name.to_owned())]);
+        for key in keys {
+            if let Some(tags) = device_list.tags.get_mut(&key) {
+                tags.remove(tag);
+                if tags.is_empty() {
+                    device_list.tags.remove(&key);
+                }
+            }
+        }
+    }
+
+    /// Returns the tags of a device, sorted.
+    pub fn device_tags(&self, device_name: &str) -> Vec<String> {
+        self.read_device_list()
+            .device_tags(device_name)
+            .into_iter()
+            .map(str::to_owned)
+            .collect()
+    }
+
+    /// Returns the discovered devices with the tag, sorted. Tagged devices that aren't on the network aren't in it.
+    pub fn devices_with_tag(&self, tag: &str) -> Vec<String> {
+        let device_list = self.read_device_list();
+        let mut device_names: Vec<String> = device_list
+            .devices
+            .keys()
+            .filter(|device_name| device_list.device_tags(device_name).contains(tag))
+            .map(|device_name| device_name.to_string())
+            .collect();
+        device_names.sort();
+        device_names
+    }
+
+    /// clear_device_subscriptions() for every discovered device with the tag, like clearing all routes of the devices tagged "rehearsal". A device that fails doesn't stop the others. Returns how each device went, by name.
+    pub fn clear_subscriptions_with_tag(
+        &mut self,
+        tag: &str,
+    ) -> BTreeMap<String, Result<u16, ClearSubscriptionError>> {
+        self.devices_with_tag(tag)
+            .into_iter()
+            .map(|device_name| {
+                let result = self.clear_device_subscriptions(&device_name);
+                (device_name, result)
+            })
+            .collect()
+    }
+}
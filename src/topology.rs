@@ -0,0 +1,307 @@
+use crate::{ChannelDirection, DanteDeviceList, SubscriptionConflict};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::fmt::{Display, Formatter};
+use std::net::Ipv4Addr;
+use std::time::{Duration, Instant};
+
+/// How many address sets are remembered per device.
+const MAX_ADDRESS_SIGHTINGS: usize = 8;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+    Error,
+    Warning,
+    Info,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Severity::Error => "Error",
+            Severity::Warning => "Warning",
+            Severity::Info => "Info",
+        })
+    }
+}
+
+/// A problem found by validate_network_topology() or diagnose_device().
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TopologyWarning {
+    pub severity: Severity,
+    /// The device the warning is about, or None for network wide warnings.
+    pub device_name: Option<String>,
+    pub message: String,
+}
+
+impl Display for TopologyWarning {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match &self.device_name {
+            Some(device_name) => write!(f, "{}: {}: {}", self.severity, device_name, self.message),
+            None => write!(f, "{}: {}", self.severity, self.message),
+        }
+    }
+}
+
+impl TopologyWarning {
+    fn new(severity: Severity, device_name: Option<&str>, message: String) -> Self {
+        TopologyWarning {
+            severity,
+            device_name: device_name.map(str::to_owned),
+            message,
+        }
+    }
+}
+
+/// A name more than one device uses, see DanteDeviceManager::check_duplicate_device_names().
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DuplicateNameEntry {
+    pub name: String,
+    /// Addresses of all the devices using the name, sorted.
+    pub ips: Vec<Ipv4Addr>,
+}
+
+/// An address set a device's services resolved to, and when.
+pub(crate) struct AddressSighting {
+    addresses: BTreeSet<Ipv4Addr>,
+    first_seen: Instant,
+    last_seen: Instant,
+}
+
+impl AddressSighting {
+    /// Two devices answering to the same name keep resolving to their own addresses in turn, so their sightings overlap in time. A device that changed address stops being seen at the old one before it's seen at the new one.
+    fn conflicts_with(&self, other: &AddressSighting) -> bool {
+        self.addresses.is_disjoint(&other.addresses)
+            && self.last_seen > other.first_seen
+            && other.last_seen > self.first_seen
+    }
+}
+
+impl DanteDeviceList {
+    /// Remembers the addresses a service of the device resolved to.
+    pub(crate) fn record_resolved_addresses(
+        &mut self,
+        device_name: &str,
+        addresses: &HashSet<Ipv4Addr>,
+    ) {
+        if addresses.is_empty() {
+            return;
+        }
+        let Some(cache) = self.caches.get_mut(device_name) else {
+            return;
+        };
+        let now = Instant::now();
+        match cache.address_sightings.iter_mut().find(|sighting| {
+            sighting.addresses.len() == addresses.len()
+                && addresses
+                    .iter()
+                    .all(|address| sighting.addresses.contains(address))
+        }) {
+            Some(sighting) => sighting.last_seen = now,
+            None => {
+                let addresses: BTreeSet<Ipv4Addr> = addresses.iter().copied().collect();
+                if cache.address_sightings.len() == MAX_ADDRESS_SIGHTINGS {
+                    cache
+                        .address_sightings
+                        .sort_by_key(|sighting| std::cmp::Reverse(sighting.last_seen));
+                    cache.address_sightings.pop();
+                }
+                cache.address_sightings.push(AddressSighting {
+                    addresses,
+                    first_seen: now,
+                    last_seen: now,
+                });
+            }
+        }
+    }
+
+    /// The addresses of every device using the name, or None if it looks like just the one.
+    fn duplicate_name_ips(&self, device_name: &str) -> Option<Vec<Ipv4Addr>> {
+        let sightings = &self.caches.get(device_name)?.address_sightings;
+        let mut ips = BTreeSet::new();
+        for (index, sighting) in sightings.iter().enumerate() {
+            for other in &sightings[index + 1..] {
+                if sighting.conflicts_with(other) {
+                    ips.extend(&sighting.addresses);
+                    ips.extend(&other.addresses);
+                }
+            }
+        }
+        (!ips.is_empty()).then(|| ips.into_iter().collect())
+    }
+
+    pub(crate) fn duplicate_device_names(&self) -> Vec<DuplicateNameEntry> {
+        let mut entries: Vec<DuplicateNameEntry> = self
+            .devices
+            .keys()
+            .filter_map(|device_name| {
+                Some(DuplicateNameEntry {
+                    name: device_name.to_string(),
+                    ips: self.duplicate_name_ips(device_name)?,
+                })
+            })
+            .collect();
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+        entries
+    }
+
+    /// Checks a single device using what discovery and the last subscription read-back know about it.
+    pub(crate) fn diagnose_device(&self, device_name: &str) -> Vec<TopologyWarning> {
+        let mut warnings = Vec::new();
+        let (Some(status), Some(cache)) =
+            (self.devices.get(device_name), self.caches.get(device_name))
+        else {
+            return warnings;
+        };
+
+        if let Some(ips) = self.duplicate_name_ips(device_name) {
+            warnings.push(TopologyWarning::new(
+                Severity::Error,
+                Some(device_name),
+                format!("name is used by more than one device, at {:?}", ips),
+            ));
+        }
+
+        if status.unresolved {
+            warnings.push(TopologyWarning::new(
+                Severity::Warning,
+                Some(device_name),
+                "device was discovered but hasn't resolved an address".to_string(),
+            ));
+        }
+
+        let sample_rates: BTreeSet<u32> = cache
+            .chan_info
+            .iter()
+            .filter_map(|chan_info| chan_info.sample_rate)
+            .collect();
+        if sample_rates.len() > 1 {
+            warnings.push(TopologyWarning::new(
+                Severity::Error,
+                Some(device_name),
+                format!(
+                    "channels advertise different sample rates {:?}",
+                    sample_rates
+                ),
+            ));
+        }
+
+        let conflicts = self.channel_name_conflicts(device_name);
+        for (direction, label) in [(ChannelDirection::Tx, "TX"), (ChannelDirection::Rx, "RX")] {
+            let names: Vec<&str> = conflicts
+                .iter()
+                .filter(|conflict| conflict.direction == direction)
+                .map(|conflict| conflict.name.as_str())
+                .collect();
+            if !names.is_empty() {
+                warnings.push(TopologyWarning::new(
+                    Severity::Error,
+                    Some(device_name),
+                    format!("duplicate {} channel names {:?}", label, names),
+                ));
+            }
+        }
+
+        warnings
+    }
+
+    /// Runs diagnose_device() on every device, then checks the devices against each other.
+    pub(crate) fn validate_network_topology(&self) -> Vec<TopologyWarning> {
+        let mut device_names: Vec<&str> = self
+            .devices
+            .keys()
+            .map(|device_name| &**device_name)
+            .collect();
+        device_names.sort();
+
+        let mut warnings: Vec<TopologyWarning> = device_names
+            .iter()
+            .flat_map(|device_name| self.diagnose_device(device_name))
+            .collect();
+
+        let mut devices_by_rate: BTreeMap<u32, Vec<&str>> = BTreeMap::new();
+        let mut devices_by_latency: BTreeMap<Duration, Vec<&str>> = BTreeMap::new();
+        let mut devices_by_lowercase_name: BTreeMap<String, Vec<&str>> = BTreeMap::new();
+        for device_name in &device_names {
+            devices_by_lowercase_name
+                .entry(device_name.to_lowercase())
+                .or_default()
+                .push(device_name);
+            let Some(cache) = self.caches.get(*device_name) else {
+                continue;
+            };
+            for sample_rate in cache
+                .chan_info
+                .iter()
+                .filter_map(|chan_info| chan_info.sample_rate)
+                .collect::<BTreeSet<_>>()
+            {
+                devices_by_rate
+                    .entry(sample_rate)
+                    .or_default()
+                    .push(device_name);
+            }
+            for latency in cache
+                .chan_info
+                .iter()
+                .filter_map(|chan_info| chan_info.latency)
+                .collect::<BTreeSet<_>>()
+            {
+                devices_by_latency
+                    .entry(latency)
+                    .or_default()
+                    .push(device_name);
+            }
+        }
+
+        if devices_by_rate.len() > 1 {
+            warnings.push(TopologyWarning::new(
+                Severity::Error,
+                None,
+                format!(
+                    "devices run at different sample rates {:?}",
+                    devices_by_rate
+                ),
+            ));
+        }
+
+        if devices_by_latency.len() > 1 {
+            warnings.push(TopologyWarning::new(
+                Severity::Warning,
+                None,
+                format!("devices use different latencies {:?}", devices_by_latency),
+            ));
+        }
+
+        for (_, same_names) in devices_by_lowercase_name {
+            if same_names.len() > 1 {
+                warnings.push(TopologyWarning::new(
+                    Severity::Error,
+                    None,
+                    format!("device names only differ by case {:?}", same_names),
+                ));
+            }
+        }
+
+        for conflict in self.detect_subscription_conflicts() {
+            warnings.push(match conflict {
+                SubscriptionConflict::RoutingLoop { devices } => TopologyWarning::new(
+                    Severity::Error,
+                    None,
+                    format!("routing loop between {:?}", devices),
+                ),
+                SubscriptionConflict::DuplicateSubscription { source, receivers } => {
+                    TopologyWarning::new(
+                        Severity::Info,
+                        Some(&source.0),
+                        format!(
+                            "TX channel \"{}\" has several subscribers {:?}",
+                            source.1, receivers
+                        ),
+                    )
+                }
+            });
+        }
+
+        warnings
+    }
+}
@@ -0,0 +1,564 @@
+use crate::command_socket::{is_receive_timeout, send_with_backoff, Transport};
+use crate::discovery_event::DiscoveryEvent;
+use crate::reader::{ParseError, Reader};
+use crate::{cutoff_address, split_chan_fullname, DanteDeviceManager, DanteService};
+use log::{debug, warn};
+use mdns_sd::ServiceInfo;
+use std::collections::{BTreeSet, HashMap};
+use std::net::Ipv4Addr;
+use std::time::Instant;
+
+const MDNS_PORT: u16 = 5353;
+
+/// Responders echo the ID of a query that doesn't come from port 5353 (RFC 6762 6.7), mdns itself doesn't use IDs.
+const PROBE_QUERY_ID: u16 = 0x4443;
+
+const TYPE_A: u16 = 1;
+const TYPE_PTR: u16 = 12;
+const TYPE_TXT: u16 = 16;
+const TYPE_SRV: u16 = 33;
+const CLASS_IN: u16 = 1;
+
+/// Follow-up questions per query, so a query for a device with many channels stays well below a datagram.
+const QUESTIONS_PER_QUERY: usize = 16;
+
+/// Names are followed through at most this many compression pointers. Real ones take one or two.
+const MAX_NAME_JUMPS: usize = 16;
+
+#[derive(thiserror::Error, Debug)]
+pub enum ProbeError {
+    #[error("failed to send the query")]
+    ConnectionFailed(#[source] std::io::Error),
+    #[error("{0} didn't answer on port 5353")]
+    NoAnswer(Ipv4Addr),
+}
+
+/// A resource record of a DNS response that's of use for finding Dante services. Names are dotted and end in a dot, like mdns_sd's full names.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum DnsRecord {
+    Ptr {
+        name: String,
+        target: String,
+    },
+    Srv {
+        name: String,
+        port: u16,
+        target: String,
+    },
+    Txt {
+        name: String,
+        properties: HashMap<String, String>,
+    },
+    A {
+        name: String,
+        address: Ipv4Addr,
+    },
+}
+
+/// A DNS query (RFC 1035 4.1) with a question of type IN for each (name, type). Names with a label that doesn't fit in one are left out.
+pub(crate) fn encode_query(id: u16, questions: &[(&str, u16)]) -> Vec<u8> {
+    let questions: Vec<_> = questions
+        .iter()
+        .filter(|(name, _)| name.split('.').all(|label| label.len() < 64))
+        .collect();
+    let mut query = Vec::new();
+    query.extend_from_slice(&id.to_be_bytes());
+    query.extend_from_slice(&0u16.to_be_bytes());
+    query.extend_from_slice(&(questions.len() as u16).to_be_bytes());
+    query.extend_from_slice(&[0; 6]);
+    for (name, record_type) in questions {
+        for label in name.split('.').filter(|label| !label.is_empty()) {
+            query.push(label.len() as u8);
+            query.extend_from_slice(label.as_bytes());
+        }
+        query.push(0);
+        query.extend_from_slice(&record_type.to_be_bytes());
+        query.extend_from_slice(&CLASS_IN.to_be_bytes());
+    }
+    query
+}
+
+/// Reads a possibly compressed name at the reader's position and moves past it.
+fn read_name(packet: &[u8], reader: &mut Reader) -> Result<String, ParseError> {
+    let mut name = String::new();
+    let mut cursor = Reader::new(packet);
+    cursor.seek(reader.position());
+    let mut jumps = 0;
+    loop {
+        let length = cursor.read_u8()?;
+        if length & 0xc0 == 0xc0 {
+            let offset = ((length & 0x3f) as usize) << 8 | cursor.read_u8()? as usize;
+            if jumps == 0 {
+                reader.seek(cursor.position());
+            }
+            jumps += 1;
+            if jumps > MAX_NAME_JUMPS {
+                return Err(ParseError::InvalidName);
+            }
+            cursor.seek(offset);
+            continue;
+        }
+        if length & 0xc0 != 0 {
+            return Err(ParseError::InvalidName);
+        }
+        if length == 0 {
+            if jumps == 0 {
+                reader.seek(cursor.position());
+            }
+            return Ok(name);
+        }
+        name.push_str(&String::from_utf8_lossy(
+            cursor.read_bytes(length as usize)?,
+        ));
+        name.push('.');
+    }
+}
+
+/// The "key=value" strings of a TXT record. Keys without a value get an empty one.
+fn parse_txt(rdata: &[u8]) -> Result<HashMap<String, String>, ParseError> {
+    let mut reader = Reader::new(rdata);
+    let mut properties = HashMap::new();
+    while reader.position() < rdata.len() {
+        let length = reader.read_u8()?;
+        let entry = String::from_utf8_lossy(reader.read_bytes(length as usize)?);
+        if entry.is_empty() {
+            continue;
+        }
+        let (key, value) = entry.split_once('=').unwrap_or((&entry, ""));
+        properties.insert(key.to_owned(), value.to_owned());
+    }
+    Ok(properties)
+}
+
+/// Parses the answer, authority and additional records of a DNS response. Records of other types are skipped, and a packet that's a query rather than a response has none.
+pub(crate) fn parse_response(packet: &[u8]) -> Result<Vec<DnsRecord>, ParseError> {
+    let mut reader = Reader::new(packet);
+    reader.skip(2)?;
+    let flags = reader.read_u16()?;
+    if flags & 0x8000 == 0 {
+        return Ok(Vec::new());
+    }
+    let question_count = reader.read_u16()?;
+    let record_count = [reader.read_u16()?, reader.read_u16()?, reader.read_u16()?]
+        .iter()
+        .map(|count| *count as usize)
+        .sum::<usize>();
+    for _ in 0..question_count {
+        read_name(packet, &mut reader)?;
+        reader.skip(4)?;
+    }
+
+    let mut records = Vec::new();
+    for _ in 0..record_count {
+        let name = read_name(packet, &mut reader)?;
+        let record_type = reader.read_u16()?;
+        // The top bit is mdns' cache flush bit.
+        let class = reader.read_u16()? & 0x7fff;
+        reader.skip(4)?;
+        let rdata_length = reader.read_u16()? as usize;
+        let rdata_start = reader.position();
+        let rdata = reader.read_bytes(rdata_length)?;
+        if class != CLASS_IN {
+            continue;
+        }
+        // Names in the data can point anywhere in the packet, so they're read from a reader over all of it.
+        let mut rdata_reader = Reader::new(packet);
+        rdata_reader.seek(rdata_start);
+        match record_type {
+            TYPE_PTR => records.push(DnsRecord::Ptr {
+                name,
+                target: read_name(packet, &mut rdata_reader)?,
+            }),
+            TYPE_SRV => {
+                // Priority and weight.
+                rdata_reader.skip(4)?;
+                let port = rdata_reader.read_u16()?;
+                records.push(DnsRecord::Srv {
+                    name,
+                    port,
+                    target: read_name(packet, &mut rdata_reader)?,
+                });
+            }
+            TYPE_TXT => records.push(DnsRecord::Txt {
+                name,
+                properties: parse_txt(rdata)?,
+            }),
+            TYPE_A => {
+                if let Ok(octets) = <[u8; 4]>::try_from(rdata) {
+                    records.push(DnsRecord::A {
+                        name,
+                        address: Ipv4Addr::from(octets),
+                    });
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(records)
+}
+
+/// The Dante service a full service name is an instance of.
+fn service_of(fullname: &str) -> Option<DanteService> {
+    DanteService::ALL.into_iter().find(|service| {
+        fullname
+            .strip_suffix(service.service_type())
+            .is_some_and(|instance| instance.len() > 1 && instance.ends_with('.'))
+    })
+}
+
+/// Services put together from the records of a device's answers, as the ServiceInfo mdns_sd would have resolved them to.
+fn resolved_services(
+    records: &[DnsRecord],
+    fallback: Ipv4Addr,
+) -> Vec<(DanteService, ServiceInfo)> {
+    let mut services = Vec::new();
+    for (instance_name, port, host) in records.iter().filter_map(|record| match record {
+        DnsRecord::Srv { name, port, target } => Some((name, *port, target)),
+        _ => None,
+    }) {
+        let Some(service) = service_of(instance_name) else {
+            continue;
+        };
+        let properties = records
+            .iter()
+            .find_map(|record| match record {
+                DnsRecord::Txt { name, properties } if name == instance_name => {
+                    Some(properties.clone())
+                }
+                _ => None,
+            })
+            .unwrap_or_default();
+        let mut addresses: Vec<String> = records
+            .iter()
+            .filter_map(|record| match record {
+                DnsRecord::A { name, address } if name == host => Some(address.to_string()),
+                _ => None,
+            })
+            .collect();
+        // The device answered from its address, which will do when it left its A records out.
+        if addresses.is_empty() {
+            addresses.push(fallback.to_string());
+        }
+        let instance = cutoff_address(instance_name, Some(service.service_type()));
+        match ServiceInfo::new(
+            service.service_type(),
+            instance,
+            host,
+            addresses.join(",").as_str(),
+            port,
+            properties,
+        ) {
+            Ok(service_info) => services.push((service, service_info)),
+            Err(error) => warn!(
+                "Ignoring {} from a unicast answer: {}",
+                instance_name, error
+            ),
+        }
+    }
+    services
+}
+
+impl DanteDeviceManager {
+    /// Sends one query to the device's mdns port and collects every answer that comes back within the response timeout.
+    fn unicast_dns_query(
+        &self,
+        address: Ipv4Addr,
+        questions: &[(&str, u16)],
+    ) -> Result<Option<Vec<DnsRecord>>, ProbeError> {
+        let target = self.transport().target(address, MDNS_PORT);
+        let socket = Transport::bind_throwaway(&target).map_err(ProbeError::ConnectionFailed)?;
+        let query = encode_query(PROBE_QUERY_ID, questions);
+        debug!("Sent DNS query {:?} to {}", hex::encode(&query), target);
+        send_with_backoff(&self.metrics, || socket.send_to(&query, target))
+            .map_err(ProbeError::ConnectionFailed)?;
+
+        // A device may answer in more than one datagram, so this listens for the whole timeout.
+        let deadline = Instant::now() + self.response_timeout;
+        let mut buffer = [0u8; 9000];
+        let mut records: Option<Vec<DnsRecord>> = None;
+        while let Some(remaining) = deadline.checked_duration_since(Instant::now()) {
+            if remaining.is_zero() {
+                break;
+            }
+            socket
+                .set_read_timeout(Some(remaining))
+                .map_err(ProbeError::ConnectionFailed)?;
+            let (length, from) = match socket.recv_from(&mut buffer) {
+                Ok(received) => received,
+                Err(error) if is_receive_timeout(&error) => break,
+                Err(error) => return Err(ProbeError::ConnectionFailed(error)),
+            };
+            let response = &buffer[..length];
+            debug!(
+                "Received DNS answer {:?} from {}",
+                hex::encode(response),
+                from
+            );
+            if from != target || response.get(..2) != Some(&query[..2]) {
+                continue;
+            }
+            match parse_response(response) {
+                Ok(answer) => records.get_or_insert_with(Vec::new).extend(answer),
+                Err(error) => warn!("Ignoring invalid DNS answer from {}: {}", from, error),
+            }
+        }
+        Ok(records)
+    }
+
+    /// Finds the Dante services of the device at address with DNS-SD queries sent straight to it, for devices on another subnet that multicast doesn't reach. Devices answer a query sent to their mdns port from any other port directly to the sender (RFC 6762 6.7). What's found goes into the device list as if discovery had found it, whether or not discovery is running. Returns the names of the devices found, sorted, usually just the one.
+    ///
+    /// A device with so many channels that its answer doesn't fit in a datagram sends a truncated one, and the channels that didn't fit aren't found. Asking again over TCP, like DNS does for truncated answers, isn't done.
+    pub fn probe_device(&self, address: Ipv4Addr) -> Result<Vec<String>, ProbeError> {
+        let browse: Vec<(&str, u16)> = DanteService::ALL
+            .into_iter()
+            .map(|service| (service.service_type(), TYPE_PTR))
+            .collect();
+        let mut records = self
+            .unicast_dns_query(address, &browse)?
+            .ok_or(ProbeError::NoAnswer(address))?;
+
+        // Responders usually send the SRV and TXT records of the instances along, the ones that didn't are asked for.
+        let instances: BTreeSet<String> = records
+            .iter()
+            .filter_map(|record| match record {
+                DnsRecord::Ptr { target, .. } if service_of(target).is_some() => {
+                    Some(target.clone())
+                }
+                _ => None,
+            })
+            .collect();
+        let has = |records: &[DnsRecord], instance: &str, record_type: u16| {
+            records.iter().any(|record| match (record, record_type) {
+                (DnsRecord::Srv { name, .. }, TYPE_SRV)
+                | (DnsRecord::Txt { name, .. }, TYPE_TXT) => name == instance,
+                _ => false,
+            })
+        };
+        let missing: Vec<(&str, u16)> = instances
+            .iter()
+            .flat_map(|instance| [(instance.as_str(), TYPE_SRV), (instance.as_str(), TYPE_TXT)])
+            .filter(|(instance, record_type)| !has(&records, instance, *record_type))
+            .collect();
+        for questions in missing.chunks(QUESTIONS_PER_QUERY) {
+            if let Some(answer) = self.unicast_dns_query(address, questions)? {
+                records.extend(answer);
+            }
+        }
+
+        let services = resolved_services(&records, address);
+        let mut device_names = BTreeSet::new();
+        let mut device_list = self.device_list.lock().unwrap();
+        for (service, service_info) in services {
+            let fullname = service_info.get_fullname().to_owned();
+            let device_name = match service {
+                DanteService::Chan => match split_chan_fullname(&fullname) {
+                    Some((_, device_name)) => device_name,
+                    None => continue,
+                },
+                _ => cutoff_address(&fullname, Some(service.service_type())),
+            };
+            device_names.insert(device_name.to_owned());
+            device_list.handle_discovery_event(service, DiscoveryEvent::Resolved(service_info));
+        }
+        Ok(device_names.into_iter().collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DanteDeviceList;
+
+    // Answers of a Dante device to a unicast PTR query for each service, laid out the way mdns responders answer: the question echoed, the PTR as the answer, and SRV, TXT, A and NSEC as additional records, with cache flush bits and names compressed against the question. These were put together by hand from the record layout of Dante devices, not captured off a network.
+    const CMC_ANSWER: &[u8] = &[
+        // header: id 0x4443, response, 1 question, 1 answer, 4 additional
+        0x44, 0x43, 0x84, 0x00, 0x00, 0x01, 0x00, 0x01, 0x00, 0x00, 0x00, 0x04,
+        // question: _netaudio-cmc._udp.local. PTR IN
+        0x0d, 0x5f, 0x6e, 0x65, 0x74, 0x61, 0x75, 0x64, 0x69, 0x6f, 0x2d, 0x63, 0x6d, 0x63, 0x04,
+        0x5f, 0x75, 0x64, 0x70, 0x05, 0x6c, 0x6f, 0x63, 0x61, 0x6c, 0x00, 0x00, 0x0c, 0x00, 0x01,
+        // answer: PTR -> Stage-Box._netaudio-cmc._udp.local.
+        0xc0, 0x0c, 0x00, 0x0c, 0x00, 0x01, 0x00, 0x00, 0x11, 0x94, 0x00, 0x0c, 0x09, 0x53, 0x74,
+        0x61, 0x67, 0x65, 0x2d, 0x42, 0x6f, 0x78, 0xc0, 0x0c,
+        // additional: SRV, cache flush, port 8800, target Stage-Box.local.
+        0xc0, 0x36, 0x00, 0x21, 0x80, 0x01, 0x00, 0x00, 0x00, 0x78, 0x00, 0x12, 0x00, 0x00, 0x00,
+        0x00, 0x22, 0x60, 0x09, 0x53, 0x74, 0x61, 0x67, 0x65, 0x2d, 0x42, 0x6f, 0x78, 0xc0, 0x1f,
+        // additional: TXT id=001dc1fffe5046a1 process=0 cmcp_vers=1.2.0 cmcp_min=1.0.0 server_vers=4.2.3.2 mf=Audinate model=DIOUSBC
+        0xc0, 0x36, 0x00, 0x10, 0x80, 0x01, 0x00, 0x00, 0x11, 0x94, 0x00, 0x6b, 0x13, 0x69, 0x64,
+        0x3d, 0x30, 0x30, 0x31, 0x64, 0x63, 0x31, 0x66, 0x66, 0x66, 0x65, 0x35, 0x30, 0x34, 0x36,
+        0x61, 0x31, 0x09, 0x70, 0x72, 0x6f, 0x63, 0x65, 0x73, 0x73, 0x3d, 0x30, 0x0f, 0x63, 0x6d,
+        0x63, 0x70, 0x5f, 0x76, 0x65, 0x72, 0x73, 0x3d, 0x31, 0x2e, 0x32, 0x2e, 0x30, 0x0e, 0x63,
+        0x6d, 0x63, 0x70, 0x5f, 0x6d, 0x69, 0x6e, 0x3d, 0x31, 0x2e, 0x30, 0x2e, 0x30, 0x13, 0x73,
+        0x65, 0x72, 0x76, 0x65, 0x72, 0x5f, 0x76, 0x65, 0x72, 0x73, 0x3d, 0x34, 0x2e, 0x32, 0x2e,
+        0x33, 0x2e, 0x32, 0x0b, 0x6d, 0x66, 0x3d, 0x41, 0x75, 0x64, 0x69, 0x6e, 0x61, 0x74, 0x65,
+        0x0d, 0x6d, 0x6f, 0x64, 0x65, 0x6c, 0x3d, 0x44, 0x49, 0x4f, 0x55, 0x53, 0x42, 0x43,
+        // additional: A 192.168.1.50
+        0xc0, 0x54, 0x00, 0x01, 0x80, 0x01, 0x00, 0x00, 0x00, 0x78, 0x00, 0x04, 0xc0, 0xa8, 0x01,
+        0x32, // additional: NSEC, skipped
+        0xc0, 0x54, 0x00, 0x2f, 0x80, 0x01, 0x00, 0x00, 0x00, 0x78, 0x00, 0x05, 0xc0, 0x54, 0x00,
+        0x01, 0x40,
+    ];
+
+    const ARC_ANSWER: &[u8] = &[
+        // header: id 0x4443, response, 1 question, 1 answer, 4 additional
+        0x44, 0x43, 0x84, 0x00, 0x00, 0x01, 0x00, 0x01, 0x00, 0x00, 0x00, 0x04,
+        // question: _netaudio-arc._udp.local. PTR IN
+        0x0d, 0x5f, 0x6e, 0x65, 0x74, 0x61, 0x75, 0x64, 0x69, 0x6f, 0x2d, 0x61, 0x72, 0x63, 0x04,
+        0x5f, 0x75, 0x64, 0x70, 0x05, 0x6c, 0x6f, 0x63, 0x61, 0x6c, 0x00, 0x00, 0x0c, 0x00, 0x01,
+        // answer: PTR -> Stage-Box._netaudio-arc._udp.local.
+        0xc0, 0x0c, 0x00, 0x0c, 0x00, 0x01, 0x00, 0x00, 0x11, 0x94, 0x00, 0x0c, 0x09, 0x53, 0x74,
+        0x61, 0x67, 0x65, 0x2d, 0x42, 0x6f, 0x78, 0xc0, 0x0c,
+        // additional: SRV, cache flush, port 4440, target Stage-Box.local.
+        0xc0, 0x36, 0x00, 0x21, 0x80, 0x01, 0x00, 0x00, 0x00, 0x78, 0x00, 0x12, 0x00, 0x00, 0x00,
+        0x00, 0x11, 0x58, 0x09, 0x53, 0x74, 0x61, 0x67, 0x65, 0x2d, 0x42, 0x6f, 0x78, 0xc0, 0x1f,
+        // additional: TXT arcp_vers=2.7.41 arcp_min=0.2.4 router_vers=4.4.1.3 router_info=DIOUSBC
+        0xc0, 0x36, 0x00, 0x10, 0x80, 0x01, 0x00, 0x00, 0x11, 0x94, 0x00, 0x48, 0x10, 0x61, 0x72,
+        0x63, 0x70, 0x5f, 0x76, 0x65, 0x72, 0x73, 0x3d, 0x32, 0x2e, 0x37, 0x2e, 0x34, 0x31, 0x0e,
+        0x61, 0x72, 0x63, 0x70, 0x5f, 0x6d, 0x69, 0x6e, 0x3d, 0x30, 0x2e, 0x32, 0x2e, 0x34, 0x13,
+        0x72, 0x6f, 0x75, 0x74, 0x65, 0x72, 0x5f, 0x76, 0x65, 0x72, 0x73, 0x3d, 0x34, 0x2e, 0x34,
+        0x2e, 0x31, 0x2e, 0x33, 0x13, 0x72, 0x6f, 0x75, 0x74, 0x65, 0x72, 0x5f, 0x69, 0x6e, 0x66,
+        0x6f, 0x3d, 0x44, 0x49, 0x4f, 0x55, 0x53, 0x42, 0x43,
+        // additional: A 192.168.1.50
+        0xc0, 0x54, 0x00, 0x01, 0x80, 0x01, 0x00, 0x00, 0x00, 0x78, 0x00, 0x04, 0xc0, 0xa8, 0x01,
+        0x32, // additional: NSEC, skipped
+        0xc0, 0x54, 0x00, 0x2f, 0x80, 0x01, 0x00, 0x00, 0x00, 0x78, 0x00, 0x05, 0xc0, 0x54, 0x00,
+        0x01, 0x40,
+    ];
+
+    const CHAN_ANSWER: &[u8] = &[
+        // header: id 0x4443, response, 1 question, 1 answer, 4 additional
+        0x44, 0x43, 0x84, 0x00, 0x00, 0x01, 0x00, 0x01, 0x00, 0x00, 0x00, 0x04,
+        // question: _netaudio-chan._udp.local. PTR IN
+        0x0e, 0x5f, 0x6e, 0x65, 0x74, 0x61, 0x75, 0x64, 0x69, 0x6f, 0x2d, 0x63, 0x68, 0x61, 0x6e,
+        0x04, 0x5f, 0x75, 0x64, 0x70, 0x05, 0x6c, 0x6f, 0x63, 0x61, 0x6c, 0x00, 0x00, 0x0c, 0x00,
+        0x01, // answer: PTR -> 01@Stage-Box._netaudio-chan._udp.local.
+        0xc0, 0x0c, 0x00, 0x0c, 0x00, 0x01, 0x00, 0x00, 0x11, 0x94, 0x00, 0x0f, 0x0c, 0x30, 0x31,
+        0x40, 0x53, 0x74, 0x61, 0x67, 0x65, 0x2d, 0x42, 0x6f, 0x78, 0xc0, 0x0c,
+        // additional: SRV, cache flush, port 4455, target Stage-Box.local.
+        0xc0, 0x37, 0x00, 0x21, 0x80, 0x01, 0x00, 0x00, 0x00, 0x78, 0x00, 0x12, 0x00, 0x00, 0x00,
+        0x00, 0x11, 0x67, 0x09, 0x53, 0x74, 0x61, 0x67, 0x65, 0x2d, 0x42, 0x6f, 0x78, 0xc0, 0x20,
+        // additional: TXT txtvers=2 dbcp1=0x1102 id=1 rate=48000 en=24 pcm=3 latency_ns=1000000
+        0xc0, 0x37, 0x00, 0x10, 0x80, 0x01, 0x00, 0x00, 0x11, 0x94, 0x00, 0x46, 0x09, 0x74, 0x78,
+        0x74, 0x76, 0x65, 0x72, 0x73, 0x3d, 0x32, 0x0c, 0x64, 0x62, 0x63, 0x70, 0x31, 0x3d, 0x30,
+        0x78, 0x31, 0x31, 0x30, 0x32, 0x04, 0x69, 0x64, 0x3d, 0x31, 0x0a, 0x72, 0x61, 0x74, 0x65,
+        0x3d, 0x34, 0x38, 0x30, 0x30, 0x30, 0x05, 0x65, 0x6e, 0x3d, 0x32, 0x34, 0x05, 0x70, 0x63,
+        0x6d, 0x3d, 0x33, 0x12, 0x6c, 0x61, 0x74, 0x65, 0x6e, 0x63, 0x79, 0x5f, 0x6e, 0x73, 0x3d,
+        0x31, 0x30, 0x30, 0x30, 0x30, 0x30, 0x30, // additional: A 192.168.1.50
+        0xc0, 0x58, 0x00, 0x01, 0x80, 0x01, 0x00, 0x00, 0x00, 0x78, 0x00, 0x04, 0xc0, 0xa8, 0x01,
+        0x32, // additional: NSEC, skipped
+        0xc0, 0x58, 0x00, 0x2f, 0x80, 0x01, 0x00, 0x00, 0x00, 0x78, 0x00, 0x05, 0xc0, 0x58, 0x00,
+        0x01, 0x40,
+    ];
+
+    fn txt(entries: &[(&str, &str)]) -> HashMap<String, String> {
+        entries
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn parses_cmc_answer() {
+        assert_eq!(
+            parse_response(CMC_ANSWER).unwrap(),
+            vec![
+                DnsRecord::Ptr {
+                    name: "_netaudio-cmc._udp.local.".to_owned(),
+                    target: "Stage-Box._netaudio-cmc._udp.local.".to_owned(),
+                },
+                DnsRecord::Srv {
+                    name: "Stage-Box._netaudio-cmc._udp.local.".to_owned(),
+                    port: 8800,
+                    target: "Stage-Box.local.".to_owned(),
+                },
+                DnsRecord::Txt {
+                    name: "Stage-Box._netaudio-cmc._udp.local.".to_owned(),
+                    properties: txt(&[
+                        ("id", "001dc1fffe5046a1"),
+                        ("process", "0"),
+                        ("cmcp_vers", "1.2.0"),
+                        ("cmcp_min", "1.0.0"),
+                        ("server_vers", "4.2.3.2"),
+                        ("mf", "Audinate"),
+                        ("model", "DIOUSBC"),
+                    ]),
+                },
+                DnsRecord::A {
+                    name: "Stage-Box.local.".to_owned(),
+                    address: Ipv4Addr::new(192, 168, 1, 50),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_arc_answer() {
+        let records = parse_response(ARC_ANSWER).unwrap();
+        assert_eq!(records.len(), 4);
+        assert!(records.contains(&DnsRecord::Srv {
+            name: "Stage-Box._netaudio-arc._udp.local.".to_owned(),
+            port: 4440,
+            target: "Stage-Box.local.".to_owned(),
+        }));
+        assert!(records.contains(&DnsRecord::Txt {
+            name: "Stage-Box._netaudio-arc._udp.local.".to_owned(),
+            properties: txt(&[
+                ("arcp_vers", "2.7.41"),
+                ("arcp_min", "0.2.4"),
+                ("router_vers", "4.4.1.3"),
+                ("router_info", "DIOUSBC"),
+            ]),
+        }));
+    }
+
+    #[test]
+    fn parses_chan_answer() {
+        let records = parse_response(CHAN_ANSWER).unwrap();
+        assert_eq!(records.len(), 4);
+        assert!(records.contains(&DnsRecord::Ptr {
+            name: "_netaudio-chan._udp.local.".to_owned(),
+            target: "01@Stage-Box._netaudio-chan._udp.local.".to_owned(),
+        }));
+        assert!(records.contains(&DnsRecord::Txt {
+            name: "01@Stage-Box._netaudio-chan._udp.local.".to_owned(),
+            properties: txt(&[
+                ("txtvers", "2"),
+                ("dbcp1", "0x1102"),
+                ("id", "1"),
+                ("rate", "48000"),
+                ("en", "24"),
+                ("pcm", "3"),
+                ("latency_ns", "1000000"),
+            ]),
+        }));
+    }
+
+    #[test]
+    fn truncated_answer_is_an_error() {
+        for length in [1, 11, 20, 60, CHAN_ANSWER.len() - 1] {
+            assert!(parse_response(&CHAN_ANSWER[..length]).is_err());
+        }
+    }
+
+    #[test]
+    fn answers_resolve_the_device() {
+        let records: Vec<DnsRecord> = [CMC_ANSWER, ARC_ANSWER, CHAN_ANSWER]
+            .into_iter()
+            .flat_map(|answer| parse_response(answer).unwrap())
+            .collect();
+        let mut device_list = DanteDeviceList::new();
+        for (service, service_info) in resolved_services(&records, Ipv4Addr::new(10, 0, 0, 1)) {
+            device_list.handle_discovery_event(service, DiscoveryEvent::Resolved(service_info));
+        }
+
+        let info = device_list.get_device_info("Stage-Box").unwrap();
+        assert_eq!(info.addresses, vec![Ipv4Addr::new(192, 168, 1, 50)]);
+        assert_eq!(info.cmc_port, Some(8800));
+        assert_eq!(info.arc_port, Some(4440));
+        assert_eq!(info.manufacturer.as_deref(), Some("Audinate"));
+        assert_eq!(info.router_vers.as_deref(), Some("4.4.1.3"));
+        assert_eq!(info.channels.len(), 1);
+        assert_eq!(info.channels[0].id, Some(1));
+        assert_eq!(info.channels[0].name, "01");
+        assert_eq!(info.channels[0].sample_rate, Some(48000));
+    }
+}
@@ -0,0 +1,149 @@
+use crate::{DanteDeviceEvent, DanteDeviceList, DanteDeviceManager};
+use log::{info, warn};
+use std::collections::HashMap;
+use std::time::Instant;
+
+/// Why a route is unresolved.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum UnresolvedReason {
+    /// Discovery doesn't see the TX device.
+    TxDeviceMissing,
+    /// The TX device is there but doesn't have a channel with the name, going by its CHAN records and TX channel names query.
+    TxChannelMissing,
+}
+
+/// A subscription whose TX side isn't on the network, which Dante keeps retrying until it comes back. See DanteDeviceManager::unresolved_routes().
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UnresolvedRoute {
+    pub rx_device: String,
+    pub rx_channel_id: u16,
+    pub rx_channel_name: String,
+    pub tx_device: String,
+    pub tx_channel: String,
+    pub reason: UnresolvedReason,
+    /// When the route was first seen unresolved. Routes are checked against discovery whenever a read-back comes in and every 100ms while discovery is running, so this is as good as that.
+    pub since: Instant,
+    /// Raw subscription status code of the read-back the route was last seen in. What the codes mean hasn't been worked out yet.
+    pub status: u16,
+}
+
+impl DanteDeviceList {
+    /// Why a subscription to tx_channel of tx_device can't be resolved, None if it can or there's no telling.
+    fn unresolved_reason(&self, tx_device: &str, tx_channel: &str) -> Option<UnresolvedReason> {
+        if !self.devices.contains_key(tx_device) {
+            return Some(UnresolvedReason::TxDeviceMissing);
+        }
+        let cache = self.caches.get(tx_device)?;
+        let mut names = cache
+            .chan_info
+            .iter()
+            .map(|chan_info| chan_info.name.as_str())
+            .chain(
+                cache
+                    .tx_channel_names
+                    .iter()
+                    .flat_map(|tx_channel_names| tx_channel_names.value.iter())
+                    .map(|(_, name)| name.as_str()),
+            )
+            .peekable();
+        // A device that hasn't told us about any of its channels may well have it.
+        names.peek()?;
+        match names.any(|name| name == tx_channel) {
+            true => None,
+            false => Some(UnresolvedReason::TxChannelMissing),
+        }
+    }
+
+    /// Checks the subscriptions of every read-back against discovery, emitting RouteUnresolved and RouteResolved for routes that changed. Routes of a device whose read-back was dropped are kept as they were until the next one comes in.
+    pub(crate) fn refresh_unresolved_routes(&mut self) {
+        let mut unresolved: HashMap<(String, u16), UnresolvedRoute> = HashMap::new();
+        for (rx_device, cache) in &self.caches {
+            if !self.devices.contains_key(rx_device) {
+                continue;
+            }
+            let Some(subscriptions) = &cache.subscriptions else {
+                unresolved.extend(
+                    self.unresolved_routes
+                        .iter()
+                        .filter(|((device, _), _)| device.as_str() == &**rx_device)
+                        .map(|(key, route)| (key.clone(), route.clone())),
+                );
+                continue;
+            };
+            for subscription in &subscriptions.value {
+                let (Some(tx_device), Some(tx_channel)) =
+                    (&subscription.tx_device, &subscription.tx_channel)
+                else {
+                    continue;
+                };
+                let Some(reason) = self.unresolved_reason(tx_device, tx_channel) else {
+                    continue;
+                };
+                let key = (rx_device.to_string(), subscription.rx_channel_id);
+                let since = self
+                    .unresolved_routes
+                    .get(&key)
+                    .map_or_else(Instant::now, |route| route.since);
+                unresolved.insert(
+                    key,
+                    UnresolvedRoute {
+                        rx_device: rx_device.to_string(),
+                        rx_channel_id: subscription.rx_channel_id,
+                        rx_channel_name: subscription.rx_channel_name.clone(),
+                        tx_device: tx_device.clone(),
+                        tx_channel: tx_channel.clone(),
+                        reason,
+                        since,
+                        status: subscription.status,
+                    },
+                );
+            }
+        }
+
+        let old = std::mem::replace(&mut self.unresolved_routes, unresolved);
+        for (key, route) in &old {
+            if !self.unresolved_routes.contains_key(key) {
+                info!(
+                    "{} channel {} resolved to {}/{}",
+                    route.rx_device, route.rx_channel_name, route.tx_device, route.tx_channel
+                );
+                self.events.emit(DanteDeviceEvent::RouteResolved {
+                    rx_device: route.rx_device.clone(),
+                    rx_channel_id: route.rx_channel_id,
+                });
+            }
+        }
+        let mut newly_unresolved: Vec<UnresolvedRoute> = self
+            .unresolved_routes
+            .iter()
+            .filter(|(key, _)| !old.contains_key(*key))
+            .map(|(_, route)| route.clone())
+            .collect();
+        newly_unresolved
+            .sort_by(|a, b| (&a.rx_device, a.rx_channel_id).cmp(&(&b.rx_device, b.rx_channel_id)));
+        for route in newly_unresolved {
+            warn!(
+                "{} channel {} is unresolved, {}/{} is missing ({:?})",
+                route.rx_device,
+                route.rx_channel_name,
+                route.tx_device,
+                route.tx_channel,
+                route.reason
+            );
+            self.events.emit(DanteDeviceEvent::RouteUnresolved(route));
+        }
+    }
+}
+
+impl DanteDeviceManager {
+    /// Returns the subscriptions across all devices whose TX device or channel is missing, sorted by RX device and channel id. Only subscriptions of devices that have been read back are known, see get_subscriptions().
+    pub fn unresolved_routes(&self) -> Vec<UnresolvedRoute> {
+        let mut device_list = self.device_list.lock().unwrap();
+        device_list.refresh_unresolved_routes();
+        let mut routes: Vec<UnresolvedRoute> =
+            device_list.unresolved_routes.values().cloned().collect();
+        routes
+            .sort_by(|a, b| (&a.rx_device, a.rx_channel_id).cmp(&(&b.rx_device, b.rx_channel_id)));
+        routes
+    }
+}